@@ -0,0 +1,473 @@
+// api_commands.rs
+// 自动化接口背后的命令：直接调用 worklist.rs 中对应的 Tauri 命令，返回相同的 ApiResponse
+use crate::api_server::{ApiError, ApiService};
+use crate::models::{
+    ApiResponse, DbState, HL7MessageSetting, Hl7SettingEntry, LoadTestConfig, MimEntry,
+    MppsEntry, MppsReportSetting, PatientEntry, WorklistEntry,
+};
+use crate::worklist;
+use futures::future::BoxFuture;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::{AppHandle, Manager};
+
+const COMMANDS: &[&str] = &[
+    "create_worklist_entry",
+    "read_worklist_entry",
+    "update_worklist_entry",
+    "delete_worklist_entry",
+    "search_worklist_data",
+    "create_mpps_entry",
+    "read_mpps_entry",
+    "update_mpps_entry",
+    "discontinue_mpps_entry",
+    "delete_mpps_entry",
+    "send_to_pacs",
+    "send_rt_s",
+    "create_mim_entry",
+    "read_mim_entry",
+    "update_mim_entry",
+    "delete_mim_entry",
+    "create_patient_entry",
+    "read_patient_entry",
+    "update_patient_entry",
+    "delete_patient_entry",
+    "send_cstore_headless",
+    "create_hl7_setting_entry",
+    "read_hl7_setting_entry",
+    "update_hl7_setting_entry",
+    "delete_hl7_setting_entry",
+    "create_hl7_message_setting",
+    "read_hl7_message_setting",
+    "update_hl7_message_setting",
+    "delete_hl7_message_setting",
+    "send_hl7_message",
+    "read_scenario",
+    "run_scenario",
+    "read_scenario_run",
+    "start_load_test",
+    "stop_load_test",
+    "read_load_test_status",
+    "read_load_test_run",
+];
+
+// 参数名与前端 invoke 相同 (camelCase)
+#[derive(Deserialize)]
+struct IdArgs {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct OptionalIdArgs {
+    #[serde(default)]
+    id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct EntryArgs<T> {
+    entry: T,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UpdateArgs<T> {
+    id: String,
+    updated_entry: T,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateMppsArgs {
+    selected_id: String,
+    entry: MppsEntry,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UpdateMppsArgs {
+    dcm_file: String,
+    selected_id: String,
+    id: String,
+    status: String,
+    description: Option<String>,
+    report: Option<MppsReportSetting>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DiscontinueMppsArgs {
+    selected_id: String,
+    id: String,
+    reason_code: String,
+    description: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SendToPacsArgs {
+    id: String,
+    current_id: String,
+    storage_commitment: Option<bool>,
+    fhir_setting_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SendRtArgs {
+    dcm_file: String,
+    selected_id: String,
+    id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SendCstoreHeadlessArgs {
+    dcm_file: String,
+    selected_id: String,
+    id: String,
+    description: Option<String>,
+    generate: Option<bool>,
+    storage_commitment: Option<bool>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SendHl7Args {
+    message: String,
+    server_address: String,
+    port: String,
+    method: String,
+    ack_timeout: Option<u64>,
+    kind: Option<String>,
+    current_id: Option<String>,
+    setting_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RunScenarioArgs {
+    id: String,
+    worklist_id: String,
+    mim_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StartLoadTestArgs {
+    worklist_id: String,
+    mim_id: Option<String>,
+    config: LoadTestConfig,
+}
+
+fn parse<T: DeserializeOwned>(args: Value) -> Result<T, ApiError> {
+    serde_json::from_value(args).map_err(|e| ApiError::InvalidArguments(e.to_string()))
+}
+
+fn reply<T: Serialize>(result: Result<ApiResponse<T>, String>) -> Result<Value, ApiError> {
+    let response = result.map_err(ApiError::Failed)?;
+    serde_json::to_value(response).map_err(|e| ApiError::Failed(e.to_string()))
+}
+
+/// Runs automation API requests against the app's managed state.
+pub struct TauriCommands {
+    handle: AppHandle,
+}
+
+impl TauriCommands {
+    pub fn new(handle: AppHandle) -> TauriCommands {
+        TauriCommands { handle }
+    }
+
+    async fn dispatch(&self, command: &str, args: Value) -> Result<Value, ApiError> {
+        // 数据库在启动后异步打开，之前的请求直接拒绝
+        if self.handle.try_state::<DbState>().is_none() {
+            return Err(ApiError::Unavailable(
+                "The database is not ready yet".to_string(),
+            ));
+        }
+        let app = &self.handle;
+        match command {
+            "create_worklist_entry" => {
+                let args: EntryArgs<WorklistEntry> = parse(args)?;
+                reply(worklist::create_worklist_entry(app.state(), args.entry).await)
+            }
+            "read_worklist_entry" => {
+                let args: OptionalIdArgs = parse(args)?;
+                reply(worklist::read_worklist_entry(app.state(), args.id).await)
+            }
+            "update_worklist_entry" => {
+                let args: UpdateArgs<WorklistEntry> = parse(args)?;
+                reply(
+                    worklist::update_worklist_entry(app.state(), args.id, args.updated_entry).await,
+                )
+            }
+            "delete_worklist_entry" => {
+                let args: IdArgs = parse(args)?;
+                reply(worklist::delete_worklist_entry(app.state(), args.id).await)
+            }
+            "search_worklist_data" => {
+                let args: IdArgs = parse(args)?;
+                // 前端拿到的是 JSON 字符串，这里解析后放进 ApiResponse
+                let result =
+                    worklist::search_worklist_data(args.id, app.state(), app.state(), app.clone())
+                        .await
+                        .and_then(|json| {
+                            serde_json::from_str::<Value>(&json)
+                                .map_err(|e| format!("An error occurred: {}", e))
+                        });
+                reply(
+                    result.map(|entries| {
+                        ApiResponse::success("Worklist query completed", Some(entries))
+                    }),
+                )
+            }
+            "create_mpps_entry" => {
+                let args: CreateMppsArgs = parse(args)?;
+                reply(
+                    worklist::create_mpps_entry(
+                        app.state(),
+                        app.state(),
+                        args.selected_id,
+                        args.entry,
+                        app.clone(),
+                    )
+                    .await,
+                )
+            }
+            "read_mpps_entry" => {
+                let args: OptionalIdArgs = parse(args)?;
+                reply(worklist::read_mpps_entry(app.state(), args.id).await)
+            }
+            "update_mpps_entry" => {
+                let args: UpdateMppsArgs = parse(args)?;
+                reply(
+                    worklist::update_mpps_entry(
+                        app.state(),
+                        app.state(),
+                        args.dcm_file,
+                        args.selected_id,
+                        args.id,
+                        args.status,
+                        args.description,
+                        args.report,
+                        app.clone(),
+                    )
+                    .await,
+                )
+            }
+            "discontinue_mpps_entry" => {
+                let args: DiscontinueMppsArgs = parse(args)?;
+                reply(
+                    worklist::discontinue_mpps_entry(
+                        app.state(),
+                        app.state(),
+                        args.selected_id,
+                        args.id,
+                        args.reason_code,
+                        args.description,
+                        app.clone(),
+                    )
+                    .await,
+                )
+            }
+            "delete_mpps_entry" => {
+                let args: IdArgs = parse(args)?;
+                reply(worklist::delete_mpps_entry(app.state(), args.id).await)
+            }
+            "send_to_pacs" => {
+                let args: SendToPacsArgs = parse(args)?;
+                reply(
+                    worklist::send_to_pacs(
+                        app.state(),
+                        app.state(),
+                        args.id,
+                        args.current_id,
+                        args.storage_commitment,
+                        args.fhir_setting_id,
+                    )
+                    .await,
+                )
+            }
+            "send_rt_s" => {
+                let args: SendRtArgs = parse(args)?;
+                reply(
+                    worklist::send_rt_s(
+                        app.state(),
+                        app.state(),
+                        args.dcm_file,
+                        args.selected_id,
+                        args.id,
+                    )
+                    .await,
+                )
+            }
+            "create_mim_entry" => {
+                let args: EntryArgs<MimEntry> = parse(args)?;
+                reply(worklist::create_mim_entry(app.state(), args.entry).await)
+            }
+            "read_mim_entry" => {
+                let args: OptionalIdArgs = parse(args)?;
+                reply(worklist::read_mim_entry(app.state(), args.id).await)
+            }
+            "update_mim_entry" => {
+                let args: UpdateArgs<MimEntry> = parse(args)?;
+                reply(worklist::update_mim_entry(app.state(), args.id, args.updated_entry).await)
+            }
+            "delete_mim_entry" => {
+                let args: IdArgs = parse(args)?;
+                reply(worklist::delete_mim_entry(app.state(), args.id).await)
+            }
+            "create_patient_entry" => {
+                let args: EntryArgs<PatientEntry> = parse(args)?;
+                reply(worklist::create_patient_entry(app.state(), args.entry).await)
+            }
+            "read_patient_entry" => {
+                let args: OptionalIdArgs = parse(args)?;
+                reply(worklist::read_patient_entry(app.state(), args.id).await)
+            }
+            "update_patient_entry" => {
+                let args: UpdateArgs<PatientEntry> = parse(args)?;
+                reply(
+                    worklist::update_patient_entry(app.state(), args.id, args.updated_entry).await,
+                )
+            }
+            "delete_patient_entry" => {
+                let args: IdArgs = parse(args)?;
+                reply(worklist::delete_patient_entry(app.state(), args.id).await)
+            }
+            "send_cstore_headless" => {
+                let args: SendCstoreHeadlessArgs = parse(args)?;
+                reply(
+                    worklist::send_cstore_headless(
+                        app.state(),
+                        app.state(),
+                        args.dcm_file,
+                        args.selected_id,
+                        args.id,
+                        args.description,
+                        args.generate,
+                        args.storage_commitment,
+                    )
+                    .await,
+                )
+            }
+            "create_hl7_setting_entry" => {
+                let args: EntryArgs<Hl7SettingEntry> = parse(args)?;
+                reply(worklist::create_hl7_setting_entry(app.state(), args.entry).await)
+            }
+            "read_hl7_setting_entry" => {
+                let args: OptionalIdArgs = parse(args)?;
+                reply(worklist::read_hl7_setting_entry(app.state(), args.id).await)
+            }
+            "update_hl7_setting_entry" => {
+                let args: UpdateArgs<Hl7SettingEntry> = parse(args)?;
+                reply(
+                    worklist::update_hl7_setting_entry(
+                        app.state(),
+                        app.state(),
+                        args.id,
+                        args.updated_entry,
+                    )
+                    .await,
+                )
+            }
+            "delete_hl7_setting_entry" => {
+                let args: IdArgs = parse(args)?;
+                reply(worklist::delete_hl7_setting_entry(app.state(), app.state(), args.id).await)
+            }
+            "create_hl7_message_setting" => {
+                let args: EntryArgs<HL7MessageSetting> = parse(args)?;
+                reply(worklist::create_hl7_message_setting(app.state(), args.entry).await)
+            }
+            "read_hl7_message_setting" => {
+                let args: OptionalIdArgs = parse(args)?;
+                reply(worklist::read_hl7_message_setting(app.state(), args.id).await)
+            }
+            "update_hl7_message_setting" => {
+                let args: UpdateArgs<HL7MessageSetting> = parse(args)?;
+                reply(
+                    worklist::update_hl7_message_setting(app.state(), args.id, args.updated_entry)
+                        .await,
+                )
+            }
+            "delete_hl7_message_setting" => {
+                let args: IdArgs = parse(args)?;
+                reply(worklist::delete_hl7_message_setting(app.state(), args.id).await)
+            }
+            "send_hl7_message" => {
+                let args: SendHl7Args = parse(args)?;
+                reply(
+                    worklist::send_hl7_message(
+                        app.state(),
+                        app.state(),
+                        &args.message,
+                        &args.server_address,
+                        &args.port,
+                        &args.method,
+                        args.ack_timeout,
+                        args.kind,
+                        args.current_id,
+                        args.setting_id,
+                        app.clone(),
+                    )
+                    .await,
+                )
+            }
+            "read_scenario" => {
+                let args: OptionalIdArgs = parse(args)?;
+                reply(worklist::read_scenario(app.state(), args.id).await)
+            }
+            "run_scenario" => {
+                let args: RunScenarioArgs = parse(args)?;
+                reply(
+                    worklist::run_scenario(
+                        app.state(),
+                        app.state(),
+                        app.clone(),
+                        args.id,
+                        args.worklist_id,
+                        args.mim_id,
+                    )
+                    .await,
+                )
+            }
+            "read_scenario_run" => {
+                let args: OptionalIdArgs = parse(args)?;
+                reply(worklist::read_scenario_run(app.state(), args.id).await)
+            }
+            "start_load_test" => {
+                let args: StartLoadTestArgs = parse(args)?;
+                reply(
+                    worklist::start_load_test(
+                        app.state(),
+                        app.state(),
+                        app.state(),
+                        app.clone(),
+                        args.worklist_id,
+                        args.mim_id,
+                        args.config,
+                    )
+                    .await,
+                )
+            }
+            "stop_load_test" => reply(worklist::stop_load_test(app.state()).await),
+            "read_load_test_status" => reply(worklist::read_load_test_status(app.state()).await),
+            "read_load_test_run" => {
+                let args: OptionalIdArgs = parse(args)?;
+                reply(worklist::read_load_test_run(app.state(), args.id).await)
+            }
+            _ => Err(ApiError::UnknownCommand(command.to_string())),
+        }
+    }
+}
+
+impl ApiService for TauriCommands {
+    fn commands(&self) -> Vec<&'static str> {
+        COMMANDS.to_vec()
+    }
+
+    fn call<'a>(&'a self, command: &'a str, args: Value) -> BoxFuture<'a, Result<Value, ApiError>> {
+        Box::pin(self.dispatch(command, args))
+    }
+}
@@ -0,0 +1,268 @@
+// api_server.rs
+// 本地自动化接口：只监听 127.0.0.1，POST /api/<command> 的 JSON 请求体与前端 invoke 的参数相同
+// 每个请求都需要携带 Authorization: Bearer <token>
+use crate::models::{ApiResponse, ApiServerStatus};
+use chrono::Local;
+use futures::future::BoxFuture;
+use hyper::header::{AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, WWW_AUTHENTICATE};
+use hyper::body::HttpBody;
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::Value;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::task::{JoinHandle, JoinSet};
+
+// 请求体上限，足够放下一个 HL7 批量文件
+const MAX_BODY_BYTES: u64 = 16 * 1024 * 1024;
+// accept 失败后重试前的等待时间
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Why a command could not produce an `ApiResponse`.
+#[derive(Debug)]
+pub enum ApiError {
+    UnknownCommand(String),
+    InvalidArguments(String),
+    Unavailable(String),
+    /// The command itself returned `Err`.
+    Failed(String),
+}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiError::UnknownCommand(_) => StatusCode::NOT_FOUND,
+            ApiError::InvalidArguments(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Failed(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            ApiError::UnknownCommand(command) => format!("Unknown command: {}", command),
+            ApiError::InvalidArguments(e) => format!("Invalid arguments: {}", e),
+            ApiError::Unavailable(e) | ApiError::Failed(e) => e.clone(),
+        }
+    }
+}
+
+/// The commands behind the automation API.
+pub trait ApiService: Send + Sync {
+    /// The command names accepted by `call`.
+    fn commands(&self) -> Vec<&'static str>;
+
+    /// Runs `command` with the JSON arguments of the request body and
+    /// returns the serialized `ApiResponse`.
+    fn call<'a>(&'a self, command: &'a str, args: Value) -> BoxFuture<'a, Result<Value, ApiError>>;
+}
+
+struct Context {
+    token: String,
+    service: Arc<dyn ApiService>,
+    requests: AtomicU64,
+    rejected: AtomicU64,
+}
+
+/// A running automation API. Stopping it also closes its open connections.
+pub struct ApiServer {
+    address: SocketAddr,
+    started_at: String,
+    context: Arc<Context>,
+    handle: JoinHandle<()>,
+}
+
+impl ApiServer {
+    pub async fn start(
+        port: u16,
+        token: String,
+        service: Arc<dyn ApiService>,
+    ) -> Result<ApiServer, String> {
+        if token.trim().is_empty() {
+            return Err("An access token is required".to_string());
+        }
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .await
+            .map_err(|e| format!("Failed to listen on port {}: {}", port, e))?;
+        let address = listener
+            .local_addr()
+            .map_err(|e| format!("Failed to listen on port {}: {}", port, e))?;
+
+        let context = Arc::new(Context {
+            token: token.trim().to_string(),
+            service,
+            requests: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        });
+        let handle = tokio::spawn(accept_loop(listener, context.clone()));
+        Ok(ApiServer {
+            address,
+            started_at: Local::now().to_rfc3339(),
+            context,
+            handle,
+        })
+    }
+
+    pub fn stop(self) {
+        self.handle.abort();
+    }
+
+    pub fn status(&self) -> ApiServerStatus {
+        ApiServerStatus {
+            address: format!("http://{}", self.address),
+            started_at: self.started_at.clone(),
+            requests: self.context.requests.load(Ordering::Relaxed),
+            rejected: self.context.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+async fn accept_loop(listener: TcpListener, context: Arc<Context>) {
+    // 连接任务挂在 JoinSet 上，监听任务被 abort 时会一并结束
+    let mut tasks = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (socket, remote_addr) = match accepted {
+                    Ok(accepted) => accepted,
+                    // EMFILE 等错误会持续出现，稍等再重试以免空转
+                    Err(e) => {
+                        eprintln!("Failed to accept a automation API connection: {}", e);
+                        tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                        continue;
+                    }
+                };
+                let context = context.clone();
+                tasks.spawn(async move {
+                    let service = service_fn(move |request| {
+                        let context = context.clone();
+                        async move { Ok::<_, Infallible>(handle(&context, request).await) }
+                    });
+                    if let Err(e) = Http::new().http1_only(true).serve_connection(socket, service).await {
+                        eprintln!("Automation API: {} closed: {}", remote_addr, e);
+                    }
+                });
+            }
+            Some(_) = tasks.join_next(), if !tasks.is_empty() => {}
+        }
+    }
+}
+
+async fn handle(context: &Context, request: Request<Body>) -> Response<Body> {
+    context.requests.fetch_add(1, Ordering::Relaxed);
+    if !authorized(&context.token, &request) {
+        context.rejected.fetch_add(1, Ordering::Relaxed);
+        let mut response = error_response(
+            StatusCode::UNAUTHORIZED,
+            "Missing or invalid access token".to_string(),
+        );
+        response
+            .headers_mut()
+            .insert(WWW_AUTHENTICATE, "Bearer".parse().unwrap());
+        return response;
+    }
+
+    let path = request.uri().path().trim_end_matches('/').to_string();
+    let Some(command) = path.strip_prefix("/api/").map(str::to_string) else {
+        return error_response(StatusCode::NOT_FOUND, format!("Not found: {}", path));
+    };
+    if command == "commands" && request.method() == Method::GET {
+        return json_response(
+            StatusCode::OK,
+            &ApiResponse::success("Available commands", Some(context.service.commands())),
+        );
+    }
+    if request.method() != Method::POST {
+        return error_response(
+            StatusCode::METHOD_NOT_ALLOWED,
+            format!("Use POST /api/{}", command),
+        );
+    }
+
+    let args = match read_args(request).await {
+        Ok(args) => args,
+        Err((status, e)) => return error_response(status, e),
+    };
+    match context.service.call(&command, args).await {
+        Ok(body) => json_response(StatusCode::OK, &body),
+        Err(e) => error_response(e.status(), e.message()),
+    }
+}
+
+// 令牌按字节逐一比较，耗时与不匹配的位置无关
+fn authorized(token: &str, request: &Request<Body>) -> bool {
+    let Some(provided) = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+    let (provided, token) = (provided.trim().as_bytes(), token.as_bytes());
+    provided.len() == token.len()
+        && provided
+            .iter()
+            .zip(token)
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+async fn read_args(request: Request<Body>) -> Result<Value, (StatusCode, String)> {
+    let too_large = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .is_some_and(|length| length > MAX_BODY_BYTES);
+    if too_large {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("The request body is larger than {} bytes", MAX_BODY_BYTES),
+        ));
+    }
+    // 分块传输没有 Content-Length，读取时也要限制长度
+    let mut body = request.into_body();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("Failed to read the request: {}", e),
+            )
+        })?;
+        if (bytes.len() + chunk.len()) as u64 > MAX_BODY_BYTES {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("The request body is larger than {} bytes", MAX_BODY_BYTES),
+            ));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    let body = bytes;
+    // 没有参数的命令可以不带请求体
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(Value::Object(Default::default()));
+    }
+    serde_json::from_slice(&body)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)))
+}
+
+fn json_response<T: serde::Serialize>(status: StatusCode, body: &T) -> Response<Body> {
+    let body = serde_json::to_vec(body).unwrap_or_default();
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+    response
+}
+
+fn error_response(status: StatusCode, message: String) -> Response<Body> {
+    json_response(status, &ApiResponse::<()>::error(&message, None))
+}
@@ -0,0 +1,6 @@
+// Headless entry point for CI runs, see cli.rs. No windows_subsystem here so
+// the console output stays visible on Windows.
+#[tokio::main]
+async fn main() {
+    std::process::exit(dicom_desktop_lib::cli::run(std::env::args_os()).await)
+}
@@ -0,0 +1,714 @@
+// cli.rs
+// dicom-sim-cli：不启动桌面程序，在命令行 / CI 中执行 worklist 查询、MPPS、C-STORE、HL7 发送、场景脚本和压力测试
+// 端点配置读取桌面程序的 SurrealDB 数据目录，或一个 JSON 配置文件
+use crate::dicom_client::{DicomNode, DimseClient, NativeDimseClient};
+use crate::dicom_utils::{
+    build_completed, build_in_progress, collect_dcm_files, find_dcm_files, load_template,
+    prepare_instances, query_worklist, read_dicom_file,
+};
+use crate::hl7_client::{Hl7Client, Hl7TlsConfig, SendMethod};
+use crate::hl7_template::{entry_values, render};
+use crate::load_test::LoadTest;
+use crate::models::{
+    HL7MessageSetting, Hl7SettingEntry, LoadTestConfig, MimEntry, MppsEntry, MppsStatus,
+    ScenarioEntry, WorklistEntry,
+};
+use crate::paths::AppPath;
+use crate::scenario::{Scenario, ScenarioRunner, ScenarioTemplates, SCENARIO_TABLE};
+use crate::utils::generate_uid;
+use clap::{Parser, Subcommand};
+use dicom_dictionary_std::uids;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use surrealdb::engine::local::{Db, File};
+use surrealdb::sql::Thing;
+use surrealdb::Surreal;
+
+#[derive(Parser)]
+#[command(
+    name = "dicom-sim-cli",
+    about = "Runs worklist queries, MPPS steps, C-STORE and HL7 sends without the desktop app"
+)]
+struct Cli {
+    /// SurrealDB directory of the desktop app. The app must be closed, it
+    /// locks the database while running.
+    #[arg(long, conflicts_with = "config", required_unless_present = "config")]
+    db: Option<PathBuf>,
+
+    /// JSON file with "worklist", "mim", "hl7_setting", "hl7_message_setting"
+    /// and "scenario" arrays, in the same format the app stores.
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Directory that contains the app's `resources` folder (DICOM message
+    /// templates and certs).
+    #[arg(long, default_value = ".")]
+    resource_dir: PathBuf,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Query a modality worklist SCP and print the scheduled steps as JSON.
+    Worklist {
+        /// Worklist setting name or id
+        #[arg(long)]
+        worklist: String,
+        /// Only print the step with this accession number
+        #[arg(long)]
+        accession: Option<String>,
+    },
+    /// Send MPPS N-CREATE (IN PROGRESS) or N-SET (COMPLETED).
+    Mpps {
+        #[command(subcommand)]
+        action: MppsAction,
+    },
+    /// C-STORE DICOM files unchanged.
+    Store {
+        /// PACS setting name or id
+        #[arg(long)]
+        pacs: String,
+        /// A .dcm file or a directory searched for .dcm files
+        #[arg(long)]
+        path: PathBuf,
+    },
+    /// Send an HL7 message, exiting with 1 unless the ACK is AA/CA.
+    Hl7 {
+        /// HL7 setting name or id
+        #[arg(long)]
+        endpoint: String,
+        /// HL7 message setting name or id
+        #[arg(long, conflicts_with = "file", required_unless_present = "file")]
+        template: Option<String>,
+        /// A file with the message
+        #[arg(long)]
+        file: Option<PathBuf>,
+        /// Fill placeholders from the MPPS step with this accession number
+        #[arg(long)]
+        accession: Option<String>,
+        /// Worklist to look the accession number up in when it is not stored
+        #[arg(long)]
+        worklist: Option<String>,
+        /// TCP (MLLP) or HTTP
+        #[arg(long, default_value = "TCP")]
+        method: String,
+    },
+    /// Run a scenario script and print its report, exiting with 1 when a step failed.
+    Scenario {
+        /// Scenario name or id
+        #[arg(long, conflicts_with = "file", required_unless_present = "file")]
+        scenario: Option<String>,
+        /// A file with the scenario JSON
+        #[arg(long)]
+        file: Option<PathBuf>,
+        /// Worklist setting name or id, also used for MPPS
+        #[arg(long)]
+        worklist: String,
+        /// PACS setting name or id for c_store steps
+        #[arg(long)]
+        pacs: Option<String>,
+    },
+    /// Run concurrent worklist/MPPS/C-STORE cycles from virtual modalities
+    /// and print latency and failure statistics.
+    Load {
+        /// Worklist setting name or id, also used for MPPS
+        #[arg(long)]
+        worklist: String,
+        /// PACS setting name or id; without it no C-STORE is sent
+        #[arg(long)]
+        pacs: Option<String>,
+        /// Number of virtual modalities
+        #[arg(long, default_value_t = 10)]
+        modalities: u32,
+        /// Calling AE title prefix, numbered per modality (SIM001, SIM002, ...)
+        #[arg(long)]
+        ae_prefix: Option<String>,
+        /// Test duration in seconds
+        #[arg(long, default_value_t = 60)]
+        duration: u64,
+        /// Seconds over which the modalities are started
+        #[arg(long, default_value_t = 0)]
+        ramp_up: u64,
+        /// Cycles per minute per modality; back to back when omitted
+        #[arg(long)]
+        rate: Option<f64>,
+        /// .dcm file or directory sent in every cycle
+        #[arg(long)]
+        path: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum MppsAction {
+    /// N-CREATE an IN PROGRESS step for a worklist item.
+    Start {
+        #[arg(long)]
+        worklist: String,
+        #[arg(long)]
+        accession: String,
+    },
+    /// N-SET the step COMPLETED with the instances found under --dcm.
+    Complete {
+        #[arg(long)]
+        worklist: String,
+        #[arg(long)]
+        accession: String,
+        /// .dcm file or directory of the performed series
+        #[arg(long)]
+        dcm: PathBuf,
+        /// MPPS SOP Instance UID of the step; defaults to the most recent
+        /// stored step and is needed when the step is not in --db
+        #[arg(long)]
+        mpps_uid: Option<String>,
+        /// Also C-STORE the instances to this PACS setting
+        #[arg(long)]
+        store: Option<String>,
+    },
+}
+
+// 端点配置的来源
+enum Settings {
+    Db(Surreal<Db>),
+    Config(Value),
+}
+
+impl Settings {
+    async fn open(cli: &Cli) -> Result<Settings, String> {
+        if let Some(path) = &cli.config {
+            let text = std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            let config = serde_json::from_str(&text)
+                .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+            return Ok(Settings::Config(config));
+        }
+        let path = cli
+            .db
+            .as_ref()
+            .ok_or("Either --db or --config is required")?;
+        let db = Surreal::new::<File>(path.to_string_lossy().as_ref())
+            .await
+            .map_err(|e| format!("Failed to open database {}: {}", path.display(), e))?;
+        db.use_ns("test")
+            .use_db("test")
+            .await
+            .map_err(|e| format!("Failed to open database {}: {}", path.display(), e))?;
+        Ok(Settings::Db(db))
+    }
+
+    fn db(&self) -> Option<&Surreal<Db>> {
+        match self {
+            Settings::Db(db) => Some(db),
+            Settings::Config(_) => None,
+        }
+    }
+
+    async fn entries<T: DeserializeOwned>(&self, table: &str) -> Result<Vec<T>, String> {
+        match self {
+            Settings::Db(db) => db
+                .select(table)
+                .await
+                .map_err(|e| format!("Failed to read {} entries: {}", table, e)),
+            Settings::Config(config) => match config.get(table) {
+                Some(entries) => serde_json::from_value(entries.clone())
+                    .map_err(|e| format!("Invalid {} entries: {}", table, e)),
+                None => Ok(Vec::new()),
+            },
+        }
+    }
+
+    /// The entry of `table` whose name or record id is `key`.
+    async fn find<T: DeserializeOwned + Named>(&self, table: &str, key: &str) -> Result<T, String> {
+        self.entries::<T>(table)
+            .await?
+            .into_iter()
+            .find(|entry| entry.name() == key || entry.id().is_some_and(|id| id.id.to_raw() == key))
+            .ok_or_else(|| format!("No {} entry named {}", table, key))
+    }
+}
+
+trait Named {
+    fn name(&self) -> &str;
+    fn id(&self) -> Option<&Thing>;
+}
+
+macro_rules! named {
+    ($($ty:ty),*) => {$(
+        impl Named for $ty {
+            fn name(&self) -> &str {
+                &self.name
+            }
+            fn id(&self) -> Option<&Thing> {
+                self.id.as_ref()
+            }
+        }
+    )*};
+}
+
+named!(
+    WorklistEntry,
+    MimEntry,
+    Hl7SettingEntry,
+    HL7MessageSetting,
+    ScenarioEntry
+);
+
+struct Runner {
+    settings: Settings,
+    resource_dir: PathBuf,
+    client: NativeDimseClient,
+}
+
+impl Runner {
+    async fn query(&self, worklist: &WorklistEntry) -> Result<Vec<MppsEntry>, String> {
+        let queries = vec![
+            load_template(&AppPath::CFindRq.under(&self.resource_dir))?,
+            load_template(&AppPath::MrModality.under(&self.resource_dir))?,
+        ];
+        query_worklist(&self.client, worklist, queries).await
+    }
+
+    async fn scheduled(
+        &self,
+        worklist: &WorklistEntry,
+        accession: &str,
+    ) -> Result<MppsEntry, String> {
+        self.query(worklist)
+            .await?
+            .into_iter()
+            .find(|e| e.accession_number == accession)
+            .ok_or_else(|| format!("No worklist item with accession number {}", accession))
+    }
+
+    // 数据库中该检查号的 MPPS 条目，按最后一次状态变化从新到旧排列
+    async fn stored_steps(&self, accession: &str) -> Result<Vec<MppsEntry>, String> {
+        let Some(db) = self.settings.db() else {
+            return Ok(Vec::new());
+        };
+        let mut response = db
+            .query(
+                "SELECT *, StatusHistory[$].timestamp AS changed_at FROM mpps \
+                 WHERE AccessionNumber = $accession_number ORDER BY changed_at DESC",
+            )
+            .bind(("accession_number", accession.to_string()))
+            .await
+            .map_err(|e| format!("Failed to read mpps entries: {}", e))?;
+        response
+            .take(0)
+            .map_err(|e| format!("Failed to read mpps entries: {}", e))
+    }
+
+    // 指定 MPPS SOP Instance UID 时取对应的条目，否则取该检查号最近的条目
+    async fn stored_step(
+        &self,
+        accession: &str,
+        mpps_uid: Option<&str>,
+    ) -> Result<Option<MppsEntry>, String> {
+        let steps = self.stored_steps(accession).await?;
+        Ok(match mpps_uid {
+            Some(uid) => steps
+                .into_iter()
+                .find(|step| step.mpps_instance_uid.as_deref() == Some(uid)),
+            None => steps.into_iter().next(),
+        })
+    }
+
+    async fn save_step(&self, entry: MppsEntry) -> Result<MppsEntry, String> {
+        let Some(db) = self.settings.db() else {
+            return Ok(entry);
+        };
+        let saved: Option<MppsEntry> = match entry.id.clone() {
+            Some(id) => db
+                .update((id.tb.as_str(), id.id.to_raw()))
+                .content(entry)
+                .await
+                .map_err(|e| format!("Failed to update mpps entry: {}", e))?,
+            None => {
+                let created: Vec<MppsEntry> = db
+                    .create("mpps")
+                    .content(entry)
+                    .await
+                    .map_err(|e| format!("Failed to create mpps entry: {}", e))?;
+                created.into_iter().next()
+            }
+        };
+        saved.ok_or_else(|| "Failed to save mpps entry".to_string())
+    }
+
+    async fn run(&self, command: Command) -> Result<Value, String> {
+        match command {
+            Command::Worklist {
+                worklist,
+                accession,
+            } => {
+                let worklist: WorklistEntry = self.settings.find("worklist", &worklist).await?;
+                let mut entries = self.query(&worklist).await?;
+                if let Some(accession) = accession {
+                    entries.retain(|e| e.accession_number == accession);
+                    if entries.is_empty() {
+                        return Err(format!(
+                            "No worklist item with accession number {}",
+                            accession
+                        ));
+                    }
+                }
+                to_json(&entries)
+            }
+            Command::Mpps { action } => self.mpps(action).await,
+            Command::Store { pacs, path } => {
+                let pacs: MimEntry = self.settings.find("mim", &pacs).await?;
+                let node = DicomNode::storage(&pacs).map_err(|e| e.to_string())?;
+                let objects = find_dcm_files(&path)?
+                    .iter()
+                    .map(|file| read_dicom_file(file))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if objects.is_empty() {
+                    return Err(format!("No .dcm files found in {}", path.display()));
+                }
+                let stored = self
+                    .client
+                    .c_store(&node, objects)
+                    .await
+                    .map_err(|e| format!("C-STORE failed: {}", e))?;
+                Ok(serde_json::json!({ "target": node.target(), "stored": stored }))
+            }
+            Command::Hl7 {
+                endpoint,
+                template,
+                file,
+                accession,
+                worklist,
+                method,
+            } => {
+                let setting: Hl7SettingEntry = self.settings.find("hl7_setting", &endpoint).await?;
+                let message = match (template, file) {
+                    (Some(template), _) => {
+                        let template: HL7MessageSetting =
+                            self.settings.find("hl7_message_setting", &template).await?;
+                        template.message
+                    }
+                    (None, Some(file)) => std::fs::read_to_string(&file)
+                        .map_err(|e| format!("Failed to read {}: {}", file.display(), e))?,
+                    (None, None) => return Err("Either --template or --file is required".into()),
+                };
+                let values = match accession {
+                    Some(accession) => {
+                        let entry = match (self.stored_step(&accession, None).await?, worklist) {
+                            (Some(entry), _) => entry,
+                            (None, Some(worklist)) => {
+                                let worklist: WorklistEntry =
+                                    self.settings.find("worklist", &worklist).await?;
+                                self.scheduled(&worklist, &accession).await?
+                            }
+                            (None, None) => {
+                                return Err(format!(
+                                    "No stored step with accession number {}; pass --worklist to query it",
+                                    accession
+                                ))
+                            }
+                        };
+                        entry_values(&serde_json::to_value(&entry).map_err(|e| e.to_string())?)
+                    }
+                    None => HashMap::new(),
+                };
+                let message = render(&message, &values)?;
+                let port = setting
+                    .port
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("Invalid port number: {}", setting.port))?;
+                let method = match method.to_uppercase().as_str() {
+                    "TCP" => SendMethod::Tcp,
+                    "HTTP" => SendMethod::Http,
+                    other => return Err(format!("Unknown send method: {}", other)),
+                };
+                let mut client = Hl7Client::new(message, setting.ip.clone(), port, method)
+                    .with_tls(Hl7TlsConfig::from_setting(
+                        &setting,
+                        AppPath::Certs.under(&self.resource_dir),
+                    ));
+                if let Some(seconds) = setting.ack_timeout.filter(|s| *s > 0) {
+                    client = client.with_ack_timeout(Duration::from_secs(seconds));
+                }
+                let result = client.send_message().await.map_err(|e| e.to_string())?;
+                if let Some(ack) = result.ack.as_ref().filter(|ack| !ack.accepted) {
+                    return Err(ack.summary(&result.control_id));
+                }
+                to_json(&result)
+            }
+            Command::Scenario {
+                scenario,
+                file,
+                worklist,
+                pacs,
+            } => {
+                let (name, definition) = match (scenario, file) {
+                    (Some(scenario), _) => {
+                        let entry: ScenarioEntry =
+                            self.settings.find(SCENARIO_TABLE, &scenario).await?;
+                        (entry.name, entry.definition)
+                    }
+                    (None, Some(file)) => {
+                        let definition = std::fs::read_to_string(&file)
+                            .map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+                        (file.display().to_string(), definition)
+                    }
+                    (None, None) => return Err("Either --scenario or --file is required".into()),
+                };
+                let mut scenario = Scenario::parse(&definition)?;
+                if scenario.name.is_empty() {
+                    scenario.name = name;
+                }
+                let worklist: WorklistEntry = self.settings.find("worklist", &worklist).await?;
+                let pacs: Option<MimEntry> = match pacs {
+                    Some(pacs) => Some(self.settings.find("mim", &pacs).await?),
+                    None => None,
+                };
+                let templates = self.templates()?;
+                let client = NativeDimseClient::new(AppPath::Certs.under(&self.resource_dir));
+                let mut runner = ScenarioRunner::new(
+                    Arc::new(client),
+                    worklist,
+                    templates,
+                    AppPath::Dcm.under(&self.resource_dir),
+                )
+                .with_pacs(pacs);
+                if let Some(db) = self.settings.db() {
+                    runner = runner.with_db(db.clone());
+                }
+                let report = runner.run(&scenario).await;
+                // 失败时也输出完整报告，便于在 CI 日志中定位失败的步骤
+                if !report.passed {
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&report).unwrap_or_default()
+                    );
+                    return Err(report.summary());
+                }
+                to_json(&report)
+            }
+            Command::Load {
+                worklist,
+                pacs,
+                modalities,
+                ae_prefix,
+                duration,
+                ramp_up,
+                rate,
+                path,
+            } => {
+                let worklist: WorklistEntry = self.settings.find("worklist", &worklist).await?;
+                let pacs: Option<MimEntry> = match pacs {
+                    Some(pacs) => Some(self.settings.find("mim", &pacs).await?),
+                    None => None,
+                };
+                let config = LoadTestConfig {
+                    modalities,
+                    ae_prefix,
+                    duration_secs: duration,
+                    ramp_up_secs: ramp_up,
+                    cycles_per_minute: rate,
+                    dcm_path: path,
+                };
+                let client = NativeDimseClient::new(AppPath::Certs.under(&self.resource_dir));
+                let test = LoadTest::new(
+                    Arc::new(client),
+                    worklist,
+                    pacs,
+                    self.templates()?,
+                    &std::env::current_dir().map_err(|e| e.to_string())?,
+                    config,
+                )?;
+                // 运行中每 10 秒在 stderr 输出一次进度
+                let handle = test.handle();
+                let progress = tokio::spawn(async move {
+                    loop {
+                        tokio::time::sleep(Duration::from_secs(10)).await;
+                        eprintln!("{}", handle.report().summary());
+                    }
+                });
+                let report = test.run().await;
+                progress.abort();
+                if report.failed_cycles > 0 {
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&report).unwrap_or_default()
+                    );
+                    return Err(report.summary());
+                }
+                to_json(&report)
+            }
+        }
+    }
+
+    fn templates(&self) -> Result<ScenarioTemplates, String> {
+        Ok(ScenarioTemplates {
+            queries: vec![
+                load_template(&AppPath::CFindRq.under(&self.resource_dir))?,
+                load_template(&AppPath::MrModality.under(&self.resource_dir))?,
+            ],
+            in_progress: load_template(&AppPath::InProgress.under(&self.resource_dir))?,
+            completed: load_template(&AppPath::Completed.under(&self.resource_dir))?,
+            discontinued: load_template(&AppPath::DisContinued.under(&self.resource_dir))?,
+        })
+    }
+
+    async fn mpps(&self, action: MppsAction) -> Result<Value, String> {
+        match action {
+            MppsAction::Start {
+                worklist,
+                accession,
+            } => {
+                let worklist: WorklistEntry = self.settings.find("worklist", &worklist).await?;
+                // 已完成或已中止的步骤可以重新开始，仍可完成的 (IN PROGRESS) 步骤只能有一个
+                let steps = self.stored_steps(&accession).await?;
+                if let Some(open) = steps
+                    .iter()
+                    .find(|step| step.check_transition(MppsStatus::Completed).is_ok())
+                {
+                    return Err(format!(
+                        "The step {} is already IN PROGRESS ({})",
+                        accession,
+                        open.mpps_instance_uid.as_deref().unwrap_or_default()
+                    ));
+                }
+                let mut entry = self.scheduled(&worklist, &accession).await?;
+                entry.check_transition(MppsStatus::InProgress)?;
+                let node = DicomNode::mpps(&worklist).map_err(|e| e.to_string())?;
+                let mpps_instance_uid = generate_uid();
+                let attributes = build_in_progress(
+                    load_template(&AppPath::InProgress.under(&self.resource_dir))?,
+                    &entry,
+                    &worklist,
+                )?;
+                self.client
+                    .n_create(
+                        &node,
+                        uids::MODALITY_PERFORMED_PROCEDURE_STEP,
+                        &mpps_instance_uid,
+                        attributes,
+                    )
+                    .await
+                    .map_err(|e| format!("N-CREATE failed: {}", e))?;
+                entry.mpps_instance_uid = Some(mpps_instance_uid);
+                entry.status_history.clear();
+                entry.transition(MppsStatus::InProgress)?;
+                to_json(&self.save_step(entry).await?)
+            }
+            MppsAction::Complete {
+                worklist,
+                accession,
+                dcm,
+                mpps_uid,
+                store,
+            } => {
+                let worklist: WorklistEntry = self.settings.find("worklist", &worklist).await?;
+                // 没有保存的条目时 (配置文件模式或由其他程序开始) 按 --mpps-uid 重新查询
+                let mut entry = match self.stored_step(&accession, mpps_uid.as_deref()).await? {
+                    Some(entry) => entry,
+                    None => {
+                        let uid =
+                            mpps_uid.ok_or("--mpps-uid is required when the step is not stored")?;
+                        let mut entry = self.scheduled(&worklist, &accession).await?;
+                        entry.mpps_instance_uid = Some(uid);
+                        entry.transition(MppsStatus::InProgress)?;
+                        entry
+                    }
+                };
+                entry.check_transition(MppsStatus::Completed)?;
+                let mpps_instance_uid = entry
+                    .mpps_instance_uid
+                    .clone()
+                    .ok_or_else(|| format!("The step {} has no MPPS instance UID", accession))?;
+                let series = collect_dcm_files(&dcm)?;
+                if series.is_empty() {
+                    return Err(format!("No .dcm files found in {}", dcm.display()));
+                }
+                let modifications = build_completed(
+                    load_template(&AppPath::Completed.under(&self.resource_dir))?,
+                    &entry,
+                    &series,
+                )?;
+                let node = DicomNode::mpps(&worklist).map_err(|e| e.to_string())?;
+                self.client
+                    .n_set(
+                        &node,
+                        uids::MODALITY_PERFORMED_PROCEDURE_STEP,
+                        &mpps_instance_uid,
+                        modifications,
+                    )
+                    .await
+                    .map_err(|e| format!("N-SET failed: {}", e))?;
+                entry.sop_instance_uids = Some(
+                    serde_json::to_string(&series)
+                        .map_err(|e| format!("Failed to serialize SopInstanceUids: {}", e))?,
+                );
+                entry.dcm_file = Some(dcm.to_string_lossy().into_owned());
+                entry.transition(MppsStatus::Completed)?;
+                let entry = self.save_step(entry).await?;
+
+                if let Some(pacs) = store {
+                    let pacs: MimEntry = self.settings.find("mim", &pacs).await?;
+                    let node = DicomNode::storage(&pacs).map_err(|e| e.to_string())?;
+                    self.client
+                        .c_store(&node, prepare_instances(&entry)?)
+                        .await
+                        .map_err(|e| format!("C-STORE failed: {}", e))?;
+                }
+                to_json(&entry)
+            }
+        }
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<Value, String> {
+    serde_json::to_value(value).map_err(|e| format!("Failed to serialize the result: {}", e))
+}
+
+/// Runs one command and returns the process exit code: 0 on success, 1 when
+/// the operation failed and 2 for invalid arguments. The result is printed to
+/// stdout as JSON, errors to stderr.
+pub async fn run<I, T>(args: I) -> i32
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let cli = match Cli::try_parse_from(args) {
+        Ok(cli) => cli,
+        Err(e) => {
+            let _ = e.print();
+            return if e.use_stderr() { 2 } else { 0 };
+        }
+    };
+    let settings = match Settings::open(&cli).await {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let runner = Runner {
+        settings,
+        client: NativeDimseClient::new(AppPath::Certs.under(&cli.resource_dir)),
+        resource_dir: cli.resource_dir,
+    };
+    match runner.run(cli.command).await {
+        Ok(result) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&result).unwrap_or_default()
+            );
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
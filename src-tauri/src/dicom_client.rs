@@ -0,0 +1,1491 @@
+use crate::models::{MimEntry, WorklistEntry};
+use dicom_core::value::DataSetSequence;
+use dicom_core::{dicom_value, DataElement, PrimitiveValue, VR};
+use dicom_dictionary_std::{tags, uids};
+use dicom_encoding::transfer_syntax::TransferSyntaxIndex;
+use dicom_object::{DefaultDicomObject, InMemDicomObject};
+use dicom_transfer_syntax_registry::{entries, TransferSyntaxRegistry};
+use dicom_ul::association::client::{AsyncClientAssociation, AsyncTlsStream};
+use dicom_ul::association::ClientAssociationOptions;
+use dicom_ul::pdu::{PDataValue, PDataValueType, Pdu, PresentationContextNegotiated};
+use futures::future::BoxFuture;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::ring::default_provider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+pub const EXPLICIT_VR_LITTLE_ENDIAN: &str = "1.2.840.10008.1.2.1";
+pub const IMPLICIT_VR_LITTLE_ENDIAN: &str = "1.2.840.10008.1.2";
+
+// CommandDataSetType 值为 0101H 时表示报文不带数据集
+pub(crate) const NO_DATA_SET: u16 = 0x0101;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DicomClientError {
+    details: String,
+}
+
+impl DicomClientError {
+    pub fn new(msg: &str) -> DicomClientError {
+        DicomClientError {
+            details: msg.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for DicomClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for DicomClientError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+pub(crate) fn client_error(context: &str, e: impl fmt::Display) -> DicomClientError {
+    DicomClientError::new(&format!("{}: {}", context, e))
+}
+
+/// The remote application entity an operation is addressed to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DicomNode {
+    pub calling_ae_title: String,
+    pub called_ae_title: String,
+    pub address: String,
+    pub port: u16,
+    pub tls_enabled: bool,
+}
+
+impl DicomNode {
+    /// The modality worklist SCP configured on a `WorklistEntry`.
+    pub fn worklist(entry: &WorklistEntry) -> Result<DicomNode, DicomClientError> {
+        Ok(DicomNode {
+            calling_ae_title: entry.calling_ae_title.clone(),
+            called_ae_title: entry.worklist_ae_title.clone(),
+            address: entry.worklist_ip.clone(),
+            port: parse_port(&entry.worklist_port)?,
+            tls_enabled: entry.tls_enabled.unwrap_or(false),
+        })
+    }
+
+    /// The MPPS SCP configured on a `WorklistEntry`, which shares the worklist host.
+    pub fn mpps(entry: &WorklistEntry) -> Result<DicomNode, DicomClientError> {
+        Ok(DicomNode {
+            calling_ae_title: entry.mpps_calling_ae_title.clone(),
+            called_ae_title: entry.mpps_ae_title.clone(),
+            address: entry.worklist_ip.clone(),
+            port: parse_port(&entry.mpps_port)?,
+            tls_enabled: entry.tls_enabled.unwrap_or(false),
+        })
+    }
+
+    /// The storage SCP (PACS) configured on a `MimEntry`.
+    pub fn storage(entry: &MimEntry) -> Result<DicomNode, DicomClientError> {
+        Ok(DicomNode {
+            calling_ae_title: entry.calling_ae_title.clone(),
+            called_ae_title: entry.ae_title.clone(),
+            address: entry.ip.clone(),
+            port: parse_port(&entry.port)?,
+            tls_enabled: entry.tls_enabled.unwrap_or(false),
+        })
+    }
+
+    fn socket_addr(&self) -> String {
+        format!("{}:{}", self.address, self.port)
+    }
+
+    /// `AE@host:port`, as shown to the user.
+    pub fn target(&self) -> String {
+        format!("{}@{}", self.called_ae_title, self.socket_addr())
+    }
+}
+
+fn parse_port(port: &str) -> Result<u16, DicomClientError> {
+    port.trim()
+        .parse()
+        .map_err(|e| client_error(&format!("Invalid port '{}'", port), e))
+}
+
+/// A DIMSE status code as returned in the (0000,0900) Status attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DimseStatus(pub u16);
+
+impl DimseStatus {
+    pub fn is_success(self) -> bool {
+        self.0 == 0x0000
+    }
+
+    pub fn is_pending(self) -> bool {
+        matches!(self.0, 0xFF00 | 0xFF01)
+    }
+
+    pub fn is_warning(self) -> bool {
+        matches!(self.0, 0x0001 | 0x0107 | 0x0116 | 0xB000..=0xBFFF)
+    }
+}
+
+impl fmt::Display for DimseStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:04x}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EchoOutcome {
+    Accepted,
+    Rejected,
+    Aborted,
+    Failed,
+    Reachable, // 只确认了 TCP (TLS) 连接，没有应用层的回应 (HL7)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TlsDetails {
+    pub protocol_version: String,
+    pub cipher_suite: String,
+}
+
+/// The result of verifying a node, with timings in milliseconds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EchoReport {
+    pub outcome: EchoOutcome,
+    pub detail: String,
+    pub status: Option<String>,
+    pub association_ms: Option<u64>,
+    pub round_trip_ms: Option<u64>,
+    pub tls: Option<TlsDetails>,
+}
+
+impl EchoReport {
+    pub fn failed(outcome: EchoOutcome, detail: String) -> EchoReport {
+        EchoReport {
+            outcome,
+            detail,
+            status: None,
+            association_ms: None,
+            round_trip_ms: None,
+            tls: None,
+        }
+    }
+}
+
+/// The outcome of a storage commitment transaction, as carried by the N-EVENT-REPORT.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommitmentReport {
+    pub transaction_uid: String,
+    pub committed: Vec<String>,
+    /// SOP Instance UID and Failure Reason of each instance the SCP did not commit.
+    pub failed: Vec<(String, u16)>,
+}
+
+/// Reads the event information of a Storage Commitment N-EVENT-REPORT.
+pub(crate) fn commitment_report(
+    event_information: &InMemDicomObject,
+) -> Result<CommitmentReport, DicomClientError> {
+    let instance_uids = |tag| -> Vec<InMemDicomObject> {
+        event_information
+            .get(tag)
+            .and_then(|e| e.items())
+            .map(|items| items.to_vec())
+            .unwrap_or_default()
+    };
+    let committed = instance_uids(tags::REFERENCED_SOP_SEQUENCE)
+        .iter()
+        .filter_map(|item| element_str(item, tags::REFERENCED_SOP_INSTANCE_UID).ok())
+        .collect();
+    let failed = instance_uids(tags::FAILED_SOP_SEQUENCE)
+        .iter()
+        .filter_map(|item| {
+            let uid = element_str(item, tags::REFERENCED_SOP_INSTANCE_UID).ok()?;
+            // 缺少 FailureReason 时按 Processing failure 处理
+            let reason = item
+                .get(tags::FAILURE_REASON)
+                .and_then(|e| e.to_int::<u16>().ok())
+                .unwrap_or(0x0110);
+            Some((uid, reason))
+        })
+        .collect();
+    Ok(CommitmentReport {
+        transaction_uid: element_str(event_information, tags::TRANSACTION_UID)?,
+        committed,
+        failed,
+    })
+}
+
+type AssociationError = dicom_ul::association::Error;
+
+// 把底层错误的 source 链拼接起来，避免只看到 "could not connect to server"
+fn error_chain(e: &dyn Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+fn classify_association_error(e: &AssociationError) -> (EchoOutcome, String) {
+    match e {
+        AssociationError::Rejected { association_rj, .. } => (
+            EchoOutcome::Rejected,
+            format!(
+                "Association rejected ({:?}): {}",
+                association_rj.result, association_rj.source
+            ),
+        ),
+        AssociationError::NoAcceptedPresentationContexts { .. } => (
+            EchoOutcome::Rejected,
+            "No presentation context accepted for Verification".to_string(),
+        ),
+        AssociationError::Aborted { .. } => (
+            EchoOutcome::Aborted,
+            "Association aborted by peer".to_string(),
+        ),
+        _ => (EchoOutcome::Failed, error_chain(e)),
+    }
+}
+
+/// The DIMSE service user operations the simulator workflows rely on.
+///
+/// Each call opens its own association and releases it before returning,
+/// so a single client can be shared between concurrent commands.
+pub trait DimseClient: Send + Sync {
+    /// Sends every query over one association and returns the pending
+    /// matches of each query, in the order the queries were given.
+    fn c_find<'a>(
+        &'a self,
+        node: &'a DicomNode,
+        sop_class_uid: &'a str,
+        queries: Vec<InMemDicomObject>,
+    ) -> BoxFuture<'a, Result<Vec<Vec<InMemDicomObject>>, DicomClientError>>;
+
+    fn n_create<'a>(
+        &'a self,
+        node: &'a DicomNode,
+        sop_class_uid: &'a str,
+        sop_instance_uid: &'a str,
+        attributes: InMemDicomObject,
+    ) -> BoxFuture<'a, Result<DimseStatus, DicomClientError>>;
+
+    fn n_set<'a>(
+        &'a self,
+        node: &'a DicomNode,
+        sop_class_uid: &'a str,
+        sop_instance_uid: &'a str,
+        modifications: InMemDicomObject,
+    ) -> BoxFuture<'a, Result<DimseStatus, DicomClientError>>;
+
+    /// N-SET over a presentation context for `abstract_syntax`, for services such as
+    /// UPS whose commands name a different SOP class than the one negotiated.
+    fn n_set_on<'a>(
+        &'a self,
+        node: &'a DicomNode,
+        abstract_syntax: &'a str,
+        sop_class_uid: &'a str,
+        sop_instance_uid: &'a str,
+        modifications: InMemDicomObject,
+    ) -> BoxFuture<'a, Result<DimseStatus, DicomClientError>>;
+
+    /// N-ACTION over a presentation context for `abstract_syntax`.
+    fn n_action<'a>(
+        &'a self,
+        node: &'a DicomNode,
+        abstract_syntax: &'a str,
+        sop_class_uid: &'a str,
+        sop_instance_uid: &'a str,
+        action_type_id: u16,
+        action_information: Option<InMemDicomObject>,
+    ) -> BoxFuture<'a, Result<DimseStatus, DicomClientError>>;
+
+    /// Stores the objects over one association, stopping at the first failure.
+    /// Returns the number of instances stored.
+    fn c_store<'a>(
+        &'a self,
+        node: &'a DicomNode,
+        objects: Vec<DefaultDicomObject>,
+    ) -> BoxFuture<'a, Result<usize, DicomClientError>>;
+
+    /// Verifies the node with a C-ECHO. Failures are described by the report
+    /// rather than returned as errors.
+    fn c_echo<'a>(&'a self, node: &'a DicomNode) -> BoxFuture<'a, EchoReport>;
+
+    /// Requests storage commitment for `(SOP Class UID, SOP Instance UID)` pairs
+    /// and waits up to `wait` for the N-EVENT-REPORT on the same association.
+    /// Returns `None` when the SCP reports on a separate association instead.
+    fn storage_commitment<'a>(
+        &'a self,
+        node: &'a DicomNode,
+        transaction_uid: &'a str,
+        references: &'a [(String, String)],
+        wait: Duration,
+    ) -> BoxFuture<'a, Result<Option<CommitmentReport>, DicomClientError>>;
+}
+
+/// `DimseClient` implemented on top of the dicom-rs upper layer protocol.
+pub struct NativeDimseClient {
+    certs_dir: PathBuf,
+    timeout: Duration,
+}
+
+impl NativeDimseClient {
+    pub fn new(certs_dir: PathBuf) -> NativeDimseClient {
+        NativeDimseClient {
+            certs_dir,
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    async fn associate(
+        &self,
+        node: &DicomNode,
+        contexts: Vec<(String, Vec<String>)>,
+    ) -> Result<Association, DicomClientError> {
+        let tls_config = self.tls_config_for(node)?;
+        self.establish(node, contexts, tls_config)
+            .await
+            .map_err(|e| {
+                DicomClientError::new(&format!(
+                    "Failed to establish association with {}: {}",
+                    node.target(),
+                    error_chain(&e)
+                ))
+            })
+    }
+
+    fn tls_config_for(
+        &self,
+        node: &DicomNode,
+    ) -> Result<Option<Arc<ClientConfig>>, DicomClientError> {
+        if node.tls_enabled {
+            tls_client_config(&self.certs_dir).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn establish(
+        &self,
+        node: &DicomNode,
+        contexts: Vec<(String, Vec<String>)>,
+        tls_config: Option<Arc<ClientConfig>>,
+    ) -> Result<Association, AssociationError> {
+        let mut options = ClientAssociationOptions::new()
+            .calling_ae_title(node.calling_ae_title.clone())
+            .called_ae_title(node.called_ae_title.clone())
+            .connection_timeout(self.timeout)
+            .read_timeout(self.timeout)
+            .write_timeout(self.timeout);
+        for (abstract_syntax, transfer_syntaxes) in contexts {
+            // Storage Commitment 的 N-EVENT-REPORT 可能在同一关联上由对方发起，需要同时声明 SCP 角色
+            if abstract_syntax == uids::STORAGE_COMMITMENT_PUSH_MODEL {
+                options = options.with_role_selection(abstract_syntax.clone(), true, true);
+            }
+            options = options.with_presentation_context(abstract_syntax, transfer_syntaxes);
+        }
+
+        match tls_config {
+            Some(tls_config) => {
+                let association = options
+                    .tls_config(tls_config)
+                    .server_name(&node.address)
+                    .establish_tls_async(node.socket_addr())
+                    .await?;
+                Ok(Association::Tls(Box::new(association)))
+            }
+            None => {
+                let association = options.establish_async(node.socket_addr()).await?;
+                Ok(Association::Plain(Box::new(association)))
+            }
+        }
+    }
+
+    async fn normalized_request(
+        &self,
+        node: &DicomNode,
+        command: InMemDicomObject,
+        abstract_syntax: &str,
+        attributes: Option<InMemDicomObject>,
+    ) -> Result<DimseStatus, DicomClientError> {
+        let mut association = self
+            .associate(node, vec![default_context(abstract_syntax)])
+            .await?;
+        let result = association
+            .normalized_request(command, abstract_syntax, attributes)
+            .await;
+        association.finish(result.is_ok()).await;
+        result
+    }
+}
+
+impl DimseClient for NativeDimseClient {
+    fn c_find<'a>(
+        &'a self,
+        node: &'a DicomNode,
+        sop_class_uid: &'a str,
+        queries: Vec<InMemDicomObject>,
+    ) -> BoxFuture<'a, Result<Vec<Vec<InMemDicomObject>>, DicomClientError>> {
+        Box::pin(async move {
+            let mut association = self
+                .associate(node, vec![default_context(sop_class_uid)])
+                .await?;
+            let result = association.c_find(sop_class_uid, queries).await;
+            association.finish(result.is_ok()).await;
+            result
+        })
+    }
+
+    fn n_create<'a>(
+        &'a self,
+        node: &'a DicomNode,
+        sop_class_uid: &'a str,
+        sop_instance_uid: &'a str,
+        attributes: InMemDicomObject,
+    ) -> BoxFuture<'a, Result<DimseStatus, DicomClientError>> {
+        Box::pin(async move {
+            let command = n_create_rq(1, sop_class_uid, sop_instance_uid);
+            self.normalized_request(node, command, sop_class_uid, Some(attributes))
+                .await
+        })
+    }
+
+    fn n_set<'a>(
+        &'a self,
+        node: &'a DicomNode,
+        sop_class_uid: &'a str,
+        sop_instance_uid: &'a str,
+        modifications: InMemDicomObject,
+    ) -> BoxFuture<'a, Result<DimseStatus, DicomClientError>> {
+        self.n_set_on(node, sop_class_uid, sop_class_uid, sop_instance_uid, modifications)
+    }
+
+    fn n_set_on<'a>(
+        &'a self,
+        node: &'a DicomNode,
+        abstract_syntax: &'a str,
+        sop_class_uid: &'a str,
+        sop_instance_uid: &'a str,
+        modifications: InMemDicomObject,
+    ) -> BoxFuture<'a, Result<DimseStatus, DicomClientError>> {
+        Box::pin(async move {
+            let command = n_set_rq(1, sop_class_uid, sop_instance_uid);
+            self.normalized_request(node, command, abstract_syntax, Some(modifications))
+                .await
+        })
+    }
+
+    fn n_action<'a>(
+        &'a self,
+        node: &'a DicomNode,
+        abstract_syntax: &'a str,
+        sop_class_uid: &'a str,
+        sop_instance_uid: &'a str,
+        action_type_id: u16,
+        action_information: Option<InMemDicomObject>,
+    ) -> BoxFuture<'a, Result<DimseStatus, DicomClientError>> {
+        Box::pin(async move {
+            let command = n_action_rq(
+                1,
+                sop_class_uid,
+                sop_instance_uid,
+                action_type_id,
+                action_information.is_some(),
+            );
+            self.normalized_request(node, command, abstract_syntax, action_information)
+                .await
+        })
+    }
+
+    fn c_store<'a>(
+        &'a self,
+        node: &'a DicomNode,
+        objects: Vec<DefaultDicomObject>,
+    ) -> BoxFuture<'a, Result<usize, DicomClientError>> {
+        Box::pin(async move {
+            if objects.is_empty() {
+                return Ok(0);
+            }
+            let mut association = self.associate(node, storage_contexts(&objects)).await?;
+            let result = association.c_store(objects).await;
+            association.finish(result.is_ok()).await;
+            result
+        })
+    }
+
+    fn c_echo<'a>(&'a self, node: &'a DicomNode) -> BoxFuture<'a, EchoReport> {
+        Box::pin(async move {
+            let tls_config = match self.tls_config_for(node) {
+                Ok(tls_config) => tls_config,
+                Err(e) => return EchoReport::failed(EchoOutcome::Failed, e.to_string()),
+            };
+
+            let started = Instant::now();
+            let established = self
+                .establish(node, vec![default_context(uids::VERIFICATION)], tls_config)
+                .await;
+            let association_ms = started.elapsed().as_millis() as u64;
+            let mut association = match established {
+                Ok(association) => association,
+                Err(e) => {
+                    let (outcome, detail) = classify_association_error(&e);
+                    let mut report = EchoReport::failed(outcome, detail);
+                    report.association_ms = Some(association_ms);
+                    return report;
+                }
+            };
+
+            let tls = association.tls_details();
+            let started = Instant::now();
+            let result = association.c_echo().await;
+            let round_trip_ms = started.elapsed().as_millis() as u64;
+            association.finish(result.is_ok()).await;
+
+            let (outcome, detail, status) = match result {
+                Ok(status) if status.is_success() => {
+                    (EchoOutcome::Accepted, "C-ECHO succeeded".to_string(), Some(status))
+                }
+                Ok(status) => (
+                    EchoOutcome::Failed,
+                    format!("C-ECHO returned status {}", status),
+                    Some(status),
+                ),
+                Err((outcome, detail)) => (outcome, detail, None),
+            };
+            EchoReport {
+                outcome,
+                detail,
+                status: status.map(|s| s.to_string()),
+                association_ms: Some(association_ms),
+                round_trip_ms: Some(round_trip_ms),
+                tls,
+            }
+        })
+    }
+    fn storage_commitment<'a>(
+        &'a self,
+        node: &'a DicomNode,
+        transaction_uid: &'a str,
+        references: &'a [(String, String)],
+        wait: Duration,
+    ) -> BoxFuture<'a, Result<Option<CommitmentReport>, DicomClientError>> {
+        Box::pin(async move {
+            let mut association = self
+                .associate(node, vec![default_context(uids::STORAGE_COMMITMENT_PUSH_MODEL)])
+                .await?;
+            // 等待时间不能超过关联的读超时
+            let result = association
+                .storage_commitment(transaction_uid, references, wait.min(self.timeout))
+                .await;
+            association.finish(result.is_ok()).await;
+            result
+        })
+    }
+}
+
+/// An established association, either over plain TCP or TLS.
+enum Association {
+    Plain(Box<AsyncClientAssociation<TcpStream>>),
+    Tls(Box<AsyncClientAssociation<AsyncTlsStream>>),
+}
+
+/// PDU transport shared by the client and server side associations, with the
+/// DIMSE message framing built on top of it.
+pub(crate) trait DimseChannel {
+    fn presentation_contexts(&self) -> &[PresentationContextNegotiated];
+
+    fn peer_max_pdu_length(&self) -> u32;
+
+    async fn send(&mut self, pdu: &Pdu) -> Result<(), DicomClientError>;
+
+    async fn receive(&mut self) -> Result<Pdu, DicomClientError>;
+
+    async fn send_fragmented(
+        &mut self,
+        presentation_context_id: u8,
+        data: &[u8],
+    ) -> Result<(), DicomClientError>;
+
+    fn context_for(
+        &self,
+        abstract_syntax: &str,
+    ) -> Result<PresentationContextNegotiated, DicomClientError> {
+        self.presentation_contexts()
+            .iter()
+            .find(|pc| pc.abstract_syntax == abstract_syntax)
+            .cloned()
+            .ok_or_else(|| {
+                DicomClientError::new(&format!(
+                    "No presentation context accepted for {}",
+                    abstract_syntax
+                ))
+            })
+    }
+
+    async fn send_message(
+        &mut self,
+        presentation_context_id: u8,
+        command: &InMemDicomObject,
+        data_set: Option<Vec<u8>>,
+    ) -> Result<(), DicomClientError> {
+        let mut command_data = Vec::with_capacity(128);
+        command
+            .write_dataset_with_ts(
+                &mut command_data,
+                &entries::IMPLICIT_VR_LITTLE_ENDIAN.erased(),
+            )
+            .map_err(|e| client_error("Failed to encode command", e))?;
+        let command_value = PDataValue {
+            presentation_context_id,
+            value_type: PDataValueType::Command,
+            is_last: true,
+            data: command_data,
+        };
+
+        match data_set {
+            Some(data)
+                if command_value.data.len() + data.len()
+                    < self.peer_max_pdu_length().saturating_sub(100) as usize =>
+            {
+                let pdu = Pdu::PData {
+                    data: vec![
+                        command_value,
+                        PDataValue {
+                            presentation_context_id,
+                            value_type: PDataValueType::Data,
+                            is_last: true,
+                            data,
+                        },
+                    ],
+                };
+                self.send(&pdu).await
+            }
+            Some(data) => {
+                self.send(&Pdu::PData {
+                    data: vec![command_value],
+                })
+                .await?;
+                self.send_fragmented(presentation_context_id, &data).await
+            }
+            None => {
+                self.send(&Pdu::PData {
+                    data: vec![command_value],
+                })
+                .await
+            }
+        }
+    }
+
+    /// Reads one DIMSE message, returning the presentation context it arrived on,
+    /// the command and the raw data set if any. Returns `None` once the peer has
+    /// released the association.
+    async fn next_message(
+        &mut self,
+    ) -> Result<Option<(u8, InMemDicomObject, Option<Vec<u8>>)>, DicomClientError> {
+        let mut presentation_context_id = 0;
+        let mut command_data = Vec::new();
+        let mut command: Option<InMemDicomObject> = None;
+        let mut data_set = Vec::new();
+        let mut data_set_complete = false;
+
+        loop {
+            match self.receive().await? {
+                Pdu::PData { data } => {
+                    for value in data {
+                        presentation_context_id = value.presentation_context_id;
+                        match value.value_type {
+                            PDataValueType::Command => {
+                                command_data.extend_from_slice(&value.data);
+                                if value.is_last {
+                                    command = Some(
+                                        InMemDicomObject::read_dataset_with_ts(
+                                            &command_data[..],
+                                            &entries::IMPLICIT_VR_LITTLE_ENDIAN.erased(),
+                                        )
+                                        .map_err(|e| client_error("Failed to read command", e))?,
+                                    );
+                                }
+                            }
+                            PDataValueType::Data => {
+                                data_set.extend_from_slice(&value.data);
+                                data_set_complete = value.is_last;
+                            }
+                        }
+                    }
+                }
+                Pdu::ReleaseRQ => {
+                    self.send(&Pdu::ReleaseRP).await?;
+                    return Ok(None);
+                }
+                Pdu::AbortRQ { source } => {
+                    return Err(DicomClientError::new(&format!(
+                        "Association aborted by peer: {:?}",
+                        source
+                    )))
+                }
+                pdu => {
+                    return Err(DicomClientError::new(&format!(
+                        "Unexpected PDU from peer: {}",
+                        pdu.short_description()
+                    )))
+                }
+            }
+
+            if let Some(cmd) = &command {
+                let data_set_type = cmd
+                    .get(tags::COMMAND_DATA_SET_TYPE)
+                    .and_then(|e| e.to_int::<u16>().ok())
+                    .unwrap_or(NO_DATA_SET);
+                if data_set_type == NO_DATA_SET {
+                    return Ok(Some((presentation_context_id, command.take().unwrap(), None)));
+                }
+                // 数据集可能还在后续的 PDU 中
+                if data_set_complete {
+                    return Ok(Some((
+                        presentation_context_id,
+                        command.take().unwrap(),
+                        Some(data_set),
+                    )));
+                }
+            }
+        }
+    }
+
+    /// Reads the response to a request sent on this association.
+    async fn receive_message(
+        &mut self,
+    ) -> Result<(InMemDicomObject, Option<Vec<u8>>), DicomClientError> {
+        match self.next_message().await? {
+            Some((_, command, data_set)) => Ok((command, data_set)),
+            None => Err(DicomClientError::new("Association released by peer")),
+        }
+    }
+}
+
+impl DimseChannel for Association {
+    fn presentation_contexts(&self) -> &[PresentationContextNegotiated] {
+        match self {
+            Association::Plain(a) => a.presentation_contexts(),
+            Association::Tls(a) => a.presentation_contexts(),
+        }
+    }
+
+    fn peer_max_pdu_length(&self) -> u32 {
+        match self {
+            Association::Plain(a) => a.acceptor_max_pdu_length(),
+            Association::Tls(a) => a.acceptor_max_pdu_length(),
+        }
+    }
+
+    async fn send(&mut self, pdu: &Pdu) -> Result<(), DicomClientError> {
+        match self {
+            Association::Plain(a) => a.send(pdu).await,
+            Association::Tls(a) => a.send(pdu).await,
+        }
+        .map_err(|e| client_error("Failed to send PDU", e))
+    }
+
+    async fn receive(&mut self) -> Result<Pdu, DicomClientError> {
+        match self {
+            Association::Plain(a) => a.receive().await,
+            Association::Tls(a) => a.receive().await,
+        }
+        .map_err(|e| client_error("Failed to receive PDU", e))
+    }
+
+    async fn send_fragmented(
+        &mut self,
+        presentation_context_id: u8,
+        data: &[u8],
+    ) -> Result<(), DicomClientError> {
+        let result = match self {
+            Association::Plain(a) => {
+                let mut writer = a.send_pdata(presentation_context_id);
+                match writer.write_all(data).await {
+                    Ok(()) => writer.finish().await,
+                    Err(e) => Err(e),
+                }
+            }
+            Association::Tls(a) => {
+                let mut writer = a.send_pdata(presentation_context_id);
+                match writer.write_all(data).await {
+                    Ok(()) => writer.finish().await,
+                    Err(e) => Err(e),
+                }
+            }
+        };
+        result.map_err(|e| client_error("Failed to send data set", e))
+    }
+}
+
+impl Association {
+    /// Releases the association after a successful exchange, aborts it otherwise.
+    async fn finish(self, release: bool) {
+        let _ = match (self, release) {
+            (Association::Plain(a), true) => a.release().await,
+            (Association::Plain(a), false) => a.abort().await,
+            (Association::Tls(a), true) => a.release().await,
+            (Association::Tls(a), false) => a.abort().await,
+        };
+    }
+
+    fn tls_details(&mut self) -> Option<TlsDetails> {
+        match self {
+            Association::Plain(_) => None,
+            Association::Tls(a) => {
+                let (_, connection) = a.inner_stream().get_ref();
+                Some(TlsDetails {
+                    protocol_version: connection
+                        .protocol_version()
+                        .map(|v| format!("{:?}", v))
+                        .unwrap_or_default(),
+                    cipher_suite: connection
+                        .negotiated_cipher_suite()
+                        .map(|c| format!("{:?}", c.suite()))
+                        .unwrap_or_default(),
+                })
+            }
+        }
+    }
+
+    async fn receive_raw(&mut self) -> Result<Pdu, AssociationError> {
+        match self {
+            Association::Plain(a) => a.receive().await,
+            Association::Tls(a) => a.receive().await,
+        }
+    }
+
+    /// Sends a C-ECHO-RQ and waits for its response, telling peer aborts apart
+    /// from other failures.
+    async fn c_echo(&mut self) -> Result<DimseStatus, (EchoOutcome, String)> {
+        let pc = self
+            .context_for(uids::VERIFICATION)
+            .map_err(|e| (EchoOutcome::Rejected, e.to_string()))?;
+        self.send_message(pc.id, &c_echo_rq(1), None)
+            .await
+            .map_err(|e| (EchoOutcome::Failed, e.to_string()))?;
+
+        let mut command_data = Vec::new();
+        loop {
+            match self.receive_raw().await {
+                Ok(Pdu::PData { data }) => {
+                    for value in data {
+                        if value.value_type != PDataValueType::Command {
+                            continue;
+                        }
+                        command_data.extend_from_slice(&value.data);
+                        if value.is_last {
+                            let response = InMemDicomObject::read_dataset_with_ts(
+                                &command_data[..],
+                                &entries::IMPLICIT_VR_LITTLE_ENDIAN.erased(),
+                            )
+                            .map_err(|e| (EchoOutcome::Failed, e.to_string()))?;
+                            return status_of(&response)
+                                .map_err(|e| (EchoOutcome::Failed, e.to_string()));
+                        }
+                    }
+                }
+                Ok(Pdu::AbortRQ { source }) => {
+                    return Err((
+                        EchoOutcome::Aborted,
+                        format!("Association aborted by peer: {:?}", source),
+                    ))
+                }
+                Ok(pdu) => {
+                    return Err((
+                        EchoOutcome::Failed,
+                        format!("Unexpected response from peer: {}", pdu.short_description()),
+                    ))
+                }
+                Err(e) => return Err(classify_association_error(&e)),
+            }
+        }
+    }
+
+    async fn c_find(
+        &mut self,
+        sop_class_uid: &str,
+        queries: Vec<InMemDicomObject>,
+    ) -> Result<Vec<Vec<InMemDicomObject>>, DicomClientError> {
+        let pc = self.context_for(sop_class_uid)?;
+        let ts = TransferSyntaxRegistry
+            .get(&pc.transfer_syntax)
+            .ok_or_else(|| {
+                DicomClientError::new(&format!(
+                    "Unsupported transfer syntax {}",
+                    pc.transfer_syntax
+                ))
+            })?;
+
+        let mut results = Vec::with_capacity(queries.len());
+        for (i, query) in queries.into_iter().enumerate() {
+            let mut identifier = Vec::new();
+            query
+                .write_dataset_with_ts(&mut identifier, ts)
+                .map_err(|e| client_error("Failed to encode C-FIND identifier", e))?;
+            let command = c_find_rq(i as u16 + 1, sop_class_uid);
+            self.send_message(pc.id, &command, Some(identifier)).await?;
+
+            let mut matches = Vec::new();
+            loop {
+                let (response, data) = self.receive_message().await?;
+                let status = status_of(&response)?;
+                if status.is_pending() {
+                    if let Some(data) = data {
+                        let found = InMemDicomObject::read_dataset_with_ts(&data[..], ts)
+                            .map_err(|e| client_error("Failed to read C-FIND response", e))?;
+                        matches.push(found);
+                    }
+                } else if status.is_success() {
+                    break;
+                } else {
+                    return Err(DicomClientError::new(&format!(
+                        "C-FIND failed with status {}",
+                        status
+                    )));
+                }
+            }
+            results.push(matches);
+        }
+        Ok(results)
+    }
+
+    async fn normalized_request(
+        &mut self,
+        command: InMemDicomObject,
+        abstract_syntax: &str,
+        attributes: Option<InMemDicomObject>,
+    ) -> Result<DimseStatus, DicomClientError> {
+        let pc = self.context_for(abstract_syntax)?;
+        let data = match attributes {
+            Some(attributes) => {
+                let ts = TransferSyntaxRegistry
+                    .get(&pc.transfer_syntax)
+                    .ok_or_else(|| {
+                        DicomClientError::new(&format!(
+                            "Unsupported transfer syntax {}",
+                            pc.transfer_syntax
+                        ))
+                    })?;
+                let mut data = Vec::new();
+                attributes
+                    .write_dataset_with_ts(&mut data, ts)
+                    .map_err(|e| client_error("Failed to encode attribute list", e))?;
+                Some(data)
+            }
+            None => None,
+        };
+        self.send_message(pc.id, &command, data).await?;
+
+        let (response, _) = self.receive_message().await?;
+        let status = status_of(&response)?;
+        if status.is_success() || status.is_warning() {
+            Ok(status)
+        } else {
+            Err(DicomClientError::new(&format!(
+                "{} failed with status {}",
+                command_name(&command),
+                status
+            )))
+        }
+    }
+
+    async fn c_store(&mut self, objects: Vec<DefaultDicomObject>) -> Result<usize, DicomClientError> {
+        let mut stored = 0;
+        for (i, object) in objects.into_iter().enumerate() {
+            let sop_class_uid = element_str(&object, tags::SOP_CLASS_UID)?;
+            let sop_instance_uid = element_str(&object, tags::SOP_INSTANCE_UID)?;
+            let file_ts = trim_uid(object.meta().transfer_syntax()).to_string();
+
+            let pc = self.storage_context_for(&sop_class_uid, &file_ts)?;
+            let ts = TransferSyntaxRegistry
+                .get(&pc.transfer_syntax)
+                .ok_or_else(|| {
+                    DicomClientError::new(&format!(
+                        "Unsupported transfer syntax {}",
+                        pc.transfer_syntax
+                    ))
+                })?;
+            let mut data = Vec::new();
+            object
+                .write_dataset_with_ts(&mut data, ts)
+                .map_err(|e| client_error("Failed to encode data set", e))?;
+
+            let command = c_store_rq(i as u16 + 1, &sop_class_uid, &sop_instance_uid);
+            self.send_message(pc.id, &command, Some(data)).await?;
+
+            let (response, _) = self.receive_message().await?;
+            let status = status_of(&response)?;
+            if !(status.is_success() || status.is_warning()) {
+                return Err(DicomClientError::new(&format!(
+                    "C-STORE of {} failed with status {}",
+                    sop_instance_uid, status
+                )));
+            }
+            stored += 1;
+        }
+        Ok(stored)
+    }
+
+    async fn storage_commitment(
+        &mut self,
+        transaction_uid: &str,
+        references: &[(String, String)],
+        wait: Duration,
+    ) -> Result<Option<CommitmentReport>, DicomClientError> {
+        let items: Vec<InMemDicomObject> = references
+            .iter()
+            .map(|(sop_class_uid, sop_instance_uid)| {
+                InMemDicomObject::from_element_iter([
+                    DataElement::new(
+                        tags::REFERENCED_SOP_CLASS_UID,
+                        VR::UI,
+                        PrimitiveValue::from(sop_class_uid.as_str()),
+                    ),
+                    DataElement::new(
+                        tags::REFERENCED_SOP_INSTANCE_UID,
+                        VR::UI,
+                        PrimitiveValue::from(sop_instance_uid.as_str()),
+                    ),
+                ])
+            })
+            .collect();
+        let action_information = InMemDicomObject::from_element_iter([
+            DataElement::new(
+                tags::TRANSACTION_UID,
+                VR::UI,
+                PrimitiveValue::from(transaction_uid),
+            ),
+            DataElement::new(
+                tags::REFERENCED_SOP_SEQUENCE,
+                VR::SQ,
+                DataSetSequence::from(items),
+            ),
+        ]);
+        // Action Type ID 1: Request Storage Commitment
+        let command = n_action_rq(
+            1,
+            uids::STORAGE_COMMITMENT_PUSH_MODEL,
+            uids::STORAGE_COMMITMENT_PUSH_MODEL_INSTANCE,
+            1,
+            true,
+        );
+        self.normalized_request(
+            command,
+            uids::STORAGE_COMMITMENT_PUSH_MODEL,
+            Some(action_information),
+        )
+        .await?;
+
+        let message = match tokio::time::timeout(wait, self.next_message()).await {
+            Ok(message) => message?,
+            Err(_) => return Ok(None),
+        };
+        let Some((presentation_context_id, command, data_set)) = message else {
+            return Ok(None);
+        };
+        let command_field = command
+            .get(tags::COMMAND_FIELD)
+            .and_then(|e| e.to_int::<u16>().ok());
+        if command_field != Some(0x0100) {
+            return Err(DicomClientError::new(&format!(
+                "Unexpected {} while waiting for the storage commitment result",
+                command_name(&command)
+            )));
+        }
+
+        let pc = self.context_for(uids::STORAGE_COMMITMENT_PUSH_MODEL)?;
+        let ts = TransferSyntaxRegistry
+            .get(&pc.transfer_syntax)
+            .ok_or_else(|| {
+                DicomClientError::new(&format!(
+                    "Unsupported transfer syntax {}",
+                    pc.transfer_syntax
+                ))
+            })?;
+        let event_information = InMemDicomObject::read_dataset_with_ts(
+            data_set.as_deref().unwrap_or_default(),
+            ts,
+        )
+        .map_err(|e| client_error("Failed to read event information", e))?;
+        let report = commitment_report(&event_information)?;
+        self.send_message(presentation_context_id, &n_event_report_rsp(&command), None)
+            .await?;
+        Ok(Some(report))
+    }
+
+    /// Picks the context negotiated in the file's own transfer syntax, falling back
+    /// to a native little endian one when the file itself is not encapsulated.
+    fn storage_context_for(
+        &self,
+        sop_class_uid: &str,
+        file_ts: &str,
+    ) -> Result<PresentationContextNegotiated, DicomClientError> {
+        let contexts = self.presentation_contexts();
+        let exact = contexts
+            .iter()
+            .find(|pc| pc.abstract_syntax == sop_class_uid && pc.transfer_syntax == file_ts);
+        let native = || {
+            let file_is_native = TransferSyntaxRegistry
+                .get(file_ts)
+                .map(|ts| !ts.is_encapsulated_pixel_data())
+                .unwrap_or(false);
+            contexts.iter().find(|pc| {
+                file_is_native
+                    && pc.abstract_syntax == sop_class_uid
+                    && (pc.transfer_syntax == EXPLICIT_VR_LITTLE_ENDIAN
+                        || pc.transfer_syntax == IMPLICIT_VR_LITTLE_ENDIAN)
+            })
+        };
+        exact.or_else(native).cloned().ok_or_else(|| {
+            DicomClientError::new(&format!(
+                "No presentation context accepted for {} in {}",
+                sop_class_uid, file_ts
+            ))
+        })
+    }
+}
+
+fn default_context(abstract_syntax: &str) -> (String, Vec<String>) {
+    (
+        abstract_syntax.to_string(),
+        vec![
+            EXPLICIT_VR_LITTLE_ENDIAN.to_string(),
+            IMPLICIT_VR_LITTLE_ENDIAN.to_string(),
+        ],
+    )
+}
+
+/// One presentation context per SOP class, offering the files' own transfer
+/// syntaxes followed by the native little endian ones.
+fn storage_contexts(objects: &[DefaultDicomObject]) -> Vec<(String, Vec<String>)> {
+    let mut contexts: Vec<(String, Vec<String>)> = Vec::new();
+    for object in objects {
+        let Ok(sop_class_uid) = element_str(object, tags::SOP_CLASS_UID) else {
+            continue;
+        };
+        let file_ts = trim_uid(object.meta().transfer_syntax()).to_string();
+        let index = match contexts.iter().position(|(uid, _)| *uid == sop_class_uid) {
+            Some(index) => index,
+            None => {
+                contexts.push((sop_class_uid, Vec::new()));
+                contexts.len() - 1
+            }
+        };
+        let transfer_syntaxes = &mut contexts[index].1;
+        if !transfer_syntaxes.contains(&file_ts) {
+            transfer_syntaxes.push(file_ts);
+        }
+    }
+    for (_, transfer_syntaxes) in contexts.iter_mut() {
+        for ts in [EXPLICIT_VR_LITTLE_ENDIAN, IMPLICIT_VR_LITTLE_ENDIAN] {
+            if !transfer_syntaxes.iter().any(|t| t == ts) {
+                transfer_syntaxes.push(ts.to_string());
+            }
+        }
+    }
+    contexts
+}
+
+pub(crate) fn trim_uid(uid: &str) -> &str {
+    uid.trim_end_matches(|c: char| c.is_whitespace() || c == '\0')
+}
+
+fn element_str(
+    object: &InMemDicomObject,
+    tag: dicom_core::Tag,
+) -> Result<String, DicomClientError> {
+    object
+        .get(tag)
+        .and_then(|e| e.to_str().ok())
+        .map(|s| trim_uid(&s).to_string())
+        .ok_or_else(|| DicomClientError::new(&format!("Missing attribute {}", tag)))
+}
+
+pub(crate) fn status_of(command: &InMemDicomObject) -> Result<DimseStatus, DicomClientError> {
+    command
+        .get(tags::STATUS)
+        .and_then(|e| e.to_int::<u16>().ok())
+        .map(DimseStatus)
+        .ok_or_else(|| DicomClientError::new("Response is missing the status attribute"))
+}
+
+fn command_name(command: &InMemDicomObject) -> &'static str {
+    match command
+        .get(tags::COMMAND_FIELD)
+        .and_then(|e| e.to_int::<u16>().ok())
+    {
+        Some(0x0001) => "C-STORE",
+        Some(0x0020) => "C-FIND",
+        Some(0x0030) => "C-ECHO",
+        Some(0x0100) => "N-EVENT-REPORT",
+        Some(0x0120) => "N-SET",
+        Some(0x0130) => "N-ACTION",
+        Some(0x0140) => "N-CREATE",
+        _ => "DIMSE request",
+    }
+}
+
+fn c_find_rq(message_id: u16, sop_class_uid: &str) -> InMemDicomObject {
+    InMemDicomObject::command_from_element_iter([
+        DataElement::new(
+            tags::AFFECTED_SOP_CLASS_UID,
+            VR::UI,
+            PrimitiveValue::from(sop_class_uid),
+        ),
+        DataElement::new(tags::COMMAND_FIELD, VR::US, dicom_value!(U16, [0x0020])),
+        DataElement::new(tags::MESSAGE_ID, VR::US, dicom_value!(U16, [message_id])),
+        DataElement::new(tags::PRIORITY, VR::US, dicom_value!(U16, [0x0000])),
+        DataElement::new(
+            tags::COMMAND_DATA_SET_TYPE,
+            VR::US,
+            dicom_value!(U16, [0x0001]),
+        ),
+    ])
+}
+
+fn c_echo_rq(message_id: u16) -> InMemDicomObject {
+    InMemDicomObject::command_from_element_iter([
+        DataElement::new(
+            tags::AFFECTED_SOP_CLASS_UID,
+            VR::UI,
+            PrimitiveValue::from(uids::VERIFICATION),
+        ),
+        DataElement::new(tags::COMMAND_FIELD, VR::US, dicom_value!(U16, [0x0030])),
+        DataElement::new(tags::MESSAGE_ID, VR::US, dicom_value!(U16, [message_id])),
+        DataElement::new(
+            tags::COMMAND_DATA_SET_TYPE,
+            VR::US,
+            dicom_value!(U16, [NO_DATA_SET]),
+        ),
+    ])
+}
+
+fn c_store_rq(message_id: u16, sop_class_uid: &str, sop_instance_uid: &str) -> InMemDicomObject {
+    InMemDicomObject::command_from_element_iter([
+        DataElement::new(
+            tags::AFFECTED_SOP_CLASS_UID,
+            VR::UI,
+            PrimitiveValue::from(sop_class_uid),
+        ),
+        DataElement::new(tags::COMMAND_FIELD, VR::US, dicom_value!(U16, [0x0001])),
+        DataElement::new(tags::MESSAGE_ID, VR::US, dicom_value!(U16, [message_id])),
+        DataElement::new(tags::PRIORITY, VR::US, dicom_value!(U16, [0x0000])),
+        DataElement::new(
+            tags::COMMAND_DATA_SET_TYPE,
+            VR::US,
+            dicom_value!(U16, [0x0001]),
+        ),
+        DataElement::new(
+            tags::AFFECTED_SOP_INSTANCE_UID,
+            VR::UI,
+            PrimitiveValue::from(sop_instance_uid),
+        ),
+    ])
+}
+
+fn n_create_rq(message_id: u16, sop_class_uid: &str, sop_instance_uid: &str) -> InMemDicomObject {
+    InMemDicomObject::command_from_element_iter([
+        DataElement::new(
+            tags::AFFECTED_SOP_CLASS_UID,
+            VR::UI,
+            PrimitiveValue::from(sop_class_uid),
+        ),
+        DataElement::new(tags::COMMAND_FIELD, VR::US, dicom_value!(U16, [0x0140])),
+        DataElement::new(tags::MESSAGE_ID, VR::US, dicom_value!(U16, [message_id])),
+        DataElement::new(
+            tags::COMMAND_DATA_SET_TYPE,
+            VR::US,
+            dicom_value!(U16, [0x0001]),
+        ),
+        DataElement::new(
+            tags::AFFECTED_SOP_INSTANCE_UID,
+            VR::UI,
+            PrimitiveValue::from(sop_instance_uid),
+        ),
+    ])
+}
+
+fn n_set_rq(message_id: u16, sop_class_uid: &str, sop_instance_uid: &str) -> InMemDicomObject {
+    InMemDicomObject::command_from_element_iter([
+        DataElement::new(
+            tags::REQUESTED_SOP_CLASS_UID,
+            VR::UI,
+            PrimitiveValue::from(sop_class_uid),
+        ),
+        DataElement::new(tags::COMMAND_FIELD, VR::US, dicom_value!(U16, [0x0120])),
+        DataElement::new(tags::MESSAGE_ID, VR::US, dicom_value!(U16, [message_id])),
+        DataElement::new(
+            tags::COMMAND_DATA_SET_TYPE,
+            VR::US,
+            dicom_value!(U16, [0x0001]),
+        ),
+        DataElement::new(
+            tags::REQUESTED_SOP_INSTANCE_UID,
+            VR::UI,
+            PrimitiveValue::from(sop_instance_uid),
+        ),
+    ])
+}
+
+fn n_action_rq(
+    message_id: u16,
+    sop_class_uid: &str,
+    sop_instance_uid: &str,
+    action_type_id: u16,
+    has_data_set: bool,
+) -> InMemDicomObject {
+    let data_set_type = if has_data_set { 0x0001 } else { NO_DATA_SET };
+    InMemDicomObject::command_from_element_iter([
+        DataElement::new(
+            tags::REQUESTED_SOP_CLASS_UID,
+            VR::UI,
+            PrimitiveValue::from(sop_class_uid),
+        ),
+        DataElement::new(tags::COMMAND_FIELD, VR::US, dicom_value!(U16, [0x0130])),
+        DataElement::new(tags::MESSAGE_ID, VR::US, dicom_value!(U16, [message_id])),
+        DataElement::new(
+            tags::COMMAND_DATA_SET_TYPE,
+            VR::US,
+            dicom_value!(U16, [data_set_type]),
+        ),
+        DataElement::new(
+            tags::REQUESTED_SOP_INSTANCE_UID,
+            VR::UI,
+            PrimitiveValue::from(sop_instance_uid),
+        ),
+        DataElement::new(
+            tags::ACTION_TYPE_ID,
+            VR::US,
+            dicom_value!(U16, [action_type_id]),
+        ),
+    ])
+}
+
+fn n_event_report_rsp(request: &InMemDicomObject) -> InMemDicomObject {
+    let message_id = request
+        .get(tags::MESSAGE_ID)
+        .and_then(|e| e.to_int::<u16>().ok())
+        .unwrap_or(1);
+    let mut elements = vec![
+        DataElement::new(tags::COMMAND_FIELD, VR::US, dicom_value!(U16, [0x8100])),
+        DataElement::new(
+            tags::MESSAGE_ID_BEING_RESPONDED_TO,
+            VR::US,
+            dicom_value!(U16, [message_id]),
+        ),
+        DataElement::new(
+            tags::COMMAND_DATA_SET_TYPE,
+            VR::US,
+            dicom_value!(U16, [NO_DATA_SET]),
+        ),
+        DataElement::new(tags::STATUS, VR::US, dicom_value!(U16, [0x0000])),
+    ];
+    for tag in [
+        tags::AFFECTED_SOP_CLASS_UID,
+        tags::AFFECTED_SOP_INSTANCE_UID,
+        tags::EVENT_TYPE_ID,
+    ] {
+        if let Some(element) = request.get(tag) {
+            elements.push(element.clone());
+        }
+    }
+    InMemDicomObject::command_from_element_iter(elements)
+}
+
+/// Builds the TLS client configuration from `ca.pem`, `tls.pem` and `tls.key` in the
+/// certs directory. The peer chain is checked against the CA, the host name is not.
+fn tls_client_config(certs_dir: &Path) -> Result<Arc<ClientConfig>, DicomClientError> {
+    let provider = Arc::new(default_provider());
+
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(certs_dir.join("ca.pem"))
+        .map_err(|e| client_error("Failed to read ca.pem", e))?
+    {
+        let cert = cert.map_err(|e| client_error("Failed to parse ca.pem", e))?;
+        roots
+            .add(cert)
+            .map_err(|e| client_error("Failed to load ca.pem", e))?;
+    }
+    let client_certs = CertificateDer::pem_file_iter(certs_dir.join("tls.pem"))
+        .map_err(|e| client_error("Failed to read tls.pem", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| client_error("Failed to parse tls.pem", e))?;
+    let client_key = PrivateKeyDer::from_pem_file(certs_dir.join("tls.key"))
+        .map_err(|e| client_error("Failed to read tls.key", e))?;
+
+    let verifier = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+        .build()
+        .map_err(|e| client_error("Failed to build certificate verifier", e))?;
+
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| client_error("Failed to configure TLS", e))?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(IgnoreHostnameVerifier(verifier)))
+        .with_client_auth_cert(client_certs, client_key)
+        .map_err(|e| client_error("Failed to load client certificate", e))?;
+    Ok(Arc::new(config))
+}
+
+/// Verifies the server chain like `WebPkiServerVerifier` but accepts certificates
+/// issued for another host name, since DICOM peers are usually addressed by IP.
+#[derive(Debug)]
+pub(crate) struct IgnoreHostnameVerifier(pub(crate) Arc<WebPkiServerVerifier>);
+
+impl ServerCertVerifier for IgnoreHostnameVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        match self
+            .0
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+        {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. },
+            )) => Ok(ServerCertVerified::assertion()),
+            other => other,
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_verify_schemes()
+    }
+}
@@ -0,0 +1,519 @@
+use crate::dicom_client::{
+    client_error, status_of, trim_uid, DicomClientError, DimseChannel, NO_DATA_SET,
+};
+use crate::models::{AssociationSummary, ScpStatus};
+use chrono::Local;
+use dicom_core::header::Header;
+use dicom_core::{dicom_value, DataElement, PrimitiveValue, VR};
+use dicom_dictionary_std::{tags, uids};
+use dicom_encoding::transfer_syntax::TransferSyntaxIndex;
+use dicom_object::InMemDicomObject;
+use dicom_transfer_syntax_registry::TransferSyntaxRegistry;
+use dicom_ul::association::server::AsyncTlsStream;
+use dicom_ul::association::{Association, AsyncServerAssociation, ServerAssociationOptions};
+use dicom_ul::pdu::{Pdu, PresentationContextNegotiated, PresentationContextResultReason};
+use futures::future::BoxFuture;
+use rustls::crypto::ring::default_provider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::ServerConfig;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::{JoinHandle, JoinSet};
+
+// DIMSE 状态码
+pub const STATUS_SUCCESS: u16 = 0x0000;
+pub const STATUS_PENDING: u16 = 0xFF00;
+pub const STATUS_INVALID_ATTRIBUTE_VALUE: u16 = 0x0106;
+pub const STATUS_PROCESSING_FAILURE: u16 = 0x0110;
+pub const STATUS_DUPLICATE_SOP_INSTANCE: u16 = 0x0111;
+pub const STATUS_NO_SUCH_SOP_INSTANCE: u16 = 0x0112;
+pub const STATUS_MISSING_ATTRIBUTE: u16 = 0x0120;
+pub const STATUS_MISSING_ATTRIBUTE_VALUE: u16 = 0x0121;
+pub const STATUS_UNRECOGNIZED_OPERATION: u16 = 0x0211;
+pub const STATUS_IDENTIFIER_DOES_NOT_MATCH_SOP_CLASS: u16 = 0xA900;
+
+// accept 失败后重试前的等待时间
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Listener settings shared by all the built-in SCPs.
+#[derive(Debug, Clone)]
+pub struct ScpConfig {
+    pub ae_title: String,
+    pub port: u16,
+    pub tls_enabled: bool,
+}
+
+/// One DIMSE request received by an SCP.
+pub struct ScpRequest {
+    pub calling_ae_title: String,
+    pub transfer_syntax: String,
+    pub command: InMemDicomObject,
+    pub data_set: Option<Vec<u8>>,
+}
+
+impl ScpRequest {
+    pub fn command_field(&self) -> u16 {
+        self.command
+            .get(tags::COMMAND_FIELD)
+            .and_then(|e| e.to_int::<u16>().ok())
+            .unwrap_or_default()
+    }
+
+    pub fn message_id(&self) -> u16 {
+        self.command
+            .get(tags::MESSAGE_ID)
+            .and_then(|e| e.to_int::<u16>().ok())
+            .unwrap_or_default()
+    }
+
+    /// A string attribute of the command set, e.g. the affected SOP instance UID.
+    pub fn command_str(&self, tag: dicom_core::Tag) -> Option<String> {
+        self.command
+            .get(tag)
+            .and_then(|e| e.to_str().ok())
+            .map(|s| trim_uid(&s).to_string())
+            .filter(|s| !s.is_empty())
+    }
+
+    /// Decodes the data set with the transfer syntax of its presentation context.
+    pub fn decode_data_set(&self) -> Result<Option<InMemDicomObject>, DicomClientError> {
+        let Some(data) = &self.data_set else {
+            return Ok(None);
+        };
+        let ts = TransferSyntaxRegistry
+            .get(&self.transfer_syntax)
+            .ok_or_else(|| {
+                DicomClientError::new(&format!(
+                    "Unsupported transfer syntax {}",
+                    self.transfer_syntax
+                ))
+            })?;
+        InMemDicomObject::read_dataset_with_ts(&data[..], ts)
+            .map(Some)
+            .map_err(|e| client_error("Failed to read data set", e))
+    }
+}
+
+/// A message sent back on the association, usually the response to the request.
+pub struct ScpReply {
+    pub command: InMemDicomObject,
+    pub data_set: Option<InMemDicomObject>,
+}
+
+impl ScpReply {
+    /// A response to `request` with the given status and optional data set.
+    pub fn response(
+        request: &ScpRequest,
+        status: u16,
+        data_set: Option<InMemDicomObject>,
+    ) -> ScpReply {
+        let mut command = InMemDicomObject::command_from_element_iter([
+            DataElement::new(
+                tags::COMMAND_FIELD,
+                VR::US,
+                dicom_value!(U16, [request.command_field() | 0x8000]),
+            ),
+            DataElement::new(
+                tags::MESSAGE_ID_BEING_RESPONDED_TO,
+                VR::US,
+                dicom_value!(U16, [request.message_id()]),
+            ),
+            DataElement::new(
+                tags::COMMAND_DATA_SET_TYPE,
+                VR::US,
+                dicom_value!(U16, [if data_set.is_some() { 0x0000 } else { NO_DATA_SET }]),
+            ),
+            DataElement::new(tags::STATUS, VR::US, dicom_value!(U16, [status])),
+        ]);
+        let sop_class_uid = request
+            .command_str(tags::AFFECTED_SOP_CLASS_UID)
+            .or_else(|| request.command_str(tags::REQUESTED_SOP_CLASS_UID));
+        if let Some(uid) = sop_class_uid {
+            command.put(DataElement::new(
+                tags::AFFECTED_SOP_CLASS_UID,
+                VR::UI,
+                PrimitiveValue::from(uid),
+            ));
+        }
+        let sop_instance_uid = request
+            .command_str(tags::AFFECTED_SOP_INSTANCE_UID)
+            .or_else(|| request.command_str(tags::REQUESTED_SOP_INSTANCE_UID));
+        if let Some(uid) = sop_instance_uid {
+            command.put(DataElement::new(
+                tags::AFFECTED_SOP_INSTANCE_UID,
+                VR::UI,
+                PrimitiveValue::from(uid),
+            ));
+        }
+        ScpReply {
+            command: with_group_length(command),
+            data_set,
+        }
+    }
+
+    /// Adds an Error Comment, which is limited to 64 characters.
+    pub fn with_error_comment(mut self, comment: &str) -> ScpReply {
+        let comment: String = comment.chars().take(64).collect();
+        self.command.put(DataElement::new(
+            tags::ERROR_COMMENT,
+            VR::LO,
+            PrimitiveValue::from(comment),
+        ));
+        self.command = with_group_length(self.command);
+        self
+    }
+
+    /// Sets the Affected SOP Instance UID, e.g. one generated by the SCP.
+    pub fn with_affected_sop_instance_uid(mut self, uid: &str) -> ScpReply {
+        self.command.put(DataElement::new(
+            tags::AFFECTED_SOP_INSTANCE_UID,
+            VR::UI,
+            PrimitiveValue::from(uid),
+        ));
+        self.command = with_group_length(self.command);
+        self
+    }
+}
+
+// 命令集追加元素后需要重新计算 CommandGroupLength
+fn with_group_length(command: InMemDicomObject) -> InMemDicomObject {
+    InMemDicomObject::command_from_element_iter(
+        command
+            .into_iter()
+            .filter(|e| e.tag() != tags::COMMAND_GROUP_LENGTH),
+    )
+}
+
+/// The service behind an SCP listener. Verification is always answered by the
+/// listener itself.
+pub trait ScpService: Send + Sync {
+    /// Abstract syntaxes accepted in addition to Verification.
+    fn abstract_syntaxes(&self) -> Vec<String>;
+
+    /// Accepts any abstract syntax offered, e.g. for storage.
+    fn accepts_any_abstract_syntax(&self) -> bool {
+        false
+    }
+
+    /// Handles one request. Failures are reported through the response status.
+    fn handle<'a>(&'a self, request: &'a ScpRequest) -> BoxFuture<'a, Vec<ScpReply>>;
+
+    /// Called once an association ends, whether released or not.
+    fn association_closed<'a>(&'a self, _summary: &'a AssociationSummary) -> BoxFuture<'a, ()> {
+        Box::pin(async {})
+    }
+}
+
+/// A running SCP listener. Stopping it also aborts its open associations.
+pub struct ScpServer {
+    config: ScpConfig,
+    started_at: String,
+    associations: Arc<AtomicU64>,
+    handle: JoinHandle<()>,
+}
+
+impl ScpServer {
+    pub async fn start(
+        config: ScpConfig,
+        certs_dir: &Path,
+        service: Arc<dyn ScpService>,
+    ) -> Result<ScpServer, DicomClientError> {
+        let tls_config = if config.tls_enabled {
+            Some(tls_server_config(certs_dir)?)
+        } else {
+            None
+        };
+        let listener = TcpListener::bind(("0.0.0.0", config.port))
+            .await
+            .map_err(|e| client_error(&format!("Failed to listen on port {}", config.port), e))?;
+
+        let associations = Arc::new(AtomicU64::new(0));
+        let handle = tokio::spawn(accept_loop(
+            listener,
+            config.ae_title.clone(),
+            tls_config,
+            service,
+            associations.clone(),
+        ));
+        Ok(ScpServer {
+            config,
+            started_at: Local::now().to_rfc3339(),
+            associations,
+            handle,
+        })
+    }
+
+    pub fn stop(self) {
+        self.handle.abort();
+    }
+
+    pub fn status(&self, kind: &str) -> ScpStatus {
+        ScpStatus {
+            kind: kind.to_string(),
+            ae_title: self.config.ae_title.clone(),
+            port: self.config.port,
+            tls_enabled: self.config.tls_enabled,
+            started_at: self.started_at.clone(),
+            associations: self.associations.load(Ordering::Relaxed),
+        }
+    }
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    ae_title: String,
+    tls_config: Option<Arc<ServerConfig>>,
+    service: Arc<dyn ScpService>,
+    associations: Arc<AtomicU64>,
+) {
+    // 关联任务挂在 JoinSet 上，监听任务被 abort 时会一并结束
+    let mut tasks = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (socket, _) = match accepted {
+                    Ok(accepted) => accepted,
+                    // EMFILE 等错误会持续出现，稍等再重试以免空转
+                    Err(e) => {
+                        eprintln!("Failed to accept a DICOM connection: {}", e);
+                        tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                        continue;
+                    }
+                };
+                associations.fetch_add(1, Ordering::Relaxed);
+                tasks.spawn(serve_association(
+                    socket,
+                    ae_title.clone(),
+                    tls_config.clone(),
+                    service.clone(),
+                ));
+            }
+            Some(_) = tasks.join_next(), if !tasks.is_empty() => {}
+        }
+    }
+}
+
+async fn serve_association(
+    socket: TcpStream,
+    ae_title: String,
+    tls_config: Option<Arc<ServerConfig>>,
+    service: Arc<dyn ScpService>,
+) {
+    let mut options = ServerAssociationOptions::new()
+        .accept_called_ae_title()
+        .ae_title(ae_title.clone())
+        .with_abstract_syntax(uids::VERIFICATION)
+        .promiscuous(service.accepts_any_abstract_syntax())
+        .read_timeout(Duration::from_secs(60))
+        .write_timeout(Duration::from_secs(60));
+    for abstract_syntax in service.abstract_syntaxes() {
+        options = options.with_abstract_syntax(abstract_syntax);
+    }
+
+    let association = match tls_config {
+        Some(tls_config) => options
+            .tls_config(tls_config)
+            .establish_tls_async(socket)
+            .await
+            .map(|a| ScpAssociation::Tls(Box::new(a))),
+        None => options
+            .establish_async(socket)
+            .await
+            .map(|a| ScpAssociation::Plain(Box::new(a))),
+    };
+    let Ok(mut association) = association else {
+        return;
+    };
+
+    let started = Instant::now();
+    let mut summary = AssociationSummary {
+        calling_ae_title: association.calling_ae_title(),
+        called_ae_title: ae_title,
+        started_at: Local::now().to_rfc3339(),
+        duration_ms: 0,
+        requests: 0,
+        failures: 0,
+        bytes_received: 0,
+        error: None,
+    };
+    let result = association.serve(service.as_ref(), &mut summary).await;
+    summary.duration_ms = started.elapsed().as_millis() as u64;
+    if let Err(e) = result {
+        summary.error = Some(e.to_string());
+        association.abort().await;
+    }
+    service.association_closed(&summary).await;
+}
+
+enum ScpAssociation {
+    Plain(Box<AsyncServerAssociation<TcpStream>>),
+    Tls(Box<AsyncServerAssociation<AsyncTlsStream>>),
+}
+
+impl ScpAssociation {
+    fn calling_ae_title(&self) -> String {
+        match self {
+            ScpAssociation::Plain(a) => a.peer_ae_title().to_string(),
+            ScpAssociation::Tls(a) => a.peer_ae_title().to_string(),
+        }
+    }
+
+    async fn abort(self) {
+        let _ = match self {
+            ScpAssociation::Plain(a) => a.abort().await,
+            ScpAssociation::Tls(a) => a.abort().await,
+        };
+    }
+
+    /// Answers requests until the peer releases the association.
+    async fn serve(
+        &mut self,
+        service: &dyn ScpService,
+        summary: &mut AssociationSummary,
+    ) -> Result<(), DicomClientError> {
+        let calling_ae_title = self.calling_ae_title();
+        while let Some((pc_id, command, data_set)) = self.next_message().await? {
+            let pc = self
+                .presentation_contexts()
+                .iter()
+                .find(|pc| pc.id == pc_id && pc.reason == PresentationContextResultReason::Acceptance)
+                .cloned()
+                .ok_or_else(|| {
+                    DicomClientError::new(&format!("Unknown presentation context {}", pc_id))
+                })?;
+            let request = ScpRequest {
+                calling_ae_title: calling_ae_title.clone(),
+                transfer_syntax: pc.transfer_syntax.clone(),
+                command,
+                data_set,
+            };
+
+            // 响应消息（命令字段最高位为 1）不需要回复，例如 N-EVENT-REPORT-RSP
+            if request.command_field() & 0x8000 != 0 {
+                continue;
+            }
+            summary.requests += 1;
+            summary.bytes_received += request.data_set.as_ref().map_or(0, |d| d.len() as u64);
+            let replies = if request.command_field() == 0x0030 {
+                vec![ScpReply::response(&request, STATUS_SUCCESS, None)]
+            } else {
+                service.handle(&request).await
+            };
+            for reply in replies {
+                let failed = status_of(&reply.command)
+                    .is_ok_and(|s| !s.is_success() && !s.is_pending() && !s.is_warning());
+                if failed {
+                    summary.failures += 1;
+                }
+                self.send_reply(&pc, reply).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn send_reply(
+        &mut self,
+        pc: &PresentationContextNegotiated,
+        reply: ScpReply,
+    ) -> Result<(), DicomClientError> {
+        let data_set = match reply.data_set {
+            Some(data_set) => {
+                let ts = TransferSyntaxRegistry
+                    .get(&pc.transfer_syntax)
+                    .ok_or_else(|| {
+                        DicomClientError::new(&format!(
+                            "Unsupported transfer syntax {}",
+                            pc.transfer_syntax
+                        ))
+                    })?;
+                let mut data = Vec::new();
+                data_set
+                    .write_dataset_with_ts(&mut data, ts)
+                    .map_err(|e| client_error("Failed to encode data set", e))?;
+                Some(data)
+            }
+            None => None,
+        };
+        self.send_message(pc.id, &reply.command, data_set).await
+    }
+}
+
+impl DimseChannel for ScpAssociation {
+    fn presentation_contexts(&self) -> &[PresentationContextNegotiated] {
+        match self {
+            ScpAssociation::Plain(a) => a.presentation_contexts(),
+            ScpAssociation::Tls(a) => a.presentation_contexts(),
+        }
+    }
+
+    fn peer_max_pdu_length(&self) -> u32 {
+        match self {
+            ScpAssociation::Plain(a) => a.requestor_max_pdu_length(),
+            ScpAssociation::Tls(a) => a.requestor_max_pdu_length(),
+        }
+    }
+
+    async fn send(&mut self, pdu: &Pdu) -> Result<(), DicomClientError> {
+        match self {
+            ScpAssociation::Plain(a) => a.send(pdu).await,
+            ScpAssociation::Tls(a) => a.send(pdu).await,
+        }
+        .map_err(|e| client_error("Failed to send PDU", e))
+    }
+
+    async fn receive(&mut self) -> Result<Pdu, DicomClientError> {
+        match self {
+            ScpAssociation::Plain(a) => a.receive().await,
+            ScpAssociation::Tls(a) => a.receive().await,
+        }
+        .map_err(|e| client_error("Failed to receive PDU", e))
+    }
+
+    async fn send_fragmented(
+        &mut self,
+        presentation_context_id: u8,
+        data: &[u8],
+    ) -> Result<(), DicomClientError> {
+        let result = match self {
+            ScpAssociation::Plain(a) => {
+                let mut writer = a.send_pdata(presentation_context_id);
+                match writer.write_all(data).await {
+                    Ok(()) => writer.finish().await,
+                    Err(e) => Err(e),
+                }
+            }
+            ScpAssociation::Tls(a) => {
+                let mut writer = a.send_pdata(presentation_context_id);
+                match writer.write_all(data).await {
+                    Ok(()) => writer.finish().await,
+                    Err(e) => Err(e),
+                }
+            }
+        };
+        result.map_err(|e| client_error("Failed to send data set", e))
+    }
+}
+
+/// Serves the simulator's own certificate (`tls.pem` / `tls.key`) without
+/// asking peers for a client certificate.
+fn tls_server_config(certs_dir: &Path) -> Result<Arc<ServerConfig>, DicomClientError> {
+    let certs = CertificateDer::pem_file_iter(certs_dir.join("tls.pem"))
+        .map_err(|e| client_error("Failed to read tls.pem", e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| client_error("Failed to parse tls.pem", e))?;
+    let key = PrivateKeyDer::from_pem_file(certs_dir.join("tls.key"))
+        .map_err(|e| client_error("Failed to read tls.key", e))?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| client_error("Failed to configure TLS", e))?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| client_error("Failed to load server certificate", e))?;
+    Ok(Arc::new(config))
+}
@@ -0,0 +1,1098 @@
+// dicom_utils.rs
+// 构建 / 解析 worklist、MPPS、UPS、C-STORE 使用的数据集
+use crate::dicom_client::{DicomNode, DimseClient};
+use crate::models::{
+    DiscontinuationReason, MppsEntry, MppsStatus, PatientEntry, SopInstanceInfo,
+    SopInstanceUids, UpsEntry, UpsState, WorklistEntry,
+};
+use crate::utils::generate_uid;
+use chrono::Local;
+use dicom_core::value::DataSetSequence;
+use dicom_core::header::Header;
+use dicom_core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom_dictionary_std::{tags, uids};
+use dicom_object::{open_file, DefaultDicomObject, InMemDicomObject};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// 模拟器自带 RTSTRUCT 模板中引用的影像 SOP Instance UID
+const RT_TEMPLATE_IMAGE_UIDS: [&str; 3] = [
+    "1.2.840.113619.2.374.2807.4219983.23592.1454490194.266",
+    "1.2.840.113619.2.374.2807.4219983.23592.1454490194.264",
+    "1.2.840.113619.2.374.2807.4219983.23592.1454490194.261",
+];
+
+// 模板中 OperatorsName 为该值时，使用影像自身的 SeriesDescription
+const SIMULATOR_OPERATOR: &str = "iRT DICOM Device Simulator";
+
+/// Procedure Discontinuation Reasons (CID 9300), coded in the `DCM` scheme.
+const DISCONTINUATION_REASONS: [(&str, &str); 17] = [
+    ("110500", "Doctor canceled procedure"),
+    ("110501", "Equipment failure"),
+    ("110502", "Incorrect procedure ordered"),
+    ("110503", "Patient allergic to media/contrast"),
+    ("110504", "Patient died"),
+    ("110505", "Patient refused to continue procedure"),
+    ("110506", "Patient taken for treatment or surgery"),
+    ("110507", "Patient did not arrive"),
+    ("110508", "Patient pregnant"),
+    ("110509", "Change of procedure for correct charging"),
+    ("110510", "Duplicate order"),
+    ("110511", "Nursing unit cancel"),
+    ("110512", "Incorrect side ordered"),
+    ("110513", "Discontinued for unspecified reason"),
+    ("110514", "Incorrect worklist entry selected"),
+    ("110515", "Patient condition prevented continuing"),
+    ("110516", "Equipment change"),
+];
+
+/// Reads a DICOM file from disk.
+pub fn read_dicom_file(path: &Path) -> Result<DefaultDicomObject, String> {
+    open_file(path).map_err(|e| format!("Failed to read DICOM file {}: {}", path.display(), e))
+}
+
+/// Reads a message template, dropping the group length elements it was saved with
+/// since they no longer hold once the data set is modified.
+pub fn load_template(path: &Path) -> Result<InMemDicomObject, String> {
+    let mut template = read_dicom_file(path)?.into_inner();
+    strip_group_lengths(&mut template);
+    Ok(template)
+}
+
+pub fn strip_group_lengths(obj: &mut InMemDicomObject) {
+    obj.retain(|e| e.tag().element() != 0x0000);
+    let sequences: Vec<Tag> = obj
+        .iter()
+        .filter(|e| e.items().is_some())
+        .map(|e| e.tag())
+        .collect();
+    for tag in sequences {
+        obj.update_value(tag, |value| {
+            if let Some(items) = value.items_mut() {
+                items.iter_mut().for_each(strip_group_lengths);
+            }
+        });
+    }
+}
+
+fn get_str(obj: &InMemDicomObject, tag: Tag) -> String {
+    obj.get(tag)
+        .and_then(|e| e.to_str().ok())
+        .map(|s| s.trim_end_matches(['\0', ' ']).to_string())
+        .unwrap_or_default()
+}
+
+// PN 只取字母表示部分 (Alphabetic)
+fn get_person_name(obj: &InMemDicomObject, tag: Tag) -> String {
+    get_str(obj, tag)
+        .split('=')
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn first_item(obj: &InMemDicomObject, tag: Tag) -> Option<&InMemDicomObject> {
+    obj.get(tag).and_then(|e| e.items()).and_then(|i| i.first())
+}
+
+/// Runs `f` on the item at `index` of the sequence `tag`.
+fn with_item<R>(
+    obj: &mut InMemDicomObject,
+    tag: Tag,
+    index: usize,
+    f: impl FnOnce(&mut InMemDicomObject) -> Result<R, String>,
+) -> Result<R, String> {
+    let mut f = Some(f);
+    let mut result = None;
+    obj.update_value(tag, |value| {
+        if let Some(item) = value.items_mut().and_then(|items| items.get_mut(index)) {
+            if let Some(f) = f.take() {
+                result = Some(f(item));
+            }
+        }
+    });
+    result.unwrap_or_else(|| Err(format!("Missing item {} of sequence {}", index, tag)))
+}
+
+fn put_sequence(obj: &mut InMemDicomObject, tag: Tag, items: Vec<InMemDicomObject>) {
+    obj.put(DataElement::new(tag, VR::SQ, DataSetSequence::from(items)));
+}
+
+fn sequence_len(obj: &InMemDicomObject, tag: Tag) -> usize {
+    obj.get(tag).and_then(|e| e.items()).map_or(0, |i| i.len())
+}
+
+fn now_date() -> String {
+    Local::now().format("%Y%m%d").to_string()
+}
+
+fn now_time() -> String {
+    Local::now().format("%H%M%S").to_string()
+}
+
+fn now_date_time() -> String {
+    Local::now().format("%Y%m%d%H%M%S").to_string()
+}
+
+fn code_item(
+    code_value: &str,
+    coding_scheme_designator: &str,
+    code_meaning: &str,
+) -> InMemDicomObject {
+    let mut code = InMemDicomObject::new_empty();
+    code.put_str(tags::CODE_VALUE, VR::SH, code_value);
+    code.put_str(tags::CODING_SCHEME_DESIGNATOR, VR::SH, coding_scheme_designator);
+    code.put_str(tags::CODE_MEANING, VR::LO, code_meaning);
+    code
+}
+
+/// Maps a C-FIND worklist response to the fields kept on an MPPS entry.
+pub fn extract_fields(obj: &InMemDicomObject) -> MppsEntry {
+    let step = first_item(obj, tags::SCHEDULED_PROCEDURE_STEP_SEQUENCE);
+    let step_str = |tag| step.map(|s| get_str(s, tag)).unwrap_or_default();
+    MppsEntry {
+        id: None,
+        accession_number: get_str(obj, tags::ACCESSION_NUMBER),
+        requested_procedure_description: get_str(obj, tags::REQUESTED_PROCEDURE_DESCRIPTION),
+        patient_name: get_person_name(obj, tags::PATIENT_NAME),
+        patient_id: get_str(obj, tags::PATIENT_ID),
+        patient_birth_date: get_str(obj, tags::PATIENT_BIRTH_DATE),
+        patient_sex: get_str(obj, tags::PATIENT_SEX),
+        modality: step_str(tags::MODALITY),
+        scheduled_station_ae_title: step_str(tags::SCHEDULED_STATION_AE_TITLE),
+        scheduled_procedure_step_start_date: step_str(tags::SCHEDULED_PROCEDURE_STEP_START_DATE),
+        scheduled_performing_physician_name: step
+            .map(|s| get_person_name(s, tags::SCHEDULED_PERFORMING_PHYSICIAN_NAME))
+            .unwrap_or_default(),
+        study_instance_uid: get_str(obj, tags::STUDY_INSTANCE_UID),
+        status: None,
+        mpps_instance_uid: None,
+        sop_instance_uids: None,
+        dcm_file: None,
+        description: None,
+        status_history: Vec::new(),
+        storage_commitment: None,
+        hl7_report: None,
+        fhir_publish: Vec::new(),
+    }
+}
+
+/// Sends the generic and the MR C-FIND in one association. MR matches come
+/// first; generic ones only add accession numbers not seen yet.
+pub(crate) async fn query_worklist(
+    client: &dyn DimseClient,
+    entry: &WorklistEntry,
+    queries: Vec<InMemDicomObject>,
+) -> Result<Vec<MppsEntry>, String> {
+    let node = DicomNode::worklist(entry).map_err(|e| e.to_string())?;
+    let mut responses = client
+        .c_find(&node, uids::MODALITY_WORKLIST_INFORMATION_MODEL_FIND, queries)
+        .await
+        .map_err(|e| format!("An error occurred: {}", e))?;
+
+    let generic_result = responses.remove(0);
+    let mut result: Vec<MppsEntry> = responses.remove(0).iter().map(extract_fields).collect();
+    // MR 查询结果优先，通用查询只补充未出现的检查号
+    for found in generic_result.iter().map(extract_fields) {
+        if !result
+            .iter()
+            .any(|e| e.accession_number == found.accession_number)
+        {
+            result.push(found);
+        }
+    }
+    Ok(result)
+}
+
+/// The Scheduled Procedure Step Status served for an entry by the worklist SCP.
+fn scheduled_step_status(status: Option<MppsStatus>) -> &'static str {
+    match status {
+        None => "SCHEDULED",
+        Some(MppsStatus::InProgress) => "STARTED",
+        Some(MppsStatus::Completed) => "COMPLETED",
+        Some(MppsStatus::Discontinued) => "DISCONTINUED",
+    }
+}
+
+/// Builds the worklist item served by the worklist SCP for an entry.
+pub fn worklist_item(entry: &MppsEntry) -> InMemDicomObject {
+    let mut step = InMemDicomObject::new_empty();
+    step.put_str(tags::MODALITY, VR::CS, entry.modality.as_str());
+    step.put_str(
+        tags::SCHEDULED_STATION_AE_TITLE,
+        VR::AE,
+        entry.scheduled_station_ae_title.as_str(),
+    );
+    step.put_str(
+        tags::SCHEDULED_PROCEDURE_STEP_START_DATE,
+        VR::DA,
+        entry.scheduled_procedure_step_start_date.as_str(),
+    );
+    step.put_str(tags::SCHEDULED_PROCEDURE_STEP_START_TIME, VR::TM, "");
+    step.put_str(
+        tags::SCHEDULED_PERFORMING_PHYSICIAN_NAME,
+        VR::PN,
+        entry.scheduled_performing_physician_name.as_str(),
+    );
+    step.put_str(
+        tags::SCHEDULED_PROCEDURE_STEP_DESCRIPTION,
+        VR::LO,
+        entry.requested_procedure_description.as_str(),
+    );
+    step.put_str(tags::SCHEDULED_PROCEDURE_STEP_ID, VR::SH, entry.accession_number.as_str());
+    step.put_str(
+        tags::SCHEDULED_PROCEDURE_STEP_STATUS,
+        VR::CS,
+        scheduled_step_status(entry.status),
+    );
+
+    let mut item = InMemDicomObject::new_empty();
+    item.put_str(tags::SPECIFIC_CHARACTER_SET, VR::CS, "ISO_IR 192");
+    item.put_str(tags::ACCESSION_NUMBER, VR::SH, entry.accession_number.as_str());
+    item.put_str(tags::REFERRING_PHYSICIAN_NAME, VR::PN, "");
+    put_patient(
+        &mut item,
+        &entry.patient_name,
+        &entry.patient_id,
+        &entry.patient_birth_date,
+        &entry.patient_sex,
+    );
+    item.put_str(tags::STUDY_INSTANCE_UID, VR::UI, entry.study_instance_uid.as_str());
+    item.put_str(tags::REQUESTED_PROCEDURE_ID, VR::SH, entry.accession_number.as_str());
+    item.put_str(
+        tags::REQUESTED_PROCEDURE_DESCRIPTION,
+        VR::LO,
+        entry.requested_procedure_description.as_str(),
+    );
+    put_sequence(&mut item, tags::SCHEDULED_PROCEDURE_STEP_SEQUENCE, vec![step]);
+    item
+}
+
+/// C-FIND matching of `item` against the keys of `query` (PS3.4 C.2.2.2):
+/// universal, single value, wildcard, UID list, range and sequence matching.
+pub fn matches_query(item: &InMemDicomObject, query: &InMemDicomObject) -> bool {
+    query.iter().all(|key| {
+        let tag = key.tag();
+        if tag == tags::SPECIFIC_CHARACTER_SET {
+            return true;
+        }
+        if let Some(query_items) = key.items() {
+            return match query_items.first() {
+                Some(query_item) if query_item.iter().next().is_some() => item
+                    .get(tag)
+                    .and_then(|e| e.items())
+                    .is_some_and(|items| items.iter().any(|i| matches_query(i, query_item))),
+                _ => true,
+            };
+        }
+
+        let pattern = get_str(query, tag);
+        if pattern.is_empty() || pattern == "*" {
+            return true;
+        }
+        let value = get_str(item, tag);
+        match key.vr() {
+            VR::UI => pattern.split('\\').any(|uid| uid == value),
+            VR::DA | VR::TM | VR::DT if pattern.contains('-') => range_match(&pattern, &value),
+            // 人名匹配不区分大小写，且只比较字母表示部分
+            VR::PN => wildcard_match(
+                &pattern.split('=').next().unwrap_or_default().to_uppercase(),
+                &value.split('=').next().unwrap_or_default().to_uppercase(),
+            ),
+            _ => wildcard_match(&pattern, &value),
+        }
+    })
+}
+
+/// Copies the attributes requested by `query` from a matching item, with empty
+/// values for the ones the item does not have.
+pub fn response_identifier(item: &InMemDicomObject, query: &InMemDicomObject) -> InMemDicomObject {
+    let mut response = InMemDicomObject::new_empty();
+    if let Some(charset) = item.get(tags::SPECIFIC_CHARACTER_SET) {
+        response.put(charset.clone());
+    }
+    for key in query.iter() {
+        let tag = key.tag();
+        if tag == tags::SPECIFIC_CHARACTER_SET {
+            continue;
+        }
+        if let Some(query_items) = key.items() {
+            let items = item
+                .get(tag)
+                .and_then(|e| e.items())
+                .map(|items| match query_items.first() {
+                    Some(query_item) if query_item.iter().next().is_some() => items
+                        .iter()
+                        .filter(|i| matches_query(i, query_item))
+                        .map(|i| response_identifier(i, query_item))
+                        .collect(),
+                    _ => items.to_vec(),
+                })
+                .unwrap_or_default();
+            put_sequence(&mut response, tag, items);
+            continue;
+        }
+        match item.get(tag) {
+            Some(element) => response.put(element.clone()),
+            None => response.put(DataElement::new(tag, key.vr(), PrimitiveValue::Empty)),
+        };
+    }
+    response
+}
+
+// 日期 / 时间范围匹配，形如 "20240101-20240131"、"-20240131" 或 "20240101-"
+fn range_match(pattern: &str, value: &str) -> bool {
+    if value.is_empty() {
+        return false;
+    }
+    let (from, to) = pattern.split_once('-').unwrap_or((pattern, pattern));
+    (from.is_empty() || value >= from) && (to.is_empty() || value <= to)
+}
+
+// '*' 匹配任意个字符，'?' 匹配单个字符
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let value: Vec<char> = value.chars().collect();
+    let (mut p, mut v) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while v < value.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == value[v]) {
+            p += 1;
+            v += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, v));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            v = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Builds the N-CREATE attribute list from the in-progress template.
+pub fn build_in_progress(
+    mut ds: InMemDicomObject,
+    entry: &MppsEntry,
+    worklist: &WorklistEntry,
+) -> Result<InMemDicomObject, String> {
+    let description = &entry.requested_procedure_description;
+    with_item(&mut ds, tags::SCHEDULED_STEP_ATTRIBUTES_SEQUENCE, 0, |step| {
+        step.put_str(tags::STUDY_INSTANCE_UID, VR::UI, &entry.study_instance_uid);
+        step.put_str(tags::ACCESSION_NUMBER, VR::SH, &entry.accession_number);
+        step.put_str(tags::REQUESTED_PROCEDURE_ID, VR::SH, description);
+        step.put_str(tags::REQUESTED_PROCEDURE_DESCRIPTION, VR::LO, description);
+        step.put_str(tags::SCHEDULED_PROCEDURE_STEP_ID, VR::SH, description);
+        step.put_str(tags::SCHEDULED_PROCEDURE_STEP_DESCRIPTION, VR::LO, description);
+        put_sequence(step, tags::SCHEDULED_PROTOCOL_CODE_SEQUENCE, vec![]);
+        Ok(())
+    })?;
+
+    ds.put_str(tags::PATIENT_NAME, VR::PN, &entry.patient_name);
+    ds.put_str(tags::PATIENT_ID, VR::LO, &entry.patient_id);
+    ds.put_str(tags::PATIENT_BIRTH_DATE, VR::DA, &entry.patient_birth_date);
+    ds.put_str(tags::PATIENT_SEX, VR::CS, &entry.patient_sex);
+    put_sequence(&mut ds, tags::REFERENCED_PATIENT_SEQUENCE, vec![]);
+
+    ds.put_str(
+        tags::PERFORMED_PROCEDURE_STEP_ID,
+        VR::SH,
+        format!("PPS ID {}", entry.accession_number),
+    );
+    ds.put_str(tags::PERFORMED_STATION_AE_TITLE, VR::AE, &worklist.calling_ae_title);
+    ds.put_str(tags::PERFORMED_STATION_NAME, VR::SH, &worklist.name);
+    ds.put_str(tags::PERFORMED_LOCATION, VR::SH, &worklist.name);
+    ds.put_str(
+        tags::PERFORMED_PROCEDURE_STEP_START_DATE,
+        VR::DA,
+        &entry.scheduled_procedure_step_start_date,
+    );
+    ds.put_str(tags::PERFORMED_PROCEDURE_STEP_START_TIME, VR::TM, now_time());
+    ds.put_str(
+        tags::PERFORMED_PROCEDURE_STEP_STATUS,
+        VR::CS,
+        MppsStatus::InProgress.as_str(),
+    );
+    ds.put_str(tags::PERFORMED_PROCEDURE_STEP_DESCRIPTION, VR::LO, "description");
+    ds.put_str(tags::PERFORMED_PROCEDURE_TYPE_DESCRIPTION, VR::LO, "type");
+    ds.put_str(tags::PERFORMED_PROCEDURE_STEP_END_DATE, VR::DA, "");
+    ds.put_str(tags::PERFORMED_PROCEDURE_STEP_END_TIME, VR::TM, "");
+    ds.put_str(tags::MODALITY, VR::CS, &entry.modality);
+    ds.put_str(tags::STUDY_ID, VR::SH, &entry.accession_number);
+    put_sequence(&mut ds, tags::PERFORMED_PROTOCOL_CODE_SEQUENCE, vec![]);
+    put_sequence(&mut ds, tags::PERFORMED_SERIES_SEQUENCE, vec![]);
+    put_sequence(
+        &mut ds,
+        tags::PERFORMED_PROCEDURE_STEP_DISCONTINUATION_REASON_CODE_SEQUENCE,
+        vec![],
+    );
+    Ok(ds)
+}
+
+pub fn discontinuation_reasons() -> Vec<DiscontinuationReason> {
+    DISCONTINUATION_REASONS
+        .iter()
+        .map(|(code_value, code_meaning)| DiscontinuationReason {
+            code_value: code_value.to_string(),
+            coding_scheme_designator: "DCM".to_string(),
+            code_meaning: code_meaning.to_string(),
+        })
+        .collect()
+}
+
+pub fn discontinuation_reason(code_value: &str) -> Option<DiscontinuationReason> {
+    discontinuation_reasons()
+        .into_iter()
+        .find(|reason| reason.code_value == code_value)
+}
+
+/// Builds the N-SET modification list from the discontinued template. No images are
+/// referenced, the performed series only carries a new series UID.
+pub fn build_discontinued(
+    mut ds: InMemDicomObject,
+    entry: &MppsEntry,
+    reason: &DiscontinuationReason,
+) -> Result<InMemDicomObject, String> {
+    ds.put_str(tags::PERFORMED_PROCEDURE_STEP_END_DATE, VR::DA, now_date());
+    ds.put_str(tags::PERFORMED_PROCEDURE_STEP_END_TIME, VR::TM, now_time());
+    ds.put_str(
+        tags::PERFORMED_PROCEDURE_STEP_STATUS,
+        VR::CS,
+        MppsStatus::Discontinued.as_str(),
+    );
+
+    let code = code_item(
+        &reason.code_value,
+        &reason.coding_scheme_designator,
+        &reason.code_meaning,
+    );
+    put_sequence(
+        &mut ds,
+        tags::PERFORMED_PROCEDURE_STEP_DISCONTINUATION_REASON_CODE_SEQUENCE,
+        vec![code],
+    );
+
+    with_item(&mut ds, tags::PERFORMED_SERIES_SEQUENCE, 0, |series| {
+        series.put_str(tags::SERIES_INSTANCE_UID, VR::UI, generate_uid());
+        series.put_str(
+            tags::PERFORMING_PHYSICIAN_NAME,
+            VR::PN,
+            &entry.scheduled_performing_physician_name,
+        );
+        if let Some(description) = &entry.description {
+            series.put_str(tags::SERIES_DESCRIPTION, VR::LO, description);
+        }
+        put_sequence(series, tags::REFERENCED_IMAGE_SEQUENCE, vec![]);
+        Ok(())
+    })?;
+    Ok(ds)
+}
+
+// 模拟器没有工作项编码，使用私有编码方案并以标签作为含义
+fn ups_workitem_code(entry: &UpsEntry) -> InMemDicomObject {
+    code_item("SIMWORK", "99DCMSIM", &entry.procedure_step_label)
+}
+
+/// Builds the N-CREATE attribute list of a scheduled UPS workitem.
+pub fn build_ups_create(entry: &UpsEntry, worklist: &WorklistEntry) -> InMemDicomObject {
+    let mut ds = InMemDicomObject::new_empty();
+    ds.put_str(tags::SPECIFIC_CHARACTER_SET, VR::CS, "ISO_IR 192");
+    // N-CREATE 时 TransactionUID 必须为空，认领时才分配
+    ds.put_str(tags::TRANSACTION_UID, VR::UI, "");
+    ds.put_str(
+        tags::SCHEDULED_PROCEDURE_STEP_PRIORITY,
+        VR::CS,
+        &entry.scheduled_procedure_step_priority,
+    );
+    ds.put_str(tags::PROCEDURE_STEP_LABEL, VR::LO, &entry.procedure_step_label);
+    ds.put_str(tags::WORKLIST_LABEL, VR::LO, &worklist.name);
+    ds.put_str(
+        tags::SCHEDULED_PROCEDURE_STEP_START_DATE_TIME,
+        VR::DT,
+        &entry.scheduled_procedure_step_start_date_time,
+    );
+    put_sequence(&mut ds, tags::SCHEDULED_STATION_NAME_CODE_SEQUENCE, vec![]);
+    put_sequence(&mut ds, tags::SCHEDULED_STATION_CLASS_CODE_SEQUENCE, vec![]);
+    put_sequence(
+        &mut ds,
+        tags::SCHEDULED_STATION_GEOGRAPHIC_LOCATION_CODE_SEQUENCE,
+        vec![],
+    );
+    put_sequence(&mut ds, tags::SCHEDULED_PROCESSING_PARAMETERS_SEQUENCE, vec![]);
+    put_sequence(
+        &mut ds,
+        tags::SCHEDULED_WORKITEM_CODE_SEQUENCE,
+        vec![ups_workitem_code(entry)],
+    );
+    ds.put_str(
+        tags::COMMENTS_ON_THE_SCHEDULED_PROCEDURE_STEP,
+        VR::LT,
+        entry.description.as_deref().unwrap_or_default(),
+    );
+    ds.put_str(tags::INPUT_READINESS_STATE, VR::CS, "READY");
+    put_sequence(&mut ds, tags::INPUT_INFORMATION_SEQUENCE, vec![]);
+    ds.put_str(tags::PROCEDURE_STEP_STATE, VR::CS, UpsState::Scheduled.as_str());
+
+    ds.put_str(tags::PATIENT_NAME, VR::PN, &entry.patient_name);
+    ds.put_str(tags::PATIENT_ID, VR::LO, &entry.patient_id);
+    ds.put_str(tags::ISSUER_OF_PATIENT_ID, VR::LO, "");
+    ds.put_str(tags::PATIENT_BIRTH_DATE, VR::DA, &entry.patient_birth_date);
+    ds.put_str(tags::PATIENT_SEX, VR::CS, &entry.patient_sex);
+    ds.put_str(tags::ADMISSION_ID, VR::LO, "");
+    put_sequence(&mut ds, tags::ISSUER_OF_ADMISSION_ID_SEQUENCE, vec![]);
+    ds.put_str(tags::STUDY_INSTANCE_UID, VR::UI, &entry.study_instance_uid);
+
+    let mut request = InMemDicomObject::new_empty();
+    request.put_str(tags::STUDY_INSTANCE_UID, VR::UI, &entry.study_instance_uid);
+    request.put_str(tags::ACCESSION_NUMBER, VR::SH, &entry.accession_number);
+    put_sequence(&mut request, tags::ISSUER_OF_ACCESSION_NUMBER_SEQUENCE, vec![]);
+    request.put_str(tags::REQUESTED_PROCEDURE_ID, VR::SH, &entry.accession_number);
+    request.put_str(
+        tags::REQUESTED_PROCEDURE_DESCRIPTION,
+        VR::LO,
+        &entry.procedure_step_label,
+    );
+    put_sequence(&mut request, tags::REQUESTED_PROCEDURE_CODE_SEQUENCE, vec![]);
+    put_sequence(&mut ds, tags::REFERENCED_REQUEST_SEQUENCE, vec![request]);
+    ds
+}
+
+/// The Action Information of a Change UPS State request.
+pub fn ups_change_state(state: UpsState, transaction_uid: &str) -> InMemDicomObject {
+    let mut ds = InMemDicomObject::new_empty();
+    ds.put_str(tags::PROCEDURE_STEP_STATE, VR::CS, state.as_str());
+    ds.put_str(tags::TRANSACTION_UID, VR::UI, transaction_uid);
+    ds
+}
+
+fn ups_progress_item(entry: &UpsEntry) -> InMemDicomObject {
+    let mut progress = InMemDicomObject::new_empty();
+    progress.put_str(
+        tags::PROCEDURE_STEP_PROGRESS,
+        VR::DS,
+        entry.progress.unwrap_or(0).to_string(),
+    );
+    if let Some(description) = &entry.description {
+        progress.put_str(tags::PROCEDURE_STEP_PROGRESS_DESCRIPTION, VR::ST, description);
+    }
+    progress
+}
+
+/// Builds the N-SET modification list reporting the progress of a claimed workitem.
+pub fn build_ups_progress(entry: &UpsEntry, transaction_uid: &str) -> InMemDicomObject {
+    let mut ds = InMemDicomObject::new_empty();
+    ds.put_str(tags::TRANSACTION_UID, VR::UI, transaction_uid);
+    put_sequence(
+        &mut ds,
+        tags::PROCEDURE_STEP_PROGRESS_INFORMATION_SEQUENCE,
+        vec![ups_progress_item(entry)],
+    );
+    ds
+}
+
+/// Builds the N-SET modification list that has to be in place before a claimed
+/// workitem moves to `state` (COMPLETED or CANCELED).
+pub fn build_ups_final(
+    entry: &UpsEntry,
+    worklist: &WorklistEntry,
+    transaction_uid: &str,
+    state: UpsState,
+    reason: Option<&DiscontinuationReason>,
+) -> InMemDicomObject {
+    let mut ds = InMemDicomObject::new_empty();
+    ds.put_str(tags::TRANSACTION_UID, VR::UI, transaction_uid);
+
+    let mut progress = ups_progress_item(entry);
+    if state == UpsState::Canceled {
+        progress.put_str(
+            tags::PROCEDURE_STEP_CANCELLATION_DATE_TIME,
+            VR::DT,
+            now_date_time(),
+        );
+        if let Some(reason) = reason {
+            progress.put_str(tags::REASON_FOR_CANCELLATION, VR::LT, &reason.code_meaning);
+            put_sequence(
+                &mut progress,
+                tags::PROCEDURE_STEP_DISCONTINUATION_REASON_CODE_SEQUENCE,
+                vec![code_item(
+                    &reason.code_value,
+                    &reason.coding_scheme_designator,
+                    &reason.code_meaning,
+                )],
+            );
+        }
+    }
+    put_sequence(
+        &mut ds,
+        tags::PROCEDURE_STEP_PROGRESS_INFORMATION_SEQUENCE,
+        vec![progress],
+    );
+
+    // 开始时间取认领时刻，没有记录时使用当前时间
+    let started = entry
+        .state_history
+        .iter()
+        .rev()
+        .find(|t| t.to == UpsState::InProgress)
+        .and_then(|t| chrono::DateTime::parse_from_rfc3339(&t.timestamp).ok())
+        .map(|t| t.format("%Y%m%d%H%M%S").to_string())
+        .unwrap_or_else(now_date_time);
+    let mut performed = InMemDicomObject::new_empty();
+    put_sequence(&mut performed, tags::ACTUAL_HUMAN_PERFORMERS_SEQUENCE, vec![]);
+    put_sequence(
+        &mut performed,
+        tags::PERFORMED_STATION_NAME_CODE_SEQUENCE,
+        vec![code_item(&worklist.calling_ae_title, "99DCMSIM", &worklist.name)],
+    );
+    performed.put_str(tags::PERFORMED_PROCEDURE_STEP_START_DATE_TIME, VR::DT, started);
+    performed.put_str(
+        tags::PERFORMED_PROCEDURE_STEP_END_DATE_TIME,
+        VR::DT,
+        now_date_time(),
+    );
+    put_sequence(
+        &mut performed,
+        tags::PERFORMED_WORKITEM_CODE_SEQUENCE,
+        vec![ups_workitem_code(entry)],
+    );
+    put_sequence(&mut performed, tags::PERFORMED_PROCESSING_PARAMETERS_SEQUENCE, vec![]);
+    put_sequence(&mut performed, tags::OUTPUT_INFORMATION_SEQUENCE, vec![]);
+    put_sequence(
+        &mut ds,
+        tags::UNIFIED_PROCEDURE_STEP_PERFORMED_PROCEDURE_SEQUENCE,
+        vec![performed],
+    );
+    ds
+}
+
+/// The Action Information of a Request UPS Cancel sent for a workitem this
+/// simulator does not own.
+pub fn ups_cancel_request(reason: &DiscontinuationReason) -> InMemDicomObject {
+    let mut ds = InMemDicomObject::new_empty();
+    ds.put_str(tags::REASON_FOR_CANCELLATION, VR::LT, &reason.code_meaning);
+    put_sequence(
+        &mut ds,
+        tags::PROCEDURE_STEP_DISCONTINUATION_REASON_CODE_SEQUENCE,
+        vec![code_item(
+            &reason.code_value,
+            &reason.coding_scheme_designator,
+            &reason.code_meaning,
+        )],
+    );
+    ds
+}
+
+/// The Action Information of a subscription to UPS events.
+pub fn ups_subscription(deletion_lock: bool) -> InMemDicomObject {
+    let mut ds = InMemDicomObject::new_empty();
+    ds.put_str(
+        tags::DELETION_LOCK,
+        VR::LO,
+        if deletion_lock { "TRUE" } else { "FALSE" },
+    );
+    ds
+}
+
+/// Walks `path` for `.dcm` files, grouping them into one series per directory with
+/// freshly generated series and instance UIDs.
+pub fn collect_dcm_files(path: &Path) -> Result<Vec<SopInstanceUids>, String> {
+    if !path.exists() {
+        return Err(format!("Path does not exist: {}", path.display()));
+    }
+    let mut groups: BTreeMap<PathBuf, Vec<SopInstanceInfo>> = BTreeMap::new();
+    for file in find_dcm_files(path)? {
+        let obj = read_dicom_file(&file)?;
+        let sop_class_uid = get_str(&obj, tags::SOP_CLASS_UID);
+        if sop_class_uid.is_empty() {
+            continue;
+        }
+        let dir = file.parent().map(Path::to_path_buf).unwrap_or_default();
+        groups.entry(dir).or_default().push(SopInstanceInfo {
+            sop_instance_uid: generate_uid(),
+            path: file.to_string_lossy().into_owned(),
+            sop_class_uid,
+        });
+    }
+    Ok(groups
+        .into_values()
+        .map(|infos| SopInstanceUids {
+            series_instance_uid: generate_uid(),
+            sop_class_uid: infos[0].sop_class_uid.clone(),
+            sop_instance_infos: infos,
+        })
+        .collect())
+}
+
+/// All `.dcm` files below `path` (or `path` itself when it is a file), in a stable order.
+pub fn find_dcm_files(path: &Path) -> Result<Vec<PathBuf>, String> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    let mut entries: Vec<PathBuf> = fs::read_dir(path)
+        .map_err(|e| format!("Failed to read directory {}: {}", path.display(), e))?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .collect();
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            files.extend(find_dcm_files(&entry)?);
+        } else if entry.extension().is_some_and(|ext| ext == "dcm") {
+            files.push(entry);
+        }
+    }
+    Ok(files)
+}
+
+/// Builds the N-SET modification list from the completed template, listing the
+/// collected series in PerformedSeriesSequence.
+pub fn build_completed(
+    mut ds: InMemDicomObject,
+    entry: &MppsEntry,
+    series: &[SopInstanceUids],
+) -> Result<InMemDicomObject, String> {
+    ds.put_str(tags::PERFORMED_PROCEDURE_STEP_END_DATE, VR::DA, now_date());
+    ds.put_str(
+        tags::PERFORMED_PROCEDURE_STEP_END_TIME,
+        VR::TM,
+        Local::now().format("%H%M").to_string(),
+    );
+
+    let series_template = first_item(&ds, tags::PERFORMED_SERIES_SEQUENCE)
+        .cloned()
+        .ok_or("Template is missing PerformedSeriesSequence")?;
+    let image_template = first_item(&series_template, tags::REFERENCED_IMAGE_SEQUENCE)
+        .cloned()
+        .ok_or("Template is missing ReferencedImageSequence")?;
+
+    let mut performed_series = Vec::with_capacity(series.len());
+    for series_info in series {
+        let mut item = series_template.clone();
+        item.put_str(tags::SERIES_INSTANCE_UID, VR::UI, &series_info.series_instance_uid);
+        item.put_str(
+            tags::PERFORMING_PHYSICIAN_NAME,
+            VR::PN,
+            &entry.scheduled_performing_physician_name,
+        );
+        if let Some(description) = &entry.description {
+            item.put_str(tags::SERIES_DESCRIPTION, VR::LO, description);
+        }
+
+        let mut images = Vec::with_capacity(series_info.sop_instance_infos.len());
+        for instance in &series_info.sop_instance_infos {
+            let mut image = image_template.clone();
+            image.put_str(tags::REFERENCED_SOP_CLASS_UID, VR::UI, &series_info.sop_class_uid);
+            image.put_str(tags::REFERENCED_SOP_INSTANCE_UID, VR::UI, &instance.sop_instance_uid);
+            if get_str(&item, tags::OPERATORS_NAME) == SIMULATOR_OPERATOR {
+                let source = read_dicom_file(Path::new(&instance.path))?;
+                let series_description = get_str(&source, tags::SERIES_DESCRIPTION);
+                if !series_description.is_empty() {
+                    item.put_str(tags::OPERATORS_NAME, VR::PN, &series_description);
+                    item.put_str(tags::SERIES_DESCRIPTION, VR::LO, &series_description);
+                }
+            }
+            images.push(image);
+        }
+        put_sequence(&mut item, tags::REFERENCED_IMAGE_SEQUENCE, images);
+        performed_series.push(item);
+    }
+    put_sequence(&mut ds, tags::PERFORMED_SERIES_SEQUENCE, performed_series);
+    Ok(ds)
+}
+
+/// Maps an MPPS received by the MPPS SCP to the fields kept on an MPPS entry.
+/// Status and history are left to the caller.
+pub fn received_mpps_fields(obj: &InMemDicomObject, sop_instance_uid: &str) -> MppsEntry {
+    let step = first_item(obj, tags::SCHEDULED_STEP_ATTRIBUTES_SEQUENCE);
+    let step_str = |tag| step.map(|s| get_str(s, tag)).unwrap_or_default();
+    let performing_physician = first_item(obj, tags::PERFORMED_SERIES_SEQUENCE)
+        .map(|s| get_person_name(s, tags::PERFORMING_PHYSICIAN_NAME))
+        .unwrap_or_default();
+    let series = performed_series(obj);
+    MppsEntry {
+        id: None,
+        accession_number: step_str(tags::ACCESSION_NUMBER),
+        requested_procedure_description: step_str(tags::REQUESTED_PROCEDURE_DESCRIPTION),
+        patient_name: get_person_name(obj, tags::PATIENT_NAME),
+        patient_id: get_str(obj, tags::PATIENT_ID),
+        patient_birth_date: get_str(obj, tags::PATIENT_BIRTH_DATE),
+        patient_sex: get_str(obj, tags::PATIENT_SEX),
+        modality: get_str(obj, tags::MODALITY),
+        scheduled_station_ae_title: get_str(obj, tags::PERFORMED_STATION_AE_TITLE),
+        scheduled_procedure_step_start_date: get_str(
+            obj,
+            tags::PERFORMED_PROCEDURE_STEP_START_DATE,
+        ),
+        scheduled_performing_physician_name: performing_physician,
+        study_instance_uid: step_str(tags::STUDY_INSTANCE_UID),
+        status: None,
+        mpps_instance_uid: Some(sop_instance_uid.to_string()),
+        sop_instance_uids: if series.is_empty() {
+            None
+        } else {
+            serde_json::to_string(&series).ok()
+        },
+        dcm_file: None,
+        description: Some(get_str(obj, tags::PERFORMED_PROCEDURE_STEP_DESCRIPTION))
+            .filter(|d| !d.is_empty()),
+        status_history: Vec::new(),
+        storage_commitment: None,
+        hl7_report: None,
+        fhir_publish: Vec::new(),
+    }
+}
+
+/// Lists the instances referenced by the PerformedSeriesSequence of an MPPS.
+pub fn performed_series(obj: &InMemDicomObject) -> Vec<SopInstanceUids> {
+    let Some(series_items) = obj
+        .get(tags::PERFORMED_SERIES_SEQUENCE)
+        .and_then(|e| e.items())
+    else {
+        return Vec::new();
+    };
+    series_items
+        .iter()
+        .map(|series| {
+            let references: Vec<&InMemDicomObject> = [
+                tags::REFERENCED_IMAGE_SEQUENCE,
+                tags::REFERENCED_NON_IMAGE_COMPOSITE_SOP_INSTANCE_SEQUENCE,
+            ]
+            .into_iter()
+            .filter_map(|tag| series.get(tag).and_then(|e| e.items()))
+            .flatten()
+            .collect();
+            SopInstanceUids {
+                series_instance_uid: get_str(series, tags::SERIES_INSTANCE_UID),
+                sop_class_uid: references
+                    .first()
+                    .map(|r| get_str(r, tags::REFERENCED_SOP_CLASS_UID))
+                    .unwrap_or_default(),
+                sop_instance_infos: references
+                    .iter()
+                    .map(|r| SopInstanceInfo {
+                        sop_instance_uid: get_str(r, tags::REFERENCED_SOP_INSTANCE_UID),
+                        path: String::new(),
+                        sop_class_uid: get_str(r, tags::REFERENCED_SOP_CLASS_UID),
+                    })
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+fn put_patient(
+    obj: &mut InMemDicomObject,
+    name: &str,
+    id: &str,
+    birth_date: &str,
+    sex: &str,
+) {
+    obj.put_str(tags::PATIENT_NAME, VR::PN, name);
+    obj.put_str(tags::PATIENT_ID, VR::LO, id);
+    obj.put_str(tags::PATIENT_BIRTH_DATE, VR::DA, birth_date);
+    obj.put_str(tags::PATIENT_SEX, VR::CS, sex);
+}
+
+fn put_instance_header(obj: &mut DefaultDicomObject, sop_instance_uid: &str) {
+    obj.put_str(tags::INSTANCE_CREATION_DATE, VR::DA, now_date());
+    obj.put_str(tags::INSTANCE_CREATION_TIME, VR::TM, now_time());
+    obj.put_str(tags::SOP_INSTANCE_UID, VR::UI, sop_instance_uid);
+    obj.meta_mut().media_storage_sop_instance_uid = sop_instance_uid.to_string();
+}
+
+/// Loads the collected images of an MPPS entry and rewrites them for the
+/// entry's patient and study.
+pub fn prepare_instances(entry: &MppsEntry) -> Result<Vec<DefaultDicomObject>, String> {
+    let series = parse_sop_instance_uids(entry)?;
+    let mut objects = Vec::new();
+    for series_info in &series {
+        for instance in &series_info.sop_instance_infos {
+            let mut obj = read_dicom_file(Path::new(&instance.path))?;
+            rewrite_instance(
+                &mut obj,
+                entry,
+                &series_info.series_instance_uid,
+                &instance.sop_instance_uid,
+            );
+            objects.push(obj);
+        }
+    }
+    Ok(objects)
+}
+
+/// Rewrites one image for the entry's patient and study under the given
+/// series and instance UIDs.
+pub fn rewrite_instance(
+    obj: &mut DefaultDicomObject,
+    entry: &MppsEntry,
+    series_instance_uid: &str,
+    sop_instance_uid: &str,
+) {
+    put_instance_header(obj, sop_instance_uid);
+    put_patient(
+        obj,
+        &entry.patient_name,
+        &entry.patient_id,
+        &entry.patient_birth_date,
+        &entry.patient_sex,
+    );
+    obj.put_str(tags::STUDY_INSTANCE_UID, VR::UI, &entry.study_instance_uid);
+    obj.put_str(tags::SERIES_INSTANCE_UID, VR::UI, series_instance_uid);
+    obj.put_str(
+        tags::SERIES_DESCRIPTION,
+        VR::LO,
+        entry.description.as_deref().unwrap_or_default(),
+    );
+}
+
+pub fn parse_sop_instance_uids(entry: &MppsEntry) -> Result<Vec<SopInstanceUids>, String> {
+    let series: Vec<SopInstanceUids> = match entry.sop_instance_uids.as_deref() {
+        Some(json) if !json.is_empty() => serde_json::from_str(json)
+            .map_err(|e| format!("Failed to parse SopInstanceUids: {}", e))?,
+        _ => Vec::new(),
+    };
+    if series.is_empty() {
+        return Err("No SOP Instance UIDs available for this MPPS entry.".to_string());
+    }
+    Ok(series)
+}
+
+/// Rewrites the RTSTRUCT at `path` so that it references the single image series
+/// sent for `entry`.
+pub fn prepare_rt_struct(path: &Path, entry: &MppsEntry) -> Result<DefaultDicomObject, String> {
+    let series = parse_sop_instance_uids(entry)?;
+    let mut ds = read_dicom_file(path)?;
+    put_instance_header(&mut ds, &generate_uid());
+    put_patient(
+        &mut ds,
+        &entry.patient_name,
+        &entry.patient_id,
+        &entry.patient_birth_date,
+        &entry.patient_sex,
+    );
+    ds.put_str(tags::STUDY_INSTANCE_UID, VR::UI, &entry.study_instance_uid);
+    ds.put_str(tags::SERIES_INSTANCE_UID, VR::UI, generate_uid());
+
+    let frame_of_reference_uid = generate_uid();
+    for index in 0..3 {
+        with_item(&mut ds, tags::STRUCTURE_SET_ROI_SEQUENCE, index, |roi| {
+            roi.put_str(
+                tags::REFERENCED_FRAME_OF_REFERENCE_UID,
+                VR::UI,
+                &frame_of_reference_uid,
+            );
+            Ok(())
+        })?;
+    }
+
+    let [series_info] = series.as_slice() else {
+        return Err(
+            "Failed to update contour image sequence: more than one SopInstanceUids entry found"
+                .to_string(),
+        );
+    };
+    let mut replaced_rois = Vec::new();
+    with_item(&mut ds, tags::REFERENCED_FRAME_OF_REFERENCE_SEQUENCE, 0, |frame| {
+        frame.put_str(tags::FRAME_OF_REFERENCE_UID, VR::UI, &frame_of_reference_uid);
+        with_item(frame, tags::RT_REFERENCED_STUDY_SEQUENCE, 0, |study| {
+            study.put_str(tags::REFERENCED_SOP_INSTANCE_UID, VR::UI, &entry.study_instance_uid);
+            with_item(study, tags::RT_REFERENCED_SERIES_SEQUENCE, 0, |rt_series| {
+                rt_series.put_str(tags::SERIES_INSTANCE_UID, VR::UI, generate_uid());
+                update_contour_images(rt_series, &series_info.sop_instance_infos, &mut replaced_rois)
+            })
+        })
+    })?;
+
+    // 模板自带影像 UID 对应的 ROI 轮廓也要指向新影像
+    let roi_count = sequence_len(&ds, tags::ROI_CONTOUR_SEQUENCE);
+    for (roi_index, sop_instance_uid) in replaced_rois {
+        if roi_index >= roi_count {
+            return Err(format!(
+                "Failed to update contour image sequence: ROIContourSequence does not have enough elements for index {}",
+                roi_index
+            ));
+        }
+        with_item(&mut ds, tags::ROI_CONTOUR_SEQUENCE, roi_index, |roi| {
+            with_item(roi, tags::CONTOUR_SEQUENCE, 0, |contour| {
+                with_item(contour, tags::CONTOUR_IMAGE_SEQUENCE, 0, |image| {
+                    image.put_str(tags::REFERENCED_SOP_INSTANCE_UID, VR::UI, &sop_instance_uid);
+                    Ok(())
+                })
+            })
+        })?;
+    }
+    Ok(ds)
+}
+
+// 按顺序替换 ContourImageSequence 中引用的影像，记录需要同步修改的 ROI
+fn update_contour_images(
+    rt_series: &mut InMemDicomObject,
+    infos: &[SopInstanceInfo],
+    replaced_rois: &mut Vec<(usize, String)>,
+) -> Result<(), String> {
+    if sequence_len(rt_series, tags::CONTOUR_IMAGE_SEQUENCE) == 0 {
+        return Err("Failed to update contour image sequence: No ContourImageSequence data available.".to_string());
+    }
+    for (index, info) in infos.iter().enumerate() {
+        with_item(rt_series, tags::CONTOUR_IMAGE_SEQUENCE, index, |image| {
+            let old_uid = get_str(image, tags::REFERENCED_SOP_INSTANCE_UID);
+            if let Some(roi_index) = RT_TEMPLATE_IMAGE_UIDS.iter().position(|uid| *uid == old_uid) {
+                replaced_rois.push((roi_index, info.sop_instance_uid.clone()));
+            }
+            image.put_str(tags::REFERENCED_SOP_INSTANCE_UID, VR::UI, &info.sop_instance_uid);
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+/// Rewrites a standalone file for the patient of a headless C-STORE.
+///
+/// With `study_instance_uid` set the file is moved to that study under a new series,
+/// and a referenced RT series is pointed at `referenced_series_uid`. Returns the
+/// object and the series UID to report back.
+pub fn prepare_headless(
+    path: &Path,
+    patient: &PatientEntry,
+    study_instance_uid: Option<&str>,
+    referenced_series_uid: Option<&str>,
+) -> Result<(DefaultDicomObject, Option<String>), String> {
+    let mut ds = read_dicom_file(path)?;
+    put_instance_header(&mut ds, &generate_uid());
+    put_patient(
+        &mut ds,
+        &patient.patient_name,
+        &patient.patient_id,
+        &patient.patient_birth_date,
+        &patient.patient_sex,
+    );
+
+    let series_uid = match study_instance_uid {
+        Some(study_instance_uid) => {
+            ds.put_str(tags::STUDY_INSTANCE_UID, VR::UI, study_instance_uid);
+            ds.put_str(tags::SERIES_INSTANCE_UID, VR::UI, generate_uid());
+            if ds.get(tags::REFERENCED_FRAME_OF_REFERENCE_SEQUENCE).is_some() {
+                let referenced = referenced_series_uid.unwrap_or_default();
+                with_item(&mut ds, tags::REFERENCED_FRAME_OF_REFERENCE_SEQUENCE, 0, |frame| {
+                    with_item(frame, tags::RT_REFERENCED_STUDY_SEQUENCE, 0, |study| {
+                        with_item(study, tags::RT_REFERENCED_SERIES_SEQUENCE, 0, |rt_series| {
+                            rt_series.put_str(tags::SERIES_INSTANCE_UID, VR::UI, referenced);
+                            Ok(())
+                        })
+                    })
+                })?;
+            }
+            referenced_series_uid.map(str::to_string)
+        }
+        None => Some(get_str(&ds, tags::SERIES_INSTANCE_UID)),
+    };
+
+    if let Some(description) = patient.description.as_deref().filter(|d| !d.is_empty()) {
+        ds.put_str(tags::SERIES_DESCRIPTION, VR::LO, description);
+    }
+    Ok((ds, series_uid))
+}
@@ -0,0 +1,482 @@
+// fhir_client.rs
+// FHIR R4 客户端：由 PatientEntry / MppsEntry 生成 Patient、ServiceRequest、ImagingStudy 并提交到 FHIR 服务器
+use crate::dicom_utils::parse_sop_instance_uids;
+use crate::models::{FhirPublishLog, FhirSettingEntry, MppsEntry, MppsStatus, PatientEntry};
+use chrono::Local;
+use reqwest::header::{HeaderMap, ACCEPT, AUTHORIZATION, CONTENT_TYPE, LOCATION};
+use reqwest::{Client, RequestBuilder, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_FHIR_TIMEOUT: Duration = Duration::from_secs(30);
+
+const FHIR_JSON: &str = "application/fhir+json";
+const DCM_SYSTEM: &str = "http://dicom.nema.org/resources/ontology/DCM";
+const IDENTIFIER_TYPE_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/v2-0203";
+
+#[derive(Debug)]
+pub struct FhirClientError {
+    details: String,
+}
+
+impl FhirClientError {
+    pub fn new(msg: &str) -> FhirClientError {
+        FhirClientError {
+            details: msg.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for FhirClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for FhirClientError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+/// What the server answered to a create.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirResponse {
+    pub resource_type: String,
+    pub status: u16,              // 201 新建，200 表示条件创建命中已有资源
+    pub id: Option<String>,       // 服务器分配的资源 id
+    pub location: Option<String>, // Location 响应头
+    pub resource: Value,          // 提交的资源
+    pub body: Value,              // 响应体，可能为空
+}
+
+impl FhirResponse {
+    /// A relative reference such as `Patient/123`.
+    pub fn reference(&self) -> Option<String> {
+        self.id
+            .as_ref()
+            .map(|id| format!("{}/{}", self.resource_type, id))
+    }
+}
+
+/// The patient fields shared by `PatientEntry` and `MppsEntry`.
+pub struct FhirPatient<'a> {
+    pub name: &'a str, // DICOM PN，姓^名^中间名
+    pub id: &'a str,
+    pub birth_date: &'a str, // YYYYMMDD
+    pub sex: &'a str,        // M / F / O
+}
+
+impl<'a> From<&'a PatientEntry> for FhirPatient<'a> {
+    fn from(entry: &'a PatientEntry) -> Self {
+        FhirPatient {
+            name: &entry.patient_name,
+            id: &entry.patient_id,
+            birth_date: &entry.patient_birth_date,
+            sex: &entry.patient_sex,
+        }
+    }
+}
+
+impl<'a> From<&'a MppsEntry> for FhirPatient<'a> {
+    fn from(entry: &'a MppsEntry) -> Self {
+        FhirPatient {
+            name: &entry.patient_name,
+            id: &entry.patient_id,
+            birth_date: &entry.patient_birth_date,
+            sex: &entry.patient_sex,
+        }
+    }
+}
+
+// YYYYMMDD[HHMMSS] 转为 FHIR date / dateTime，格式不符时忽略
+fn fhir_date(value: &str) -> Option<String> {
+    let value = value.trim();
+    if value.len() < 8
+        || !value.is_char_boundary(8)
+        || !value[..8].chars().all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    Some(format!("{}-{}-{}", &value[..4], &value[4..6], &value[6..8]))
+}
+
+fn gender(sex: &str) -> &'static str {
+    match sex.trim().to_uppercase().as_str() {
+        "M" => "male",
+        "F" => "female",
+        "O" => "other",
+        _ => "unknown",
+    }
+}
+
+fn human_name(name: &str) -> Value {
+    let mut parts = name.split('^').map(str::trim);
+    let family = parts.next().unwrap_or_default();
+    let given: Vec<&str> = parts.take(2).filter(|p| !p.is_empty()).collect();
+    let mut value = json!({ "family": family });
+    if !given.is_empty() {
+        value["given"] = json!(given);
+    }
+    value
+}
+
+fn uid_value(uid: &str) -> String {
+    format!("urn:oid:{}", uid.trim())
+}
+
+/// Builds FHIR R4 resources and creates them on one server.
+pub struct FhirClient {
+    base_url: String,
+    auth_token: Option<String>,
+    identifier_system: Option<String>,
+    http: Client,
+}
+
+impl FhirClient {
+    pub fn from_setting(setting: &FhirSettingEntry) -> Result<FhirClient, FhirClientError> {
+        let base_url = setting.base_url.trim().trim_end_matches('/').to_string();
+        if !base_url.starts_with("http://") && !base_url.starts_with("https://") {
+            return Err(FhirClientError::new(&format!(
+                "Invalid FHIR base URL: {}",
+                setting.base_url
+            )));
+        }
+        let timeout = setting
+            .timeout
+            .filter(|s| *s > 0)
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_FHIR_TIMEOUT);
+        let http = Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| FhirClientError::new(&format!("Failed to build HTTP client: {}", e)))?;
+        let non_empty = |value: &Option<String>| {
+            value
+                .as_deref()
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(str::to_string)
+        };
+        Ok(FhirClient {
+            base_url,
+            auth_token: non_empty(&setting.auth_token),
+            identifier_system: non_empty(&setting.identifier_system),
+            http,
+        })
+    }
+
+    fn request(&self, builder: RequestBuilder) -> RequestBuilder {
+        let builder = builder.header(ACCEPT, FHIR_JSON);
+        match &self.auth_token {
+            Some(token) => builder.header(AUTHORIZATION, format!("Bearer {}", token)),
+            None => builder,
+        }
+    }
+
+    fn identifier(&self, value: &str, type_code: Option<&str>) -> Value {
+        let mut identifier = json!({ "value": value.trim() });
+        if let Some(system) = &self.identifier_system {
+            identifier["system"] = json!(system);
+        }
+        if let Some(code) = type_code {
+            identifier["type"] = json!({
+                "coding": [{ "system": IDENTIFIER_TYPE_SYSTEM, "code": code }]
+            });
+        }
+        identifier
+    }
+
+    // If-None-Exist 的查询条件，配置了 system 时写作 system|value
+    fn identifier_query(&self, value: &str) -> String {
+        match &self.identifier_system {
+            Some(system) => format!("identifier={}|{}", system, value.trim()),
+            None => format!("identifier={}", value.trim()),
+        }
+    }
+
+    pub fn patient(&self, patient: &FhirPatient) -> Value {
+        let mut resource = json!({
+            "resourceType": "Patient",
+            "identifier": [self.identifier(patient.id, Some("MR"))],
+            "name": [human_name(patient.name)],
+            "gender": gender(patient.sex),
+        });
+        if let Some(birth_date) = fhir_date(patient.birth_date) {
+            resource["birthDate"] = json!(birth_date);
+        }
+        resource
+    }
+
+    pub fn service_request(&self, entry: &MppsEntry, subject: &str) -> Value {
+        let mut resource = json!({
+            "resourceType": "ServiceRequest",
+            "identifier": [self.identifier(&entry.accession_number, Some("ACSN"))],
+            // 检查步骤完成或中止时，申请随之完成或撤销
+            "status": match entry.status {
+                Some(MppsStatus::Completed) => "completed",
+                Some(MppsStatus::Discontinued) => "revoked",
+                _ => "active",
+            },
+            "intent": "order",
+            "category": [{
+                "coding": [{
+                    "system": "http://snomed.info/sct",
+                    "code": "363679005",
+                    "display": "Imaging"
+                }]
+            }],
+            "code": { "text": entry.requested_procedure_description },
+            "subject": { "reference": subject },
+        });
+        if let Some(date) = fhir_date(&entry.scheduled_procedure_step_start_date) {
+            resource["occurrenceDateTime"] = json!(date);
+        }
+        if !entry.scheduled_performing_physician_name.is_empty() {
+            resource["performer"] = json!([{
+                "display": entry.scheduled_performing_physician_name.replace('^', " ")
+            }]);
+        }
+        resource
+    }
+
+    /// The ImagingStudy of the instances listed in `SopInstanceUids`.
+    pub fn imaging_study(&self, entry: &MppsEntry, subject: &str) -> Result<Value, String> {
+        let series = parse_sop_instance_uids(entry)?;
+        let modality = json!({ "system": DCM_SYSTEM, "code": entry.modality });
+        let instances: usize = series.iter().map(|s| s.sop_instance_infos.len()).sum();
+        let mut resource = json!({
+            "resourceType": "ImagingStudy",
+            "identifier": [
+                { "system": "urn:dicom:uid", "value": uid_value(&entry.study_instance_uid) },
+                self.identifier(&entry.accession_number, Some("ACSN")),
+            ],
+            "status": "available",
+            "subject": { "reference": subject },
+            "basedOn": [{
+                "type": "ServiceRequest",
+                "identifier": self.identifier(&entry.accession_number, Some("ACSN")),
+            }],
+            "modality": [modality.clone()],
+            "numberOfSeries": series.len(),
+            "numberOfInstances": instances,
+            "series": series.iter().enumerate().map(|(index, s)| json!({
+                "uid": s.series_instance_uid,
+                "number": index + 1,
+                "modality": modality,
+                "numberOfInstances": s.sop_instance_infos.len(),
+                "instance": s.sop_instance_infos.iter().enumerate().map(|(number, instance)| json!({
+                    "uid": instance.sop_instance_uid,
+                    "sopClass": {
+                        "system": "urn:ietf:rfc:3986",
+                        "code": uid_value(&instance.sop_class_uid),
+                    },
+                    "number": number + 1,
+                })).collect::<Vec<Value>>(),
+            })).collect::<Vec<Value>>(),
+        });
+        if !entry.requested_procedure_description.is_empty() {
+            resource["description"] = json!(entry.requested_procedure_description);
+        }
+        // 开始时间取 IN PROGRESS 的时间
+        if let Some(started) = entry
+            .status_history
+            .iter()
+            .find(|t| t.to == MppsStatus::InProgress)
+        {
+            resource["started"] = json!(started.timestamp);
+        }
+        Ok(resource)
+    }
+
+    /// Fetches the CapabilityStatement. Returns the round trip and the FHIR
+    /// version and software the server reports.
+    pub async fn metadata(&self) -> Result<(Duration, String), FhirClientError> {
+        let started = Instant::now();
+        let response = self
+            .request(self.http.get(format!("{}/metadata", self.base_url)))
+            .send()
+            .await
+            .map_err(|e| FhirClientError::new(&format!("Request failed: {}", e)))?;
+        let status = response.status();
+        let body = json_body(response).await;
+        if !status.is_success() || body["resourceType"] != "CapabilityStatement" {
+            return Err(FhirClientError::new(&format!(
+                "No CapabilityStatement returned (HTTP {})",
+                status.as_u16()
+            )));
+        }
+        let software = [&body["software"]["name"], &body["software"]["version"]]
+            .iter()
+            .filter_map(|v| v.as_str())
+            .collect::<Vec<&str>>()
+            .join(" ");
+        let version = body["fhirVersion"].as_str().unwrap_or("unknown version");
+        Ok((
+            started.elapsed(),
+            format!("FHIR {} {}", version, software).trim().to_string(),
+        ))
+    }
+
+    /// POSTs `resource` to its type endpoint. With `if_none_exist` the server
+    /// returns the matching resource instead of creating a second one.
+    pub async fn create(
+        &self,
+        resource: Value,
+        if_none_exist: Option<String>,
+    ) -> Result<FhirResponse, FhirClientError> {
+        let resource_type = resource["resourceType"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        let mut request = self
+            .request(
+                self.http
+                    .post(format!("{}/{}", self.base_url, resource_type)),
+            )
+            .header(CONTENT_TYPE, FHIR_JSON)
+            .body(resource.to_string());
+        if let Some(query) = if_none_exist {
+            request = request.header("If-None-Exist", query);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| FhirClientError::new(&format!("Request failed: {}", e)))?;
+        let status = response.status();
+        let location = location(response.headers());
+        let body = json_body(response).await;
+        if !status.is_success() {
+            return Err(FhirClientError::new(&format!(
+                "{} rejected (HTTP {}): {}",
+                resource_type,
+                status.as_u16(),
+                outcome(&body)
+            )));
+        }
+        // id 优先取响应体，否则从 Location 的 {type}/{id}/_history/{vid} 中取
+        let id = body["id"].as_str().map(str::to_string).or_else(|| {
+            let location = location.as_deref()?;
+            let mut parts = location.split('/').skip_while(|p| *p != resource_type);
+            parts.next()?;
+            parts.next().map(str::to_string)
+        });
+        Ok(FhirResponse {
+            resource_type,
+            status: status.as_u16(),
+            id,
+            location,
+            resource,
+            body,
+        })
+    }
+
+    pub async fn create_patient(
+        &self,
+        patient: &FhirPatient<'_>,
+    ) -> Result<FhirResponse, FhirClientError> {
+        if patient.id.trim().is_empty() {
+            return Err(FhirClientError::new("The patient has no patient ID"));
+        }
+        let query = self.identifier_query(patient.id);
+        self.create(self.patient(patient), Some(query)).await
+    }
+
+    // ServiceRequest 和 ImagingStudy 引用的患者，按患者 ID 条件创建
+    async fn subject(&self, entry: &MppsEntry) -> Result<String, FhirClientError> {
+        self.create_patient(&FhirPatient::from(entry))
+            .await?
+            .reference()
+            .ok_or_else(|| FhirClientError::new("The server returned no Patient id"))
+    }
+
+    pub async fn create_service_request(
+        &self,
+        entry: &MppsEntry,
+    ) -> Result<FhirResponse, FhirClientError> {
+        let subject = self.subject(entry).await?;
+        let query = self.identifier_query(&entry.accession_number);
+        self.create(self.service_request(entry, &subject), Some(query))
+            .await
+    }
+
+    pub async fn create_imaging_study(
+        &self,
+        entry: &MppsEntry,
+    ) -> Result<FhirResponse, FhirClientError> {
+        // 没有实例时不创建患者
+        parse_sop_instance_uids(entry).map_err(|e| FhirClientError::new(&e))?;
+        let subject = self.subject(entry).await?;
+        let resource = self
+            .imaging_study(entry, &subject)
+            .map_err(|e| FhirClientError::new(&e))?;
+        let query = format!(
+            "identifier=urn:dicom:uid|{}",
+            uid_value(&entry.study_instance_uid)
+        );
+        self.create(resource, Some(query)).await
+    }
+}
+
+// 响应体为空或不是 JSON 时为 Null
+async fn json_body(response: Response) -> Value {
+    response
+        .text()
+        .await
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or(Value::Null)
+}
+
+fn location(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(LOCATION)
+        .or_else(|| headers.get("Content-Location"))
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+// OperationOutcome 中的诊断信息
+fn outcome(body: &Value) -> String {
+    let issues: Vec<String> = body["issue"]
+        .as_array()
+        .map(|issues| {
+            issues
+                .iter()
+                .map(|issue| {
+                    issue["diagnostics"]
+                        .as_str()
+                        .or_else(|| issue["details"]["text"].as_str())
+                        .or_else(|| issue["code"].as_str())
+                        .unwrap_or_default()
+                        .to_string()
+                })
+                .filter(|d| !d.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+    if issues.is_empty() {
+        "no OperationOutcome".to_string()
+    } else {
+        issues.join("; ")
+    }
+}
+
+/// The log entry kept on the MPPS entry for one create.
+pub fn publish_log(
+    resource_type: &str,
+    setting: &FhirSettingEntry,
+    result: &Result<FhirResponse, FhirClientError>,
+) -> FhirPublishLog {
+    FhirPublishLog {
+        resource_type: resource_type.to_string(),
+        destination: setting.name.clone(),
+        sent_at: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        status: result.as_ref().ok().map(|r| r.status),
+        reference: result.as_ref().ok().and_then(|r| r.reference()),
+        error: result.as_ref().err().map(|e| e.to_string()),
+    }
+}
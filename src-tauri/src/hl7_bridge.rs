@@ -0,0 +1,419 @@
+// hl7_bridge.rs
+// 将 ORM^O01 / OMI^O23 订单转换为 mpps 表中尚未开始的检查步骤，由内置 Worklist SCP 提供查询
+use crate::hl7_client::{component, normalize_segments, parse_message};
+use crate::models::{Hl7BridgeResult, MppsEntry};
+use crate::utils::generate_uid;
+use chrono::Local;
+use rusthl7::segments::Segment;
+use surrealdb::engine::local::Db;
+use surrealdb::Surreal;
+
+pub const HL7_BRIDGE_EVENT: &str = "hl7-worklist-bridge";
+
+/// One order of a message: its ORC-1 order control code and the step it schedules.
+pub struct Hl7Order {
+    pub order_control: String, // NW / XO / CA / DC ...
+    pub entry: MppsEntry,
+}
+
+// 一个 ORC 及其后的 OBR / TQ1 / ZDS / IPC，PID 和 PV1 取订单之前最近的一个
+#[derive(Default)]
+struct OrderSegments<'s, 'a> {
+    pid: Option<&'s Segment<'a>>,
+    pv1: Option<&'s Segment<'a>>,
+    orc: Option<&'s Segment<'a>>,
+    obr: Option<&'s Segment<'a>>,
+    tq1: Option<&'s Segment<'a>>,
+    zds: Option<&'s Segment<'a>>,
+    ipc: Option<&'s Segment<'a>>,
+}
+
+/// Whether MSH-9 names an order message the bridge understands.
+pub fn is_order_message(message_type: &str) -> bool {
+    let code = message_type.split('^').next().unwrap_or_default();
+    code.eq_ignore_ascii_case("ORM") || code.eq_ignore_ascii_case("OMI")
+}
+
+/// Maps every ORC/OBR group of an ORM^O01 or OMI^O23 message to a scheduled
+/// step. The Study Instance UID is left empty unless ZDS or IPC carries one.
+pub fn parse_orders(message: &str) -> Result<Vec<Hl7Order>, String> {
+    let source = normalize_segments(message);
+    let message =
+        parse_message(&source).map_err(|e| format!("Failed to parse HL7 message: {}", e))?;
+    // MSH 的字段号比下标多 1
+    let message_type = format!(
+        "{}^{}",
+        component(&message.segments[0], 8, 0, 0),
+        component(&message.segments[0], 8, 1, 0)
+    );
+    if !is_order_message(&message_type) {
+        return Err(format!(
+            "{} is not an ORM^O01 or OMI^O23 message",
+            message_type
+        ));
+    }
+
+    let mut pid = None;
+    let mut pv1 = None;
+    let mut groups: Vec<OrderSegments> = Vec::new();
+    for segment in &message.segments[1..] {
+        let name = segment.fields[0].source;
+        match name {
+            "PID" => pid = Some(segment),
+            "PV1" => pv1 = Some(segment),
+            "ORC" => groups.push(OrderSegments {
+                pid,
+                pv1,
+                orc: Some(segment),
+                ..Default::default()
+            }),
+            "OBR" | "TQ1" | "ZDS" | "IPC" => {
+                // 没有 ORC 的 OBR 也算作一个订单
+                let starts_order = match groups.last() {
+                    None => true,
+                    Some(group) => name == "OBR" && group.obr.is_some(),
+                };
+                if starts_order {
+                    groups.push(OrderSegments {
+                        pid,
+                        pv1,
+                        ..Default::default()
+                    });
+                }
+                let Some(group) = groups.last_mut() else {
+                    continue;
+                };
+                let slot = match name {
+                    "OBR" => &mut group.obr,
+                    "TQ1" => &mut group.tq1,
+                    "ZDS" => &mut group.zds,
+                    _ => &mut group.ipc,
+                };
+                // OMI 中一个订单可带多个 IPC，只使用第一个
+                slot.get_or_insert(segment);
+            }
+            _ => {}
+        }
+    }
+    if groups.is_empty() {
+        return Err("The message contains no ORC or OBR segment".to_string());
+    }
+    groups.iter().map(order).collect()
+}
+
+fn order(group: &OrderSegments) -> Result<Hl7Order, String> {
+    let field = |segment: Option<&Segment>, field: usize, comp: usize| -> String {
+        segment
+            .map(|s| component(s, field, comp, 0).trim().to_string())
+            .unwrap_or_default()
+    };
+    let first = |values: Vec<String>| values.into_iter().find(|v| !v.is_empty());
+
+    let accession_number = first(vec![
+        field(group.ipc, 1, 0),
+        field(group.obr, 18, 0),
+        field(group.orc, 3, 0),
+        field(group.obr, 3, 0),
+        field(group.orc, 2, 0),
+        field(group.obr, 2, 0),
+    ])
+    .ok_or("The order has no accession or order number")?;
+
+    // 开始时间依次取 TQ1-7、OBR-27.4、ORC-7.4、OBR-36、OBR-7
+    let start_date = first(vec![
+        field(group.tq1, 7, 0),
+        field(group.obr, 27, 3),
+        field(group.orc, 7, 3),
+        field(group.obr, 36, 0),
+        field(group.obr, 7, 0),
+    ])
+    .map(|dt| dt.chars().take(8).collect())
+    .unwrap_or_else(|| Local::now().format("%Y%m%d").to_string());
+
+    let entry = MppsEntry {
+        id: None,
+        accession_number,
+        requested_procedure_description: first(vec![
+            field(group.obr, 4, 1),
+            field(group.obr, 4, 0),
+        ])
+        .unwrap_or_default(),
+        // XPN 与 DICOM PN 同样以 ^ 分隔姓名各部分
+        patient_name: group
+            .pid
+            .and_then(|pid| pid.fields.get(5))
+            .and_then(|f| f.repeats.first())
+            .map(|name| name.trim().to_string())
+            .unwrap_or_default(),
+        patient_id: field(group.pid, 3, 0),
+        patient_birth_date: field(group.pid, 7, 0).chars().take(8).collect(),
+        patient_sex: field(group.pid, 8, 0),
+        modality: first(vec![field(group.ipc, 5, 0), field(group.obr, 24, 0)])
+            .unwrap_or_else(|| "OT".to_string()),
+        scheduled_station_ae_title: field(group.ipc, 9, 0),
+        scheduled_procedure_step_start_date: start_date,
+        // 技师 OBR-34 或主治医生 PV1-7，格式为 ID^姓^名
+        scheduled_performing_physician_name: [(group.obr, 34), (group.pv1, 7)]
+            .into_iter()
+            .map(|(segment, f)| person_name(&field(segment, f, 1), &field(segment, f, 2)))
+            .find(|name| !name.is_empty())
+            .unwrap_or_default(),
+        study_instance_uid: first(vec![field(group.zds, 1, 0), field(group.ipc, 3, 0)])
+            .unwrap_or_default(),
+        status: None,
+        mpps_instance_uid: None,
+        sop_instance_uids: None,
+        dcm_file: None,
+        description: None,
+        status_history: Vec::new(),
+        storage_commitment: None,
+        hl7_report: None,
+        fhir_publish: Vec::new(),
+    };
+    Ok(Hl7Order {
+        order_control: first(vec![field(group.orc, 1, 0)])
+            .unwrap_or_else(|| "NW".to_string())
+            .to_uppercase(),
+        entry,
+    })
+}
+
+fn person_name(family: &str, given: &str) -> String {
+    match (family.is_empty(), given.is_empty()) {
+        (true, true) => String::new(),
+        (_, true) => family.to_string(),
+        _ => format!("{}^{}", family, given),
+    }
+}
+
+/// Stores the orders in the `mpps` table. New and changed orders replace the
+/// step with the same accession number while it has not started; cancelled
+/// orders remove it.
+pub async fn apply_orders(
+    db: &Surreal<Db>,
+    orders: Vec<Hl7Order>,
+) -> Result<Hl7BridgeResult, String> {
+    let mut result = Hl7BridgeResult::default();
+    for Hl7Order {
+        order_control,
+        mut entry,
+    } in orders
+    {
+        let mut response = db
+            .query("SELECT * FROM mpps WHERE AccessionNumber = $accession_number")
+            .bind(("accession_number", entry.accession_number.clone()))
+            .await
+            .map_err(|e| format!("Failed to read mpps entries: {}", e))?;
+        let existing: Vec<MppsEntry> = response
+            .take(0)
+            .map_err(|e| format!("Failed to read mpps entries: {}", e))?;
+        // 已经开始的检查步骤不再修改
+        if existing.iter().any(|e| e.status.is_some()) {
+            result.skipped.push(entry.accession_number);
+            continue;
+        }
+        let scheduled = existing.into_iter().next();
+
+        if matches!(order_control.as_str(), "CA" | "DC" | "OC" | "OD") {
+            if let Some(id) = scheduled.and_then(|e| e.id) {
+                let deleted: Option<MppsEntry> = db
+                    .delete((id.tb.as_str(), id.id.to_raw()))
+                    .await
+                    .map_err(|e| format!("Failed to delete mpps entry: {}", e))?;
+                result.canceled.extend(deleted);
+            }
+            continue;
+        }
+
+        if entry.study_instance_uid.is_empty() {
+            entry.study_instance_uid = scheduled
+                .as_ref()
+                .map(|e| e.study_instance_uid.clone())
+                .unwrap_or_else(generate_uid);
+        }
+        let stored: Option<MppsEntry> = match scheduled.and_then(|e| e.id) {
+            Some(id) => db
+                .update((id.tb.as_str(), id.id.to_raw()))
+                .content(entry)
+                .await
+                .map_err(|e| format!("Failed to update mpps entry: {}", e))?,
+            None => {
+                let created: Vec<MppsEntry> = db
+                    .create("mpps")
+                    .content(entry)
+                    .await
+                    .map_err(|e| format!("Failed to create mpps entry: {}", e))?;
+                created.into_iter().next()
+            }
+        };
+        result.scheduled.extend(stored);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 按字段号生成一个段，未给出的字段留空
+    fn segment(name: &str, fields: &[(usize, &str)]) -> String {
+        let count = fields.iter().map(|(n, _)| *n).max().unwrap_or(0);
+        let mut values = vec![""; count + 1];
+        values[0] = name;
+        for (n, value) in fields {
+            values[*n] = value;
+        }
+        values.join("|")
+    }
+
+    fn message(message_type: &str, segments: &[String]) -> String {
+        let msh = format!(
+            "MSH|^~\\&|RIS|HOSP|SIM|DICOM|20240101120000||{}|MSG0001|P|2.5",
+            message_type
+        );
+        std::iter::once(msh)
+            .chain(segments.iter().cloned())
+            .collect::<Vec<_>>()
+            .join("\r")
+    }
+
+    #[test]
+    fn maps_each_orc_group_of_an_orm() {
+        let message = message(
+            "ORM^O01",
+            &[
+                segment(
+                    "PID",
+                    &[
+                        (1, "1"),
+                        (3, "P1001^^^HOSP"),
+                        (5, "Doe^John^A"),
+                        (7, "19800101"),
+                        (8, "M"),
+                    ],
+                ),
+                segment("PV1", &[(1, "1"), (2, "O"), (7, "D1^House^Greg")]),
+                segment("ORC", &[(1, "NW"), (2, "ORD1"), (3, "ACC1")]),
+                segment(
+                    "OBR",
+                    &[
+                        (1, "1"),
+                        (2, "ORD1"),
+                        (4, "CTCHEST^CT Chest"),
+                        (24, "CT"),
+                        (34, "T1^Smith^Anna"),
+                    ],
+                ),
+                segment("TQ1", &[(1, "1"), (7, "202401021030")]),
+                segment("NTE", &[(1, "1"), (3, "Contrast allergy")]),
+                segment("ORC", &[(1, "ca"), (2, "ORD2"), (3, "ACC2")]),
+                segment(
+                    "OBR",
+                    &[
+                        (1, "2"),
+                        (2, "ORD2"),
+                        (4, "MRHEAD^MR Head"),
+                        (24, "MR"),
+                        (36, "20240105"),
+                    ],
+                ),
+                segment("ZDS", &[(1, "1.2.840.99.1^100^Application^DICOM")]),
+            ],
+        );
+        let orders = parse_orders(&message).unwrap();
+        assert_eq!(orders.len(), 2);
+
+        let (first, second) = (&orders[0], &orders[1]);
+        assert_eq!(first.order_control, "NW");
+        assert_eq!(first.entry.accession_number, "ACC1");
+        assert_eq!(first.entry.requested_procedure_description, "CT Chest");
+        assert_eq!(first.entry.patient_name, "Doe^John^A");
+        assert_eq!(first.entry.patient_id, "P1001");
+        assert_eq!(first.entry.patient_birth_date, "19800101");
+        assert_eq!(first.entry.patient_sex, "M");
+        assert_eq!(first.entry.modality, "CT");
+        assert_eq!(first.entry.scheduled_procedure_step_start_date, "20240102");
+        assert_eq!(
+            first.entry.scheduled_performing_physician_name,
+            "Smith^Anna"
+        );
+        assert_eq!(first.entry.study_instance_uid, "");
+        assert!(first.entry.status.is_none());
+
+        assert_eq!(second.order_control, "CA");
+        assert_eq!(second.entry.accession_number, "ACC2");
+        assert_eq!(second.entry.requested_procedure_description, "MR Head");
+        assert_eq!(second.entry.patient_id, "P1001");
+        assert_eq!(second.entry.modality, "MR");
+        assert_eq!(second.entry.scheduled_procedure_step_start_date, "20240105");
+        assert_eq!(
+            second.entry.scheduled_performing_physician_name,
+            "House^Greg"
+        );
+        assert_eq!(second.entry.study_instance_uid, "1.2.840.99.1");
+    }
+
+    #[test]
+    fn uses_the_first_ipc_of_an_omi() {
+        let message = message(
+            "OMI^O23^OMI_O23",
+            &[
+                segment("PID", &[(3, "P2002"), (5, "Roe^Jane")]),
+                segment("ORC", &[(1, "XO"), (2, "ORD3")]),
+                segment("OBR", &[(2, "ORD3"), (4, "USABD^US Abdomen"), (24, "RAD")]),
+                segment(
+                    "IPC",
+                    &[
+                        (1, "ACC3"),
+                        (2, "RP3"),
+                        (3, "1.2.840.99.3"),
+                        (4, "SPS3"),
+                        (5, "US"),
+                        (9, "US_ROOM1"),
+                    ],
+                ),
+                segment("IPC", &[(1, "ACC4"), (3, "1.2.840.99.4"), (5, "CT")]),
+            ],
+        );
+        let orders = parse_orders(&message).unwrap();
+        assert_eq!(orders.len(), 1);
+        let entry = &orders[0].entry;
+        assert_eq!(orders[0].order_control, "XO");
+        assert_eq!(entry.accession_number, "ACC3");
+        assert_eq!(entry.study_instance_uid, "1.2.840.99.3");
+        assert_eq!(entry.modality, "US");
+        assert_eq!(entry.scheduled_station_ae_title, "US_ROOM1");
+    }
+
+    #[test]
+    fn treats_an_obr_without_orc_as_a_new_order() {
+        let message = message(
+            "ORM^O01",
+            &[
+                segment("PID", &[(3, "P3003")]),
+                segment("OBR", &[(1, "1"), (3, "ACC5"), (4, "XRCHEST^Chest X-Ray")]),
+            ],
+        );
+        let orders = parse_orders(&message).unwrap();
+        assert_eq!(orders.len(), 1);
+        assert_eq!(orders[0].order_control, "NW");
+        assert_eq!(orders[0].entry.accession_number, "ACC5");
+        assert_eq!(orders[0].entry.modality, "OT");
+    }
+
+    #[test]
+    fn rejects_messages_that_are_not_orders() {
+        let adt = message("ADT^A01", &[segment("PID", &[(3, "P1001")])]);
+        assert_eq!(
+            parse_orders(&adt).err().unwrap(),
+            "ADT^A01 is not an ORM^O01 or OMI^O23 message"
+        );
+        let empty = message("ORM^O01", &[segment("PID", &[(3, "P1001")])]);
+        assert_eq!(
+            parse_orders(&empty).err().unwrap(),
+            "The message contains no ORC or OBR segment"
+        );
+        let no_accession = message("ORM^O01", &[segment("ORC", &[(1, "NW")])]);
+        assert!(parse_orders(&no_accession).is_err());
+    }
+}
@@ -1,14 +1,40 @@
+use crate::dicom_client::{IgnoreHostnameVerifier, TlsDetails};
+use crate::models::Hl7SettingEntry;
 use chrono::Local;
 use reqwest::Client;
 use reqwest::header::{CONTENT_TYPE, CONTENT_LANGUAGE};
 use rusthl7::message::Message;
+use rusthl7::segments::Segment;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::ring::default_provider;
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
 use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
 use std::error::Error;
+use std::ffi::OsStr;
 use std::fmt;
+use std::fs;
 use std::net::SocketAddr;
-use std::time::Duration;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use bytes::BytesMut;
+use futures::{FutureExt, SinkExt, StreamExt};
+use hl7_mllp_codec::MllpCodec;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+use tokio_util::codec::Framed;
+
+/// How long `send_hl7_message_tcp` waits for the ACK when no timeout is configured.
+pub const DEFAULT_ACK_TIMEOUT: Duration = Duration::from_secs(10);
+// 建立连接 (含 TLS 握手) 的超时，避免不响应的地址一直挂起
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -36,6 +62,10 @@ impl Error for Hl7ClientError {
     }
 }
 
+fn tls_error(context: &str, e: impl fmt::Display) -> Hl7ClientError {
+    Hl7ClientError::new(&format!("{}: {}", context, e))
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 // 枚举来定义发送方式
 pub enum SendMethod {
@@ -43,12 +73,590 @@ pub enum SendMethod {
     Http,
 }
 
+/// One ERR segment of an ACK.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hl7AckError {
+    pub location: String,    // 出错位置，如 PID-3
+    pub code: String,        // HL7 错误码 (表 0357)
+    pub description: String, // 错误码说明
+    pub severity: String,    // E / W / I
+    pub message: String,     // 对方给出的诊断信息
+}
+
+/// The ACK returned for a sent message, checked against its control ID.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hl7Ack {
+    pub ack_code: String,    // MSA-1: AA / AE / AR / CA / CE / CR
+    pub accepted: bool,
+    pub control_id: String,  // MSA-2
+    pub control_id_matches: bool,
+    pub text_message: String, // MSA-3
+    pub errors: Vec<Hl7AckError>,
+    pub raw: String,
+}
+
+impl Hl7Ack {
+    /// Parses an ACK/NAK. `sent_control_id` is MSH-10 of the message it answers.
+    pub fn parse(response: &str, sent_control_id: &str) -> Result<Hl7Ack, Hl7ClientError> {
+        let raw = normalize_segments(response);
+        if !raw.starts_with("MSH") {
+            return Err(Hl7ClientError::new("Response is not an HL7 message"));
+        }
+        let message = parse_message(&raw)
+            .map_err(|e| Hl7ClientError::new(&format!("Failed to parse ACK: {}", e)))?;
+        let msa = message
+            .segments
+            .iter()
+            .find(|s| s.fields[0].source == "MSA")
+            .ok_or_else(|| Hl7ClientError::new("ACK has no MSA segment"))?;
+
+        let ack_code = msa[1].trim().to_uppercase();
+        let control_id = msa[2].trim().to_string();
+        let control_id_matches = control_id == sent_control_id;
+        let errors = message
+            .segments
+            .iter()
+            .filter(|s| s.fields[0].source == "ERR")
+            .map(|err| {
+                // v2.5 起使用 ERR-2..ERR-8，v2.3/2.4 只有 ERR-1 (seg^seq^field^code&text)
+                let structured = err[1].is_empty() || !err[2].is_empty() || !err[3].is_empty();
+                let (location, code, description) = if structured {
+                    let location = [0, 1, 2].map(|c| component(err, 2, c, 0));
+                    (
+                        join_location(&location),
+                        component(err, 3, 0, 0),
+                        component(err, 3, 1, 0),
+                    )
+                } else {
+                    let location = [0, 1, 2].map(|c| component(err, 1, c, 0));
+                    (
+                        join_location(&location),
+                        component(err, 1, 3, 0),
+                        component(err, 1, 3, 1),
+                    )
+                };
+                Hl7AckError {
+                    location,
+                    code: code.to_string(),
+                    description: description.to_string(),
+                    severity: err[4].to_string(),
+                    message: err[8].to_string(),
+                }
+            })
+            .collect();
+
+        Ok(Hl7Ack {
+            accepted: matches!(ack_code.as_str(), "AA" | "CA") && control_id_matches,
+            ack_code,
+            control_id,
+            control_id_matches,
+            text_message: msa[3].to_string(),
+            errors,
+            raw: raw.replace('\r', "\n"),
+        })
+    }
+
+    /// One line describing the outcome, shown as the response message.
+    pub fn summary(&self, sent_control_id: &str) -> String {
+        let mut summary = match self.ack_code.as_str() {
+            "AA" | "CA" => format!("{}: Message accepted", self.ack_code),
+            "AE" | "CE" => format!("{}: Application error", self.ack_code),
+            "AR" | "CR" => format!("{}: Message rejected", self.ack_code),
+            code => format!("{}: Unknown acknowledgment code", code),
+        };
+        if !self.control_id_matches {
+            summary.push_str(&format!(
+                "; control ID mismatch (sent {}, ACK references {})",
+                sent_control_id, self.control_id
+            ));
+        }
+        if !self.text_message.is_empty() {
+            summary.push_str(&format!("; {}", self.text_message));
+        }
+        for error in &self.errors {
+            let detail = [&error.location, &error.code, &error.description, &error.message]
+                .iter()
+                .filter(|s| !s.is_empty())
+                .map(|s| s.as_str())
+                .collect::<Vec<_>>()
+                .join(" ");
+            if !detail.is_empty() {
+                summary.push_str(&format!("; {}", detail));
+            }
+        }
+        summary
+    }
+}
+
+/// What a send produced: the connection log and, when the peer answered with
+/// HL7, the parsed ACK.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hl7SendResult {
+    pub log: String,
+    pub control_id: String, // 发送报文的 MSH-10
+    pub ack: Option<Hl7Ack>,
+}
+
+/// The outcome of one message of a batch.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hl7BatchItem {
+    pub index: usize,         // 在文件中的序号，从 0 开始
+    pub message_type: String, // MSH-9
+    pub control_id: String,   // MSH-10
+    pub ack: Option<Hl7Ack>,
+    pub error: Option<String>, // 无法解析、未收到 ACK 或未发送的原因
+    pub elapsed_ms: f64,       // 发送到收到 ACK 的耗时
+}
+
+/// Per-message results and throughput of a batch send.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hl7BatchResult {
+    pub total: usize,
+    pub accepted: usize,
+    pub rejected: usize, // 收到 AE / AR 等非 AA 的 ACK
+    pub failed: usize,   // 未发送或未收到 ACK
+    pub connections: usize,
+    pub elapsed_ms: u64,
+    pub messages_per_second: f64,
+    pub average_ack_ms: f64,
+    pub items: Vec<Hl7BatchItem>,
+}
+
+impl Hl7BatchResult {
+    fn new(items: Vec<Hl7BatchItem>, connections: usize, elapsed: Duration) -> Hl7BatchResult {
+        let accepted = items
+            .iter()
+            .filter(|i| i.ack.as_ref().is_some_and(|a| a.accepted))
+            .count();
+        let acknowledged: Vec<&Hl7BatchItem> = items.iter().filter(|i| i.ack.is_some()).collect();
+        let average_ack_ms = match acknowledged.len() {
+            0 => 0.0,
+            n => acknowledged.iter().map(|i| i.elapsed_ms).sum::<f64>() / n as f64,
+        };
+        let seconds = elapsed.as_secs_f64();
+        Hl7BatchResult {
+            total: items.len(),
+            accepted,
+            rejected: acknowledged.len() - accepted,
+            failed: items.len() - acknowledged.len(),
+            connections,
+            elapsed_ms: elapsed.as_millis() as u64,
+            messages_per_second: if seconds > 0.0 {
+                acknowledged.len() as f64 / seconds
+            } else {
+                0.0
+            },
+            average_ack_ms,
+            items,
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "{} of {} messages accepted, {} rejected, {} failed in {:.1} s ({:.1} msg/s)",
+            self.accepted,
+            self.total,
+            self.rejected,
+            self.failed,
+            self.elapsed_ms as f64 / 1000.0,
+            self.messages_per_second
+        )
+    }
+}
+
+/// Splits the content of an HL7 file into single messages. Every MSH starts
+/// a new message; FHS/BHS/BTS/FTS batch envelopes and MLLP framing bytes are
+/// dropped.
+pub fn split_batch(content: &str) -> Vec<String> {
+    let mut messages = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    for line in content.split(['\r', '\n', '\x0b', '\x1c']) {
+        let segment = line.trim_start();
+        if segment.trim().is_empty() {
+            continue;
+        }
+        match segment.get(..3).unwrap_or_default() {
+            "FHS" | "BHS" | "BTS" | "FTS" => continue,
+            "MSH" => {
+                if !current.is_empty() {
+                    messages.push(current.join("\r"));
+                }
+                current = vec![segment];
+            }
+            // MSH 之前的内容不属于任何报文
+            _ if current.is_empty() => continue,
+            _ => current.push(segment),
+        }
+    }
+    if !current.is_empty() {
+        messages.push(current.join("\r"));
+    }
+    messages
+}
+
+trait MllpStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> MllpStream for T {}
+
+/// An open MLLP connection, plain or TLS. Each message waits for its ACK
+/// before the next one is sent.
+pub struct Hl7Connection {
+    transport: Framed<Box<dyn MllpStream>, MllpCodec>,
+    addr: SocketAddr,
+    ack_timeout: Duration,
+    tls: Option<TlsDetails>,
+}
+
+impl Hl7Connection {
+    /// Sends one message and reads one ACK frame. Returns the control ID of the
+    /// message together with the parsed ACK.
+    pub async fn send(&mut self, message: &str) -> Result<(String, Hl7Ack), Box<dyn Error>> {
+        let hl7_message_formatted = normalize_segments(message);
+        let hl7_message = parse_message(&hl7_message_formatted)?;
+        let control_id = control_id(&hl7_message);
+        let hl7_bytes = hl7_message.to_string().into_bytes();
+        self.transport.send(BytesMut::from(&hl7_bytes[..])).await?;
+
+        // 只读取一个 ACK 帧，不再等待对端关闭连接
+        let frame = match tokio::time::timeout(self.ack_timeout, self.transport.next()).await {
+            Ok(Some(frame)) => frame?,
+            Ok(None) => {
+                return Err(Box::new(Hl7ClientError::new(
+                    "Connection closed before an ACK was received.",
+                )))
+            }
+            Err(_) => {
+                return Err(Box::new(Hl7ClientError::new(&format!(
+                    "No ACK received within {} seconds.",
+                    self.ack_timeout.as_secs_f64()
+                ))))
+            }
+        };
+        let ack = Hl7Ack::parse(&String::from_utf8_lossy(&frame), &control_id)?;
+        Ok((control_id, ack))
+    }
+
+    pub async fn close(mut self) -> Result<(), Box<dyn Error>> {
+        self.transport.close().await?;
+        Ok(())
+    }
+
+    /// Whether the peer has closed the connection, checked without waiting.
+    /// Frames that arrive unasked are discarded.
+    pub fn is_closed(&mut self) -> bool {
+        loop {
+            match self.transport.next().now_or_never() {
+                None => return false,
+                Some(Some(Ok(_))) => continue,
+                Some(Some(Err(_))) | Some(None) => return true,
+            }
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn tls(&self) -> Option<&TlsDetails> {
+        self.tls.as_ref()
+    }
+
+    pub fn set_ack_timeout(&mut self, ack_timeout: Duration) {
+        self.ack_timeout = ack_timeout;
+    }
+}
+
+// rust-hl7 在 MSH 不足 8 个字符 (MSH 加 5 个分隔符) 时会 panic，解析前先检查
+pub(crate) fn parse_message(source: &str) -> Result<Message<'_>, Hl7ClientError> {
+    if !source.starts_with("MSH") || source.chars().take(8).count() < 8 {
+        return Err(Hl7ClientError::new(
+            "The message does not start with a complete MSH segment",
+        ));
+    }
+    Message::try_from(source).map_err(|e| Hl7ClientError::new(&e.to_string()))
+}
+
+// 统一使用 \r 作为段分隔符
+pub(crate) fn normalize_segments(message: &str) -> String {
+    message
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<&str>>()
+        .join("\r")
+}
+
+// 取第一个重复中的组件/子组件，不存在时为空
+pub(crate) fn component<'a>(segment: &Segment<'a>, field: usize, component: usize, sub: usize) -> &'a str {
+    segment
+        .fields
+        .get(field)
+        .and_then(|f| f.subcomponents.first())
+        .and_then(|components| components.get(component))
+        .and_then(|subs| subs.get(sub))
+        .copied()
+        .unwrap_or_default()
+}
+
+fn join_location(parts: &[&str]) -> String {
+    let parts: Vec<&str> = parts.iter().copied().filter(|p| !p.is_empty()).collect();
+    match parts.as_slice() {
+        [] => String::new(),
+        [segment] => segment.to_string(),
+        [segment, rest @ ..] => format!("{}-{}", segment, rest.join(".")),
+    }
+}
+
+// MSH 的字段号比下标多 1，MSH-10 即第 9 个字段
+fn message_type(message: &Message) -> String {
+    message
+        .segments
+        .first()
+        .filter(|s| s.fields[0].source == "MSH")
+        .map(|msh| msh[8].to_string())
+        .unwrap_or_default()
+}
+
+fn control_id(message: &Message) -> String {
+    message
+        .segments
+        .first()
+        .filter(|s| s.fields[0].source == "MSH")
+        .map(|msh| msh[9].to_string())
+        .unwrap_or_default()
+}
+
+// 不含目录部分的文件名 (不接受绝对路径、子目录和 ..)
+fn is_bare_file_name(name: &str) -> bool {
+    Path::new(name).file_name() == Some(OsStr::new(name))
+}
+
+/// TLS options for MLLP/TLS and HTTPS. The files are looked up in the certs
+/// directory, next to the ones DICOM TLS uses.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hl7TlsConfig {
+    pub certs_dir: PathBuf,
+    pub ca_file: String,             // 校验服务端证书的 CA
+    pub cert_file: String,           // 客户端证书，文件不存在时不做客户端认证
+    pub key_file: String,            // 客户端私钥 (PKCS#8 PEM)
+    pub server_name: Option<String>, // SNI，为空时使用服务器地址且不校验主机名
+    pub verify: bool,                // false 时接受任意服务端证书
+}
+
+impl Hl7TlsConfig {
+    /// The TLS options of `entry`, or `None` when TLS is off for it.
+    pub fn from_setting(entry: &Hl7SettingEntry, certs_dir: PathBuf) -> Option<Hl7TlsConfig> {
+        if !entry.tls_enabled.unwrap_or(false) {
+            return None;
+        }
+        // 保存时已校验；旧记录中带路径的名称不使用，避免读取证书目录以外的文件
+        let file = |name: &Option<String>, default: &str| {
+            name.as_deref()
+                .map(str::trim)
+                .filter(|n| !n.is_empty() && is_bare_file_name(n))
+                .unwrap_or(default)
+                .to_string()
+        };
+        Some(Hl7TlsConfig {
+            certs_dir,
+            ca_file: file(&entry.tls_ca_file, "ca.pem"),
+            cert_file: file(&entry.tls_cert_file, "tls.pem"),
+            key_file: file(&entry.tls_key_file, "tls.key"),
+            server_name: entry
+                .tls_server_name
+                .as_deref()
+                .map(str::trim)
+                .filter(|n| !n.is_empty())
+                .map(str::to_string),
+            verify: entry.tls_verify.unwrap_or(true),
+        })
+    }
+
+    /// Rejects CA, certificate and key names that are not a bare file name,
+    /// so a setting cannot point outside the certs directory.
+    pub fn check_file_names(entry: &Hl7SettingEntry) -> Result<(), String> {
+        let files = [
+            ("CA file", &entry.tls_ca_file),
+            ("certificate file", &entry.tls_cert_file),
+            ("key file", &entry.tls_key_file),
+        ];
+        for (label, name) in files {
+            let Some(name) = name.as_deref().map(str::trim).filter(|n| !n.is_empty()) else {
+                continue;
+            };
+            if !is_bare_file_name(name) {
+                return Err(format!(
+                    "The TLS {} must be a file name in the certs directory: {}",
+                    label, name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn has_client_cert(&self) -> bool {
+        self.certs_dir.join(&self.cert_file).is_file()
+    }
+
+    fn client_config(&self) -> Result<Arc<ClientConfig>, Hl7ClientError> {
+        let provider = Arc::new(default_provider());
+        let verifier: Arc<dyn ServerCertVerifier> = if self.verify {
+            let mut roots = RootCertStore::empty();
+            let ca_path = self.certs_dir.join(&self.ca_file);
+            for cert in CertificateDer::pem_file_iter(&ca_path)
+                .map_err(|e| tls_error(&format!("Failed to read {}", self.ca_file), e))?
+            {
+                let cert = cert
+                    .map_err(|e| tls_error(&format!("Failed to parse {}", self.ca_file), e))?;
+                roots
+                    .add(cert)
+                    .map_err(|e| tls_error(&format!("Failed to load {}", self.ca_file), e))?;
+            }
+            let verifier =
+                WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                    .build()
+                    .map_err(|e| tls_error("Failed to build certificate verifier", e))?;
+            // 未配置 SNI 时按 IP 连接，与 DICOM TLS 一样不校验主机名
+            match self.server_name {
+                Some(_) => verifier,
+                None => Arc::new(IgnoreHostnameVerifier(verifier)),
+            }
+        } else {
+            Arc::new(AcceptAnyServerCert(provider.clone()))
+        };
+
+        let builder = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| tls_error("Failed to configure TLS", e))?
+            .dangerous()
+            .with_custom_certificate_verifier(verifier);
+        let config = if self.has_client_cert() {
+            let client_certs = CertificateDer::pem_file_iter(self.certs_dir.join(&self.cert_file))
+                .map_err(|e| tls_error(&format!("Failed to read {}", self.cert_file), e))?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| tls_error(&format!("Failed to parse {}", self.cert_file), e))?;
+            let client_key = PrivateKeyDer::from_pem_file(self.certs_dir.join(&self.key_file))
+                .map_err(|e| tls_error(&format!("Failed to read {}", self.key_file), e))?;
+            builder
+                .with_client_auth_cert(client_certs, client_key)
+                .map_err(|e| tls_error("Failed to load client certificate", e))?
+        } else {
+            builder.with_no_client_auth()
+        };
+        Ok(Arc::new(config))
+    }
+
+    /// Runs the TLS handshake on `stream`.
+    async fn connect(
+        &self,
+        server_address: &str,
+        stream: TcpStream,
+    ) -> Result<TlsStream<TcpStream>, Hl7ClientError> {
+        let name = self
+            .server_name
+            .clone()
+            .unwrap_or_else(|| server_address.to_string());
+        let server_name =
+            ServerName::try_from(name).map_err(|e| tls_error("Invalid TLS server name", e))?;
+        TlsConnector::from(self.client_config()?)
+            .connect(server_name, stream)
+            .await
+            .map_err(|e| tls_error("TLS handshake failed", e))
+    }
+
+    // reqwest 使用 native-tls，客户端私钥需为 PKCS#8 格式
+    fn http_client(
+        &self,
+        builder: reqwest::ClientBuilder,
+    ) -> Result<reqwest::ClientBuilder, Hl7ClientError> {
+        let mut builder = builder.tls_sni(true);
+        if self.verify {
+            let ca = fs::read(self.certs_dir.join(&self.ca_file))
+                .map_err(|e| tls_error(&format!("Failed to read {}", self.ca_file), e))?;
+            for cert in reqwest::Certificate::from_pem_bundle(&ca)
+                .map_err(|e| tls_error(&format!("Failed to parse {}", self.ca_file), e))?
+            {
+                builder = builder.add_root_certificate(cert);
+            }
+            builder = builder.danger_accept_invalid_hostnames(self.server_name.is_none());
+        } else {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        if self.has_client_cert() {
+            let cert = fs::read(self.certs_dir.join(&self.cert_file))
+                .map_err(|e| tls_error(&format!("Failed to read {}", self.cert_file), e))?;
+            let key = fs::read(self.certs_dir.join(&self.key_file))
+                .map_err(|e| tls_error(&format!("Failed to read {}", self.key_file), e))?;
+            let identity = reqwest::Identity::from_pkcs8_pem(&cert, &key)
+                .map_err(|e| tls_error("Failed to load client certificate", e))?;
+            builder = builder.identity(identity);
+        }
+        Ok(builder)
+    }
+}
+
+fn tls_details(stream: &TlsStream<TcpStream>) -> TlsDetails {
+    let (_, connection) = stream.get_ref();
+    TlsDetails {
+        protocol_version: connection
+            .protocol_version()
+            .map(|v| format!("{:?}", v))
+            .unwrap_or_default(),
+        cipher_suite: connection
+            .negotiated_cipher_suite()
+            .map(|c| format!("{:?}", c.suite()))
+            .unwrap_or_default(),
+    }
+}
+
+/// Accepts any server certificate but still checks the handshake signatures,
+/// for test endpoints with self-signed certificates.
+#[derive(Debug)]
+struct AcceptAnyServerCert(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyServerCert {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Hl7Client {
     message: String,
     server_address: String,
     port: u16,
     method: SendMethod, // 发送方式
+    ack_timeout: Duration, // 等待 ACK 的超时时间
+    tls: Option<Hl7TlsConfig>, // MLLP/TLS 或 HTTPS
 }
 
 impl Hl7Client {
@@ -59,84 +667,217 @@ impl Hl7Client {
             server_address,
             port,
             method,
+            ack_timeout: DEFAULT_ACK_TIMEOUT,
+            tls: None,
         }
     }
 
+    pub fn with_ack_timeout(mut self, ack_timeout: Duration) -> Hl7Client {
+        self.ack_timeout = ack_timeout;
+        self
+    }
+
+    pub fn with_tls(mut self, tls: Option<Hl7TlsConfig>) -> Hl7Client {
+        self.tls = tls;
+        self
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.server_address, self.port)
+    }
+
+    pub fn ack_timeout(&self) -> Duration {
+        self.ack_timeout
+    }
+
+    /// Whether `other` connects to the same address with the same TLS options.
+    pub fn same_endpoint(&self, other: &Hl7Client) -> bool {
+        self.server_address == other.server_address
+            && self.port == other.port
+            && self.tls == other.tls
+    }
+
+    /// Opens an MLLP connection to the server, with the TLS handshake when
+    /// TLS is configured.
+    pub async fn connect(&self) -> Result<Hl7Connection, Box<dyn Error>> {
+        let connect = async {
+            // 地址可以是主机名，连接后记录实际解析到的地址
+            let stream = TcpStream::connect((self.server_address.as_str(), self.port)).await?;
+            let addr = stream.peer_addr()?;
+            let (stream, tls): (Box<dyn MllpStream>, _) = match &self.tls {
+                Some(tls) => {
+                    let stream = tls.connect(&self.server_address, stream).await?;
+                    let details = tls_details(&stream);
+                    (Box::new(stream), Some(details))
+                }
+                None => (Box::new(stream), None),
+            };
+            Ok::<_, Box<dyn Error>>((stream, addr, tls))
+        };
+        let (stream, addr, tls) = tokio::time::timeout(CONNECT_TIMEOUT, connect)
+            .await
+            .map_err(|_| {
+                Hl7ClientError::new(&format!("Connection to {} timed out", self.address()))
+            })??;
+        Ok(Hl7Connection {
+            // MLLP 帧：0x0B + 报文 + 0x1C 0x0D，由 codec 负责封装和拆分
+            transport: Framed::new(stream, MllpCodec::new()),
+            addr,
+            ack_timeout: self.ack_timeout,
+            tls,
+        })
+    }
+
     // 发送 HL7 消息通过 TCP
-    pub async fn send_hl7_message_tcp(&self) -> Result<String, Box<dyn Error>> {
+    pub async fn send_hl7_message_tcp(&self) -> Result<Hl7SendResult, Box<dyn Error>> {
         let mut status_log = String::new();
-        let hl7_message_formatted: String = self
-            .message
-            .lines()
-            .map(|line| line.trim())
-            .collect::<Vec<&str>>()
-            .join("\r");
-        let hl7_message = Message::new(&hl7_message_formatted);
-        let hl7_bytes = hl7_message.to_string().into_bytes();
-
-        let addr = format!("{}:{}", self.server_address, self.port);
-        let addr: SocketAddr = addr.parse()?;
-        let mut stream = TcpStream::connect(&addr).await?;
+        let mut connection = self.connect().await?;
         status_log.push_str(&format!(
             "[{}] Connected to {}\n",
             Local::now().format("%H:%M:%S"),
-            addr
+            connection.addr
         ));
-
-        stream.write_all(&hl7_bytes).await?;
-        status_log.push_str(&format!(
-            "[{}] Message sent\n",
-            Local::now().format("%H:%M:%S")
-        ));
-
-        let mut response = String::new();
-        let mut buffer = [0; 1024];
-        let mut total_read = 0;
-        loop {
-            let n = stream.read(&mut buffer).await?;
-            if n == 0 {
-                break; // No more data
-            }
-            total_read += n;
-            response.push_str(&String::from_utf8_lossy(&buffer[..n]));
-        }
-
-        if total_read > 0 {
+        if let Some(details) = &connection.tls {
             status_log.push_str(&format!(
-                "[{}] ACK Received:\n{}\n",
+                "[{}] TLS established: {} {}\n",
                 Local::now().format("%H:%M:%S"),
-                response
+                details.protocol_version,
+                details.cipher_suite
             ));
-        } else {
-            return Err(Box::new(Hl7ClientError::new("No response from server.")));
         }
 
-        stream.shutdown().await?;
+        let (control_id, ack) = connection.send(&self.message).await?;
+        status_log.push_str(&format!(
+            "[{}] Message sent\n[{}] ACK Received:\n{}\n",
+            Local::now().format("%H:%M:%S"),
+            Local::now().format("%H:%M:%S"),
+            ack.raw
+        ));
+
+        let addr = connection.addr;
+        connection.close().await?;
         status_log.push_str(&format!(
             "[{}] Connection to {} has been closed\n",
             Local::now().format("%H:%M:%S"),
             addr
         ));
 
-        Ok(status_log)
+        Ok(Hl7SendResult {
+            log: status_log,
+            control_id,
+            ack: Some(ack),
+        })
+    }
+
+    /// Sends `messages` one after another over one MLLP connection. When a
+    /// message gets no ACK the connection is reopened for the next one; the
+    /// batch stops only if that fails.
+    pub async fn send_batch(&self, messages: &[String]) -> Result<Hl7BatchResult, Hl7ClientError> {
+        let started = Instant::now();
+        let mut connection = self
+            .connect()
+            .await
+            .map_err(|e| Hl7ClientError::new(&format!("Connection failed: {}", e)))?;
+        let mut connections = 1;
+        let mut items: Vec<Hl7BatchItem> = Vec::with_capacity(messages.len());
+
+        for (index, message) in messages.iter().enumerate() {
+            let mut item = Hl7BatchItem {
+                index,
+                message_type: String::new(),
+                control_id: String::new(),
+                ack: None,
+                error: None,
+                elapsed_ms: 0.0,
+            };
+            // 无法解析的报文不发送，也不影响连接
+            let normalized = normalize_segments(message);
+            match parse_message(&normalized) {
+                Ok(parsed) => {
+                    item.message_type = message_type(&parsed);
+                    item.control_id = control_id(&parsed);
+                }
+                Err(e) => {
+                    item.error = Some(format!("Failed to parse HL7 message: {}", e));
+                    items.push(item);
+                    continue;
+                }
+            }
+
+            let sent = Instant::now();
+            let result = connection.send(&normalized).await.map_err(|e| e.to_string());
+            item.elapsed_ms = sent.elapsed().as_secs_f64() * 1000.0;
+            match result {
+                Ok((_, ack)) => item.ack = Some(ack),
+                Err(e) => {
+                    item.error = Some(e);
+                    // 未收到 ACK 时连接状态未知，重新连接后继续
+                    match self.connect().await {
+                        Ok(reopened) => {
+                            connection = reopened;
+                            connections += 1;
+                        }
+                        Err(e) => {
+                            items.push(item);
+                            let reason = format!("Not sent: reconnect failed: {}", e);
+                            for index in index + 1..messages.len() {
+                                items.push(Hl7BatchItem {
+                                    index,
+                                    message_type: String::new(),
+                                    control_id: String::new(),
+                                    ack: None,
+                                    error: Some(reason.clone()),
+                                    elapsed_ms: 0.0,
+                                });
+                            }
+                            return Ok(Hl7BatchResult::new(items, connections, started.elapsed()));
+                        }
+                    }
+                }
+            }
+            items.push(item);
+        }
+
+        if let Err(e) = connection.close().await {
+            eprintln!("HL7 batch: Failed to close connection: {}", e);
+        }
+        Ok(Hl7BatchResult::new(items, connections, started.elapsed()))
     }
 
     // 发送 HL7 消息通过 HTTP
-    pub async fn send_hl7_message_http(&self) -> Result<String, Box<dyn Error>> {
+    pub async fn send_hl7_message_http(&self) -> Result<Hl7SendResult, Box<dyn Error>> {
         let mut status_log = String::new();
-        let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(10)) // 设置超时时间为10秒
-        .build()?;
-        let hl7_message_formatted: String = self
-            .message
-            .lines()
-            .map(|line| line.trim())
-            .collect::<Vec<&str>>()
-            .join("\r");
-        let hl7_message = Message::new(&hl7_message_formatted);
+        let mut builder = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10)); // 设置超时时间为10秒
+        let mut scheme = "http";
+        let mut host = self.server_address.clone();
+        if let Some(tls) = &self.tls {
+            builder = tls.http_client(builder)?;
+            scheme = "https";
+            // URL 使用 SNI 主机名，实际连接配置的地址
+            if let Some(server_name) = &tls.server_name {
+                let addr: SocketAddr =
+                    tokio::net::lookup_host((self.server_address.as_str(), self.port))
+                        .await?
+                        .next()
+                        .ok_or_else(|| {
+                            Hl7ClientError::new(&format!("Failed to resolve {}", self.address()))
+                        })?;
+                builder = builder.resolve(server_name, addr);
+                host = server_name.clone();
+            }
+        }
+        let client = builder.build()?;
+        let hl7_message_formatted = normalize_segments(&self.message);
+        let hl7_message = parse_message(&hl7_message_formatted)?;
+        let control_id = control_id(&hl7_message);
 
         let hl7_bytes: Vec<u8> = hl7_message.to_string().into_bytes();
-        let addr = format!("http://{}:{}", self.server_address, self.port);
+        let addr = format!("{}://{}:{}", scheme, host, self.port);
         status_log.push_str(&format!(
             "[{}] Sending HTTP request to {}\n",
             Local::now().format("%H:%M:%S"),
@@ -152,7 +893,17 @@ impl Hl7Client {
                         Local::now().format("%H:%M:%S"),
                         resp_text
                     ));
-                    Ok(status_log)
+                    // 响应体是 HL7 报文时按 ACK 解析，其他内容只记录
+                    let ack = if resp_text.trim_start().starts_with("MSH") {
+                        Some(Hl7Ack::parse(&resp_text, &control_id)?)
+                    } else {
+                        None
+                    };
+                    Ok(Hl7SendResult {
+                        log: status_log,
+                        control_id,
+                        ack,
+                    })
                 } else {
                     status_log.push_str(&format!(
                         "[{}] Failed with status: {}\n",
@@ -180,11 +931,195 @@ impl Hl7Client {
         // Ok(res_text)
     }
 
+    // 仅建立 TCP 连接以确认 MLLP 端口可达，配置了 TLS 时同时完成握手，返回连接耗时
+    pub async fn check_connection(
+        server_address: &str,
+        port: u16,
+        timeout: Duration,
+        tls: Option<&Hl7TlsConfig>,
+    ) -> Result<(Duration, Option<TlsDetails>), Hl7ClientError> {
+        let started = Instant::now();
+        let connect = async {
+            let stream = TcpStream::connect((server_address, port))
+                .await
+                .map_err(|e| Hl7ClientError::new(&format!("Connection failed: {}", e)))?;
+            match tls {
+                Some(tls) => {
+                    let stream = tls.connect(server_address, stream).await?;
+                    Ok(Some(tls_details(&stream)))
+                }
+                None => Ok(None),
+            }
+        };
+        let details = tokio::time::timeout(timeout, connect)
+            .await
+            .map_err(|_| Hl7ClientError::new("Connection timed out"))??;
+        Ok((started.elapsed(), details))
+    }
+
     // 发送消息根据配置的方法
-    pub async fn send_message(&self) -> Result<String, Box<dyn Error>> {
+    pub async fn send_message(&self) -> Result<Hl7SendResult, Box<dyn Error>> {
         match self.method {
             SendMethod::Tcp => self.send_hl7_message_tcp().await,
             SendMethod::Http => self.send_hl7_message_http().await,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MSH: &str = "MSH|^~\\&|RIS|HOSP|DICOM|SIM|20240101120000||ACK^A01|ACK0001|P|2.5";
+
+    #[test]
+    fn parses_accepted_ack() {
+        let response = format!("{}\rMSA|AA|MSG0001|Message received\r", MSH);
+        let ack = Hl7Ack::parse(&response, "MSG0001").unwrap();
+        assert_eq!(ack.ack_code, "AA");
+        assert!(ack.accepted);
+        assert!(ack.control_id_matches);
+        assert_eq!(ack.text_message, "Message received");
+        assert!(ack.errors.is_empty());
+        assert_eq!(
+            ack.summary("MSG0001"),
+            "AA: Message accepted; Message received"
+        );
+    }
+
+    #[test]
+    fn accepts_newline_separated_segments() {
+        let response = format!("{}\nMSA|aa|MSG0001\n", MSH);
+        let ack = Hl7Ack::parse(&response, "MSG0001").unwrap();
+        assert_eq!(ack.ack_code, "AA");
+        assert!(ack.accepted);
+    }
+
+    #[test]
+    fn rejects_ack_for_another_message() {
+        let response = format!("{}\rMSA|AA|MSG0002\r", MSH);
+        let ack = Hl7Ack::parse(&response, "MSG0001").unwrap();
+        assert!(!ack.accepted);
+        assert!(!ack.control_id_matches);
+        assert_eq!(
+            ack.summary("MSG0001"),
+            "AA: Message accepted; control ID mismatch (sent MSG0001, ACK references MSG0002)"
+        );
+    }
+
+    #[test]
+    fn reads_v25_err_segments() {
+        let response = format!(
+            "{}\rMSA|AE|MSG0001|Invalid message\r\
+             ERR||PID^1^3|101^Required field missing^HL70357|E||||Patient identifier is required\r",
+            MSH
+        );
+        let ack = Hl7Ack::parse(&response, "MSG0001").unwrap();
+        assert_eq!(ack.ack_code, "AE");
+        assert!(!ack.accepted);
+        assert_eq!(
+            ack.errors,
+            vec![Hl7AckError {
+                location: "PID-1.3".to_string(),
+                code: "101".to_string(),
+                description: "Required field missing".to_string(),
+                severity: "E".to_string(),
+                message: "Patient identifier is required".to_string(),
+            }]
+        );
+        assert_eq!(
+            ack.summary("MSG0001"),
+            "AE: Application error; Invalid message; \
+             PID-1.3 101 Required field missing Patient identifier is required"
+        );
+    }
+
+    #[test]
+    fn reads_v23_err_segments() {
+        let response = format!(
+            "{}\rMSA|AR|MSG0001\rERR|PV1^1^2^207&Application internal error\r",
+            MSH.replace("|2.5", "|2.3")
+        );
+        let ack = Hl7Ack::parse(&response, "MSG0001").unwrap();
+        assert_eq!(ack.ack_code, "AR");
+        assert_eq!(ack.errors.len(), 1);
+        assert_eq!(ack.errors[0].location, "PV1-1.2");
+        assert_eq!(ack.errors[0].code, "207");
+        assert_eq!(ack.errors[0].description, "Application internal error");
+        assert_eq!(ack.errors[0].severity, "");
+    }
+
+    #[test]
+    fn rejects_responses_without_msa() {
+        assert!(Hl7Ack::parse("HTTP/1.1 200 OK", "MSG0001").is_err());
+        let error = Hl7Ack::parse(&format!("{}\r", MSH), "MSG0001").unwrap_err();
+        assert_eq!(error.to_string(), "ACK has no MSA segment");
+    }
+
+    #[test]
+    fn splits_a_batch_file_into_messages() {
+        let content = "FHS|^~\\&|RIS|HOSP|||20240101120000\r\n\
+             BHS|^~\\&|RIS|HOSP|||20240101120000\r\n\
+             MSH|^~\\&|RIS|HOSP|SIM|DICOM|20240101120000||ADT^A01|MSG0001|P|2.5\r\n\
+             PID|1||P1001||Doe^John\r\n\
+             MSH|^~\\&|RIS|HOSP|SIM|DICOM|20240101120000||ADT^A08|MSG0002|P|2.5\r\n\
+             PID|1||P1001||Doe^Johnny\r\n\
+             BTS|2\r\n\
+             FTS|1\r\n";
+        let messages = split_batch(content);
+        assert_eq!(
+            messages,
+            vec![
+                "MSH|^~\\&|RIS|HOSP|SIM|DICOM|20240101120000||ADT^A01|MSG0001|P|2.5\r\
+                 PID|1||P1001||Doe^John",
+                "MSH|^~\\&|RIS|HOSP|SIM|DICOM|20240101120000||ADT^A08|MSG0002|P|2.5\r\
+                 PID|1||P1001||Doe^Johnny",
+            ]
+        );
+    }
+
+    #[test]
+    fn drops_mllp_framing_and_leading_noise() {
+        let content = "garbage before the first message\n\
+             \x0bMSH|^~\\&|RIS|HOSP|SIM|DICOM|20240101120000||ORM^O01|MSG0003|P|2.3\r\
+             ORC|NW|ORD1\r\x1c\r\n\n";
+        let messages = split_batch(content);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(
+            messages[0],
+            "MSH|^~\\&|RIS|HOSP|SIM|DICOM|20240101120000||ORM^O01|MSG0003|P|2.3\rORC|NW|ORD1"
+        );
+        assert!(split_batch("FHS|^~\\&\nFTS|0\n").is_empty());
+    }
+
+    fn tls_setting(ca_file: &str) -> Hl7SettingEntry {
+        serde_json::from_value(serde_json::json!({
+            "name": "RIS",
+            "ip": "127.0.0.1",
+            "port": "2575",
+            "tlsEnabled": true,
+            "tlsCaFile": ca_file,
+            "tlsKeyFile": " client.key ",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn accepts_only_bare_tls_file_names() {
+        assert!(Hl7TlsConfig::check_file_names(&tls_setting("hospital-ca.pem")).is_ok());
+        assert!(Hl7TlsConfig::check_file_names(&tls_setting("")).is_ok());
+        for name in ["../ca.pem", "/etc/ssl/ca.pem", "sub/ca.pem", ".."] {
+            assert!(
+                Hl7TlsConfig::check_file_names(&tls_setting(name)).is_err(),
+                "{}",
+                name
+            );
+        }
+
+        // 旧记录中带路径的名称回退到默认文件
+        let config =
+            Hl7TlsConfig::from_setting(&tls_setting("../ca.pem"), PathBuf::from("certs")).unwrap();
+        assert_eq!(config.ca_file, "ca.pem");
+        assert_eq!(config.key_file, "client.key");
+    }
+}
@@ -0,0 +1,159 @@
+// hl7_listener.rs
+// 模拟 HL7 接收方：保存收到的报文并按配置回复 AA / AE / AR、延迟回复或不回复
+// 开启 worklist_bridge 时收到的 ORM / OMI 订单会转为 Worklist 条目
+use crate::hl7_bridge::{apply_orders, is_order_message, parse_orders, HL7_BRIDGE_EVENT};
+use crate::hl7_server::{build_ack, Hl7Request, Hl7Service};
+use crate::models::{Hl7ListenerSetting, ReceivedHl7Message};
+use chrono::Local;
+use futures::future::BoxFuture;
+use std::time::Duration;
+use surrealdb::engine::local::Db;
+use surrealdb::Surreal;
+use tauri::{AppHandle, Emitter};
+
+pub const HL7_RECEIVED_TABLE: &str = "hl7_received";
+pub const HL7_RECEIVED_EVENT: &str = "hl7-received";
+
+pub struct Hl7Listener {
+    db: Surreal<Db>,
+    handle: AppHandle,
+    setting: Hl7ListenerSetting,
+}
+
+impl Hl7Listener {
+    pub fn new(
+        db: Surreal<Db>,
+        handle: AppHandle,
+        setting: Hl7ListenerSetting,
+    ) -> Hl7Listener {
+        Hl7Listener {
+            db,
+            handle,
+            setting,
+        }
+    }
+
+    /// The ACK code for a message, or `None` when no ACK is sent.
+    fn ack_code(&self, message_type: &str) -> Option<String> {
+        let ack_mode = self.setting.ack_mode.as_deref().unwrap_or("AA");
+        if ack_mode.eq_ignore_ascii_case("NONE") {
+            return None;
+        }
+        let is_error = self
+            .setting
+            .error_message_types
+            .iter()
+            .any(|pattern| matches_message_type(pattern, message_type));
+        if is_error {
+            Some(
+                self.setting
+                    .error_ack_code
+                    .clone()
+                    .unwrap_or_else(|| "AE".to_string())
+                    .to_uppercase(),
+            )
+        } else {
+            Some("AA".to_string())
+        }
+    }
+
+    async fn bridge(&self, message: &str) -> Result<(), String> {
+        let orders = parse_orders(message)?;
+        let result = apply_orders(&self.db, orders).await?;
+        if let Err(e) = self.handle.emit(HL7_BRIDGE_EVENT, &result) {
+            eprintln!("Failed to emit {}: {}", HL7_BRIDGE_EVENT, e);
+        }
+        Ok(())
+    }
+
+    async fn record(&self, received: ReceivedHl7Message) {
+        let stored: Result<Vec<ReceivedHl7Message>, _> = self
+            .db
+            .create(HL7_RECEIVED_TABLE)
+            .content(received.clone())
+            .await;
+        let received = match stored {
+            Ok(stored) => stored.into_iter().next().unwrap_or(received),
+            Err(e) => {
+                eprintln!("HL7 listener: Failed to save message: {}", e);
+                received
+            }
+        };
+        if let Err(e) = self.handle.emit(HL7_RECEIVED_EVENT, &received) {
+            eprintln!("Failed to emit {}: {}", HL7_RECEIVED_EVENT, e);
+        }
+    }
+}
+
+impl Hl7Service for Hl7Listener {
+    fn handle<'a>(&'a self, request: &'a Hl7Request) -> BoxFuture<'a, Option<String>> {
+        Box::pin(async move {
+            let message_type = request.msh(9);
+            // 无法解析 MSH 时无法构造 ACK，只记录报文
+            let mut ack_code = if request.is_hl7() {
+                self.ack_code(&message_type)
+            } else {
+                None
+            };
+            let mut text = match ack_code.as_deref() {
+                Some("AA") | None => String::new(),
+                Some(_) => "Rejected by the simulator".to_string(),
+            };
+            // 不转换配置为以 AE / AR 拒绝的订单，转换失败时改为 AE
+            let bridge = self.setting.worklist_bridge.unwrap_or(false)
+                && request.is_hl7()
+                && is_order_message(&message_type)
+                && matches!(ack_code.as_deref(), Some("AA") | None);
+            if bridge {
+                if let Err(e) = self.bridge(&request.message).await {
+                    eprintln!("HL7 listener: {}", e);
+                    ack_code = ack_code.map(|_| "AE".to_string());
+                    text = e;
+                }
+            }
+            let ack = ack_code.as_deref().map(|code| {
+                let control_id = format!("ACK{}", Local::now().format("%Y%m%d%H%M%S%3f"));
+                build_ack(request, code, &text, &control_id)
+            });
+
+            self.record(ReceivedHl7Message {
+                id: None,
+                sending_application: request.msh(3),
+                sending_facility: request.msh(4),
+                receiving_application: request.msh(5),
+                receiving_facility: request.msh(6),
+                message_date_time: request.msh(7),
+                message_type,
+                control_id: request.msh(10),
+                processing_id: request.msh(11),
+                version_id: request.msh(12),
+                message: request.message.replace('\r', "\n"),
+                ack_code,
+                ack: ack.as_ref().map(|ack| ack.replace('\r', "\n")),
+                remote_addr: request.remote_addr.clone(),
+                received_at: Local::now().to_rfc3339(),
+            })
+            .await;
+
+            if let Some(delay) = self.setting.ack_delay_ms.filter(|ms| *ms > 0) {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+            }
+            ack
+        })
+    }
+}
+
+// ADT 匹配所有 ADT 消息，ADT^A08 只匹配该事件，* 匹配全部
+fn matches_message_type(pattern: &str, message_type: &str) -> bool {
+    let pattern = pattern.trim();
+    if pattern == "*" {
+        return true;
+    }
+    let message_type: Vec<&str> = message_type.split('^').collect();
+    !pattern.is_empty()
+        && pattern.split('^').enumerate().all(|(i, part)| {
+            message_type
+                .get(i)
+                .is_some_and(|actual| actual.trim().eq_ignore_ascii_case(part.trim()))
+        })
+}
@@ -0,0 +1,348 @@
+// hl7_pool.rs
+// 按 HL7 配置保持 MLLP 长连接：同一连接上的报文依次发送，断开后按指数退避自动重连
+use crate::dicom_client::TlsDetails;
+use crate::hl7_client::{
+    normalize_segments, parse_message, Hl7Client, Hl7Connection, Hl7SendResult,
+};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+pub const HL7_CONNECTION_EVENT: &str = "hl7-connection-state";
+
+// 自动重连间隔 0.5s、1s、2s ... 最长 30s，失败 8 次后放弃，下次发送时再连接
+const RECONNECT_ATTEMPTS: u32 = 8;
+const BACKOFF_BASE: Duration = Duration::from_millis(500);
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Hl7ConnectionState {
+    Connecting,
+    Connected,
+    Reconnecting, // 等待下一次自动重连
+    Disconnected,
+}
+
+/// A kept connection, as shown to the frontend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hl7ConnectionStatus {
+    pub setting_id: String,
+    pub name: String,
+    pub address: String,
+    pub state: Hl7ConnectionState,
+    pub tls: Option<TlsDetails>,
+    pub connected_at: Option<String>,
+    pub last_activity: Option<String>,
+    pub messages: u64,    // 已收到 ACK 的报文数
+    pub connections: u64, // 打开过的连接数，大于 1 表示重连过
+    pub failures: u32,    // 连续失败次数
+    pub next_retry: Option<String>,
+    pub last_error: Option<String>,
+}
+
+/// Called with the new status whenever a kept connection changes.
+pub type StatusListener = Arc<dyn Fn(&Hl7ConnectionStatus) + Send + Sync>;
+
+fn now() -> String {
+    Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+fn backoff(attempt: u32) -> Duration {
+    BACKOFF_BASE
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(BACKOFF_MAX)
+}
+
+struct Pooled {
+    client: Hl7Client,
+    // 发送期间持有该锁，同一连接上的报文不会交错
+    connection: Mutex<Option<Hl7Connection>>,
+    status: std::sync::Mutex<Hl7ConnectionStatus>,
+    reconnecting: AtomicBool,
+    closed: AtomicBool,
+    listener: Option<StatusListener>,
+}
+
+impl Pooled {
+    fn status(&self) -> Hl7ConnectionStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    fn update(&self, change: impl FnOnce(&mut Hl7ConnectionStatus)) {
+        let status = {
+            let mut status = self.status.lock().unwrap();
+            change(&mut status);
+            status.clone()
+        };
+        if let Some(listener) = &self.listener {
+            listener(&status);
+        }
+    }
+
+    fn lost(&self, error: &str) {
+        self.update(|s| {
+            s.state = Hl7ConnectionState::Disconnected;
+            s.tls = None;
+            s.connected_at = None;
+            s.failures += 1;
+            s.last_error = Some(error.to_string());
+        });
+    }
+
+    // 调用方持有 connection 锁
+    async fn open(&self) -> Result<Hl7Connection, Box<dyn Error>> {
+        self.update(|s| s.state = Hl7ConnectionState::Connecting);
+        match self.client.connect().await {
+            Ok(connection) => {
+                self.update(|s| {
+                    s.state = Hl7ConnectionState::Connected;
+                    s.tls = connection.tls().cloned();
+                    s.connected_at = Some(now());
+                    s.connections += 1;
+                    s.failures = 0;
+                    s.next_retry = None;
+                });
+                Ok(connection)
+            }
+            Err(e) => {
+                self.lost(&e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    async fn send(
+        self: &Arc<Self>,
+        message: &str,
+        ack_timeout: Duration,
+    ) -> Result<Hl7SendResult, Box<dyn Error>> {
+        let mut guard = self.connection.lock().await;
+        let mut log = String::new();
+        // 空闲期间对端可能已关闭连接，发送前先检查，避免报文发到已关闭的连接上
+        if let Some(connection) = guard.as_mut() {
+            if connection.is_closed() {
+                log.push_str(&format!(
+                    "[{}] Connection to {} was closed by the peer\n",
+                    Local::now().format("%H:%M:%S"),
+                    connection.addr()
+                ));
+                *guard = None;
+                self.lost("Connection closed by the peer");
+            } else {
+                log.push_str(&format!(
+                    "[{}] Using open connection to {}\n",
+                    Local::now().format("%H:%M:%S"),
+                    connection.addr()
+                ));
+            }
+        }
+        let connection = match guard.as_mut() {
+            Some(connection) => connection,
+            None => {
+                let connection = match self.open().await {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        drop(guard);
+                        self.schedule_reconnect();
+                        return Err(e);
+                    }
+                };
+                log.push_str(&format!(
+                    "[{}] Connected to {}\n",
+                    Local::now().format("%H:%M:%S"),
+                    connection.addr()
+                ));
+                if let Some(details) = connection.tls() {
+                    log.push_str(&format!(
+                        "[{}] TLS established: {} {}\n",
+                        Local::now().format("%H:%M:%S"),
+                        details.protocol_version,
+                        details.cipher_suite
+                    ));
+                }
+                guard.insert(connection)
+            }
+        };
+
+        connection.set_ack_timeout(ack_timeout);
+        match connection.send(message).await {
+            Ok((control_id, ack)) => {
+                log.push_str(&format!(
+                    "[{}] Message sent\n[{}] ACK Received:\n{}\n[{}] Connection kept open\n",
+                    Local::now().format("%H:%M:%S"),
+                    Local::now().format("%H:%M:%S"),
+                    ack.raw,
+                    Local::now().format("%H:%M:%S"),
+                ));
+                self.update(|s| {
+                    s.messages += 1;
+                    s.last_activity = Some(now());
+                    s.last_error = None;
+                });
+                Ok(Hl7SendResult {
+                    log,
+                    control_id,
+                    ack: Some(ack),
+                })
+            }
+            Err(e) => {
+                // 不确定对端是否已处理该报文，不自动重发，只重新建立连接
+                *guard = None;
+                drop(guard);
+                self.lost(&e.to_string());
+                self.schedule_reconnect();
+                Err(e)
+            }
+        }
+    }
+
+    fn schedule_reconnect(self: &Arc<Self>) {
+        if self.closed.load(Ordering::SeqCst) || self.reconnecting.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let pooled = self.clone();
+        tokio::spawn(async move {
+            pooled.reconnect().await;
+            pooled.reconnecting.store(false, Ordering::SeqCst);
+        });
+    }
+
+    async fn reconnect(&self) {
+        for attempt in 0..RECONNECT_ATTEMPTS {
+            let delay = backoff(attempt);
+            let next_retry = Local::now() + chrono::Duration::from_std(delay).unwrap_or_default();
+            self.update(|s| {
+                s.state = Hl7ConnectionState::Reconnecting;
+                s.next_retry = Some(next_retry.format("%Y-%m-%d %H:%M:%S").to_string());
+            });
+            tokio::time::sleep(delay).await;
+
+            let mut guard = self.connection.lock().await;
+            // 已关闭，或发送时已经重新连接
+            if self.closed.load(Ordering::SeqCst) || guard.is_some() {
+                return;
+            }
+            if let Ok(connection) = self.open().await {
+                *guard = Some(connection);
+                return;
+            }
+        }
+        self.update(|s| {
+            s.state = Hl7ConnectionState::Disconnected;
+            s.next_retry = None;
+        });
+    }
+
+    async fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        if let Some(connection) = self.connection.lock().await.take() {
+            if let Err(e) = connection.close().await {
+                eprintln!("HL7 pool: Failed to close connection: {}", e);
+            }
+        }
+        self.update(|s| {
+            s.state = Hl7ConnectionState::Disconnected;
+            s.tls = None;
+            s.connected_at = None;
+            s.next_retry = None;
+        });
+    }
+}
+
+/// Long-lived MLLP connections, one per HL7 setting.
+pub struct Hl7Pool {
+    connections: Mutex<HashMap<String, Arc<Pooled>>>,
+    listener: Option<StatusListener>,
+}
+
+impl Hl7Pool {
+    pub fn new(listener: Option<StatusListener>) -> Hl7Pool {
+        Hl7Pool {
+            connections: Mutex::new(HashMap::new()),
+            listener,
+        }
+    }
+
+    /// Sends the message of `client` over the connection kept for
+    /// `setting_id`, opening it first when needed. A kept connection to another
+    /// address or with other TLS options is closed and replaced.
+    pub async fn send(
+        &self,
+        setting_id: &str,
+        name: &str,
+        client: Hl7Client,
+    ) -> Result<Hl7SendResult, Box<dyn Error>> {
+        // 无法解析的报文不占用连接
+        let message = normalize_segments(client.message());
+        parse_message(&message)?;
+
+        let (pooled, replaced) = {
+            let mut connections = self.connections.lock().await;
+            match connections.get(setting_id) {
+                Some(pooled) if pooled.client.same_endpoint(&client) => (pooled.clone(), None),
+                _ => {
+                    let pooled = Arc::new(Pooled {
+                        status: std::sync::Mutex::new(Hl7ConnectionStatus {
+                            setting_id: setting_id.to_string(),
+                            name: name.to_string(),
+                            address: client.address(),
+                            state: Hl7ConnectionState::Disconnected,
+                            tls: None,
+                            connected_at: None,
+                            last_activity: None,
+                            messages: 0,
+                            connections: 0,
+                            failures: 0,
+                            next_retry: None,
+                            last_error: None,
+                        }),
+                        client: client.clone(),
+                        connection: Mutex::new(None),
+                        reconnecting: AtomicBool::new(false),
+                        closed: AtomicBool::new(false),
+                        listener: self.listener.clone(),
+                    });
+                    let replaced = connections.insert(setting_id.to_string(), pooled.clone());
+                    (pooled, replaced)
+                }
+            }
+        };
+        if let Some(replaced) = replaced {
+            replaced.close().await;
+        }
+        if pooled.status().name != name {
+            pooled.update(|s| s.name = name.to_string());
+        }
+        pooled.send(&message, client.ack_timeout()).await
+    }
+
+    /// Closes the connection kept for `setting_id`. Returns false when there
+    /// was none.
+    pub async fn close(&self, setting_id: &str) -> bool {
+        let removed = self.connections.lock().await.remove(setting_id);
+        match removed {
+            Some(pooled) => {
+                pooled.close().await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub async fn status(&self) -> Vec<Hl7ConnectionStatus> {
+        let mut statuses: Vec<Hl7ConnectionStatus> = self
+            .connections
+            .lock()
+            .await
+            .values()
+            .map(|pooled| pooled.status())
+            .collect();
+        statuses.sort_by(|a, b| a.name.cmp(&b.name));
+        statuses
+    }
+}
@@ -0,0 +1,131 @@
+// hl7_report.rs
+// MPPS 完成后向下游 RIS 发送 ORU^R01 结果或 ORM^O01 状态更新，ACK 记录在 MPPS 条目上
+use crate::dicom_utils::parse_sop_instance_uids;
+use crate::hl7_client::{Hl7Client, Hl7TlsConfig, SendMethod};
+use crate::hl7_template::escape;
+use crate::models::{Hl7ReportLog, Hl7SettingEntry, MppsEntry};
+use chrono::Local;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// 返回 MSH-9 及使用的 HL7 版本
+fn report_type(message_type: &str) -> (&'static str, &'static str) {
+    if message_type.eq_ignore_ascii_case("ORM") {
+        ("ORM^O01", "2.3")
+    } else {
+        ("ORU^R01^ORU_R01", "2.5")
+    }
+}
+
+/// Builds the report for a completed step. `message_type` is `ORU` (default)
+/// or `ORM`.
+pub fn build_report(
+    entry: &MppsEntry,
+    message_type: &str,
+    receiving_application: &str,
+    control_id: &str,
+) -> String {
+    let now = Local::now().format("%Y%m%d%H%M%S").to_string();
+    let accession = escape(&entry.accession_number);
+    let procedure = escape(&entry.requested_procedure_description);
+    let (msh_type, version) = report_type(message_type);
+    let mut segments = vec![
+        format!(
+            "MSH|^~\\&|DICOMSIM|SIMULATOR|{}||{}||{}|{}|P|{}",
+            escape(receiving_application),
+            now,
+            msh_type,
+            control_id,
+            version
+        ),
+        format!(
+            "PID|1||{}||{}||{}|{}",
+            escape(&entry.patient_id),
+            escape(&entry.patient_name),
+            escape(&entry.patient_birth_date),
+            escape(&entry.patient_sex)
+        ),
+        // ORC-5 CM 表示订单已完成
+        format!("ORC|SC|{}|{}||CM", accession, accession),
+        format!(
+            "OBR|1|{}|{}|^{}|||{}|||||||||||{}||||||{}|F",
+            accession,
+            accession,
+            procedure,
+            escape(&entry.scheduled_procedure_step_start_date),
+            accession,
+            escape(&entry.modality)
+        ),
+        format!(
+            "ZDS|{}^DICOMSIM^Application^DICOM",
+            escape(&entry.study_instance_uid)
+        ),
+    ];
+
+    if msh_type.starts_with("ORU") {
+        let instances: usize = parse_sop_instance_uids(entry)
+            .map(|series| series.iter().map(|s| s.sop_instance_infos.len()).sum())
+            .unwrap_or_default();
+        segments.push(format!(
+            "OBX|1|TX|PROCSTATUS^Procedure Status||Performed procedure step completed, {} instances||||||F|||{}",
+            instances, now
+        ));
+        if let Some(description) = entry.description.as_deref().filter(|d| !d.is_empty()) {
+            segments.push(format!(
+                "OBX|2|TX|COMMENT^Comment||{}||||||F|||{}",
+                escape(description),
+                now
+            ));
+        }
+    }
+    segments.join("\r")
+}
+
+/// Sends the report over MLLP (or MLLP/TLS) and records the ACK, or why there is none.
+/// Failures are only logged: the MPPS step is completed either way.
+pub async fn send_report(
+    entry: &MppsEntry,
+    setting: &Hl7SettingEntry,
+    message_type: &str,
+    certs_dir: PathBuf,
+) -> Hl7ReportLog {
+    let now = Local::now();
+    let control_id = format!(
+        "R{}{:03}",
+        now.format("%Y%m%d%H%M%S"),
+        now.timestamp_subsec_millis()
+    );
+    let message = build_report(entry, message_type, &setting.name, &control_id);
+    let mut log = Hl7ReportLog {
+        message_type: report_type(message_type).0.to_string(),
+        destination: format!("{} [{}:{}]", setting.name, setting.ip, setting.port),
+        sent_at: now.to_rfc3339(),
+        control_id,
+        message: message.replace('\r', "\n"),
+        ack: None,
+        error: None,
+    };
+
+    let port = match setting.port.trim().parse() {
+        Ok(port) => port,
+        Err(_) => {
+            log.error = Some(format!("Invalid port number: {}", setting.port));
+            return log;
+        }
+    };
+    let mut client = Hl7Client::new(message, setting.ip.clone(), port, SendMethod::Tcp)
+        .with_tls(Hl7TlsConfig::from_setting(setting, certs_dir));
+    if let Some(seconds) = setting.ack_timeout.filter(|s| *s > 0) {
+        client = client.with_ack_timeout(Duration::from_secs(seconds));
+    }
+    match client.send_message().await {
+        Ok(result) => {
+            if let Some(ack) = result.ack.as_ref().filter(|ack| !ack.accepted) {
+                log.error = Some(ack.summary(&result.control_id));
+            }
+            log.ack = result.ack;
+        }
+        Err(e) => log.error = Some(e.to_string()),
+    }
+    log
+}
@@ -0,0 +1,205 @@
+// hl7_server.rs
+// 通用的 MLLP 监听器，每个连接上逐帧读取报文并交给 Hl7Service 处理
+use crate::hl7_client::{normalize_segments, parse_message, Hl7ClientError};
+use crate::models::Hl7ListenerStatus;
+use bytes::BytesMut;
+use chrono::Local;
+use futures::future::BoxFuture;
+use futures::{SinkExt, StreamExt};
+use hl7_mllp_codec::MllpCodec;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio_util::codec::Framed;
+
+// accept 失败后重试前的等待时间
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// One message received on an MLLP connection.
+pub struct Hl7Request {
+    pub remote_addr: String,
+    /// The message with `\r` segment separators.
+    pub message: String,
+}
+
+impl Hl7Request {
+    /// A field of the MSH segment, numbered as in the standard (MSH-9 etc).
+    pub fn msh(&self, field: usize) -> String {
+        let Ok(message) = parse_message(&self.message) else {
+            return String::new();
+        };
+        match message.segments.first() {
+            Some(msh) if msh.fields[0].source == "MSH" && field > 1 => msh[field - 1].to_string(),
+            Some(msh) if msh.fields[0].source == "MSH" && field == 1 => {
+                message.get_separators().field.to_string()
+            }
+            _ => String::new(),
+        }
+    }
+
+    pub fn is_hl7(&self) -> bool {
+        parse_message(&self.message).is_ok()
+    }
+}
+
+/// The service behind an MLLP listener.
+pub trait Hl7Service: Send + Sync {
+    /// Handles one message and returns the ACK to send back, or `None` to
+    /// leave the message unanswered.
+    fn handle<'a>(&'a self, request: &'a Hl7Request) -> BoxFuture<'a, Option<String>>;
+}
+
+/// Builds an ACK for `request` with a new control ID. AE/AR carry an ERR segment.
+pub fn build_ack(request: &Hl7Request, ack_code: &str, text: &str, control_id: &str) -> String {
+    let message_type = request.msh(9);
+    let event = message_type.split('^').nth(1).unwrap_or_default();
+    let version = request.msh(12);
+    let mut ack = format!(
+        "MSH|^~\\&|{}|{}|{}|{}|{}||ACK^{}^ACK|{}|{}|{}\rMSA|{}|{}",
+        request.msh(5),
+        request.msh(6),
+        request.msh(3),
+        request.msh(4),
+        Local::now().format("%Y%m%d%H%M%S"),
+        event,
+        control_id,
+        request.msh(11),
+        version,
+        ack_code,
+        request.msh(10),
+    );
+    if !text.is_empty() {
+        ack.push_str(&format!("|{}", text));
+    }
+    // 错误码取自 HL7 表 0357
+    let (code, description) = match ack_code {
+        "AE" | "CE" => ("207", "Application internal error"),
+        "AR" | "CR" => ("200", "Unsupported message type"),
+        _ => return ack,
+    };
+    // v2.5 之前 ERR 只有 ERR-1
+    if version.as_str() < "2.5" {
+        ack.push_str(&format!("\rERR|^^^{}&{}", code, description));
+    } else {
+        ack.push_str(&format!(
+            "\rERR|||{}^{}^HL70357|E||||{}",
+            code, description, text
+        ));
+    }
+    ack
+}
+
+/// A running MLLP listener. Stopping it also closes its open connections.
+pub struct Hl7Server {
+    port: u16,
+    started_at: String,
+    connections: Arc<AtomicU64>,
+    messages: Arc<AtomicU64>,
+    handle: JoinHandle<()>,
+}
+
+impl Hl7Server {
+    pub async fn start(
+        port: u16,
+        service: Arc<dyn Hl7Service>,
+    ) -> Result<Hl7Server, Hl7ClientError> {
+        let listener = TcpListener::bind(("0.0.0.0", port)).await.map_err(|e| {
+            Hl7ClientError::new(&format!("Failed to listen on port {}: {}", port, e))
+        })?;
+
+        let connections = Arc::new(AtomicU64::new(0));
+        let messages = Arc::new(AtomicU64::new(0));
+        let handle = tokio::spawn(accept_loop(
+            listener,
+            service,
+            connections.clone(),
+            messages.clone(),
+        ));
+        Ok(Hl7Server {
+            port,
+            started_at: Local::now().to_rfc3339(),
+            connections,
+            messages,
+            handle,
+        })
+    }
+
+    pub fn stop(self) {
+        self.handle.abort();
+    }
+
+    pub fn status(&self) -> Hl7ListenerStatus {
+        Hl7ListenerStatus {
+            port: self.port,
+            started_at: self.started_at.clone(),
+            connections: self.connections.load(Ordering::Relaxed),
+            messages: self.messages.load(Ordering::Relaxed),
+        }
+    }
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    service: Arc<dyn Hl7Service>,
+    connections: Arc<AtomicU64>,
+    messages: Arc<AtomicU64>,
+) {
+    // 连接任务挂在 JoinSet 上，监听任务被 abort 时会一并结束
+    let mut tasks = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (socket, remote_addr) = match accepted {
+                    Ok(accepted) => accepted,
+                    // EMFILE 等错误会持续出现，稍等再重试以免空转
+                    Err(e) => {
+                        eprintln!("Failed to accept a HL7 connection: {}", e);
+                        tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                        continue;
+                    }
+                };
+                connections.fetch_add(1, Ordering::Relaxed);
+                tasks.spawn(serve_connection(
+                    socket,
+                    remote_addr,
+                    service.clone(),
+                    messages.clone(),
+                ));
+            }
+            Some(_) = tasks.join_next(), if !tasks.is_empty() => {}
+        }
+    }
+}
+
+async fn serve_connection(
+    socket: TcpStream,
+    remote_addr: SocketAddr,
+    service: Arc<dyn Hl7Service>,
+    messages: Arc<AtomicU64>,
+) {
+    let mut transport = Framed::new(socket, MllpCodec::new());
+    while let Some(frame) = transport.next().await {
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(e) => {
+                eprintln!("HL7 listener: {} closed: {}", remote_addr, e);
+                return;
+            }
+        };
+        messages.fetch_add(1, Ordering::Relaxed);
+        let request = Hl7Request {
+            remote_addr: remote_addr.to_string(),
+            message: normalize_segments(&String::from_utf8_lossy(&frame)),
+        };
+        let Some(ack) = service.handle(&request).await else {
+            continue;
+        };
+        if let Err(e) = transport.send(BytesMut::from(ack.as_bytes())).await {
+            eprintln!("HL7 listener: Failed to send ACK to {}: {}", remote_addr, e);
+            return;
+        }
+    }
+}
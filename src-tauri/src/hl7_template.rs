@@ -0,0 +1,163 @@
+// hl7_template.rs
+// HL7 报文模板中的 {{...}} 占位符，在发送或预览时替换
+// 内置: now / date / time / control_id / accession / uid，其余取自选中的 patient 或 mpps 条目
+use crate::utils::generate_uid;
+use chrono::Local;
+use std::collections::HashMap;
+
+/// Field values of a `PatientEntry` or `MppsEntry`, keyed by their serde names.
+pub fn entry_values(entry: &serde_json::Value) -> HashMap<String, String> {
+    let Some(fields) = entry.as_object() else {
+        return HashMap::new();
+    };
+    fields
+        .iter()
+        .filter_map(|(key, value)| {
+            let value = match value {
+                serde_json::Value::String(s) => s.clone(),
+                serde_json::Value::Number(n) => n.to_string(),
+                serde_json::Value::Bool(b) => b.to_string(),
+                _ => return None,
+            };
+            Some((key.clone(), value))
+        })
+        .collect()
+}
+
+// PatientName、patient_name 与 patientname 视为同一个名称
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| *c != '_')
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+// 值中的字段分隔符和换行会破坏报文结构，按 HL7 转义
+pub(crate) fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\E\\")
+        .replace('|', "\\F\\")
+        .replace(['\r', '\n'], " ")
+}
+
+/// Replaces the `{{name}}` placeholders of `template`. Built-in placeholders
+/// get one value per call, so a control ID repeated in the message stays the
+/// same. Fails with the names of the placeholders that have no value.
+pub fn render(template: &str, values: &HashMap<String, String>) -> Result<String, String> {
+    let now = Local::now();
+    let serial = uuid::Uuid::new_v4().as_u128();
+    let mut builtins: HashMap<&str, String> = HashMap::new();
+    builtins.insert("now", now.format("%Y%m%d%H%M%S").to_string());
+    builtins.insert("date", now.format("%Y%m%d").to_string());
+    builtins.insert("time", now.format("%H%M%S").to_string());
+    // MSH-10 最长 20 个字符
+    builtins.insert(
+        "controlid",
+        format!("{}{:04}", now.format("%Y%m%d%H%M%S"), serial % 10_000),
+    );
+    builtins.insert("accession", format!("A{:010}", serial % 10_000_000_000));
+    builtins.insert("uid", generate_uid());
+    let values: HashMap<String, &String> = values.iter().map(|(k, v)| (normalize(k), v)).collect();
+
+    let mut rendered = String::with_capacity(template.len());
+    let mut missing: Vec<String> = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        rendered.push_str(&rest[..start]);
+        let name = rest[start + 2..start + end].trim();
+        let key = normalize(name);
+        match builtins.get(key.as_str()) {
+            Some(value) => rendered.push_str(value),
+            None => match values.get(&key) {
+                Some(value) => rendered.push_str(&escape(value)),
+                None => {
+                    if !missing.iter().any(|m| m == name) {
+                        missing.push(name.to_string());
+                    }
+                }
+            },
+        }
+        rest = &rest[start + end + 2..];
+    }
+    rendered.push_str(rest);
+
+    if missing.is_empty() {
+        Ok(rendered)
+    } else {
+        Err(format!("No value for placeholder: {}", missing.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn fills_entry_fields_regardless_of_case_and_underscores() {
+        let values = values(&[("PatientName", "Doe^John"), ("patient_id", "P1001")]);
+        let rendered = render(
+            "PID|1||{{patient_id}}||{{ patient_name }}||{{PATIENTNAME}}",
+            &values,
+        )
+        .unwrap();
+        assert_eq!(rendered, "PID|1||P1001||Doe^John||Doe^John");
+    }
+
+    #[test]
+    fn keeps_builtins_stable_within_one_message() {
+        let rendered = render("{{control_id}}|{{controlId}}|{{date}}", &HashMap::new()).unwrap();
+        let parts: Vec<&str> = rendered.split('|').collect();
+        assert_eq!(parts[0], parts[1]);
+        assert!(parts[0].len() <= 20);
+        assert_eq!(parts[2], Local::now().format("%Y%m%d").to_string());
+    }
+
+    #[test]
+    fn escapes_separators_in_values() {
+        let values = values(&[("description", "CT|Chest\\Abd\r\nfollow-up")]);
+        let rendered = render("OBR|1|||{{description}}", &values).unwrap();
+        assert_eq!(rendered, "OBR|1|||CT\\F\\Chest\\E\\Abd  follow-up");
+    }
+
+    #[test]
+    fn reports_every_unresolved_placeholder_once() {
+        let values = values(&[("PatientID", "P1001")]);
+        let error = render(
+            "PID|1||{{PatientID}}||{{PatientName}}|{{Ward}}|{{PatientName}}",
+            &values,
+        )
+        .unwrap_err();
+        assert_eq!(error, "No value for placeholder: PatientName, Ward");
+    }
+
+    #[test]
+    fn leaves_unterminated_braces_alone() {
+        let rendered = render("NTE|1||{{PatientID", &values(&[("PatientID", "P1")])).unwrap();
+        assert_eq!(rendered, "NTE|1||{{PatientID");
+    }
+
+    #[test]
+    fn reads_scalar_fields_of_an_entry() {
+        let entry = serde_json::json!({
+            "PatientName": "Doe^John",
+            "size": 3,
+            "generate": true,
+            "status": null,
+            "fhir_publish": [],
+        });
+        let values = entry_values(&entry);
+        assert_eq!(values.len(), 3);
+        assert_eq!(values["size"], "3");
+        assert_eq!(values["generate"], "true");
+    }
+}
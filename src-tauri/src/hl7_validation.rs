@@ -0,0 +1,962 @@
+// hl7_validation.rs
+// 按消息结构 (v2.5) 检查 HL7 报文：必需段、段顺序与重复、必填字段、字段长度及常用数据类型
+// 支持 ADT^A01/A04/A08、ORM^O01、ORU^R01、SIU^S12，Z 段不参与结构检查
+use crate::hl7_client::{normalize_segments, parse_message};
+use serde::{Deserialize, Serialize};
+
+/// One problem found in a message. `segment` is the 1-based position of the
+/// segment in the message, `field` the field number as in the standard.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hl7ValidationIssue {
+    pub severity: String, // error / warning
+    pub code: String, // MISSING_SEGMENT / UNEXPECTED_SEGMENT / REQUIRED_FIELD / FIELD_LENGTH / DATA_TYPE ...
+    pub location: String, // 如 PID-3、OBX[2]-14
+    pub segment: Option<usize>,
+    pub field: Option<usize>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hl7ValidationResult {
+    pub message_type: String,      // MSH-9
+    pub structure: Option<String>, // 使用的消息结构，如 ADT_A01
+    pub valid: bool,               // 没有 error 级别的问题
+    pub issues: Vec<Hl7ValidationIssue>,
+}
+
+impl Hl7ValidationResult {
+    pub fn summary(&self) -> String {
+        let errors = self
+            .issues
+            .iter()
+            .filter(|issue| issue.severity == "error")
+            .count();
+        let warnings = self.issues.len() - errors;
+        let structure = self.structure.as_deref().unwrap_or("no structure profile");
+        match (errors, warnings) {
+            (0, 0) => format!("{} is valid ({})", self.message_type, structure),
+            _ => format!(
+                "{} ({}): {} error(s), {} warning(s)",
+                self.message_type, structure, errors, warnings
+            ),
+        }
+    }
+}
+
+// ---- 消息结构 ----
+
+enum Rule {
+    Segment(&'static str),
+    // 同一位置可出现的几种段之一，如 ORM 的 OBR / RQD / RXO
+    Choice(&'static [&'static str]),
+    Group(&'static str, &'static [Item]),
+}
+
+struct Item {
+    rule: Rule,
+    required: bool,
+    repeat: bool,
+}
+
+const fn seg(name: &'static str, required: bool, repeat: bool) -> Item {
+    Item {
+        rule: Rule::Segment(name),
+        required,
+        repeat,
+    }
+}
+
+const fn choice(names: &'static [&'static str], required: bool) -> Item {
+    Item {
+        rule: Rule::Choice(names),
+        required,
+        repeat: false,
+    }
+}
+
+const fn group(name: &'static str, items: &'static [Item], required: bool, repeat: bool) -> Item {
+    Item {
+        rule: Rule::Group(name, items),
+        required,
+        repeat,
+    }
+}
+
+const R: bool = true;
+const O: bool = false;
+const REP: bool = true;
+const ONE: bool = false;
+
+const ADT_A01: &[Item] = &[
+    seg("MSH", R, ONE),
+    seg("SFT", O, REP),
+    seg("EVN", R, ONE),
+    seg("PID", R, ONE),
+    seg("PD1", O, ONE),
+    seg("ROL", O, REP),
+    seg("NK1", O, REP),
+    seg("PV1", R, ONE),
+    seg("PV2", O, ONE),
+    seg("ROL", O, REP),
+    seg("DB1", O, REP),
+    seg("OBX", O, REP),
+    seg("AL1", O, REP),
+    seg("DG1", O, REP),
+    seg("DRG", O, ONE),
+    group(
+        "PROCEDURE",
+        &[seg("PR1", R, ONE), seg("ROL", O, REP)],
+        O,
+        REP,
+    ),
+    seg("GT1", O, REP),
+    group(
+        "INSURANCE",
+        &[
+            seg("IN1", R, ONE),
+            seg("IN2", O, ONE),
+            seg("IN3", O, REP),
+            seg("ROL", O, REP),
+        ],
+        O,
+        REP,
+    ),
+    seg("ACC", O, ONE),
+    seg("UB1", O, ONE),
+    seg("UB2", O, ONE),
+    seg("PDA", O, ONE),
+];
+
+const ORM_O01: &[Item] = &[
+    seg("MSH", R, ONE),
+    seg("NTE", O, REP),
+    group(
+        "PATIENT",
+        &[
+            seg("PID", R, ONE),
+            seg("PD1", O, ONE),
+            seg("NTE", O, REP),
+            group(
+                "PATIENT_VISIT",
+                &[seg("PV1", R, ONE), seg("PV2", O, ONE)],
+                O,
+                ONE,
+            ),
+            group(
+                "INSURANCE",
+                &[seg("IN1", R, ONE), seg("IN2", O, ONE), seg("IN3", O, ONE)],
+                O,
+                REP,
+            ),
+            seg("GT1", O, ONE),
+            seg("AL1", O, REP),
+        ],
+        O,
+        ONE,
+    ),
+    group(
+        "ORDER",
+        &[
+            seg("ORC", R, ONE),
+            group(
+                "ORDER_DETAIL",
+                &[
+                    choice(&["OBR", "RQD", "RQ1", "RXO", "ODS", "ODT"], R),
+                    seg("NTE", O, REP),
+                    seg("CTD", O, ONE),
+                    seg("DG1", O, REP),
+                    group(
+                        "OBSERVATION",
+                        &[seg("OBX", R, ONE), seg("NTE", O, REP)],
+                        O,
+                        REP,
+                    ),
+                ],
+                O,
+                ONE,
+            ),
+            seg("FT1", O, REP),
+            seg("CTI", O, REP),
+            seg("BLG", O, ONE),
+        ],
+        R,
+        REP,
+    ),
+];
+
+const ORU_R01: &[Item] = &[
+    seg("MSH", R, ONE),
+    seg("SFT", O, REP),
+    group(
+        "PATIENT_RESULT",
+        &[
+            group(
+                "PATIENT",
+                &[
+                    seg("PID", R, ONE),
+                    seg("PD1", O, ONE),
+                    seg("NTE", O, REP),
+                    seg("NK1", O, REP),
+                    group("VISIT", &[seg("PV1", R, ONE), seg("PV2", O, ONE)], O, ONE),
+                ],
+                O,
+                ONE,
+            ),
+            group(
+                "ORDER_OBSERVATION",
+                &[
+                    seg("ORC", O, ONE),
+                    seg("OBR", R, ONE),
+                    seg("NTE", O, REP),
+                    seg("TQ1", O, REP),
+                    seg("CTD", O, ONE),
+                    group(
+                        "OBSERVATION",
+                        &[seg("OBX", R, ONE), seg("NTE", O, REP)],
+                        O,
+                        REP,
+                    ),
+                    seg("FT1", O, REP),
+                    seg("CTI", O, REP),
+                    group(
+                        "SPECIMEN",
+                        &[seg("SPM", R, ONE), seg("OBX", O, REP)],
+                        O,
+                        REP,
+                    ),
+                ],
+                R,
+                REP,
+            ),
+        ],
+        R,
+        REP,
+    ),
+    seg("DSC", O, ONE),
+];
+
+const SIU_S12: &[Item] = &[
+    seg("MSH", R, ONE),
+    seg("SCH", R, ONE),
+    seg("TQ1", O, REP),
+    seg("NTE", O, REP),
+    group(
+        "PATIENT",
+        &[
+            seg("PID", R, ONE),
+            seg("PD1", O, ONE),
+            seg("PV1", O, ONE),
+            seg("PV2", O, ONE),
+            seg("OBX", O, REP),
+            seg("DG1", O, REP),
+        ],
+        O,
+        REP,
+    ),
+    group(
+        "RESOURCES",
+        &[
+            seg("RGS", R, ONE),
+            group("SERVICE", &[seg("AIS", R, ONE), seg("NTE", O, REP)], O, REP),
+            group(
+                "GENERAL_RESOURCE",
+                &[seg("AIG", R, ONE), seg("NTE", O, REP)],
+                O,
+                REP,
+            ),
+            group(
+                "LOCATION_RESOURCE",
+                &[seg("AIL", R, ONE), seg("NTE", O, REP)],
+                O,
+                REP,
+            ),
+            group(
+                "PERSONNEL_RESOURCE",
+                &[seg("AIP", R, ONE), seg("NTE", O, REP)],
+                O,
+                REP,
+            ),
+        ],
+        R,
+        REP,
+    ),
+];
+
+// MSH-9 的消息类型与触发事件对应的结构
+fn structure_for(message_type: &str) -> Option<(&'static str, &'static [Item])> {
+    let mut parts = message_type.split('^').map(|p| p.trim().to_uppercase());
+    let code = parts.next().unwrap_or_default();
+    let event = parts.next().unwrap_or_default();
+    match (code.as_str(), event.as_str()) {
+        ("ADT", "A01" | "A04" | "A08") => Some(("ADT_A01", ADT_A01)),
+        ("ORM", "O01") => Some(("ORM_O01", ORM_O01)),
+        ("ORU", "R01") => Some(("ORU_R01", ORU_R01)),
+        ("SIU", "S12") => Some(("SIU_S12", SIU_S12)),
+        _ => None,
+    }
+}
+
+// 组可以由哪些段开始
+fn first_segments(items: &[Item], names: &mut Vec<&'static str>) {
+    for item in items {
+        match &item.rule {
+            Rule::Segment(name) => names.push(name),
+            Rule::Choice(choices) => names.extend_from_slice(choices),
+            Rule::Group(_, items) => first_segments(items, names),
+        }
+        if item.required {
+            return;
+        }
+    }
+}
+
+fn item_starts(item: &Item, name: &str) -> bool {
+    let mut names = Vec::new();
+    first_segments(std::slice::from_ref(item), &mut names);
+    names.contains(&name)
+}
+
+fn item_label(item: &Item) -> String {
+    match &item.rule {
+        Rule::Segment(name) => name.to_string(),
+        Rule::Choice(names) => names.join(" / "),
+        Rule::Group(name, items) => {
+            let mut names = Vec::new();
+            first_segments(items, &mut names);
+            format!("{} group ({})", name, names.join(" / "))
+        }
+    }
+}
+
+fn contains_segment(items: &[Item], name: &str) -> bool {
+    items.iter().any(|item| match &item.rule {
+        Rule::Segment(segment) => *segment == name,
+        Rule::Choice(names) => names.contains(&name),
+        Rule::Group(_, items) => contains_segment(items, name),
+    })
+}
+
+// 贪心匹配，不回溯；返回匹配结束的位置
+fn match_items(
+    items: &[Item],
+    segments: &[(usize, &str)],
+    mut pos: usize,
+    issues: &mut Vec<Hl7ValidationIssue>,
+) -> usize {
+    for item in items {
+        let mut matched = 0;
+        while let Some((_, name)) = segments.get(pos) {
+            if (matched > 0 && !item.repeat) || !item_starts(item, name) {
+                break;
+            }
+            pos = match &item.rule {
+                Rule::Segment(_) | Rule::Choice(_) => pos + 1,
+                Rule::Group(_, items) => match_items(items, segments, pos, issues),
+            };
+            matched += 1;
+        }
+        if matched == 0 && item.required {
+            let (segment, found) = match segments.get(pos) {
+                Some((index, name)) => (Some(*index), format!(", found {}", name)),
+                None => (None, " at the end of the message".to_string()),
+            };
+            issues.push(Hl7ValidationIssue {
+                severity: "error".to_string(),
+                code: "MISSING_SEGMENT".to_string(),
+                location: item_label(item),
+                segment,
+                field: None,
+                message: format!("Required {} is missing{}", item_label(item), found),
+            });
+        }
+    }
+    pos
+}
+
+// ---- 字段规则 ----
+
+#[derive(Clone, Copy, PartialEq)]
+enum DataType {
+    St, // 字符串、编码值及复合类型，只检查长度
+    Si, // 序号
+    Nm, // 数值
+    Dt, // 日期
+    Ts, // 时间戳，检查第一个组件
+}
+
+struct FieldRule {
+    field: usize,
+    name: &'static str,
+    data_type: DataType,
+    max_length: usize,
+    required: bool,
+}
+
+const fn field(
+    field: usize,
+    name: &'static str,
+    data_type: DataType,
+    max_length: usize,
+    required: bool,
+) -> FieldRule {
+    FieldRule {
+        field,
+        name,
+        data_type,
+        max_length,
+        required,
+    }
+}
+
+use DataType::{Dt, Nm, Si, St, Ts};
+
+// 长度取自 v2.5 各字段的最大长度
+const FIELD_RULES: &[(&str, &[FieldRule])] = &[
+    (
+        "MSH",
+        &[
+            field(3, "Sending Application", St, 227, O),
+            field(4, "Sending Facility", St, 227, O),
+            field(5, "Receiving Application", St, 227, O),
+            field(6, "Receiving Facility", St, 227, O),
+            field(7, "Date/Time Of Message", Ts, 26, R),
+            field(9, "Message Type", St, 15, R),
+            field(10, "Message Control ID", St, 20, R),
+            field(11, "Processing ID", St, 3, R),
+            field(12, "Version ID", St, 60, R),
+        ],
+    ),
+    (
+        "EVN",
+        &[
+            field(1, "Event Type Code", St, 3, O),
+            field(2, "Recorded Date/Time", Ts, 26, R),
+            field(6, "Event Occurred", Ts, 26, O),
+        ],
+    ),
+    (
+        "PID",
+        &[
+            field(1, "Set ID", Si, 4, O),
+            field(3, "Patient Identifier List", St, 250, R),
+            field(5, "Patient Name", St, 250, R),
+            field(6, "Mother's Maiden Name", St, 250, O),
+            field(7, "Date/Time of Birth", Ts, 26, O),
+            field(8, "Administrative Sex", St, 1, O),
+            field(18, "Patient Account Number", St, 250, O),
+            field(29, "Patient Death Date and Time", Ts, 26, O),
+        ],
+    ),
+    (
+        "PV1",
+        &[
+            field(1, "Set ID", Si, 4, O),
+            field(2, "Patient Class", St, 1, R),
+            field(3, "Assigned Patient Location", St, 80, O),
+            field(19, "Visit Number", St, 250, O),
+            field(44, "Admit Date/Time", Ts, 26, O),
+            field(45, "Discharge Date/Time", Ts, 26, O),
+        ],
+    ),
+    ("NK1", &[field(1, "Set ID", Si, 4, R)]),
+    (
+        "AL1",
+        &[
+            field(1, "Set ID", Si, 4, R),
+            field(3, "Allergen Code", St, 250, R),
+        ],
+    ),
+    (
+        "DG1",
+        &[
+            field(1, "Set ID", Si, 4, R),
+            field(5, "Diagnosis Date/Time", Ts, 26, O),
+            field(6, "Diagnosis Type", St, 2, R),
+        ],
+    ),
+    (
+        "IN1",
+        &[
+            field(1, "Set ID", Si, 4, R),
+            field(2, "Insurance Plan ID", St, 250, R),
+            field(3, "Insurance Company ID", St, 250, R),
+            field(12, "Plan Effective Date", Dt, 8, O),
+            field(13, "Plan Expiration Date", Dt, 8, O),
+        ],
+    ),
+    (
+        "ORC",
+        &[
+            field(1, "Order Control", St, 2, R),
+            field(2, "Placer Order Number", St, 22, O),
+            field(3, "Filler Order Number", St, 22, O),
+            field(5, "Order Status", St, 2, O),
+            field(9, "Date/Time of Transaction", Ts, 26, O),
+        ],
+    ),
+    (
+        "OBR",
+        &[
+            field(1, "Set ID", Si, 4, O),
+            field(2, "Placer Order Number", St, 22, O),
+            field(3, "Filler Order Number", St, 22, O),
+            field(4, "Universal Service Identifier", St, 250, R),
+            field(6, "Requested Date/Time", Ts, 26, O),
+            field(7, "Observation Date/Time", Ts, 26, O),
+            field(22, "Results Rpt/Status Chng - Date/Time", Ts, 26, O),
+            field(24, "Diagnostic Serv Sect ID", St, 10, O),
+            field(25, "Result Status", St, 1, O),
+        ],
+    ),
+    (
+        "OBX",
+        &[
+            field(1, "Set ID", Si, 4, O),
+            field(2, "Value Type", St, 2, O),
+            field(3, "Observation Identifier", St, 250, R),
+            field(11, "Observation Result Status", St, 1, R),
+            field(14, "Date/Time of the Observation", Ts, 26, O),
+        ],
+    ),
+    ("NTE", &[field(1, "Set ID", Si, 4, O)]),
+    (
+        "TQ1",
+        &[
+            field(1, "Set ID", Si, 4, O),
+            field(7, "Start date/time", Ts, 26, O),
+            field(8, "End date/time", Ts, 26, O),
+        ],
+    ),
+    (
+        "SCH",
+        &[
+            field(1, "Placer Appointment ID", St, 75, O),
+            field(2, "Filler Appointment ID", St, 75, O),
+            field(6, "Event Reason", St, 250, R),
+            field(9, "Appointment Duration", Nm, 20, O),
+        ],
+    ),
+    ("RGS", &[field(1, "Set ID", Si, 4, R)]),
+    (
+        "AIS",
+        &[
+            field(1, "Set ID", Si, 4, R),
+            field(3, "Universal Service Identifier", St, 250, R),
+            field(4, "Start Date/Time", Ts, 26, O),
+        ],
+    ),
+    (
+        "AIG",
+        &[
+            field(1, "Set ID", Si, 4, R),
+            field(3, "Resource ID", St, 250, O),
+            field(8, "Start Date/Time", Ts, 26, O),
+        ],
+    ),
+    (
+        "AIL",
+        &[
+            field(1, "Set ID", Si, 4, R),
+            field(3, "Location Resource ID", St, 80, O),
+            field(6, "Start Date/Time", Ts, 26, O),
+        ],
+    ),
+    (
+        "AIP",
+        &[
+            field(1, "Set ID", Si, 4, R),
+            field(3, "Personnel Resource ID", St, 250, O),
+            field(6, "Start Date/Time", Ts, 26, O),
+        ],
+    ),
+    ("SPM", &[field(1, "Set ID", Si, 4, O)]),
+];
+
+fn field_rules(segment: &str) -> &'static [FieldRule] {
+    FIELD_RULES
+        .iter()
+        .find(|(name, _)| *name == segment)
+        .map(|(_, rules)| *rules)
+        .unwrap_or_default()
+}
+
+fn all_digits(value: &str) -> bool {
+    !value.is_empty() && value.chars().all(|c| c.is_ascii_digit())
+}
+
+// YYYY[MM[DD]]
+fn valid_date(value: &str) -> bool {
+    if !all_digits(value) || ![4, 6, 8].contains(&value.len()) {
+        return false;
+    }
+    let month = value.get(4..6).map(|m| m.parse::<u32>().unwrap_or(0));
+    let day = value.get(6..8).map(|d| d.parse::<u32>().unwrap_or(0));
+    month.is_none_or(|m| (1..=12).contains(&m)) && day.is_none_or(|d| (1..=31).contains(&d))
+}
+
+// YYYY[MM[DD[HH[MM[SS[.S[S[S[S]]]]]]]]][+/-ZZZZ]
+fn valid_timestamp(value: &str) -> bool {
+    let (value, zone) = match value.find(['+', '-']) {
+        Some(i) => (&value[..i], Some(&value[i + 1..])),
+        None => (value, None),
+    };
+    if zone.is_some_and(|z| z.len() != 4 || !all_digits(z)) {
+        return false;
+    }
+    let (value, fraction) = match value.split_once('.') {
+        Some((value, fraction)) => (value, Some(fraction)),
+        None => (value, None),
+    };
+    if fraction.is_some_and(|f| f.len() > 4 || !all_digits(f) || value.len() != 14) {
+        return false;
+    }
+    if !all_digits(value) || ![4, 6, 8, 10, 12, 14].contains(&value.len()) {
+        return false;
+    }
+    let part =
+        |range: std::ops::Range<usize>| value.get(range).map(|p| p.parse::<u32>().unwrap_or(99));
+    valid_date(&value[..value.len().min(8)])
+        && part(8..10).is_none_or(|h| h < 24)
+        && part(10..12).is_none_or(|m| m < 60)
+        && part(12..14).is_none_or(|s| s < 60)
+}
+
+fn valid_number(value: &str) -> bool {
+    let digits = value.strip_prefix(['+', '-']).unwrap_or(value);
+    let mut parts = digits.splitn(2, '.');
+    let integer = parts.next().unwrap_or_default();
+    let fraction = parts.next();
+    (!integer.is_empty() || fraction.is_some_and(|f| !f.is_empty()))
+        && integer.chars().all(|c| c.is_ascii_digit())
+        && fraction.is_none_or(|f| f.chars().all(|c| c.is_ascii_digit()))
+}
+
+struct Separators {
+    field: char,
+    component: char,
+    repeat: char,
+}
+
+fn check_fields(
+    name: &str,
+    index: usize,
+    occurrence: usize,
+    line: &str,
+    separators: &Separators,
+    issues: &mut Vec<Hl7ValidationIssue>,
+) {
+    let fields: Vec<&str> = line.split(separators.field).collect();
+    let label = if occurrence > 1 {
+        format!("{}[{}]", name, occurrence)
+    } else {
+        name.to_string()
+    };
+    for rule in field_rules(name) {
+        // MSH-1 是字段分隔符本身，MSH 的字段号比下标多 1
+        let position = if name == "MSH" {
+            rule.field - 1
+        } else {
+            rule.field
+        };
+        let value = fields.get(position).copied().unwrap_or_default();
+        let location = format!("{}-{}", label, rule.field);
+        let mut issue = |severity: &str, code: &str, message: String| {
+            issues.push(Hl7ValidationIssue {
+                severity: severity.to_string(),
+                code: code.to_string(),
+                location: location.clone(),
+                segment: Some(index),
+                field: Some(rule.field),
+                message,
+            });
+        };
+        if value.is_empty() {
+            if rule.required {
+                issue(
+                    "error",
+                    "REQUIRED_FIELD",
+                    format!("{} ({}) is required", rule.name, location),
+                );
+            }
+            continue;
+        }
+        // 模板中尚未替换的占位符不检查格式
+        if value.contains("{{") {
+            continue;
+        }
+        for repetition in value.split(separators.repeat) {
+            if repetition == "\"\"" {
+                continue;
+            }
+            let length = repetition.chars().count();
+            if length > rule.max_length {
+                issue(
+                    "warning",
+                    "FIELD_LENGTH",
+                    format!(
+                        "{} ({}) is {} characters long, the maximum is {}",
+                        rule.name, location, length, rule.max_length
+                    ),
+                );
+            }
+            let first = repetition
+                .split(separators.component)
+                .next()
+                .unwrap_or_default();
+            let (valid, expected) = match rule.data_type {
+                St => (true, ""),
+                Si => (
+                    all_digits(repetition) && repetition.parse::<u64>().is_ok_and(|n| n > 0),
+                    "a positive sequence number",
+                ),
+                Nm => (valid_number(repetition), "a number"),
+                Dt => (valid_date(first), "a date (YYYY[MM[DD]])"),
+                Ts => (
+                    valid_timestamp(first),
+                    "a timestamp (YYYY[MM[DD[HH[MM[SS[.S]]]]]][+/-ZZZZ])",
+                ),
+            };
+            if !valid {
+                issue(
+                    "error",
+                    "DATA_TYPE",
+                    format!(
+                        "{} ({}) should be {}, got '{}'",
+                        rule.name, location, expected, repetition
+                    ),
+                );
+            }
+        }
+    }
+}
+
+/// Validates the structure, required fields, field lengths and common data
+/// types of `message`. Message types without a structure profile only get
+/// their segments checked field by field.
+pub fn validate(message: &str) -> Hl7ValidationResult {
+    let source = normalize_segments(message);
+    let mut issues = Vec::new();
+    if let Err(e) = parse_message(&source) {
+        return Hl7ValidationResult {
+            message_type: String::new(),
+            structure: None,
+            valid: false,
+            issues: vec![Hl7ValidationIssue {
+                severity: "error".to_string(),
+                code: "PARSE".to_string(),
+                location: "MSH".to_string(),
+                segment: Some(1),
+                field: None,
+                message: format!("Failed to parse HL7 message: {}", e),
+            }],
+        };
+    }
+
+    // MSH-1 和 MSH-2 定义分隔符
+    let mut chars = source.chars().skip(3);
+    let separators = Separators {
+        field: chars.next().unwrap_or('|'),
+        component: chars.next().unwrap_or('^'),
+        repeat: chars.next().unwrap_or('~'),
+    };
+    let lines: Vec<&str> = source.split('\r').filter(|l| !l.is_empty()).collect();
+    let message_type = lines[0]
+        .split(separators.field)
+        .nth(8)
+        .unwrap_or_default()
+        .to_string();
+    let message_type_key = message_type.replace(separators.component, "^");
+
+    let mut segments: Vec<(usize, &str)> = Vec::new();
+    let mut occurrences: Vec<(&str, usize)> = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        let name = line.split(separators.field).next().unwrap_or_default();
+        let index = i + 1;
+        if name.len() != 3
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+        {
+            issues.push(Hl7ValidationIssue {
+                severity: "error".to_string(),
+                code: "SEGMENT_NAME".to_string(),
+                location: format!("segment {}", index),
+                segment: Some(index),
+                field: None,
+                message: format!("'{}' is not a valid segment name", name),
+            });
+            continue;
+        }
+        let occurrence = match occurrences.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, count)) => {
+                *count += 1;
+                *count
+            }
+            None => {
+                occurrences.push((name, 1));
+                1
+            }
+        };
+        check_fields(name, index, occurrence, line, &separators, &mut issues);
+        // Z 段为自定义段，可以出现在任何位置
+        if !name.starts_with('Z') {
+            segments.push((index, name));
+        }
+    }
+
+    let structure = structure_for(&message_type_key);
+    match structure {
+        Some((structure_name, items)) => {
+            let end = match_items(items, &segments, 0, &mut issues);
+            for (index, name) in &segments[end..] {
+                let message = if contains_segment(items, name) {
+                    format!(
+                        "{} is out of order or repeated more often than {} allows",
+                        name, structure_name
+                    )
+                } else {
+                    format!("{} is not part of {}", name, structure_name)
+                };
+                issues.push(Hl7ValidationIssue {
+                    severity: "error".to_string(),
+                    code: "UNEXPECTED_SEGMENT".to_string(),
+                    location: name.to_string(),
+                    segment: Some(*index),
+                    field: None,
+                    message,
+                });
+            }
+        }
+        None => issues.push(Hl7ValidationIssue {
+            severity: "warning".to_string(),
+            code: "UNSUPPORTED_TYPE".to_string(),
+            location: "MSH-9".to_string(),
+            segment: Some(1),
+            field: Some(9),
+            message: format!(
+                "No structure profile for {}; only the fields of known segments were checked",
+                message_type
+            ),
+        }),
+    }
+
+    issues.sort_by_key(|issue| issue.segment.unwrap_or(usize::MAX));
+    Hl7ValidationResult {
+        valid: !issues.iter().any(|issue| issue.severity == "error"),
+        message_type,
+        structure: structure.map(|(name, _)| name.to_string()),
+        issues,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADT_A01_MESSAGE: &str =
+        "MSH|^~\\&|ADT|HOSP|SIM|DICOM|20240101120000||ADT^A01^ADT_A01|MSG0001|P|2.5\r\
+        EVN|A01|20240101120000\r\
+        PID|1||P1001^^^HOSP^MR||Doe^John^A||19800101|M\r\
+        PV1|1|I|WARD1^101^1\r\
+        OBX|1|NM|8302-2^Body height^LN||180|cm|||||F\r\
+        AL1|1||PEN^Penicillin\r";
+
+    fn codes(result: &Hl7ValidationResult) -> Vec<(&str, &str)> {
+        result
+            .issues
+            .iter()
+            .map(|issue| (issue.code.as_str(), issue.location.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn accepts_a_valid_adt_a01() {
+        let result = validate(ADT_A01_MESSAGE);
+        assert!(result.valid, "{:?}", result.issues);
+        assert!(result.issues.is_empty());
+        assert_eq!(result.message_type, "ADT^A01^ADT_A01");
+        assert_eq!(result.structure.as_deref(), Some("ADT_A01"));
+        assert_eq!(result.summary(), "ADT^A01^ADT_A01 is valid (ADT_A01)");
+    }
+
+    #[test]
+    fn reports_a_missing_pv1() {
+        let message = ADT_A01_MESSAGE.replace("PV1|1|I|WARD1^101^1\r", "");
+        let result = validate(&message);
+        assert!(!result.valid);
+        let issue = &result.issues[0];
+        assert_eq!(issue.code, "MISSING_SEGMENT");
+        assert_eq!(issue.location, "PV1");
+        assert_eq!(issue.segment, Some(4));
+        assert_eq!(issue.message, "Required PV1 is missing, found OBX");
+    }
+
+    #[test]
+    fn reports_an_obx_before_its_obr() {
+        let message = "MSH|^~\\&|LAB|HOSP|SIM|DICOM|20240101120000||ORU^R01|MSG0002|P|2.5\r\
+            PID|1||P1001||Doe^John\r\
+            OBX|1|ST|TEXT^Impression||No acute findings||||||F\r\
+            OBR|1|ORD1|ACC1|CTCHEST^CT Chest\r";
+        let result = validate(message);
+        assert!(!result.valid);
+        assert_eq!(
+            codes(&result),
+            vec![
+                ("MISSING_SEGMENT", "ORDER_OBSERVATION group (ORC / OBR)"),
+                ("UNEXPECTED_SEGMENT", "OBX"),
+                ("UNEXPECTED_SEGMENT", "OBR"),
+            ]
+        );
+        assert_eq!(
+            result.issues[1].message,
+            "OBX is out of order or repeated more often than ORU_R01 allows"
+        );
+    }
+
+    #[test]
+    fn checks_required_fields_and_data_types() {
+        let message = ADT_A01_MESSAGE
+            .replace("PID|1||P1001^^^HOSP^MR|", "PID|1|||")
+            .replace("19800101", "19801301");
+        let result = validate(&message);
+        assert!(!result.valid);
+        assert_eq!(
+            codes(&result),
+            vec![("REQUIRED_FIELD", "PID-3"), ("DATA_TYPE", "PID-7")]
+        );
+    }
+
+    #[test]
+    fn applies_the_same_profile_to_v23_messages() {
+        let message = ADT_A01_MESSAGE.replace("|P|2.5\r", "|P|2.3\r");
+        let result = validate(&message);
+        assert!(result.valid, "{:?}", result.issues);
+        assert_eq!(result.structure.as_deref(), Some("ADT_A01"));
+    }
+
+    #[test]
+    fn only_checks_fields_of_acks_with_v23_or_v25_err() {
+        // v2.3 的 ERR-1 与 v2.5 的 ERR-2..ERR-8 都不参与结构检查
+        for err in [
+            "ERR|PID^1^3^101&Required field missing",
+            "ERR||PID^1^3|101^Required field missing^HL70357|E",
+        ] {
+            let message = format!(
+                "MSH|^~\\&|RIS|HOSP|SIM|DICOM|20240101120000||ACK^A01|ACK0001|P|2.5\r\
+                 MSA|AE|MSG0001\r{}\r",
+                err
+            );
+            let result = validate(&message);
+            assert!(result.valid, "{:?}", result.issues);
+            assert_eq!(codes(&result), vec![("UNSUPPORTED_TYPE", "MSH-9")]);
+            assert_eq!(result.structure, None);
+        }
+    }
+
+    #[test]
+    fn rejects_text_that_is_not_hl7() {
+        let result = validate("PID|1||P1001");
+        assert!(!result.valid);
+        assert_eq!(codes(&result), vec![("PARSE", "MSH")]);
+    }
+}
@@ -1,22 +1,74 @@
 // main.rs or wherever your main logic resides
+// 命令行单独构建时，共用模块里只给桌面程序用的部分不算 dead code
+#![cfg_attr(not(feature = "desktop"), allow(dead_code, unused_imports))]
+pub mod cli;
+#[cfg(feature = "desktop")]
+mod api_commands;
+#[cfg(feature = "desktop")]
+mod api_server;
 mod models;
+#[cfg(feature = "desktop")]
+mod mpps_scp;
+mod dicom_client;
+#[cfg(feature = "desktop")]
+mod dicom_server;
+mod dicom_utils;
+#[cfg(feature = "desktop")]
+mod fhir_client;
+#[cfg(feature = "desktop")]
+mod hl7_bridge;
 mod hl7_client;
+#[cfg(feature = "desktop")]
+mod hl7_listener;
+#[cfg(feature = "desktop")]
+mod hl7_pool;
+#[cfg(feature = "desktop")]
+mod hl7_report;
+#[cfg(feature = "desktop")]
+mod hl7_server;
+mod hl7_template;
+#[cfg(feature = "desktop")]
+mod hl7_validation;
+mod load_test;
 mod paths;
+mod scenario;
+#[cfg(feature = "desktop")]
+mod storage_commitment;
+#[cfg(feature = "desktop")]
+mod storage_scp;
+#[cfg(feature = "desktop")]
+mod ups;
 mod utils;
+#[cfg(feature = "desktop")]
 mod worklist; // Add this line to include the paths module
+#[cfg(feature = "desktop")]
+mod worklist_scp;
 
-use models::DbState;
+// 桌面程序部分，命令行 (--no-default-features) 不编译
+#[cfg(feature = "desktop")]
+use dicom_client::NativeDimseClient;
+#[cfg(feature = "desktop")]
+use hl7_pool::{Hl7ConnectionStatus, Hl7Pool, HL7_CONNECTION_EVENT};
+#[cfg(feature = "desktop")]
+use models::{
+    ApiServerState, DbState, DimseState, Hl7ListenerState, Hl7PoolState, LoadTestState, ScpState,
+};
+#[cfg(feature = "desktop")]
 use paths::AppPath;
+#[cfg(feature = "desktop")]
 use std::sync::Arc;
 
+#[cfg(feature = "desktop")]
 use surrealdb::engine::local::File;
 
+#[cfg(feature = "desktop")]
 use surrealdb::Surreal;
-use tauri::{command, AppHandle, Manager, State};
-use tokio::sync::Mutex;
-use utils::set_python_env; // Import the AppPath enum
+#[cfg(feature = "desktop")]
+use tauri::{command, AppHandle, Emitter, Manager, State};
+#[cfg(feature = "desktop")]
 use tauri_plugin_fs::FsExt;
 
+#[cfg(feature = "desktop")]
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
     tauri::Builder::default()
@@ -29,8 +81,23 @@ pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
                 }
             });
 
-            set_python_env(&app.handle())
-                .map_err(|e| format!("Failed to set Python environment: {}", e))?;
+            let certs_dir = AppPath::Certs.resolve(app.handle())?;
+            app.manage(DimseState {
+                client: Arc::new(NativeDimseClient::new(certs_dir)),
+            });
+            app.manage(ScpState::default());
+            app.manage(Hl7ListenerState::default());
+            app.manage(ApiServerState::default());
+            app.manage(LoadTestState::default());
+            // 保持连接的状态变化推送给前端
+            let pool_handle = app.handle().clone();
+            app.manage(Hl7PoolState {
+                pool: Hl7Pool::new(Some(Arc::new(move |status: &Hl7ConnectionStatus| {
+                    if let Err(e) = pool_handle.emit(HL7_CONNECTION_EVENT, status) {
+                        eprintln!("Failed to emit {}: {}", HL7_CONNECTION_EVENT, e);
+                    }
+                }))),
+            });
 
                       // allowed the given directory
           let scope = app.fs_scope();
@@ -49,6 +116,9 @@ pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
             worklist::delete_worklist_entry,
             worklist::create_mpps_entry,
             worklist::update_mpps_entry,
+            worklist::discontinue_mpps_entry,
+            worklist::read_discontinuation_reasons,
+            worklist::echo_endpoint,
             worklist::delete_mpps_entry,
             worklist::read_mpps_entry,
             worklist::get_base_dicom_dir,
@@ -57,11 +127,34 @@ pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
             worklist::read_mim_entry,
             worklist::create_mim_entry,
             worklist::send_to_pacs,
+            worklist::request_storage_commitment,
+            worklist::create_ups_entry,
+            worklist::claim_ups_entry,
+            worklist::update_ups_entry,
+            worklist::complete_ups_entry,
+            worklist::cancel_ups_entry,
+            worklist::read_ups_entry,
+            worklist::delete_ups_entry,
+            worklist::subscribe_ups,
+            worklist::unsubscribe_ups,
+            worklist::read_ups_subscription,
+            worklist::read_ups_event,
+            worklist::delete_ups_event,
             worklist::create_hl7_setting_entry,
             worklist::read_hl7_setting_entry,
             worklist::update_hl7_setting_entry,
             worklist::delete_hl7_setting_entry,
+            worklist::read_hl7_connections,
+            worklist::close_hl7_connection,
+            worklist::create_fhir_setting_entry,
+            worklist::read_fhir_setting_entry,
+            worklist::update_fhir_setting_entry,
+            worklist::delete_fhir_setting_entry,
+            worklist::send_fhir_resource,
             worklist::send_hl7_message,
+            worklist::send_hl7_batch,
+            worklist::preview_hl7_message,
+            worklist::validate_hl7_message,
             worklist::create_hl7_message_setting,
             worklist::read_hl7_message_setting,
             worklist::update_hl7_message_setting,
@@ -74,6 +167,37 @@ pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
             worklist::update_patient_entry,
             worklist::delete_patient_entry,
             worklist::send_cstore_headless,
+            worklist::start_worklist_scp,
+            worklist::start_mpps_scp,
+            worklist::read_received_mpps_entry,
+            worklist::delete_received_mpps_entry,
+            worklist::start_storage_scp,
+            worklist::read_received_instance,
+            worklist::read_storage_scp_associations,
+            worklist::stop_scp,
+            worklist::read_scp_status,
+            worklist::start_hl7_listener,
+            worklist::stop_hl7_listener,
+            worklist::read_hl7_listener_status,
+            worklist::read_received_hl7_message,
+            worklist::delete_received_hl7_message,
+            worklist::schedule_hl7_order,
+            worklist::read_api_setting,
+            worklist::start_api_server,
+            worklist::stop_api_server,
+            worklist::read_api_server_status,
+            worklist::create_scenario,
+            worklist::read_scenario,
+            worklist::update_scenario,
+            worklist::delete_scenario,
+            worklist::run_scenario,
+            worklist::read_scenario_run,
+            worklist::delete_scenario_run,
+            worklist::start_load_test,
+            worklist::stop_load_test,
+            worklist::read_load_test_status,
+            worklist::read_load_test_run,
+            worklist::delete_load_test_run,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -81,6 +205,7 @@ pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[cfg(feature = "desktop")]
 async fn async_init_db(app_handle: AppHandle) -> Result<(), Box<dyn std::error::Error>> {
     let database_folder = AppPath::Database.resolve(&app_handle)?;
     let db_path = std::path::Path::new(&database_folder);
@@ -98,9 +223,15 @@ async fn async_init_db(app_handle: AppHandle) -> Result<(), Box<dyn std::error::
         .await
         .map_err(|e| format!("Failed to create SurrealDB instance: {}", e))?;
     db.use_ns("test").use_db("test").await?;
-    let db_state = DbState {
-        db: Arc::new(Mutex::new(db)),
-    };
+    // 自动化接口设置为自动开启时，数据库就绪后启动
+    let api_setting = worklist::read_api_setting_record(&db).await?;
+    let db_state = DbState::new(db);
     app_handle.manage(db_state);
+    if let Some(setting) = api_setting.filter(|s| s.auto_start.unwrap_or(false)) {
+        let api_state = app_handle.state::<ApiServerState>();
+        if let Err(e) = worklist::launch_api_server(&api_state, app_handle.clone(), &setting).await {
+            eprintln!("Failed to start the automation API: {}", e);
+        }
+    }
     Ok(())
 }
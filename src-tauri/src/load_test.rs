@@ -0,0 +1,545 @@
+// load_test.rs
+// 压力测试：模拟 N 台设备 (各自的 Calling AE)，在 tokio 上并发执行 worklist 查询 -> MPPS IN PROGRESS
+// -> C-STORE -> MPPS COMPLETED，结束后汇总各操作的延时和失败统计
+// 运行过程中不访问数据库，只在结束后保存报告
+use crate::dicom_client::{DicomNode, DimseClient};
+use crate::dicom_utils::{
+    build_completed, build_in_progress, collect_dcm_files, query_worklist, read_dicom_file,
+    rewrite_instance,
+};
+use crate::models::{
+    LoadTestConfig, MimEntry, MppsEntry, MppsStatus, SopInstanceUids, WorklistEntry,
+};
+use crate::scenario::ScenarioTemplates;
+use crate::utils::generate_uid;
+use chrono::Local;
+use dicom_dictionary_std::uids;
+use dicom_object::DefaultDicomObject;
+use futures::Future;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use surrealdb::sql::Thing;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+pub const LOAD_TEST_RUN_TABLE: &str = "load_test_run";
+pub const LOAD_TEST_FINISHED_EVENT: &str = "load-test-finished";
+
+const MAX_MODALITIES: u32 = 500;
+const MAX_DURATION_SECS: u64 = 24 * 60 * 60;
+const DEFAULT_AE_PREFIX: &str = "SIM";
+// 不同的错误信息最多记录的条数，其余归入 "other"
+const MAX_ERROR_KINDS: usize = 20;
+
+// 一个流程中的操作，按执行顺序
+const OPERATIONS: [&str; 5] = ["cycle", "c_find", "n_create", "c_store", "n_set"];
+
+/// Latency statistics of one operation, in milliseconds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OperationStats {
+    pub operation: String,
+    pub count: u64,
+    pub failures: u64,
+    pub min_ms: u64,
+    pub mean_ms: f64,
+    pub p50_ms: u64,
+    pub p95_ms: u64,
+    pub p99_ms: u64,
+    pub max_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorCount {
+    pub message: String,
+    pub count: u64,
+}
+
+/// Aggregated results of a load test. While the test runs, `running` is set
+/// and the figures cover the cycles finished so far.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoadTestReport {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Thing>,
+    pub worklist: String,
+    pub pacs: Option<String>,
+    pub config: LoadTestConfig,
+    pub running: bool,
+    pub stopped: bool, // 提前停止
+    pub started_at: String,
+    pub finished_at: Option<String>,
+    pub elapsed_ms: u64,
+    pub active_modalities: u32,
+    pub cycles: u64,
+    pub failed_cycles: u64,
+    pub cycles_per_second: f64,
+    pub operations: Vec<OperationStats>,
+    pub errors: Vec<ErrorCount>,
+}
+
+impl LoadTestReport {
+    pub fn summary(&self) -> String {
+        format!(
+            "{} cycles, {} failed, {:.2} cycles/s",
+            self.cycles, self.failed_cycles, self.cycles_per_second
+        )
+    }
+}
+
+#[derive(Default)]
+struct Recorder {
+    samples: HashMap<&'static str, Vec<u64>>,
+    failures: HashMap<&'static str, u64>,
+    errors: HashMap<String, u64>,
+    active: u32,
+}
+
+impl Recorder {
+    fn record(&mut self, operation: &'static str, elapsed: Duration, error: Option<&str>) {
+        self.samples
+            .entry(operation)
+            .or_default()
+            .push(elapsed.as_millis() as u64);
+        if let Some(error) = error {
+            *self.failures.entry(operation).or_default() += 1;
+            let key = if self.errors.len() < MAX_ERROR_KINDS || self.errors.contains_key(error) {
+                error.to_string()
+            } else {
+                "other".to_string()
+            };
+            *self.errors.entry(key).or_default() += 1;
+        }
+    }
+
+    fn operations(&self) -> Vec<OperationStats> {
+        OPERATIONS
+            .iter()
+            .filter_map(|operation| {
+                let samples = self.samples.get(operation).filter(|s| !s.is_empty())?;
+                let mut sorted = samples.clone();
+                sorted.sort_unstable();
+                let percentile = |p: f64| {
+                    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+                    sorted[rank.clamp(1, sorted.len()) - 1]
+                };
+                Some(OperationStats {
+                    operation: operation.to_string(),
+                    count: sorted.len() as u64,
+                    failures: self.failures.get(operation).copied().unwrap_or(0),
+                    min_ms: sorted[0],
+                    mean_ms: sorted.iter().sum::<u64>() as f64 / sorted.len() as f64,
+                    p50_ms: percentile(50.0),
+                    p95_ms: percentile(95.0),
+                    p99_ms: percentile(99.0),
+                    max_ms: sorted[sorted.len() - 1],
+                })
+            })
+            .collect()
+    }
+}
+
+/// Stops a load test and reads its statistics while it runs.
+#[derive(Clone)]
+pub struct LoadTestHandle {
+    recorder: Arc<Mutex<Recorder>>,
+    cancel: CancellationToken,
+    worklist: String,
+    pacs: Option<String>,
+    config: LoadTestConfig,
+    started_at: String,
+    started: Instant,
+}
+
+impl LoadTestHandle {
+    /// Virtual modalities finish their current cycle and stop.
+    pub fn stop(&self) {
+        self.cancel.cancel();
+    }
+
+    pub fn report(&self) -> LoadTestReport {
+        self.build_report(true)
+    }
+
+    fn build_report(&self, running: bool) -> LoadTestReport {
+        let recorder = self.recorder.lock().unwrap();
+        let operations = recorder.operations();
+        let cycle = operations.iter().find(|o| o.operation == "cycle");
+        let cycles = cycle.map_or(0, |c| c.count);
+        let elapsed = self.started.elapsed();
+        let mut errors: Vec<ErrorCount> = recorder
+            .errors
+            .iter()
+            .map(|(message, count)| ErrorCount {
+                message: message.clone(),
+                count: *count,
+            })
+            .collect();
+        errors.sort_by_key(|e| Reverse(e.count));
+        LoadTestReport {
+            id: None,
+            worklist: self.worklist.clone(),
+            pacs: self.pacs.clone(),
+            config: self.config.clone(),
+            running,
+            stopped: self.cancel.is_cancelled(),
+            started_at: self.started_at.clone(),
+            finished_at: (!running).then(|| Local::now().to_rfc3339()),
+            elapsed_ms: elapsed.as_millis() as u64,
+            active_modalities: recorder.active,
+            cycles,
+            failed_cycles: cycle.map_or(0, |c| c.failures),
+            cycles_per_second: cycles as f64 / elapsed.as_secs_f64().max(0.001),
+            operations,
+            errors,
+        }
+    }
+}
+
+struct Shared {
+    client: Arc<dyn DimseClient>,
+    worklist: WorklistEntry,
+    pacs: Option<MimEntry>,
+    templates: ScenarioTemplates,
+    series: Vec<LoadedSeries>,
+    handle: LoadTestHandle,
+}
+
+/// Images read from `dcm_path` once at start. Every cycle sends copies of
+/// them under new series and instance UIDs.
+struct LoadedSeries {
+    uids: SopInstanceUids,
+    objects: Vec<DefaultDicomObject>,
+}
+
+impl LoadedSeries {
+    fn load(uids: SopInstanceUids) -> Result<LoadedSeries, String> {
+        let objects = uids
+            .sop_instance_infos
+            .iter()
+            .map(|info| read_dicom_file(Path::new(&info.path)))
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(LoadedSeries { uids, objects })
+    }
+
+    // 同样的文件，新的 Series / SOP Instance UID
+    fn renew_uids(&self) -> SopInstanceUids {
+        let mut uids = self.uids.clone();
+        uids.series_instance_uid = generate_uid();
+        for info in &mut uids.sop_instance_infos {
+            info.sop_instance_uid = generate_uid();
+        }
+        uids
+    }
+}
+
+/// A load test against one worklist/MPPS endpoint and an optional PACS.
+pub struct LoadTest {
+    shared: Arc<Shared>,
+}
+
+impl LoadTest {
+    /// Checks the configuration. A relative `dcm_path` starts at `dicom_dir`.
+    pub fn new(
+        client: Arc<dyn DimseClient>,
+        worklist: WorklistEntry,
+        pacs: Option<MimEntry>,
+        templates: ScenarioTemplates,
+        dicom_dir: &Path,
+        config: LoadTestConfig,
+    ) -> Result<LoadTest, String> {
+        if config.modalities == 0 || config.modalities > MAX_MODALITIES {
+            return Err(format!(
+                "The number of modalities must be 1 to {}",
+                MAX_MODALITIES
+            ));
+        }
+        if config.duration_secs == 0 || config.duration_secs > MAX_DURATION_SECS {
+            return Err(format!(
+                "The duration must be 1 to {} seconds",
+                MAX_DURATION_SECS
+            ));
+        }
+        if config.ramp_up_secs > config.duration_secs {
+            return Err("The ramp-up must not be longer than the duration".to_string());
+        }
+        if config
+            .cycles_per_minute
+            .is_some_and(|rate| !rate.is_finite() || rate <= 0.0)
+        {
+            return Err("The rate must be more than 0 cycles per minute".to_string());
+        }
+        // AE 标题最长 16 个字符，编号占 3 位
+        let prefix = ae_prefix(&config);
+        if prefix.is_empty() || prefix.len() > 13 || !prefix.is_ascii() {
+            return Err(format!("Invalid AE title prefix: {}", prefix));
+        }
+        // 文件只在开始时读取一次，流程中不再访问磁盘
+        let series = match config.dcm_path.as_deref().filter(|p| !p.trim().is_empty()) {
+            Some(path) => {
+                let path = if Path::new(path).is_absolute() {
+                    PathBuf::from(path)
+                } else {
+                    dicom_dir.join(path)
+                };
+                let series = collect_dcm_files(&path)?;
+                if series.is_empty() {
+                    return Err(format!("No .dcm files found in {}", path.display()));
+                }
+                series
+                    .into_iter()
+                    .map(LoadedSeries::load)
+                    .collect::<Result<Vec<_>, String>>()?
+            }
+            None => Vec::new(),
+        };
+
+        let handle = LoadTestHandle {
+            recorder: Arc::new(Mutex::new(Recorder::default())),
+            cancel: CancellationToken::new(),
+            worklist: worklist.name.clone(),
+            pacs: pacs.as_ref().map(|pacs| pacs.name.clone()),
+            config,
+            started_at: Local::now().to_rfc3339(),
+            started: Instant::now(),
+        };
+        Ok(LoadTest {
+            shared: Arc::new(Shared {
+                client,
+                worklist,
+                pacs,
+                templates,
+                series,
+                handle,
+            }),
+        })
+    }
+
+    pub fn handle(&self) -> LoadTestHandle {
+        self.shared.handle.clone()
+    }
+
+    /// Runs every virtual modality until the duration is over or the test is
+    /// stopped, and returns the final statistics.
+    pub async fn run(self) -> LoadTestReport {
+        let config = &self.shared.handle.config;
+        let deadline = self.shared.handle.started + Duration::from_secs(config.duration_secs);
+        let mut modalities = JoinSet::new();
+        for index in 0..config.modalities {
+            // 在 ramp-up 时间内均匀地启动各台设备
+            let delay = Duration::from_secs(config.ramp_up_secs) * index / config.modalities;
+            modalities.spawn(modality(self.shared.clone(), index, delay, deadline));
+        }
+        while modalities.join_next().await.is_some() {}
+        self.shared.handle.build_report(false)
+    }
+}
+
+fn ae_prefix(config: &LoadTestConfig) -> String {
+    config
+        .ae_prefix
+        .as_deref()
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .unwrap_or(DEFAULT_AE_PREFIX)
+        .to_string()
+}
+
+async fn modality(shared: Arc<Shared>, index: u32, delay: Duration, deadline: Instant) {
+    let cancel = &shared.handle.cancel;
+    tokio::select! {
+        _ = tokio::time::sleep(delay) => {}
+        _ = cancel.cancelled() => return,
+    }
+
+    // 每台虚拟设备使用自己的 Calling AE
+    let ae_title = format!("{}{:03}", ae_prefix(&shared.handle.config), index + 1);
+    let mut worklist = shared.worklist.clone();
+    worklist.calling_ae_title = ae_title.clone();
+    worklist.mpps_calling_ae_title = ae_title.clone();
+    let pacs = shared.pacs.clone().map(|mut pacs| {
+        pacs.calling_ae_title = ae_title.clone();
+        pacs
+    });
+    let interval = shared
+        .handle
+        .config
+        .cycles_per_minute
+        .map(|rate| Duration::from_secs_f64(60.0 / rate));
+
+    shared.handle.recorder.lock().unwrap().active += 1;
+    let mut next = Instant::now();
+    let mut cycle = 0;
+    while !cancel.is_cancelled() && Instant::now() < deadline {
+        let started = Instant::now();
+        let result = run_cycle(&shared, &worklist, pacs.as_ref(), index as usize + cycle).await;
+        shared.handle.recorder.lock().unwrap().record(
+            "cycle",
+            started.elapsed(),
+            result.as_ref().err().map(String::as_str),
+        );
+        cycle += 1;
+        if let Some(interval) = interval {
+            next += interval;
+            tokio::select! {
+                _ = tokio::time::sleep_until(next.min(deadline).into()) => {}
+                _ = cancel.cancelled() => break,
+            }
+            // 周期超时后从当前时间重新计时，不连续补发落下的周期
+            let now = Instant::now();
+            if next < now {
+                next = now;
+            }
+        }
+    }
+    shared.handle.recorder.lock().unwrap().active -= 1;
+}
+
+// 记录一次操作的耗时，失败时同时记录错误信息
+async fn timed<T>(
+    shared: &Shared,
+    operation: &'static str,
+    future: impl Future<Output = Result<T, String>>,
+) -> Result<T, String> {
+    let started = Instant::now();
+    let result = future.await;
+    shared.handle.recorder.lock().unwrap().record(
+        operation,
+        started.elapsed(),
+        result.as_ref().err().map(String::as_str),
+    );
+    result
+}
+
+async fn run_cycle(
+    shared: &Shared,
+    worklist: &WorklistEntry,
+    pacs: Option<&MimEntry>,
+    pick: usize,
+) -> Result<(), String> {
+    let templates = &shared.templates;
+    let entries = timed(
+        shared,
+        "c_find",
+        query_worklist(shared.client.as_ref(), worklist, templates.queries.clone()),
+    )
+    .await?;
+    if entries.is_empty() {
+        return Err("The worklist returned no items".to_string());
+    }
+    // 各台设备轮流选择不同的条目
+    let mut entry: MppsEntry = entries[pick % entries.len()].clone();
+
+    let node = DicomNode::mpps(worklist).map_err(|e| e.to_string())?;
+    let mpps_instance_uid = generate_uid();
+    let attributes = build_in_progress(templates.in_progress.clone(), &entry, worklist)?;
+    timed(shared, "n_create", async {
+        shared
+            .client
+            .n_create(
+                &node,
+                uids::MODALITY_PERFORMED_PROCEDURE_STEP,
+                &mpps_instance_uid,
+                attributes,
+            )
+            .await
+            .map_err(|e| format!("N-CREATE failed: {}", e))
+    })
+    .await?;
+    entry.mpps_instance_uid = Some(mpps_instance_uid.clone());
+    entry.transition(MppsStatus::InProgress)?;
+
+    // 每个流程重新生成 Series / SOP Instance UID
+    let series: Vec<SopInstanceUids> =
+        shared.series.iter().map(LoadedSeries::renew_uids).collect();
+    if let (Some(pacs), false) = (pacs, series.is_empty()) {
+        let node = DicomNode::storage(pacs).map_err(|e| e.to_string())?;
+        let mut objects = Vec::new();
+        for (uids, loaded) in series.iter().zip(&shared.series) {
+            for (info, obj) in uids.sop_instance_infos.iter().zip(&loaded.objects) {
+                let mut obj = obj.clone();
+                rewrite_instance(
+                    &mut obj,
+                    &entry,
+                    &uids.series_instance_uid,
+                    &info.sop_instance_uid,
+                );
+                objects.push(obj);
+            }
+        }
+        timed(shared, "c_store", async {
+            shared
+                .client
+                .c_store(&node, objects)
+                .await
+                .map_err(|e| format!("C-STORE failed: {}", e))
+        })
+        .await?;
+    }
+
+    let modifications = build_completed(templates.completed.clone(), &entry, &series)?;
+    timed(shared, "n_set", async {
+        shared
+            .client
+            .n_set(
+                &node,
+                uids::MODALITY_PERFORMED_PROCEDURE_STEP,
+                &mpps_instance_uid,
+                modifications,
+            )
+            .await
+            .map_err(|e| format!("N-SET failed: {}", e))
+    })
+    .await?;
+    entry.transition(MppsStatus::Completed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarizes_latencies_and_failures_per_operation() {
+        let mut recorder = Recorder::default();
+        for ms in (1..=20).rev() {
+            let error = (ms % 10 == 0).then_some("C-FIND failed: timeout");
+            recorder.record("c_find", Duration::from_millis(ms), error);
+        }
+        recorder.record("cycle", Duration::from_millis(250), None);
+
+        let operations = recorder.operations();
+        let names: Vec<&str> = operations.iter().map(|o| o.operation.as_str()).collect();
+        assert_eq!(names, ["cycle", "c_find"]);
+
+        let c_find = &operations[1];
+        assert_eq!(c_find.count, 20);
+        assert_eq!(c_find.failures, 2);
+        assert_eq!(c_find.min_ms, 1);
+        assert_eq!(c_find.mean_ms, 10.5);
+        assert_eq!(c_find.p50_ms, 10);
+        assert_eq!(c_find.p95_ms, 19);
+        assert_eq!(c_find.p99_ms, 20);
+        assert_eq!(c_find.max_ms, 20);
+        assert_eq!(recorder.errors["C-FIND failed: timeout"], 2);
+
+        let cycle = &operations[0];
+        assert_eq!((cycle.min_ms, cycle.p95_ms, cycle.max_ms), (250, 250, 250));
+        assert_eq!(cycle.failures, 0);
+    }
+
+    #[test]
+    fn groups_errors_beyond_the_limit_as_other() {
+        let mut recorder = Recorder::default();
+        for i in 0..MAX_ERROR_KINDS + 3 {
+            recorder.record("n_set", Duration::ZERO, Some(&format!("error {}", i)));
+        }
+        recorder.record("n_set", Duration::ZERO, Some("error 0"));
+
+        assert_eq!(recorder.errors.len(), MAX_ERROR_KINDS + 1);
+        assert_eq!(recorder.errors["other"], 3);
+        assert_eq!(recorder.errors["error 0"], 2);
+        assert_eq!(recorder.failures["n_set"], MAX_ERROR_KINDS as u64 + 4);
+    }
+}
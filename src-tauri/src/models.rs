@@ -1,24 +1,123 @@
 // src/models.rs
+use crate::dicom_client::{CommitmentReport, DimseClient, EchoReport};
+#[cfg(feature = "desktop")]
+use crate::api_server::ApiServer;
+#[cfg(feature = "desktop")]
+use crate::dicom_server::ScpServer;
+use crate::hl7_client::Hl7Ack;
+#[cfg(feature = "desktop")]
+use crate::hl7_pool::Hl7Pool;
+#[cfg(feature = "desktop")]
+use crate::hl7_server::Hl7Server;
+use crate::load_test::LoadTestHandle;
+use chrono::Local;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
 use std::sync::Arc;
 use surrealdb::engine::local::Db;
 use surrealdb::sql::Thing;
 use surrealdb::Surreal;
 use tokio::sync::Mutex;
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 
-#[derive(Deserialize, Debug)]
-pub struct MppsResponse {
-    pub success: bool,
-    pub message: String,
-    pub result: String, // 可以用来存储可能返回的result UID
+// MppsEntry.SopInstanceUids 中保存的序列化结构
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SopInstanceInfo {
+    pub sop_instance_uid: String,
+    pub path: String,
+    #[serde(rename = "SOPClassUID")]
+    pub sop_class_uid: String,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SopInstanceUids {
+    pub series_instance_uid: String,
+    #[serde(rename = "SOPClassUID")]
+    pub sop_class_uid: String,
+    pub sop_instance_infos: Vec<SopInstanceInfo>,
+}
 
 
 
-#[derive(Serialize, Deserialize, Debug)]
+
+// MPPS 状态机: 未开始 -> IN PROGRESS -> COMPLETED / DISCONTINUED
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MppsStatus {
+    #[serde(rename = "IN PROGRESS")]
+    InProgress,
+    #[serde(rename = "COMPLETED")]
+    Completed,
+    #[serde(rename = "DISCONTINUED")]
+    Discontinued,
+}
+
+impl MppsStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MppsStatus::InProgress => "IN PROGRESS",
+            MppsStatus::Completed => "COMPLETED",
+            MppsStatus::Discontinued => "DISCONTINUED",
+        }
+    }
+
+    /// Whether a step in `from` (`None` when not started yet) may move to `to`.
+    pub fn can_transition(from: Option<MppsStatus>, to: MppsStatus) -> bool {
+        matches!(
+            (from, to),
+            (None, MppsStatus::InProgress)
+                | (Some(MppsStatus::InProgress), MppsStatus::Completed)
+                | (Some(MppsStatus::InProgress), MppsStatus::Discontinued)
+        )
+    }
+}
+
+impl fmt::Display for MppsStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for MppsStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "IN PROGRESS" => Ok(MppsStatus::InProgress),
+            "COMPLETED" => Ok(MppsStatus::Completed),
+            "DISCONTINUED" => Ok(MppsStatus::Discontinued),
+            _ => Err(format!("Unknown mpps status: {}", s)),
+        }
+    }
+}
+
+// 旧数据中的状态是自由文本 (如 "In Progress")，读取时宽松匹配，无法识别的视为尚未开始
+fn lenient_mpps_status<'de, D>(deserializer: D) -> Result<Option<MppsStatus>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let status: Option<String> = Option::deserialize(deserializer)?;
+    Ok(status.and_then(|s| {
+        let s = s.trim().to_uppercase().replace(['_', '-'], " ");
+        match s.as_str() {
+            "INPROGRESS" => Some(MppsStatus::InProgress),
+            "COMPLETE" => Some(MppsStatus::Completed),
+            "DISCONTINUE" => Some(MppsStatus::Discontinued),
+            _ => s.parse().ok(),
+        }
+    }))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MppsTransition {
+    pub from: Option<MppsStatus>,
+    pub to: MppsStatus,
+    pub timestamp: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct MppsEntry {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub id: Option<Thing>, // 使用 Option 以便在创建时可以为空
@@ -55,7 +154,8 @@ pub struct MppsEntry {
     #[serde(rename = "StudyInstanceUID")]
     pub study_instance_uid: String,
 
-    pub status: Option<String>, // Optional to allow empty status initially
+    #[serde(default, deserialize_with = "lenient_mpps_status")]
+    pub status: Option<MppsStatus>, // None 表示尚未发送 N-CREATE
 
     #[serde(rename = "MppsInstanceUid")]
     pub mpps_instance_uid: Option<String>, 
@@ -68,9 +168,310 @@ pub struct MppsEntry {
 
     pub description: Option<String>, // Optional to allow empty status initially
 
+    #[serde(rename = "StatusHistory", default)]
+    pub status_history: Vec<MppsTransition>,
+
+    #[serde(rename = "StorageCommitment", default)]
+    pub storage_commitment: Option<StorageCommitment>,
+
+    #[serde(rename = "Hl7Report", default)]
+    pub hl7_report: Option<Hl7ReportLog>, // 完成后发送的 HL7 报告及 ACK
+
+    #[serde(rename = "FhirPublish", default)]
+    pub fhir_publish: Vec<FhirPublishLog>, // 提交到 FHIR 服务器的资源
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl MppsEntry {
+    /// Fails with a readable message when the state machine does not allow moving to `to`.
+    pub fn check_transition(&self, to: MppsStatus) -> Result<(), String> {
+        if MppsStatus::can_transition(self.status, to) {
+            Ok(())
+        } else {
+            Err(format!(
+                "Invalid mpps status transition: {} -> {}",
+                self.status.map_or("IDLE", |s| s.as_str()),
+                to
+            ))
+        }
+    }
+
+    /// Moves the entry to `to` and records the transition in its history.
+    pub fn transition(&mut self, to: MppsStatus) -> Result<(), String> {
+        self.check_transition(to)?;
+        self.status_history.push(MppsTransition {
+            from: self.status,
+            to,
+            timestamp: Local::now().to_rfc3339(),
+        });
+        self.status = Some(to);
+        Ok(())
+    }
+}
+
+// Storage Commitment 中单个实例的提交结果
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InstanceCommitment {
+    pub sop_instance_uid: String,
+    #[serde(rename = "SOPClassUID")]
+    pub sop_class_uid: String,
+    pub committed: Option<bool>, // None 表示尚未收到 N-EVENT-REPORT
+    pub failure_reason: Option<String>,
+}
+
+/// The instances last sent for a record and their storage commitment state.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StorageCommitment {
+    pub transaction_uid: String,
+    pub requested_at: Option<String>, // None 表示尚未发送 N-ACTION
+    pub completed_at: Option<String>,
+    pub instances: Vec<InstanceCommitment>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>, // N-ACTION 未能发送时的原因
+}
+
+impl StorageCommitment {
+    /// `instances` are `(SOP Class UID, SOP Instance UID)` pairs.
+    pub fn new(transaction_uid: String, instances: Vec<(String, String)>) -> StorageCommitment {
+        StorageCommitment {
+            transaction_uid,
+            requested_at: None,
+            completed_at: None,
+            error: None,
+            instances: instances
+                .into_iter()
+                .map(|(sop_class_uid, sop_instance_uid)| InstanceCommitment {
+                    sop_instance_uid,
+                    sop_class_uid,
+                    committed: None,
+                    failure_reason: None,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn references(&self) -> Vec<(String, String)> {
+        self.instances
+            .iter()
+            .map(|i| (i.sop_class_uid.clone(), i.sop_instance_uid.clone()))
+            .collect()
+    }
+
+    /// Records the result reported by the SCP. Instances the report does not
+    /// mention stay pending.
+    pub fn apply(&mut self, report: &CommitmentReport) {
+        for instance in self.instances.iter_mut() {
+            if report.committed.contains(&instance.sop_instance_uid) {
+                instance.committed = Some(true);
+                instance.failure_reason = None;
+            } else if let Some((_, reason)) = report
+                .failed
+                .iter()
+                .find(|(uid, _)| *uid == instance.sop_instance_uid)
+            {
+                instance.committed = Some(false);
+                instance.failure_reason = Some(format!("0x{:04x}", reason));
+            }
+        }
+        self.completed_at = Some(Local::now().to_rfc3339());
+    }
+
+    pub fn summary(&self) -> String {
+        let count = |state: Option<bool>| {
+            self.instances
+                .iter()
+                .filter(|i| i.committed == state)
+                .count()
+        };
+        format!(
+            "{} committed, {} failed, {} pending",
+            count(Some(true)),
+            count(Some(false)),
+            count(None)
+        )
+    }
+}
+
+// UPS 状态机: 未创建 -> SCHEDULED -> IN PROGRESS -> COMPLETED / CANCELED，SCHEDULED 也可直接 CANCELED
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpsState {
+    #[serde(rename = "SCHEDULED")]
+    Scheduled,
+    #[serde(rename = "IN PROGRESS")]
+    InProgress,
+    #[serde(rename = "COMPLETED")]
+    Completed,
+    #[serde(rename = "CANCELED")]
+    Canceled,
+}
+
+impl UpsState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UpsState::Scheduled => "SCHEDULED",
+            UpsState::InProgress => "IN PROGRESS",
+            UpsState::Completed => "COMPLETED",
+            UpsState::Canceled => "CANCELED",
+        }
+    }
+
+    /// Whether a workitem in `from` (`None` when not created yet) may move to `to`.
+    pub fn can_transition(from: Option<UpsState>, to: UpsState) -> bool {
+        matches!(
+            (from, to),
+            (None, UpsState::Scheduled)
+                | (Some(UpsState::Scheduled), UpsState::InProgress)
+                | (Some(UpsState::Scheduled), UpsState::Canceled)
+                | (Some(UpsState::InProgress), UpsState::Completed)
+                | (Some(UpsState::InProgress), UpsState::Canceled)
+        )
+    }
+}
+
+impl fmt::Display for UpsState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for UpsState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "SCHEDULED" => Ok(UpsState::Scheduled),
+            "IN PROGRESS" => Ok(UpsState::InProgress),
+            "COMPLETED" => Ok(UpsState::Completed),
+            "CANCELED" => Ok(UpsState::Canceled),
+            _ => Err(format!("Unknown ups state: {}", s)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UpsTransition {
+    pub from: Option<UpsState>,
+    pub to: UpsState,
+    pub timestamp: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UpsEntry {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Thing>, // 使用 Option 以便在创建时可以为空
+    #[serde(rename = "UpsInstanceUid")]
+    pub ups_instance_uid: Option<String>,
+
+    #[serde(rename = "ProcedureStepLabel")]
+    pub procedure_step_label: String,
+
+    #[serde(rename = "ScheduledProcedureStepPriority")]
+    pub scheduled_procedure_step_priority: String, // HIGH / MEDIUM / LOW
+
+    #[serde(rename = "ScheduledProcedureStepStartDateTime")]
+    pub scheduled_procedure_step_start_date_time: String,
+
+    #[serde(rename = "PatientName")]
+    pub patient_name: String,
+
+    #[serde(rename = "PatientID")]
+    pub patient_id: String,
+
+    #[serde(rename = "PatientBirthDate")]
+    pub patient_birth_date: String,
+
+    #[serde(rename = "PatientSex")]
+    pub patient_sex: String,
+
+    #[serde(rename = "AccessionNumber")]
+    pub accession_number: String,
+
+    #[serde(rename = "StudyInstanceUID")]
+    pub study_instance_uid: String,
+
+    pub state: Option<UpsState>, // None 表示尚未发送 N-CREATE
+
+    #[serde(rename = "TransactionUid")]
+    pub transaction_uid: Option<String>, // 认领后持有，之后的 N-SET / N-ACTION 都需要携带
+
+    pub progress: Option<u8>, // 0 - 100
+
+    pub description: Option<String>,
+
+    #[serde(rename = "StateHistory", default)]
+    pub state_history: Vec<UpsTransition>,
+}
+
+impl UpsEntry {
+    /// Fails with a readable message when the state machine does not allow moving to `to`.
+    pub fn check_transition(&self, to: UpsState) -> Result<(), String> {
+        if UpsState::can_transition(self.state, to) {
+            Ok(())
+        } else {
+            Err(format!(
+                "Invalid ups state transition: {} -> {}",
+                self.state.map_or("IDLE", |s| s.as_str()),
+                to
+            ))
+        }
+    }
+
+    /// Moves the workitem to `to` and records the transition in its history.
+    pub fn transition(&mut self, to: UpsState) -> Result<(), String> {
+        self.check_transition(to)?;
+        self.record_state(to);
+        Ok(())
+    }
+
+    /// Records a state reported by the SCP, which is authoritative for the workitem.
+    pub fn record_state(&mut self, to: UpsState) {
+        if self.state == Some(to) {
+            return;
+        }
+        self.state_history.push(UpsTransition {
+            from: self.state,
+            to,
+            timestamp: Local::now().to_rfc3339(),
+        });
+        self.state = Some(to);
+    }
+}
+
+/// A subscription to UPS events, for one workitem or globally.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpsSubscription {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Thing>,
+    pub worklist_id: String, // 订阅所用的 worklist 配置
+    pub ups_instance_uid: Option<String>, // None 表示全局订阅
+    pub deletion_lock: bool,
+    pub subscribed_at: String,
+}
+
+/// A UPS N-EVENT-REPORT received from the UPS SCP.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpsEvent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Thing>,
+    pub event_type_id: u16,
+    pub event_type: String,
+    pub ups_instance_uid: String,
+    pub calling_ae_title: String,
+    pub state: Option<UpsState>,
+    pub progress: Option<String>,
+    pub progress_description: Option<String>,
+    pub reason_for_cancellation: Option<String>,
+    pub received_at: String,
+}
+
+// MPPS 中止原因 (CID 9300)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiscontinuationReason {
+    pub code_value: String,
+    pub coding_scheme_designator: String,
+    pub code_meaning: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MimEntry {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub id: Option<Thing>, // 使用 Option 以便在创建时可以为空
@@ -90,6 +491,68 @@ pub struct Hl7SettingEntry {
     pub name: String,      // 名称字段
     pub ip: String,        // IP 地址
     pub port: String,     // 端口号
+    #[serde(rename = "ackTimeout", default)]
+    pub ack_timeout: Option<u64>, // 等待 ACK 的秒数，为空时使用默认值
+    #[serde(rename = "tlsEnabled", default)]
+    pub tls_enabled: Option<bool>, // MLLP/TLS 或 HTTPS
+    #[serde(rename = "tlsVerify", default)]
+    pub tls_verify: Option<bool>, // 校验服务端证书，默认开启
+    #[serde(rename = "tlsServerName", default)]
+    pub tls_server_name: Option<String>, // SNI 主机名
+    #[serde(rename = "tlsCaFile", default)]
+    pub tls_ca_file: Option<String>, // certs 目录下的 CA 文件，默认 ca.pem
+    #[serde(rename = "tlsCertFile", default)]
+    pub tls_cert_file: Option<String>, // 客户端证书，默认 tls.pem
+    #[serde(rename = "tlsKeyFile", default)]
+    pub tls_key_file: Option<String>, // 客户端私钥，默认 tls.key
+    #[serde(rename = "keepAlive", default)]
+    pub keep_alive: Option<bool>, // TCP 发送后保持连接，供后续报文复用
+}
+
+/// Where and how to report a completed MPPS step over HL7.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MppsReportSetting {
+    pub hl7_setting_id: String,
+    pub message_type: Option<String>, // ORU (默认) / ORM
+}
+
+/// An HL7 report sent for an MPPS step and the ACK it got.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hl7ReportLog {
+    pub message_type: String,
+    pub destination: String,
+    pub sent_at: String,
+    pub control_id: String,
+    pub message: String,
+    pub ack: Option<Hl7Ack>,
+    pub error: Option<String>, // 发送失败或 ACK 非 AA 时的原因
+}
+
+/// A FHIR R4 server that receives Patient, ServiceRequest and ImagingStudy resources.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirSettingEntry {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Thing>,
+    pub name: String,
+    #[serde(rename = "baseUrl")]
+    pub base_url: String, // 例如 http://localhost:8080/fhir
+    #[serde(rename = "authToken", default)]
+    pub auth_token: Option<String>, // Bearer token，为空时不发送 Authorization
+    #[serde(rename = "identifierSystem", default)]
+    pub identifier_system: Option<String>, // 患者 ID 和检查号的 identifier.system
+    #[serde(default)]
+    pub timeout: Option<u64>, // 请求超时秒数，为空时使用默认值
+}
+
+/// A resource created on a FHIR server for an MPPS step.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirPublishLog {
+    pub resource_type: String,
+    pub destination: String,
+    pub sent_at: String,
+    pub status: Option<u16>,       // HTTP 状态码
+    pub reference: Option<String>, // 例如 ImagingStudy/123
+    pub error: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -100,8 +563,19 @@ pub struct HL7MessageSetting {
     pub message: String,    // HL7 报文字段
 }
 
-
+/// A saved scenario script; `definition` holds the JSON parsed by `Scenario::parse`.
 #[derive(Debug, Serialize, Deserialize)]
+pub struct ScenarioEntry {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Thing>,
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub definition: String,
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WorklistEntry {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub id: Option<Thing>, // 使用 Option 以便在创建时可以为空
@@ -120,17 +594,276 @@ pub struct WorklistEntry {
 }
 
 
-pub struct DicomWorklistParams {
+/// One endpoint checked by `echo_endpoint`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EndpointEchoResult {
+    pub kind: String, // worklist / mpps / mim / hl7
+    pub id: Option<Thing>,
+    pub name: String,
+    pub target: String,
+    #[serde(flatten)]
+    pub report: EchoReport,
+}
+
+/// Listener settings of a built-in SCP.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScpSetting {
+    pub ae_title: String,
+    pub port: String,
+    #[serde(rename = "tlsEnabled")]
+    pub tls_enabled: Option<bool>,
+}
+
+/// Statistics of one association accepted by a built-in SCP.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssociationSummary {
+    pub calling_ae_title: String,
+    pub called_ae_title: String,
+    pub started_at: String,
+    pub duration_ms: u64,
+    pub requests: u64,
+    pub failures: u64,       // 返回失败状态的请求数
+    pub bytes_received: u64, // 收到的数据集字节数
+    pub error: Option<String>, // 关联异常结束时的原因
+}
+
+/// An instance received by the storage SCP, indexed by SOP Instance UID.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReceivedInstance {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Thing>,
+    #[serde(rename = "SOPInstanceUID")]
+    pub sop_instance_uid: String,
+    #[serde(rename = "SOPClassUID")]
+    pub sop_class_uid: String,
+    #[serde(rename = "PatientID")]
+    pub patient_id: String,
+    #[serde(rename = "PatientName")]
+    pub patient_name: String,
+    #[serde(rename = "StudyInstanceUID")]
+    pub study_instance_uid: String,
+    #[serde(rename = "SeriesInstanceUID")]
+    pub series_instance_uid: String,
+    #[serde(rename = "Modality")]
+    pub modality: String,
+    #[serde(rename = "TransferSyntaxUID")]
+    pub transfer_syntax: String,
+    pub path: String, // 归档文件路径
+    pub calling_ae_title: String,
+    pub received_at: String,
+    pub size: u64, // 数据集字节数
+}
+
+/// Emitted for every N-CREATE / N-SET received by the MPPS SCP.
+#[derive(Debug, Serialize)]
+pub struct MppsScpEvent {
+    pub operation: String, // N-CREATE / N-SET
+    pub sop_instance_uid: String,
     pub calling_ae_title: String,
+    pub status: String,      // 返回给设备的 DIMSE 状态
+    pub issues: Vec<String>, // 校验未通过的原因
+    pub entry: Option<MppsEntry>,
+}
+
+/// Settings of the built-in worklist SCP. `modality` and `station_ae_title` are
+/// used for the items built from the `patient` table.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorklistScpSetting {
+    pub ae_title: String,
+    pub port: String,
+    #[serde(rename = "tlsEnabled")]
+    pub tls_enabled: Option<bool>,
+    pub modality: Option<String>,
+    pub station_ae_title: Option<String>,
+}
+
+/// Settings of the HL7 MLLP listener and how it acknowledges messages.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hl7ListenerSetting {
+    pub port: String,
+    pub ack_mode: Option<String>, // AA (默认) / NONE 不回复 ACK
+    // 以 error_ack_code 应答的消息类型，按 MSH-9 匹配，如 ADT^A08 或 ORM
+    #[serde(default)]
+    pub error_message_types: Vec<String>,
+    pub error_ack_code: Option<String>, // AE / AR，默认 AE
+    pub ack_delay_ms: Option<u64>,      // 回复 ACK 前等待的毫秒数
+    pub worklist_bridge: Option<bool>,  // 收到的 ORM/OMI 转为 Worklist 条目
+}
+
+/// The running HL7 listener, as shown to the frontend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hl7ListenerStatus {
+    pub port: u16,
+    pub started_at: String,
+    pub connections: u64,
+    pub messages: u64, // 已收到的报文数
+}
+
+/// A message received by the HL7 listener, with the MSH fields used for filtering.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReceivedHl7Message {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Thing>,
+    pub sending_application: String,   // MSH-3
+    pub sending_facility: String,      // MSH-4
+    pub receiving_application: String, // MSH-5
+    pub receiving_facility: String,    // MSH-6
+    pub message_date_time: String,     // MSH-7
+    pub message_type: String,          // MSH-9，如 ADT^A01
+    pub control_id: String,            // MSH-10
+    pub processing_id: String,         // MSH-11
+    pub version_id: String,            // MSH-12
+    pub message: String,               // 原始报文，段之间以换行分隔
+    pub ack_code: Option<String>,      // 未回复 ACK 时为空
+    pub ack: Option<String>,
+    pub remote_addr: String,
+    pub received_at: String,
+}
+
+/// Scheduled steps changed by an ORM/OMI message.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Hl7BridgeResult {
+    pub scheduled: Vec<MppsEntry>,
+    pub canceled: Vec<MppsEntry>,
+    pub skipped: Vec<String>, // 检查已开始而未修改的 AccessionNumber
+}
+
+impl Hl7BridgeResult {
+    pub fn summary(&self) -> String {
+        format!(
+            "{} scheduled, {} canceled, {} skipped",
+            self.scheduled.len(),
+            self.canceled.len(),
+            self.skipped.len()
+        )
+    }
+}
+
+/// A running built-in SCP, as shown to the frontend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScpStatus {
+    pub kind: String, // worklist / mpps / storage ...
     pub ae_title: String,
-    pub ae_address: String,
-    pub ae_port: u16,
-    pub c_find_rq_path: String,
-    pub mr_modality_path: String,
+    pub port: u16,
+    #[serde(rename = "tlsEnabled")]
+    pub tls_enabled: bool,
+    pub started_at: String,
+    pub associations: u64, // 已接受的连接数
 }
 
+// Surreal<Db> 本身可以并发使用，命令之间不再共用一把锁
 pub struct DbState {
-    pub db: Arc<Mutex<Surreal<Db>>>,
+    pub db: Surreal<Db>,
+    busy: std::sync::Mutex<HashSet<String>>, // 正在执行网络操作的记录
+}
+
+impl DbState {
+    pub fn new(db: Surreal<Db>) -> DbState {
+        DbState {
+            db,
+            busy: std::sync::Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Marks a record as busy until the returned claim is dropped, so two
+    /// commands never send DIMSE requests for the same record and then
+    /// overwrite each other's result.
+    pub fn claim(&self, table: &str, id: &str) -> Result<RecordClaim<'_>, String> {
+        let key = format!("{}:{}", table, id);
+        let mut busy = self.busy.lock().unwrap_or_else(|e| e.into_inner());
+        if !busy.insert(key.clone()) {
+            return Err(format!("{} is busy with another operation", key));
+        }
+        Ok(RecordClaim {
+            busy: &self.busy,
+            key,
+        })
+    }
+}
+
+pub struct RecordClaim<'a> {
+    busy: &'a std::sync::Mutex<HashSet<String>>,
+    key: String,
+}
+
+impl Drop for RecordClaim<'_> {
+    fn drop(&mut self) {
+        self.busy
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.key);
+    }
+}
+
+pub struct DimseState {
+    pub client: Arc<dyn DimseClient>,
+}
+
+/// Built-in SCP listeners keyed by kind.
+#[cfg(feature = "desktop")]
+#[derive(Default)]
+pub struct ScpState {
+    pub servers: Mutex<HashMap<String, ScpServer>>,
+}
+
+/// MLLP connections kept open for HL7 settings with keep-alive on.
+#[cfg(feature = "desktop")]
+pub struct Hl7PoolState {
+    pub pool: Hl7Pool,
+}
+
+/// The HL7 MLLP listener, when running.
+#[cfg(feature = "desktop")]
+#[derive(Default)]
+pub struct Hl7ListenerState {
+    pub server: Mutex<Option<Hl7Server>>,
+}
+
+/// The local automation API, when running.
+#[cfg(feature = "desktop")]
+#[derive(Default)]
+pub struct ApiServerState {
+    pub server: Mutex<Option<ApiServer>>,
+}
+
+/// Settings of the local automation API, kept as the single record
+/// `api_setting:default`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiServerSetting {
+    pub port: String,
+    pub token: String, // 请求需携带 Authorization: Bearer <token>
+    #[serde(rename = "autoStart", default)]
+    pub auto_start: Option<bool>, // 程序启动时自动开启
+}
+
+/// The running load test, if any. Only one runs at a time.
+#[derive(Default)]
+pub struct LoadTestState {
+    pub run: Mutex<Option<LoadTestHandle>>,
+}
+
+/// How many virtual modalities a load test runs and for how long.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoadTestConfig {
+    pub modalities: u32,
+    #[serde(default)]
+    pub ae_prefix: Option<String>, // 虚拟设备的 Calling AE 前缀，默认 SIM，例如 SIM001
+    pub duration_secs: u64,
+    #[serde(default)]
+    pub ramp_up_secs: u64, // 在这段时间内逐个启动虚拟设备
+    #[serde(default)]
+    pub cycles_per_minute: Option<f64>, // 每台设备每分钟的流程数，为空时连续执行
+    #[serde(default)]
+    pub dcm_path: Option<String>, // 每个流程发送的影像，为空时不发送 C-STORE
+}
+
+/// The running automation API, as shown to the frontend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiServerStatus {
+    pub address: String,
+    pub started_at: String,
+    pub requests: u64,
+    pub rejected: u64, // 令牌校验失败的请求数
 }
 
 // 通用 API 响应结构
@@ -165,7 +898,7 @@ impl<T> ApiResponse<T> {
 }
 
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PatientEntry {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub id: Option<Thing>, // 使用 Option 以便在创建时可以为空
@@ -176,4 +909,122 @@ pub struct PatientEntry {
     pub description: Option<String>, // Optional to allow empty status initially
     pub generate: Option<bool>, // Optional to allow empty status initially
     pub sop_instance_uids: Option<String>, // Optional to allow empty status initially
+    #[serde(default)]
+    pub storage_commitment: Option<StorageCommitment>, // 最近一次发送的实例及其提交结果
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATUSES: [MppsStatus; 3] = [
+        MppsStatus::InProgress,
+        MppsStatus::Completed,
+        MppsStatus::Discontinued,
+    ];
+
+    fn mpps_entry(status: Option<&str>) -> MppsEntry {
+        serde_json::from_value(serde_json::json!({
+            "AccessionNumber": "ACC001",
+            "RequestedProcedureDescription": "CT HEAD",
+            "PatientName": "Doe^John",
+            "PatientID": "P1001",
+            "PatientBirthDate": "19700101",
+            "PatientSex": "M",
+            "Modality": "CT",
+            "ScheduledStationAETitle": "CT01",
+            "ScheduledProcedureStepStartDate": "20240101",
+            "ScheduledPerformingPhysicianName": "Smith",
+            "StudyInstanceUID": "1.2.3",
+            "status": status,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn allows_only_the_mpps_state_machine_transitions() {
+        let allowed = [
+            (None, MppsStatus::InProgress),
+            (Some(MppsStatus::InProgress), MppsStatus::Completed),
+            (Some(MppsStatus::InProgress), MppsStatus::Discontinued),
+        ];
+        let from_states = [None].into_iter().chain(STATUSES.into_iter().map(Some));
+        for from in from_states {
+            for to in STATUSES {
+                assert_eq!(
+                    MppsStatus::can_transition(from, to),
+                    allowed.contains(&(from, to)),
+                    "{:?} -> {:?}",
+                    from,
+                    to
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn reads_legacy_free_form_statuses() {
+        let cases = [
+            ("IN PROGRESS", Some(MppsStatus::InProgress)),
+            ("in progress", Some(MppsStatus::InProgress)),
+            ("In_Progress", Some(MppsStatus::InProgress)),
+            ("inprogress", Some(MppsStatus::InProgress)),
+            ("Completed", Some(MppsStatus::Completed)),
+            (" complete ", Some(MppsStatus::Completed)),
+            ("Discontinued", Some(MppsStatus::Discontinued)),
+            ("discontinue", Some(MppsStatus::Discontinued)),
+            ("", None),
+            ("Scheduled", None),
+        ];
+        for (status, expected) in cases {
+            assert_eq!(mpps_entry(Some(status)).status, expected, "{:?}", status);
+        }
+        assert_eq!(mpps_entry(None).status, None);
+    }
+
+    #[test]
+    fn check_transition_names_both_states_when_rejected() {
+        let idle = mpps_entry(None);
+        assert!(idle.check_transition(MppsStatus::InProgress).is_ok());
+        assert_eq!(
+            idle.check_transition(MppsStatus::Completed).unwrap_err(),
+            "Invalid mpps status transition: IDLE -> COMPLETED"
+        );
+
+        let in_progress = mpps_entry(Some("In Progress"));
+        assert!(in_progress.check_transition(MppsStatus::Completed).is_ok());
+        assert!(in_progress
+            .check_transition(MppsStatus::Discontinued)
+            .is_ok());
+
+        let completed = mpps_entry(Some("COMPLETED"));
+        assert_eq!(
+            completed
+                .check_transition(MppsStatus::InProgress)
+                .unwrap_err(),
+            "Invalid mpps status transition: COMPLETED -> IN PROGRESS"
+        );
+    }
+
+    #[test]
+    fn transition_records_the_history() {
+        let mut entry = mpps_entry(None);
+        entry.transition(MppsStatus::InProgress).unwrap();
+        entry.transition(MppsStatus::Completed).unwrap();
+        assert_eq!(entry.status, Some(MppsStatus::Completed));
+        let history: Vec<_> = entry
+            .status_history
+            .iter()
+            .map(|t| (t.from, t.to))
+            .collect();
+        assert_eq!(
+            history,
+            [
+                (None, MppsStatus::InProgress),
+                (Some(MppsStatus::InProgress), MppsStatus::Completed),
+            ]
+        );
+        assert!(entry.transition(MppsStatus::Discontinued).is_err());
+        assert_eq!(entry.status_history.len(), 2);
+    }
 }
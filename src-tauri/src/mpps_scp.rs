@@ -0,0 +1,444 @@
+// mpps_scp.rs
+// 内置的 MPPS SCP，接收设备发来的 N-CREATE / N-SET 并保存到 mpps_scp 表
+use crate::dicom_server::{
+    ScpReply, ScpRequest, ScpService, STATUS_DUPLICATE_SOP_INSTANCE,
+    STATUS_INVALID_ATTRIBUTE_VALUE, STATUS_MISSING_ATTRIBUTE, STATUS_MISSING_ATTRIBUTE_VALUE,
+    STATUS_NO_SUCH_SOP_INSTANCE, STATUS_PROCESSING_FAILURE, STATUS_SUCCESS,
+    STATUS_UNRECOGNIZED_OPERATION,
+};
+use crate::dicom_utils::received_mpps_fields;
+use crate::models::{MppsEntry, MppsScpEvent, MppsStatus};
+use crate::utils::generate_uid;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use dicom_core::value::DataSetSequence;
+use dicom_core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom_dictionary_std::{tags, uids};
+use dicom_object::InMemDicomObject;
+use dicom_transfer_syntax_registry::entries;
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use surrealdb::engine::local::Db;
+use surrealdb::Surreal;
+use tauri::{AppHandle, Emitter};
+use tokio::sync::Mutex;
+
+pub const MPPS_SCP_TABLE: &str = "mpps_scp";
+pub const MPPS_SCP_EVENT: &str = "mpps-scp-received";
+
+// N-CREATE 时 SCU 必须提供且不能为空的属性 (PS3.4 F.7.2.1)
+const CREATE_REQUIRED: [(Tag, &str); 6] = [
+    (tags::PERFORMED_STATION_AE_TITLE, "PerformedStationAETitle"),
+    (tags::PERFORMED_PROCEDURE_STEP_START_DATE, "PerformedProcedureStepStartDate"),
+    (tags::PERFORMED_PROCEDURE_STEP_START_TIME, "PerformedProcedureStepStartTime"),
+    (tags::PERFORMED_PROCEDURE_STEP_ID, "PerformedProcedureStepID"),
+    (tags::PERFORMED_PROCEDURE_STEP_STATUS, "PerformedProcedureStepStatus"),
+    (tags::MODALITY, "Modality"),
+];
+
+// N-CREATE 时必须出现、但允许为空的属性
+const CREATE_PRESENT: [(Tag, &str); 10] = [
+    (tags::PATIENT_NAME, "PatientName"),
+    (tags::PATIENT_ID, "PatientID"),
+    (tags::PATIENT_BIRTH_DATE, "PatientBirthDate"),
+    (tags::PATIENT_SEX, "PatientSex"),
+    (tags::STUDY_ID, "StudyID"),
+    (tags::PERFORMED_PROCEDURE_STEP_END_DATE, "PerformedProcedureStepEndDate"),
+    (tags::PERFORMED_PROCEDURE_STEP_END_TIME, "PerformedProcedureStepEndTime"),
+    (tags::PERFORMED_PROCEDURE_STEP_DESCRIPTION, "PerformedProcedureStepDescription"),
+    (tags::PROCEDURE_CODE_SEQUENCE, "ProcedureCodeSequence"),
+    (tags::PERFORMED_SERIES_SEQUENCE, "PerformedSeriesSequence"),
+];
+
+// 状态变为 COMPLETED / DISCONTINUED 时必须有值的属性
+const FINAL_REQUIRED: [(Tag, &str); 2] = [
+    (tags::PERFORMED_PROCEDURE_STEP_END_DATE, "PerformedProcedureStepEndDate"),
+    (tags::PERFORMED_PROCEDURE_STEP_END_TIME, "PerformedProcedureStepEndTime"),
+];
+
+/// Keeps the attribute set of every step so that N-SET modifications can be
+/// merged and validated. The set is also stored on the `mpps_scp` record and
+/// read back after the SCP was restarted.
+pub struct MppsScp {
+    db: Surreal<Db>,
+    handle: AppHandle,
+    instances: Mutex<HashMap<String, InMemDicomObject>>,
+}
+
+// 完整的属性集 (Explicit VR Little Endian，base64) 与条目保存在同一条记录上
+#[derive(Serialize, Deserialize)]
+struct StoredAttributes {
+    #[serde(rename = "Attributes", default)]
+    attributes: String,
+}
+
+/// A request rejected by the SCP: the DIMSE status and what was wrong.
+struct Rejection {
+    status: u16,
+    issues: Vec<String>,
+}
+
+impl Rejection {
+    fn new(status: u16, issue: String) -> Rejection {
+        Rejection {
+            status,
+            issues: vec![issue],
+        }
+    }
+}
+
+impl MppsScp {
+    pub fn new(db: Surreal<Db>, handle: AppHandle) -> MppsScp {
+        MppsScp {
+            db,
+            handle,
+            instances: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn n_create(
+        &self,
+        request: &ScpRequest,
+        sop_instance_uid: &str,
+    ) -> Result<MppsEntry, Rejection> {
+        let attributes = decode_attributes(request)?;
+        let mut instances = self.instances.lock().await;
+        let existing: Option<MppsEntry> = self
+            .db
+            .select((MPPS_SCP_TABLE, sop_instance_uid))
+            .await
+            .map_err(|e| Rejection::new(STATUS_PROCESSING_FAILURE, e.to_string()))?;
+        if instances.contains_key(sop_instance_uid) || existing.is_some() {
+            return Err(Rejection::new(
+                STATUS_DUPLICATE_SOP_INSTANCE,
+                format!("Duplicate SOP instance {}", sop_instance_uid),
+            ));
+        }
+
+        check_attributes(&attributes, &CREATE_REQUIRED, &CREATE_PRESENT)?;
+        check_scheduled_step_attributes(&attributes)?;
+        if performed_status(&attributes)? != MppsStatus::InProgress {
+            return Err(Rejection::new(
+                STATUS_INVALID_ATTRIBUTE_VALUE,
+                "PerformedProcedureStepStatus must be IN PROGRESS on N-CREATE".to_string(),
+            ));
+        }
+
+        let mut entry = received_mpps_fields(&attributes, sop_instance_uid);
+        entry
+            .transition(MppsStatus::InProgress)
+            .map_err(|e| Rejection::new(STATUS_INVALID_ATTRIBUTE_VALUE, e))?;
+        let created: Option<MppsEntry> = self
+            .db
+            .create((MPPS_SCP_TABLE, sop_instance_uid))
+            .content(entry)
+            .await
+            .map_err(|e| Rejection::new(STATUS_PROCESSING_FAILURE, e.to_string()))?;
+        self.save_attributes(sop_instance_uid, &attributes).await?;
+        instances.insert(sop_instance_uid.to_string(), attributes);
+        created.ok_or_else(|| {
+            Rejection::new(STATUS_PROCESSING_FAILURE, "Failed to store the step".to_string())
+        })
+    }
+
+    async fn n_set(
+        &self,
+        request: &ScpRequest,
+        sop_instance_uid: &str,
+    ) -> Result<MppsEntry, Rejection> {
+        let modifications = decode_attributes(request)?;
+        let mut instances = self.instances.lock().await;
+        let current: Option<MppsEntry> = self
+            .db
+            .select((MPPS_SCP_TABLE, sop_instance_uid))
+            .await
+            .map_err(|e| Rejection::new(STATUS_PROCESSING_FAILURE, e.to_string()))?;
+        let Some(current) = current else {
+            return Err(Rejection::new(
+                STATUS_NO_SUCH_SOP_INSTANCE,
+                format!("No such SOP instance {}", sop_instance_uid),
+            ));
+        };
+        if current.status != Some(MppsStatus::InProgress) {
+            return Err(Rejection::new(
+                STATUS_PROCESSING_FAILURE,
+                "Performed Procedure Step Object may no longer be updated".to_string(),
+            ));
+        }
+
+        let mut merged = match instances.get(sop_instance_uid) {
+            Some(stored) => stored.clone(),
+            None => self.stored_attributes(sop_instance_uid, &current).await?,
+        };
+        for element in modifications.into_iter() {
+            merged.put(element);
+        }
+        let status = performed_status(&merged)?;
+        if status != MppsStatus::InProgress {
+            current
+                .check_transition(status)
+                .map_err(|e| Rejection::new(STATUS_INVALID_ATTRIBUTE_VALUE, e))?;
+            check_attributes(&merged, &FINAL_REQUIRED, &[])?;
+        }
+
+        let mut entry = received_mpps_fields(&merged, sop_instance_uid);
+        entry.status = current.status;
+        entry.status_history = current.status_history;
+        if status != MppsStatus::InProgress {
+            entry
+                .transition(status)
+                .map_err(|e| Rejection::new(STATUS_INVALID_ATTRIBUTE_VALUE, e))?;
+        }
+        let updated: Option<MppsEntry> = self
+            .db
+            .update((MPPS_SCP_TABLE, sop_instance_uid))
+            .content(entry)
+            .await
+            .map_err(|e| Rejection::new(STATUS_PROCESSING_FAILURE, e.to_string()))?;
+        self.save_attributes(sop_instance_uid, &merged).await?;
+        instances.insert(sop_instance_uid.to_string(), merged);
+        updated.ok_or_else(|| {
+            Rejection::new(STATUS_PROCESSING_FAILURE, "Failed to store the step".to_string())
+        })
+    }
+
+    async fn save_attributes(
+        &self,
+        sop_instance_uid: &str,
+        attributes: &InMemDicomObject,
+    ) -> Result<(), Rejection> {
+        let mut data = Vec::new();
+        attributes
+            .write_dataset_with_ts(&mut data, &entries::EXPLICIT_VR_LITTLE_ENDIAN.erased())
+            .map_err(|e| Rejection::new(STATUS_PROCESSING_FAILURE, e.to_string()))?;
+        let _: Option<StoredAttributes> = self
+            .db
+            .update((MPPS_SCP_TABLE, sop_instance_uid))
+            .merge(StoredAttributes {
+                attributes: BASE64.encode(data),
+            })
+            .await
+            .map_err(|e| Rejection::new(STATUS_PROCESSING_FAILURE, e.to_string()))?;
+        Ok(())
+    }
+
+    // SCP 重启后缓存为空，从记录中读取；旧记录没有保存属性集时按条目重建
+    async fn stored_attributes(
+        &self,
+        sop_instance_uid: &str,
+        current: &MppsEntry,
+    ) -> Result<InMemDicomObject, Rejection> {
+        let stored: Option<StoredAttributes> = self
+            .db
+            .select((MPPS_SCP_TABLE, sop_instance_uid))
+            .await
+            .map_err(|e| Rejection::new(STATUS_PROCESSING_FAILURE, e.to_string()))?;
+        match stored.filter(|s| !s.attributes.is_empty()) {
+            Some(stored) => {
+                let data = BASE64
+                    .decode(&stored.attributes)
+                    .map_err(|e| Rejection::new(STATUS_PROCESSING_FAILURE, e.to_string()))?;
+                InMemDicomObject::read_dataset_with_ts(
+                    &data[..],
+                    &entries::EXPLICIT_VR_LITTLE_ENDIAN.erased(),
+                )
+                .map_err(|e| Rejection::new(STATUS_PROCESSING_FAILURE, e.to_string()))
+            }
+            None => Ok(entry_attributes(current)),
+        }
+    }
+
+    async fn receive(&self, request: &ScpRequest, operation: &str) -> ScpReply {
+        let sop_instance_uid = request
+            .command_str(tags::AFFECTED_SOP_INSTANCE_UID)
+            .or_else(|| request.command_str(tags::REQUESTED_SOP_INSTANCE_UID))
+            // N-CREATE 未指定实例 UID 时由 SCP 生成
+            .unwrap_or_else(generate_uid);
+        let result = match operation {
+            "N-CREATE" => self.n_create(request, &sop_instance_uid).await,
+            _ => self.n_set(request, &sop_instance_uid).await,
+        };
+
+        let (reply, event) = match result {
+            Ok(entry) => (
+                ScpReply::response(request, STATUS_SUCCESS, None),
+                MppsScpEvent {
+                    operation: operation.to_string(),
+                    sop_instance_uid: sop_instance_uid.clone(),
+                    calling_ae_title: request.calling_ae_title.clone(),
+                    status: format!("0x{:04X}", STATUS_SUCCESS),
+                    issues: Vec::new(),
+                    entry: Some(entry),
+                },
+            ),
+            Err(rejection) => (
+                ScpReply::response(request, rejection.status, None)
+                    .with_error_comment(&rejection.issues.join("; ")),
+                MppsScpEvent {
+                    operation: operation.to_string(),
+                    sop_instance_uid: sop_instance_uid.clone(),
+                    calling_ae_title: request.calling_ae_title.clone(),
+                    status: format!("0x{:04X}", rejection.status),
+                    issues: rejection.issues,
+                    entry: None,
+                },
+            ),
+        };
+        if let Err(e) = self.handle.emit(MPPS_SCP_EVENT, &event) {
+            eprintln!("Failed to emit {}: {}", MPPS_SCP_EVENT, e);
+        }
+        reply.with_affected_sop_instance_uid(&sop_instance_uid)
+    }
+}
+
+impl ScpService for MppsScp {
+    fn abstract_syntaxes(&self) -> Vec<String> {
+        vec![uids::MODALITY_PERFORMED_PROCEDURE_STEP.to_string()]
+    }
+
+    fn handle<'a>(&'a self, request: &'a ScpRequest) -> BoxFuture<'a, Vec<ScpReply>> {
+        Box::pin(async move {
+            let reply = match request.command_field() {
+                0x0140 => self.receive(request, "N-CREATE").await,
+                0x0120 => self.receive(request, "N-SET").await,
+                _ => ScpReply::response(request, STATUS_UNRECOGNIZED_OPERATION, None),
+            };
+            vec![reply]
+        })
+    }
+}
+
+// 按 received_mpps_fields 读取的属性反向构建属性集
+fn entry_attributes(entry: &MppsEntry) -> InMemDicomObject {
+    let text = |tag, vr, value: &str| DataElement::new(tag, vr, PrimitiveValue::from(value));
+    let step = InMemDicomObject::from_element_iter([
+        text(tags::ACCESSION_NUMBER, VR::SH, &entry.accession_number),
+        text(tags::STUDY_INSTANCE_UID, VR::UI, &entry.study_instance_uid),
+        text(
+            tags::REQUESTED_PROCEDURE_DESCRIPTION,
+            VR::LO,
+            &entry.requested_procedure_description,
+        ),
+    ]);
+    let status = entry.status.map_or("", |s| s.as_str());
+    InMemDicomObject::from_element_iter([
+        text(tags::PATIENT_NAME, VR::PN, &entry.patient_name),
+        text(tags::PATIENT_ID, VR::LO, &entry.patient_id),
+        text(tags::PATIENT_BIRTH_DATE, VR::DA, &entry.patient_birth_date),
+        text(tags::PATIENT_SEX, VR::CS, &entry.patient_sex),
+        text(tags::MODALITY, VR::CS, &entry.modality),
+        text(
+            tags::PERFORMED_STATION_AE_TITLE,
+            VR::AE,
+            &entry.scheduled_station_ae_title,
+        ),
+        text(
+            tags::PERFORMED_PROCEDURE_STEP_START_DATE,
+            VR::DA,
+            &entry.scheduled_procedure_step_start_date,
+        ),
+        text(
+            tags::PERFORMED_PROCEDURE_STEP_DESCRIPTION,
+            VR::LO,
+            entry.description.as_deref().unwrap_or_default(),
+        ),
+        text(tags::PERFORMED_PROCEDURE_STEP_STATUS, VR::CS, status),
+        DataElement::new(
+            tags::SCHEDULED_STEP_ATTRIBUTES_SEQUENCE,
+            VR::SQ,
+            DataSetSequence::from(vec![step]),
+        ),
+    ])
+}
+
+fn decode_attributes(request: &ScpRequest) -> Result<InMemDicomObject, Rejection> {
+    match request.decode_data_set() {
+        Ok(Some(attributes)) => Ok(attributes),
+        Ok(None) => Err(Rejection::new(
+            STATUS_MISSING_ATTRIBUTE,
+            "Request has no attribute list".to_string(),
+        )),
+        Err(e) => Err(Rejection::new(STATUS_PROCESSING_FAILURE, e.to_string())),
+    }
+}
+
+fn is_empty(element: &dicom_object::mem::InMemElement) -> bool {
+    match element.items() {
+        Some(items) => items.is_empty(),
+        None => element
+            .to_str()
+            .map(|s| s.trim_end_matches(['\0', ' ']).is_empty())
+            .unwrap_or(true),
+    }
+}
+
+/// Checks that `required` attributes have a value and `present` attributes exist.
+fn check_attributes(
+    attributes: &InMemDicomObject,
+    required: &[(Tag, &str)],
+    present: &[(Tag, &str)],
+) -> Result<(), Rejection> {
+    let mut missing = Vec::new();
+    let mut empty = Vec::new();
+    for (tag, name) in required {
+        match attributes.get(*tag) {
+            None => missing.push(*name),
+            Some(element) if is_empty(element) => empty.push(*name),
+            Some(_) => {}
+        }
+    }
+    for (tag, name) in present {
+        if attributes.get(*tag).is_none() {
+            missing.push(*name);
+        }
+    }
+
+    if missing.is_empty() && empty.is_empty() {
+        return Ok(());
+    }
+    let mut issues = Vec::new();
+    if !missing.is_empty() {
+        issues.push(format!("Missing {}", missing.join(", ")));
+    }
+    if !empty.is_empty() {
+        issues.push(format!("Empty {}", empty.join(", ")));
+    }
+    Err(Rejection {
+        status: if missing.is_empty() {
+            STATUS_MISSING_ATTRIBUTE_VALUE
+        } else {
+            STATUS_MISSING_ATTRIBUTE
+        },
+        issues,
+    })
+}
+
+// ScheduledStepAttributesSequence 至少一个条目，且每个条目都要有 StudyInstanceUID
+fn check_scheduled_step_attributes(attributes: &InMemDicomObject) -> Result<(), Rejection> {
+    let items = attributes
+        .get(tags::SCHEDULED_STEP_ATTRIBUTES_SEQUENCE)
+        .and_then(|e| e.items())
+        .filter(|items| !items.is_empty())
+        .ok_or_else(|| {
+            Rejection::new(
+                STATUS_MISSING_ATTRIBUTE,
+                "Missing ScheduledStepAttributesSequence".to_string(),
+            )
+        })?;
+    for item in items {
+        check_attributes(item, &[(tags::STUDY_INSTANCE_UID, "StudyInstanceUID")], &[])?;
+    }
+    Ok(())
+}
+
+fn performed_status(attributes: &InMemDicomObject) -> Result<MppsStatus, Rejection> {
+    let status = attributes
+        .get(tags::PERFORMED_PROCEDURE_STEP_STATUS)
+        .and_then(|e| e.to_str().ok())
+        .map(|s| s.trim_end_matches(['\0', ' ']).to_string())
+        .unwrap_or_default();
+    status
+        .parse()
+        .map_err(|e| Rejection::new(STATUS_INVALID_ATTRIBUTE_VALUE, e))
+}
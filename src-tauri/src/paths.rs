@@ -1,7 +1,10 @@
 // paths.rs
+#[cfg(feature = "desktop")]
 use tauri::path::BaseDirectory;
+#[cfg(feature = "desktop")]
 use tauri::AppHandle;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "desktop")]
 use crate::utils::resolve_path;
 
 pub enum AppPath {
@@ -9,7 +12,6 @@ pub enum AppPath {
     Dcm,
     CFindRq,
     MrModality,
-    PythonScript,
     Certs,
     InProgress,
     DisContinued,
@@ -18,19 +20,29 @@ pub enum AppPath {
 }
 
 impl AppPath {
-    pub fn resolve(&self, handle: &AppHandle) -> Result<PathBuf, String> {
+    /// The path below the resource directory.
+    pub fn relative(&self) -> &'static str {
         match self {
-            AppPath::Log => resolve_path(handle, "log"),
-            AppPath::Database => resolve_path(handle, "resources/database"),
-            AppPath::Dcm => resolve_path(handle, "resources/dicom"),
-            AppPath::CFindRq => resolve_path(handle, "resources/dcm/message/C-FIND-RQ.dcm"),
-            AppPath::MrModality => resolve_path(handle, "resources/dcm/message/MR_Modality.dcm"),
-            AppPath::InProgress => resolve_path(handle, "resources/dcm/message/mpps-inprogress.dcm"),
-            AppPath::DisContinued => resolve_path(handle, "resources/dcm/message/mpps-discontinued.dcm"),
-            AppPath::Completed => resolve_path(handle, "resources/dcm/message/mpps-completed.dcm"),
-            AppPath::PythonScript => resolve_path(handle, "resources/python-script/dicom_utils.py"),
-            AppPath::Certs => resolve_path(handle, "resources/certs"),
+            AppPath::Log => "log",
+            AppPath::Database => "resources/database",
+            AppPath::Dcm => "resources/dicom",
+            AppPath::CFindRq => "resources/dcm/message/C-FIND-RQ.dcm",
+            AppPath::MrModality => "resources/dcm/message/MR_Modality.dcm",
+            AppPath::InProgress => "resources/dcm/message/mpps-inprogress.dcm",
+            AppPath::DisContinued => "resources/dcm/message/mpps-discontinued.dcm",
+            AppPath::Completed => "resources/dcm/message/mpps-completed.dcm",
+            AppPath::Certs => "resources/certs",
         }
-        .map_err(|e| format!("Failed to resolve path: {}", e))
+    }
+
+    #[cfg(feature = "desktop")]
+    pub fn resolve(&self, handle: &AppHandle) -> Result<PathBuf, String> {
+        resolve_path(handle, self.relative())
+            .map_err(|e| format!("Failed to resolve path: {}", e))
+    }
+
+    // 没有 Tauri 应用时 (命令行) 从指定的资源目录解析
+    pub fn under(&self, resource_dir: &Path) -> PathBuf {
+        resource_dir.join(self.relative())
     }
 }
@@ -0,0 +1,664 @@
+// scenario.rs
+// 场景脚本：把 "FULL CT SCAN" 的各个步骤 (查询 Worklist -> MPPS IN PROGRESS -> C-STORE -> MPPS COMPLETED)
+// 写成 JSON，支持延时、循环和对结果的断言，执行后生成逐步的报告
+use crate::dicom_client::{DicomNode, DimseClient};
+use crate::dicom_utils::{
+    build_completed, build_discontinued, build_in_progress, collect_dcm_files,
+    discontinuation_reason, parse_sop_instance_uids, prepare_instances, query_worklist,
+};
+use crate::models::{MimEntry, MppsEntry, MppsStatus, WorklistEntry};
+use crate::utils::generate_uid;
+use chrono::Local;
+use dicom_dictionary_std::uids;
+use dicom_object::InMemDicomObject;
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use surrealdb::engine::local::Db;
+use surrealdb::sql::Thing;
+use surrealdb::Surreal;
+
+pub const SCENARIO_TABLE: &str = "scenario";
+pub const SCENARIO_RUN_TABLE: &str = "scenario_run";
+pub const SCENARIO_PROGRESS_EVENT: &str = "scenario-progress";
+
+// 防止脚本写错导致长时间运行
+const MAX_LOOP_TIMES: u32 = 1000;
+const MAX_DELAY_MS: u64 = 10 * 60 * 1000;
+
+/// A scripted workflow, parsed from the JSON definition of a `ScenarioEntry`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    #[serde(default)]
+    pub name: String,
+    /// Keep running the remaining steps after a failed one.
+    #[serde(default)]
+    pub continue_on_failure: bool,
+    pub steps: Vec<ScenarioStep>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ScenarioStep {
+    /// Queries the worklist and selects the item with `accession`, or the first
+    /// item this run has not performed yet. Selecting an item again continues
+    /// with the MPPS state this run left it in.
+    WorklistQuery {
+        accession: Option<String>,
+        #[serde(default)]
+        expect: Expect,
+    },
+    MppsInProgress {
+        #[serde(default)]
+        expect: Expect,
+    },
+    /// Sends the images under `path` (relative paths start at the app's dicom
+    /// folder) for the selected item. Without `path` the images collected by an
+    /// earlier step are sent again.
+    CStore {
+        path: Option<String>,
+        #[serde(default)]
+        expect: Expect,
+    },
+    /// N-SET COMPLETED, referencing the images of `path` or of the last C-STORE.
+    MppsCompleted {
+        path: Option<String>,
+        #[serde(default)]
+        expect: Expect,
+    },
+    MppsDiscontinued {
+        reason_code: String,
+        #[serde(default)]
+        expect: Expect,
+    },
+    Delay {
+        ms: u64,
+    },
+    Loop {
+        times: u32,
+        steps: Vec<ScenarioStep>,
+    },
+}
+
+/// Assertions checked after a step. A step is expected to succeed unless
+/// `success` is `false`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Expect {
+    pub success: Option<bool>,
+    pub min_results: Option<usize>, // worklist_query 返回的条目数
+    pub max_results: Option<usize>,
+    pub stored: Option<usize>,            // c_store 发送的实例数
+    pub status: Option<MppsStatus>,       // 步骤执行后选中条目的 MPPS 状态
+    pub message_contains: Option<String>, // 步骤结果或错误信息包含的文字
+}
+
+impl ScenarioStep {
+    fn action(&self) -> &'static str {
+        match self {
+            ScenarioStep::WorklistQuery { .. } => "worklist_query",
+            ScenarioStep::MppsInProgress { .. } => "mpps_in_progress",
+            ScenarioStep::CStore { .. } => "c_store",
+            ScenarioStep::MppsCompleted { .. } => "mpps_completed",
+            ScenarioStep::MppsDiscontinued { .. } => "mpps_discontinued",
+            ScenarioStep::Delay { .. } => "delay",
+            ScenarioStep::Loop { .. } => "loop",
+        }
+    }
+
+    fn expect(&self) -> Option<&Expect> {
+        match self {
+            ScenarioStep::WorklistQuery { expect, .. }
+            | ScenarioStep::MppsInProgress { expect }
+            | ScenarioStep::CStore { expect, .. }
+            | ScenarioStep::MppsCompleted { expect, .. }
+            | ScenarioStep::MppsDiscontinued { expect, .. } => Some(expect),
+            ScenarioStep::Delay { .. } | ScenarioStep::Loop { .. } => None,
+        }
+    }
+}
+
+impl Scenario {
+    /// Parses and checks a scenario definition.
+    pub fn parse(definition: &str) -> Result<Scenario, String> {
+        let scenario: Scenario = serde_json::from_str(definition)
+            .map_err(|e| format!("Invalid scenario definition: {}", e))?;
+        validate_steps(&scenario.steps, "")?;
+        Ok(scenario)
+    }
+}
+
+fn validate_steps(steps: &[ScenarioStep], prefix: &str) -> Result<(), String> {
+    if steps.is_empty() {
+        return Err(format!(
+            "Invalid scenario definition: {}steps is empty",
+            prefix
+        ));
+    }
+    for (i, step) in steps.iter().enumerate() {
+        let at = format!("{}{}", prefix, i + 1);
+        match step {
+            ScenarioStep::Loop { times, steps } => {
+                if *times == 0 || *times > MAX_LOOP_TIMES {
+                    return Err(format!(
+                        "Invalid scenario definition: step {} must loop 1 to {} times",
+                        at, MAX_LOOP_TIMES
+                    ));
+                }
+                validate_steps(steps, &format!("{}.", at))?;
+            }
+            ScenarioStep::Delay { ms } if *ms > MAX_DELAY_MS => {
+                return Err(format!(
+                    "Invalid scenario definition: step {} waits longer than {} ms",
+                    at, MAX_DELAY_MS
+                ));
+            }
+            ScenarioStep::MppsDiscontinued { reason_code, .. }
+                if discontinuation_reason(reason_code).is_none() =>
+            {
+                return Err(format!(
+                    "Invalid scenario definition: step {} has an unknown reason code {}",
+                    at, reason_code
+                ));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StepOutcome {
+    Passed,
+    Failed,
+    Skipped,
+}
+
+/// One executed step. Steps inside loops are numbered like `3#1.2` (step 2 of
+/// loop 3, first iteration).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScenarioStepReport {
+    pub step: String,
+    pub action: String,
+    pub outcome: StepOutcome,
+    pub started_at: String,
+    pub duration_ms: u64,
+    pub accession_number: Option<String>,
+    pub detail: String,
+}
+
+/// The result of a scenario run, stored in `scenario_run`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScenarioReport {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Thing>,
+    pub scenario: String,
+    pub worklist: String,
+    pub pacs: Option<String>,
+    pub started_at: String,
+    pub finished_at: String,
+    pub passed: bool,
+    pub steps: Vec<ScenarioStepReport>,
+}
+
+impl ScenarioReport {
+    pub fn summary(&self) -> String {
+        let count = |outcome| self.steps.iter().filter(|s| s.outcome == outcome).count();
+        format!(
+            "{}: {} passed, {} failed, {} skipped",
+            if self.passed { "PASSED" } else { "FAILED" },
+            count(StepOutcome::Passed),
+            count(StepOutcome::Failed),
+            count(StepOutcome::Skipped)
+        )
+    }
+}
+
+/// The DICOM message templates a run builds its requests from.
+pub struct ScenarioTemplates {
+    pub queries: Vec<InMemDicomObject>,
+    pub in_progress: InMemDicomObject,
+    pub completed: InMemDicomObject,
+    pub discontinued: InMemDicomObject,
+}
+
+pub type ProgressListener = Arc<dyn Fn(&ScenarioStepReport) + Send + Sync>;
+
+// 一个步骤的执行结果，用于断言
+#[derive(Default)]
+struct StepOutput {
+    detail: String,
+    results: Option<usize>,
+    stored: Option<usize>,
+}
+
+#[derive(Default)]
+struct RunState {
+    current: Option<String>,             // 当前选中的检查号
+    entries: HashMap<String, MppsEntry>, // 本次运行已选过的条目
+    failed: bool,
+    steps: Vec<ScenarioStepReport>,
+}
+
+impl RunState {
+    fn current(&self) -> Option<&MppsEntry> {
+        self.current.as_ref().and_then(|a| self.entries.get(a))
+    }
+}
+
+/// Runs scenarios against one worklist (and MPPS) endpoint and an optional PACS.
+pub struct ScenarioRunner {
+    client: Arc<dyn DimseClient>,
+    worklist: WorklistEntry,
+    pacs: Option<MimEntry>,
+    templates: ScenarioTemplates,
+    dicom_dir: PathBuf,
+    db: Option<Surreal<Db>>,
+    listener: Option<ProgressListener>,
+}
+
+impl ScenarioRunner {
+    pub fn new(
+        client: Arc<dyn DimseClient>,
+        worklist: WorklistEntry,
+        templates: ScenarioTemplates,
+        dicom_dir: PathBuf,
+    ) -> ScenarioRunner {
+        ScenarioRunner {
+            client,
+            worklist,
+            pacs: None,
+            templates,
+            dicom_dir,
+            db: None,
+            listener: None,
+        }
+    }
+
+    pub fn with_pacs(mut self, pacs: Option<MimEntry>) -> ScenarioRunner {
+        self.pacs = pacs;
+        self
+    }
+
+    /// Saves the MPPS entries of the run to the `mpps` table, as the UI does.
+    pub fn with_db(mut self, db: Surreal<Db>) -> ScenarioRunner {
+        self.db = Some(db);
+        self
+    }
+
+    pub fn with_listener(mut self, listener: ProgressListener) -> ScenarioRunner {
+        self.listener = Some(listener);
+        self
+    }
+
+    pub async fn run(&self, scenario: &Scenario) -> ScenarioReport {
+        let started_at = Local::now().to_rfc3339();
+        let mut state = RunState::default();
+        self.run_steps(scenario, &scenario.steps, String::new(), &mut state)
+            .await;
+        ScenarioReport {
+            id: None,
+            scenario: scenario.name.clone(),
+            worklist: self.worklist.name.clone(),
+            pacs: self.pacs.as_ref().map(|pacs| pacs.name.clone()),
+            started_at,
+            finished_at: Local::now().to_rfc3339(),
+            passed: !state.failed,
+            steps: state.steps,
+        }
+    }
+
+    fn run_steps<'a>(
+        &'a self,
+        scenario: &'a Scenario,
+        steps: &'a [ScenarioStep],
+        prefix: String,
+        state: &'a mut RunState,
+    ) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            for (i, step) in steps.iter().enumerate() {
+                let number = format!("{}{}", prefix, i + 1);
+                if state.failed && !scenario.continue_on_failure {
+                    self.record(state, step_report(&number, step, StepOutcome::Skipped));
+                    continue;
+                }
+                if let ScenarioStep::Loop { times, steps } = step {
+                    for iteration in 1..=*times {
+                        let prefix = format!("{}#{}.", number, iteration);
+                        self.run_steps(scenario, steps, prefix, state).await;
+                    }
+                    continue;
+                }
+
+                let started_at = Local::now().to_rfc3339();
+                let started = Instant::now();
+                let result = self.execute(step, state).await;
+                let (outcome, detail) = check(step.expect(), result, state.current());
+                if outcome == StepOutcome::Failed {
+                    state.failed = true;
+                }
+                let report = ScenarioStepReport {
+                    started_at,
+                    duration_ms: started.elapsed().as_millis() as u64,
+                    accession_number: state.current.clone(),
+                    detail,
+                    ..step_report(&number, step, outcome)
+                };
+                self.record(state, report);
+            }
+        })
+    }
+
+    fn record(&self, state: &mut RunState, report: ScenarioStepReport) {
+        if let Some(listener) = &self.listener {
+            listener(&report);
+        }
+        state.steps.push(report);
+    }
+
+    async fn execute(
+        &self,
+        step: &ScenarioStep,
+        state: &mut RunState,
+    ) -> Result<StepOutput, String> {
+        match step {
+            ScenarioStep::WorklistQuery { accession, .. } => {
+                let entries = query_worklist(
+                    self.client.as_ref(),
+                    &self.worklist,
+                    self.templates.queries.clone(),
+                )
+                .await?;
+                let results = entries.len();
+                let selected = entries.into_iter().find(|e| match accession {
+                    Some(accession) => &e.accession_number == accession,
+                    None => !state.entries.contains_key(&e.accession_number),
+                });
+                let detail = match &selected {
+                    Some(entry) => format!(
+                        "{} items, selected {} ({})",
+                        results, entry.accession_number, entry.patient_name
+                    ),
+                    None => format!("{} items, none selected", results),
+                };
+                state.current = selected.map(|entry| {
+                    let accession = entry.accession_number.clone();
+                    state.entries.entry(accession.clone()).or_insert(entry);
+                    accession
+                });
+                Ok(StepOutput {
+                    detail,
+                    results: Some(results),
+                    ..Default::default()
+                })
+            }
+            ScenarioStep::MppsInProgress { .. } => {
+                let entry = selected(state)?;
+                entry.check_transition(MppsStatus::InProgress)?;
+                let node = DicomNode::mpps(&self.worklist).map_err(|e| e.to_string())?;
+                let mpps_instance_uid = generate_uid();
+                let attributes =
+                    build_in_progress(self.templates.in_progress.clone(), entry, &self.worklist)?;
+                self.client
+                    .n_create(
+                        &node,
+                        uids::MODALITY_PERFORMED_PROCEDURE_STEP,
+                        &mpps_instance_uid,
+                        attributes,
+                    )
+                    .await
+                    .map_err(|e| format!("N-CREATE failed: {}", e))?;
+                entry.mpps_instance_uid = Some(mpps_instance_uid.clone());
+                entry.status_history.clear();
+                entry.transition(MppsStatus::InProgress)?;
+                self.save(entry).await?;
+                Ok(StepOutput {
+                    detail: format!("N-CREATE {} to {}", mpps_instance_uid, node.target()),
+                    ..Default::default()
+                })
+            }
+            ScenarioStep::CStore { path, .. } => {
+                let pacs = self
+                    .pacs
+                    .as_ref()
+                    .ok_or("No PACS selected for the C-STORE step")?;
+                let node = DicomNode::storage(pacs).map_err(|e| e.to_string())?;
+                let entry = selected(state)?;
+                if let Some(path) = path {
+                    self.collect(entry, path)?;
+                }
+                let objects = prepare_instances(entry)?;
+                let stored = self
+                    .client
+                    .c_store(&node, objects)
+                    .await
+                    .map_err(|e| format!("C-STORE failed: {}", e))?;
+                self.save(entry).await?;
+                Ok(StepOutput {
+                    detail: format!("Stored {} instances to {}", stored, node.target()),
+                    stored: Some(stored),
+                    ..Default::default()
+                })
+            }
+            ScenarioStep::MppsCompleted { path, .. } => {
+                let entry = selected(state)?;
+                entry.check_transition(MppsStatus::Completed)?;
+                if let Some(path) = path {
+                    self.collect(entry, path)?;
+                }
+                let series = parse_sop_instance_uids(entry)?;
+                let node = DicomNode::mpps(&self.worklist).map_err(|e| e.to_string())?;
+                let mpps_instance_uid = mpps_instance_uid(entry)?;
+                let modifications =
+                    build_completed(self.templates.completed.clone(), entry, &series)?;
+                self.client
+                    .n_set(
+                        &node,
+                        uids::MODALITY_PERFORMED_PROCEDURE_STEP,
+                        &mpps_instance_uid,
+                        modifications,
+                    )
+                    .await
+                    .map_err(|e| format!("N-SET failed: {}", e))?;
+                entry.transition(MppsStatus::Completed)?;
+                self.save(entry).await?;
+                let instances: usize = series.iter().map(|s| s.sop_instance_infos.len()).sum();
+                Ok(StepOutput {
+                    detail: format!(
+                        "N-SET COMPLETED with {} series, {} instances",
+                        series.len(),
+                        instances
+                    ),
+                    ..Default::default()
+                })
+            }
+            ScenarioStep::MppsDiscontinued { reason_code, .. } => {
+                let entry = selected(state)?;
+                entry.check_transition(MppsStatus::Discontinued)?;
+                let reason = discontinuation_reason(reason_code)
+                    .ok_or_else(|| format!("Unknown discontinuation reason: {}", reason_code))?;
+                let node = DicomNode::mpps(&self.worklist).map_err(|e| e.to_string())?;
+                let mpps_instance_uid = mpps_instance_uid(entry)?;
+                let modifications =
+                    build_discontinued(self.templates.discontinued.clone(), entry, &reason)?;
+                self.client
+                    .n_set(
+                        &node,
+                        uids::MODALITY_PERFORMED_PROCEDURE_STEP,
+                        &mpps_instance_uid,
+                        modifications,
+                    )
+                    .await
+                    .map_err(|e| format!("N-SET failed: {}", e))?;
+                entry.transition(MppsStatus::Discontinued)?;
+                self.save(entry).await?;
+                Ok(StepOutput {
+                    detail: format!("N-SET DISCONTINUED ({})", reason.code_meaning),
+                    ..Default::default()
+                })
+            }
+            ScenarioStep::Delay { ms } => {
+                tokio::time::sleep(Duration::from_millis(*ms)).await;
+                Ok(StepOutput {
+                    detail: format!("Waited {} ms", ms),
+                    ..Default::default()
+                })
+            }
+            ScenarioStep::Loop { .. } => unreachable!("loops are expanded by run_steps"),
+        }
+    }
+
+    // 收集图像并生成新的 Series / SOP Instance UID
+    fn collect(&self, entry: &mut MppsEntry, path: &str) -> Result<(), String> {
+        let path = if Path::new(path).is_absolute() {
+            PathBuf::from(path)
+        } else {
+            self.dicom_dir.join(path)
+        };
+        let series = collect_dcm_files(&path)?;
+        if series.is_empty() {
+            return Err(format!("No .dcm files found in {}", path.display()));
+        }
+        entry.sop_instance_uids = Some(
+            serde_json::to_string(&series)
+                .map_err(|e| format!("Failed to serialize SopInstanceUids: {}", e))?,
+        );
+        entry.dcm_file = Some(path.to_string_lossy().into_owned());
+        Ok(())
+    }
+
+    async fn save(&self, entry: &mut MppsEntry) -> Result<(), String> {
+        let Some(db) = &self.db else {
+            return Ok(());
+        };
+        let saved: Option<MppsEntry> = match &entry.id {
+            Some(id) => db
+                .update((id.tb.as_str(), id.id.to_raw()))
+                .content(entry.clone())
+                .await
+                .map_err(|e| format!("Failed to update mpps entry: {}", e))?,
+            None => {
+                let created: Vec<MppsEntry> = db
+                    .create("mpps")
+                    .content(entry.clone())
+                    .await
+                    .map_err(|e| format!("Failed to create mpps entry: {}", e))?;
+                created.into_iter().next()
+            }
+        };
+        if let Some(saved) = saved {
+            *entry = saved;
+        }
+        Ok(())
+    }
+}
+
+fn selected(state: &mut RunState) -> Result<&mut MppsEntry, String> {
+    state
+        .current
+        .as_ref()
+        .and_then(|accession| state.entries.get_mut(accession))
+        .ok_or_else(|| "No worklist item selected; add a worklist_query step first".to_string())
+}
+
+fn mpps_instance_uid(entry: &MppsEntry) -> Result<String, String> {
+    entry.mpps_instance_uid.clone().ok_or_else(|| {
+        format!(
+            "mpps entry {} has no MPPS instance UID",
+            entry.accession_number
+        )
+    })
+}
+
+fn step_report(number: &str, step: &ScenarioStep, outcome: StepOutcome) -> ScenarioStepReport {
+    ScenarioStepReport {
+        step: number.to_string(),
+        action: step.action().to_string(),
+        outcome,
+        started_at: String::new(),
+        duration_ms: 0,
+        accession_number: None,
+        detail: String::new(),
+    }
+}
+
+// 按 expect 判断步骤是否通过，返回结果和说明
+fn check(
+    expect: Option<&Expect>,
+    result: Result<StepOutput, String>,
+    current: Option<&MppsEntry>,
+) -> (StepOutcome, String) {
+    let default = Expect::default();
+    let expect = expect.unwrap_or(&default);
+    let expect_success = expect.success.unwrap_or(true);
+    let (output, detail) = match result {
+        Ok(output) if expect_success => {
+            let detail = output.detail.clone();
+            (output, detail)
+        }
+        Ok(output) => {
+            return (
+                StepOutcome::Failed,
+                format!(
+                    "Expected the step to fail, but it succeeded: {}",
+                    output.detail
+                ),
+            )
+        }
+        Err(e) if expect_success => return (StepOutcome::Failed, e),
+        Err(e) => (StepOutput::default(), e),
+    };
+
+    let mut failures = Vec::new();
+    if let (Some(min), Some(results)) = (expect.min_results, output.results) {
+        if results < min {
+            failures.push(format!(
+                "expected at least {} results, got {}",
+                min, results
+            ));
+        }
+    }
+    if let (Some(max), Some(results)) = (expect.max_results, output.results) {
+        if results > max {
+            failures.push(format!("expected at most {} results, got {}", max, results));
+        }
+    }
+    if let Some(stored) = expect.stored {
+        if output.stored != Some(stored) {
+            failures.push(format!(
+                "expected {} stored instances, got {}",
+                stored,
+                output.stored.unwrap_or(0)
+            ));
+        }
+    }
+    if let Some(status) = expect.status {
+        let actual = current.and_then(|e| e.status);
+        if actual != Some(status) {
+            failures.push(format!(
+                "expected status {}, got {}",
+                status,
+                actual.map_or("IDLE", |s| s.as_str())
+            ));
+        }
+    }
+    if let Some(text) = &expect.message_contains {
+        if !detail.contains(text.as_str()) {
+            failures.push(format!("expected the message to contain \"{}\"", text));
+        }
+    }
+
+    if failures.is_empty() {
+        (StepOutcome::Passed, detail)
+    } else {
+        (
+            StepOutcome::Failed,
+            format!("{} ({})", failures.join("; "), detail),
+        )
+    }
+}
@@ -0,0 +1,163 @@
+// storage_commitment.rs
+// C-STORE 之后通过 Storage Commitment Push Model 确认 PACS 已持久保存实例
+use crate::dicom_client::{trim_uid, CommitmentReport, DicomNode, DimseClient};
+use crate::dicom_utils::parse_sop_instance_uids;
+use crate::models::{MppsEntry, StorageCommitment};
+use crate::utils::generate_uid;
+use chrono::Local;
+use dicom_object::DefaultDicomObject;
+use serde::Deserialize;
+use std::time::Duration;
+use surrealdb::engine::local::Db;
+use surrealdb::sql::Thing;
+use surrealdb::Surreal;
+
+pub const STORAGE_COMMITMENT_EVENT: &str = "storage-commitment";
+
+/// How long a request waits for the N-EVENT-REPORT on its own association
+/// before leaving the result to the storage SCP.
+pub const COMMITMENT_WAIT: Duration = Duration::from_secs(10);
+
+// 保存提交状态的表及对应字段名
+const COMMITMENT_FIELDS: [(&str, &str); 2] = [
+    ("mpps", "StorageCommitment"),
+    ("patient", "storage_commitment"),
+];
+
+#[derive(Deserialize)]
+struct PendingCommitment {
+    id: Thing,
+    commitment: StorageCommitment,
+}
+
+/// The instances of an MPPS entry, taken from its `SopInstanceUids`.
+pub fn mpps_commitment(entry: &MppsEntry) -> Result<StorageCommitment, String> {
+    let references = parse_sop_instance_uids(entry)?
+        .iter()
+        .flat_map(|series| {
+            series.sop_instance_infos.iter().map(|instance| {
+                (
+                    instance.sop_class_uid.clone(),
+                    instance.sop_instance_uid.clone(),
+                )
+            })
+        })
+        .collect();
+    Ok(StorageCommitment::new(String::new(), references))
+}
+
+/// The instances about to be sent by a C-STORE, before any commitment is requested.
+pub fn sent_commitment(objects: &[DefaultDicomObject]) -> StorageCommitment {
+    let references = objects
+        .iter()
+        .map(|object| {
+            let meta = object.meta();
+            (
+                trim_uid(&meta.media_storage_sop_class_uid).to_string(),
+                trim_uid(&meta.media_storage_sop_instance_uid).to_string(),
+            )
+        })
+        .collect();
+    StorageCommitment::new(String::new(), references)
+}
+
+/// Sends the N-ACTION under a new transaction UID for the `table:id` record.
+/// The pending state is saved before the request goes out, so a report that
+/// arrives on a separate association, even ahead of the N-ACTION response,
+/// finds the record. A report on the same association is applied right away
+/// and a failed request is recorded on the record as well.
+pub async fn request_commitment(
+    db: &Surreal<Db>,
+    client: &dyn DimseClient,
+    node: &DicomNode,
+    table: &str,
+    id: &str,
+    commitment: &mut StorageCommitment,
+) -> Result<(), String> {
+    if commitment.instances.is_empty() {
+        return Err("No instances to request storage commitment for".to_string());
+    }
+    *commitment = StorageCommitment::new(generate_uid(), commitment.references());
+    commitment.requested_at = Some(Local::now().to_rfc3339());
+    save_commitment(db, table, id, commitment).await?;
+
+    let report = client
+        .storage_commitment(
+            node,
+            &commitment.transaction_uid,
+            &commitment.references(),
+            COMMITMENT_WAIT,
+        )
+        .await
+        .map_err(|e| format!("Failed: Error during storage commitment: {}", e));
+    match report {
+        // 结果在另一个关联上返回时由 storage SCP 写入，这里不再覆盖
+        Ok(None) => Ok(()),
+        Ok(Some(report)) => {
+            commitment.apply(&report);
+            save_commitment(db, table, id, commitment).await
+        }
+        Err(e) => {
+            commitment.error = Some(e.clone());
+            save_commitment(db, table, id, commitment).await?;
+            Err(e)
+        }
+    }
+}
+
+// 只更新记录中的提交状态字段，不覆盖其他字段
+async fn save_commitment(
+    db: &Surreal<Db>,
+    table: &str,
+    id: &str,
+    commitment: &StorageCommitment,
+) -> Result<(), String> {
+    let (_, field) = COMMITMENT_FIELDS
+        .iter()
+        .find(|(t, _)| *t == table)
+        .ok_or_else(|| format!("Unknown storage commitment kind: {}", table))?;
+    db.query(format!("UPDATE type::thing($table, $id) SET {field} = $commitment"))
+        .bind(("table", table.to_string()))
+        .bind(("id", id.to_string()))
+        .bind(("commitment", commitment.clone()))
+        .await
+        .map_err(|e| format!("Failed to update {} entry: {}", table, e))?
+        .check()
+        .map_err(|e| format!("Failed to update {} entry: {}", table, e))?;
+    Ok(())
+}
+
+/// Applies a report received on a separate association to the record that
+/// requested it. Returns `None` when no record has that transaction UID.
+pub async fn apply_report(
+    db: &Surreal<Db>,
+    report: &CommitmentReport,
+) -> Result<Option<StorageCommitment>, String> {
+    for (table, field) in COMMITMENT_FIELDS {
+        let mut response = db
+            .query(format!(
+                "SELECT id, {field} AS commitment FROM type::table($table) \
+                 WHERE {field}.transaction_uid = $transaction_uid"
+            ))
+            .bind(("table", table))
+            .bind(("transaction_uid", report.transaction_uid.clone()))
+            .await
+            .map_err(|e| format!("Failed to read {} entries: {}", table, e))?;
+        let pending: Vec<PendingCommitment> = response
+            .take(0)
+            .map_err(|e| format!("Failed to read {} entries: {}", table, e))?;
+
+        if let Some(PendingCommitment { id, mut commitment }) = pending.into_iter().next() {
+            commitment.apply(report);
+            db.query(format!("UPDATE $id SET {field} = $commitment"))
+                .bind(("id", id))
+                .bind(("commitment", commitment.clone()))
+                .await
+                .map_err(|e| format!("Failed to update {} entry: {}", table, e))?
+                .check()
+                .map_err(|e| format!("Failed to update {} entry: {}", table, e))?;
+            return Ok(Some(commitment));
+        }
+    }
+    Ok(None)
+}
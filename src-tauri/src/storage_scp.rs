@@ -0,0 +1,289 @@
+// storage_scp.rs
+// 内置的 Storage SCP，接收 C-STORE 并按 患者/检查/序列 归档到 dicom 目录
+// 同时接收在独立关联上发回的 Storage Commitment 结果和 UPS 事件报告
+use crate::dicom_client::commitment_report;
+use crate::dicom_server::{
+    ScpReply, ScpRequest, ScpService, STATUS_PROCESSING_FAILURE, STATUS_SUCCESS,
+    STATUS_UNRECOGNIZED_OPERATION,
+};
+use crate::models::{AssociationSummary, ReceivedInstance};
+use crate::storage_commitment::{apply_report, STORAGE_COMMITMENT_EVENT};
+use crate::ups::{record_event, ups_event, UPS_EVENT};
+use chrono::Local;
+use dicom_core::Tag;
+use dicom_dictionary_std::{tags, uids};
+use dicom_object::{FileMetaTableBuilder, InMemDicomObject};
+use futures::future::BoxFuture;
+use std::path::{Path, PathBuf};
+use surrealdb::engine::local::Db;
+use surrealdb::Surreal;
+use tauri::{AppHandle, Emitter};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+pub const STORAGE_SCP_TABLE: &str = "storage_scp";
+pub const STORAGE_SCP_ASSOCIATION_TABLE: &str = "storage_scp_association";
+pub const STORAGE_SCP_ASSOCIATION_EVENT: &str = "storage-scp-association";
+
+// C-STORE 专用的失败状态
+const STATUS_OUT_OF_RESOURCES: u16 = 0xA700;
+const STATUS_CANNOT_UNDERSTAND: u16 = 0xC000;
+
+pub struct StorageScp {
+    db: Surreal<Db>,
+    handle: AppHandle,
+    archive_dir: PathBuf,
+}
+
+impl StorageScp {
+    pub fn new(db: Surreal<Db>, handle: AppHandle, archive_dir: PathBuf) -> StorageScp {
+        StorageScp {
+            db,
+            handle,
+            archive_dir,
+        }
+    }
+
+    async fn c_store(&self, request: &ScpRequest) -> ScpReply {
+        let object = match request.decode_data_set() {
+            Ok(Some(object)) => object,
+            Ok(None) => {
+                return ScpReply::response(request, STATUS_CANNOT_UNDERSTAND, None)
+                    .with_error_comment("C-STORE request has no data set")
+            }
+            Err(e) => {
+                return ScpReply::response(request, STATUS_CANNOT_UNDERSTAND, None)
+                    .with_error_comment(&e.to_string())
+            }
+        };
+
+        let instance = self.describe(request, &object);
+        if let Err(e) = write_part10(Path::new(&instance.path), request, &instance).await {
+            eprintln!("Storage SCP: {}", e);
+            return ScpReply::response(request, STATUS_OUT_OF_RESOURCES, None)
+                .with_error_comment(&e);
+        }
+
+        let sop_instance_uid = instance.sop_instance_uid.clone();
+        // 同一实例重复发送时覆盖原有索引
+        let stored: Result<Option<ReceivedInstance>, _> = self
+            .db
+            .update((STORAGE_SCP_TABLE, sop_instance_uid.as_str()))
+            .content(instance)
+            .await;
+        if let Err(e) = stored {
+            eprintln!("Storage SCP: Failed to index {}: {}", sop_instance_uid, e);
+            return ScpReply::response(request, STATUS_OUT_OF_RESOURCES, None)
+                .with_error_comment("Failed to index the instance");
+        }
+        ScpReply::response(request, STATUS_SUCCESS, None)
+    }
+
+    async fn n_event_report(&self, request: &ScpRequest) -> ScpReply {
+        let event_information = match request.decode_data_set() {
+            Ok(event_information) => event_information,
+            Err(e) => {
+                return ScpReply::response(request, STATUS_PROCESSING_FAILURE, None)
+                    .with_error_comment(&e.to_string())
+            }
+        };
+        // UPS 事件的 Affected SOP Class 总是 UPS Push
+        if request.command_str(tags::AFFECTED_SOP_CLASS_UID).as_deref()
+            == Some(uids::UNIFIED_PROCEDURE_STEP_PUSH)
+        {
+            return self.ups_event(request, event_information.as_ref()).await;
+        }
+
+        let Some(event_information) = event_information else {
+            return ScpReply::response(request, STATUS_PROCESSING_FAILURE, None)
+                .with_error_comment("N-EVENT-REPORT request has no data set");
+        };
+        let report = match commitment_report(&event_information) {
+            Ok(report) => report,
+            Err(e) => {
+                return ScpReply::response(request, STATUS_PROCESSING_FAILURE, None)
+                    .with_error_comment(&e.to_string())
+            }
+        };
+
+        let applied = apply_report(&self.db, &report).await;
+        match applied {
+            Ok(Some(commitment)) => {
+                if let Err(e) = self.handle.emit(STORAGE_COMMITMENT_EVENT, &commitment) {
+                    eprintln!("Failed to emit {}: {}", STORAGE_COMMITMENT_EVENT, e);
+                }
+            }
+            // 未知的事务也确认收到，避免对方反复重发
+            Ok(None) => eprintln!(
+                "Storage SCP: No record requested storage commitment {}",
+                report.transaction_uid
+            ),
+            Err(e) => {
+                eprintln!("Storage SCP: {}", e);
+                return ScpReply::response(request, STATUS_PROCESSING_FAILURE, None)
+                    .with_error_comment("Failed to save the storage commitment result");
+            }
+        }
+        ScpReply::response(request, STATUS_SUCCESS, None)
+    }
+
+    async fn ups_event(
+        &self,
+        request: &ScpRequest,
+        event_information: Option<&InMemDicomObject>,
+    ) -> ScpReply {
+        let event = ups_event(request, event_information);
+        let recorded = record_event(&self.db, event).await;
+        match recorded {
+            Ok(event) => {
+                if let Err(e) = self.handle.emit(UPS_EVENT, &event) {
+                    eprintln!("Failed to emit {}: {}", UPS_EVENT, e);
+                }
+                ScpReply::response(request, STATUS_SUCCESS, None)
+            }
+            Err(e) => {
+                eprintln!("Storage SCP: {}", e);
+                ScpReply::response(request, STATUS_PROCESSING_FAILURE, None)
+                    .with_error_comment("Failed to save the UPS event")
+            }
+        }
+    }
+
+    fn describe(&self, request: &ScpRequest, object: &InMemDicomObject) -> ReceivedInstance {
+        let sop_class_uid = request
+            .command_str(tags::AFFECTED_SOP_CLASS_UID)
+            .unwrap_or_else(|| attribute(object, tags::SOP_CLASS_UID));
+        let sop_instance_uid = request
+            .command_str(tags::AFFECTED_SOP_INSTANCE_UID)
+            .unwrap_or_else(|| attribute(object, tags::SOP_INSTANCE_UID));
+        let patient_id = attribute(object, tags::PATIENT_ID);
+        let study_instance_uid = attribute(object, tags::STUDY_INSTANCE_UID);
+        let series_instance_uid = attribute(object, tags::SERIES_INSTANCE_UID);
+
+        let path = self
+            .archive_dir
+            .join(path_component(&patient_id))
+            .join(path_component(&study_instance_uid))
+            .join(path_component(&series_instance_uid))
+            .join(format!("{}.dcm", path_component(&sop_instance_uid)));
+
+        ReceivedInstance {
+            id: None,
+            sop_instance_uid,
+            sop_class_uid,
+            patient_id,
+            patient_name: attribute(object, tags::PATIENT_NAME),
+            study_instance_uid,
+            series_instance_uid,
+            modality: attribute(object, tags::MODALITY),
+            transfer_syntax: request.transfer_syntax.clone(),
+            path: path.to_string_lossy().to_string(),
+            calling_ae_title: request.calling_ae_title.clone(),
+            received_at: Local::now().to_rfc3339(),
+            size: request.data_set.as_ref().map_or(0, |d| d.len() as u64),
+        }
+    }
+}
+
+impl ScpService for StorageScp {
+    fn abstract_syntaxes(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    // 不限定存储类，设备发来的任意 SOP Class 都接收
+    fn accepts_any_abstract_syntax(&self) -> bool {
+        true
+    }
+
+    fn handle<'a>(&'a self, request: &'a ScpRequest) -> BoxFuture<'a, Vec<ScpReply>> {
+        Box::pin(async move {
+            let reply = match request.command_field() {
+                0x0001 => self.c_store(request).await,
+                0x0100 => self.n_event_report(request).await,
+                _ => ScpReply::response(request, STATUS_UNRECOGNIZED_OPERATION, None),
+            };
+            vec![reply]
+        })
+    }
+
+    fn association_closed<'a>(&'a self, summary: &'a AssociationSummary) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let stored: Result<Vec<AssociationSummary>, _> = self
+                .db
+                .create(STORAGE_SCP_ASSOCIATION_TABLE)
+                .content(summary.clone())
+                .await;
+            if let Err(e) = stored {
+                eprintln!("Storage SCP: Failed to save association statistics: {}", e);
+            }
+            if let Err(e) = self.handle.emit(STORAGE_SCP_ASSOCIATION_EVENT, summary) {
+                eprintln!("Failed to emit {}: {}", STORAGE_SCP_ASSOCIATION_EVENT, e);
+            }
+        })
+    }
+}
+
+fn attribute(object: &InMemDicomObject, tag: Tag) -> String {
+    object
+        .get(tag)
+        .and_then(|e| e.to_str().ok())
+        .map(|s| s.trim_end_matches(['\0', ' ']).to_string())
+        .unwrap_or_default()
+}
+
+// 目录名只保留安全字符，缺失时使用 UNKNOWN
+fn path_component(value: &str) -> String {
+    let component: String = value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if component.is_empty() || component.chars().all(|c| c == '.') {
+        "UNKNOWN".to_string()
+    } else {
+        component
+    }
+}
+
+/// Writes the received data set as a DICOM file, keeping the bytes exactly as
+/// they were encoded by the sender. The file goes through `tokio::fs` so a
+/// slow disk doesn't stall the runtime threads serving other associations.
+async fn write_part10(
+    path: &Path,
+    request: &ScpRequest,
+    instance: &ReceivedInstance,
+) -> Result<(), String> {
+    let meta = FileMetaTableBuilder::new()
+        .media_storage_sop_class_uid(instance.sop_class_uid.as_str())
+        .media_storage_sop_instance_uid(instance.sop_instance_uid.as_str())
+        .transfer_syntax(request.transfer_syntax.as_str())
+        .build()
+        .map_err(|e| format!("Failed to build file meta: {}", e))?;
+    let mut header = vec![0u8; 128];
+    header.extend_from_slice(b"DICM");
+    meta.write(&mut header)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .await
+            .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    let mut file = fs::File::create(path)
+        .await
+        .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+    file.write_all(&header)
+        .await
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    file.write_all(request.data_set.as_deref().unwrap_or_default())
+        .await
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    file.flush()
+        .await
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
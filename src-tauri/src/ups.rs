@@ -0,0 +1,114 @@
+// ups.rs
+// UPS 工作项、订阅及事件的存储，事件报告由 Storage SCP 在独立关联上接收
+use crate::dicom_server::ScpRequest;
+use crate::models::{UpsEntry, UpsEvent};
+use chrono::Local;
+use dicom_core::Tag;
+use dicom_dictionary_std::tags;
+use dicom_object::InMemDicomObject;
+use surrealdb::engine::local::Db;
+use surrealdb::Surreal;
+
+pub const UPS_TABLE: &str = "ups";
+pub const UPS_SUBSCRIPTION_TABLE: &str = "ups_subscription";
+pub const UPS_EVENT_TABLE: &str = "ups_event";
+pub const UPS_EVENT: &str = "ups-event";
+
+// N-ACTION 的 Action Type ID
+pub const ACTION_CHANGE_STATE: u16 = 1;
+pub const ACTION_REQUEST_CANCEL: u16 = 2;
+pub const ACTION_SUBSCRIBE: u16 = 3;
+pub const ACTION_UNSUBSCRIBE: u16 = 4;
+
+fn event_type(event_type_id: u16) -> &'static str {
+    match event_type_id {
+        1 => "UPS State Report",
+        2 => "UPS Cancel Requested",
+        3 => "UPS Progress Report",
+        4 => "SCP Status Change",
+        5 => "UPS Assigned",
+        _ => "Unknown",
+    }
+}
+
+fn attribute(object: &InMemDicomObject, tag: Tag) -> Option<String> {
+    object
+        .get(tag)
+        .and_then(|e| e.to_str().ok())
+        .map(|s| s.trim_end_matches(['\0', ' ']).to_string())
+        .filter(|s| !s.is_empty())
+}
+
+/// Maps a UPS N-EVENT-REPORT to the record kept in `ups_event`.
+pub fn ups_event(request: &ScpRequest, event_information: Option<&InMemDicomObject>) -> UpsEvent {
+    let event_type_id = request
+        .command
+        .get(tags::EVENT_TYPE_ID)
+        .and_then(|e| e.to_int::<u16>().ok())
+        .unwrap_or_default();
+    let progress = event_information
+        .and_then(|info| info.get(tags::PROCEDURE_STEP_PROGRESS_INFORMATION_SEQUENCE))
+        .and_then(|e| e.items())
+        .and_then(|items| items.first());
+
+    UpsEvent {
+        id: None,
+        event_type_id,
+        event_type: event_type(event_type_id).to_string(),
+        ups_instance_uid: request
+            .command_str(tags::AFFECTED_SOP_INSTANCE_UID)
+            .unwrap_or_default(),
+        calling_ae_title: request.calling_ae_title.clone(),
+        state: event_information
+            .and_then(|info| attribute(info, tags::PROCEDURE_STEP_STATE))
+            .and_then(|state| state.parse().ok()),
+        progress: progress.and_then(|p| attribute(p, tags::PROCEDURE_STEP_PROGRESS)),
+        progress_description: progress
+            .and_then(|p| attribute(p, tags::PROCEDURE_STEP_PROGRESS_DESCRIPTION)),
+        reason_for_cancellation: event_information
+            .and_then(|info| attribute(info, tags::REASON_FOR_CANCELLATION)),
+        received_at: Local::now().to_rfc3339(),
+    }
+}
+
+/// Saves a received event and brings the workitem it refers to up to date.
+pub async fn record_event(db: &Surreal<Db>, event: UpsEvent) -> Result<UpsEvent, String> {
+    let created: Vec<UpsEvent> = db
+        .create(UPS_EVENT_TABLE)
+        .content(event.clone())
+        .await
+        .map_err(|e| format!("Failed to create ups event: {}", e))?;
+
+    let mut response = db
+        .query("SELECT * FROM type::table($table) WHERE UpsInstanceUid = $uid")
+        .bind(("table", UPS_TABLE))
+        .bind(("uid", event.ups_instance_uid.clone()))
+        .await
+        .map_err(|e| format!("Failed to read ups entries: {}", e))?;
+    let entries: Vec<UpsEntry> = response
+        .take(0)
+        .map_err(|e| format!("Failed to read ups entries: {}", e))?;
+
+    for mut entry in entries {
+        if let Some(state) = event.state {
+            entry.record_state(state);
+        }
+        if let Some(progress) = event
+            .progress
+            .as_deref()
+            .and_then(|p| p.parse::<f64>().ok())
+        {
+            entry.progress = Some(progress.clamp(0.0, 100.0) as u8);
+        }
+        let Some(id) = entry.id.clone() else {
+            continue;
+        };
+        let _: Option<UpsEntry> = db
+            .update((id.tb.as_str(), id.id.to_raw()))
+            .content(entry)
+            .await
+            .map_err(|e| format!("Failed to update ups entry: {}", e))?;
+    }
+
+    Ok(created.into_iter().next().unwrap_or(event))
+}
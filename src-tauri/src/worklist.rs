@@ -1,44 +1,526 @@
-use crate::hl7_client::{Hl7Client, SendMethod};
+use crate::api_commands::TauriCommands;
+use crate::api_server::ApiServer;
+use crate::dicom_client::{DicomClientError, DicomNode, DimseClient, EchoOutcome, EchoReport};
+use crate::dicom_server::{ScpConfig, ScpServer, ScpService};
+use crate::dicom_utils::{
+    build_completed, build_discontinued, build_in_progress, build_ups_create, build_ups_final,
+    build_ups_progress, collect_dcm_files, discontinuation_reason, discontinuation_reasons,
+    find_dcm_files, load_template, prepare_headless, prepare_instances, prepare_rt_struct,
+    query_worklist, ups_cancel_request, ups_change_state, ups_subscription,
+};
+use crate::fhir_client::{publish_log, FhirClient, FhirPatient, FhirResponse};
+use crate::hl7_bridge::{apply_orders, parse_orders, HL7_BRIDGE_EVENT};
+use crate::hl7_client::{
+    split_batch, Hl7BatchResult, Hl7Client, Hl7SendResult, Hl7TlsConfig, SendMethod,
+};
+use crate::hl7_listener::{Hl7Listener, HL7_RECEIVED_TABLE};
+use crate::hl7_pool::Hl7ConnectionStatus;
+use crate::hl7_report::send_report;
+use crate::hl7_server::Hl7Server;
+use crate::hl7_template::{entry_values, render};
+use crate::hl7_validation::{validate, Hl7ValidationIssue, Hl7ValidationResult};
 use crate::models::ApiResponse;
+use crate::models::ApiServerSetting;
+use crate::models::ApiServerState;
+use crate::models::ApiServerStatus;
+use crate::models::AssociationSummary;
 use crate::models::DbState;
-use crate::models::DicomWorklistParams;
+use crate::models::DimseState;
+use crate::models::DiscontinuationReason;
+use crate::models::EndpointEchoResult;
+use crate::models::FhirSettingEntry;
 use crate::models::HL7MessageSetting;
+use crate::models::Hl7BridgeResult;
+use crate::models::Hl7ListenerSetting;
+use crate::models::Hl7ListenerState;
+use crate::models::Hl7ListenerStatus;
+use crate::models::Hl7PoolState;
 use crate::models::Hl7SettingEntry;
+use crate::models::LoadTestConfig;
+use crate::models::LoadTestState;
 use crate::models::MimEntry;
 use crate::models::MppsEntry;
-use crate::models::MppsResponse;
+use crate::models::MppsReportSetting;
+use crate::models::MppsStatus;
 use crate::models::WorklistEntry;
 use crate::models::PatientEntry;
+use crate::models::ReceivedHl7Message;
+use crate::models::ReceivedInstance;
+use crate::models::ScenarioEntry;
+use crate::models::ScpSetting;
+use crate::models::ScpState;
+use crate::models::ScpStatus;
+use crate::models::StorageCommitment;
+use crate::models::UpsEntry;
+use crate::models::UpsEvent;
+use crate::models::UpsState;
+use crate::models::UpsSubscription;
+use crate::models::WorklistScpSetting;
+use crate::mpps_scp::{MppsScp, MPPS_SCP_TABLE};
+use crate::load_test::{LoadTest, LoadTestReport, LOAD_TEST_FINISHED_EVENT, LOAD_TEST_RUN_TABLE};
 use crate::paths::AppPath;
-use pyo3::prelude::*;
-use pyo3::types::PyModule;
-use pyo3::types::{IntoPyDict, PyBool, PyNone};
+use crate::scenario::{
+    Scenario, ScenarioReport, ScenarioRunner, ScenarioStepReport, ScenarioTemplates,
+    SCENARIO_PROGRESS_EVENT, SCENARIO_RUN_TABLE, SCENARIO_TABLE,
+};
+use crate::storage_commitment::{mpps_commitment, request_commitment, sent_commitment};
+use crate::storage_scp::{StorageScp, STORAGE_SCP_ASSOCIATION_TABLE, STORAGE_SCP_TABLE};
+use crate::ups::{
+    ACTION_CHANGE_STATE, ACTION_REQUEST_CANCEL, ACTION_SUBSCRIBE, ACTION_UNSUBSCRIBE,
+    UPS_EVENT_TABLE, UPS_SUBSCRIPTION_TABLE, UPS_TABLE,
+};
+use crate::utils::generate_uid;
+use crate::worklist_scp::WorklistScp;
+use chrono::Local;
+use dicom_dictionary_std::uids;
+use futures::future::{join_all, BoxFuture};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
 use std::fs;
-use std::result;
-use tauri::{command, AppHandle, Manager, State};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use surrealdb::engine::local::Db;
+use surrealdb::sql::Thing;
+use surrealdb::Surreal;
+use tauri::{command, AppHandle, Emitter, Manager, State};
+
+// 选中的 patient / mpps 条目的字段，用于替换报文模板中的占位符
+async fn template_values(
+    db: &Surreal<Db>,
+    kind: Option<&str>,
+    current_id: Option<&str>,
+) -> Result<HashMap<String, String>, String> {
+    let (Some(kind), Some(id)) = (kind, current_id) else {
+        return Ok(HashMap::new());
+    };
+    let entry = match kind {
+        "patient" => {
+            let entry: Option<PatientEntry> = db
+                .select(("patient", id))
+                .await
+                .map_err(|e| format!("Failed to read patient entry: {}", e))?;
+            entry.map(serde_json::to_value)
+        }
+        "mpps" => {
+            let entry: Option<MppsEntry> = db
+                .select(("mpps", id))
+                .await
+                .map_err(|e| format!("Failed to read mpps entry: {}", e))?;
+            entry.map(serde_json::to_value)
+        }
+        other => return Err(format!("Unknown entry kind: {}", other)),
+    };
+    let entry = entry
+        .ok_or_else(|| format!("No {} entry found with id: {}", kind, id))?
+        .map_err(|e| format!("Failed to serialize {} entry: {}", kind, e))?;
+    Ok(entry_values(&entry))
+}
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn send_hl7_message(
+    db_state: State<'_, DbState>,
+    pool_state: State<'_, Hl7PoolState>,
     message: &str,
     server_address: &str,
     port: &str,
     method: &str,
-) -> Result<String, String> {
-    let result = Hl7Client::new(
-        message.to_owned(),
+    ack_timeout: Option<u64>,
+    kind: Option<String>,
+    current_id: Option<String>,
+    setting_id: Option<String>,
+    handle: AppHandle,
+) -> Result<ApiResponse<Hl7SendResult>, String> {
+    let db = &db_state.db;
+    // 占位符在发送时替换，每次发送得到新的控制 ID 和时间
+    let values = template_values(db, kind.as_deref(), current_id.as_deref()).await?;
+    // TLS 选项取自选中的 HL7 配置
+    let setting: Option<Hl7SettingEntry> = match setting_id.as_deref() {
+        Some(id) => db
+            .select(("hl7_setting", id))
+            .await
+            .map_err(|e| format!("Failed to read hl7 setting entry: {}", e))?,
+        None => None,
+    };
+    let tls = match &setting {
+        Some(setting) => Hl7TlsConfig::from_setting(setting, AppPath::Certs.resolve(&handle)?),
+        None => None,
+    };
+    let message = match render(message, &values) {
+        Ok(message) => message,
+        Err(e) => return Ok(ApiResponse::error(&e, None)),
+    };
+    let Ok(port) = port.trim().parse() else {
+        return Ok(ApiResponse::error(
+            &format!("Invalid port number: {}", port),
+            None,
+        ));
+    };
+    let mut client = Hl7Client::new(
+        message,
         server_address.to_owned(),
-        port.parse().unwrap(),
+        port,
         match method {
             "TCP" => SendMethod::Tcp,
             "HTTP" => SendMethod::Http,
             _ => SendMethod::Tcp,
         },
-    )
-    .send_message()
-    .await;
+    );
+    if let Some(seconds) = ack_timeout.filter(|s| *s > 0) {
+        client = client.with_ack_timeout(Duration::from_secs(seconds));
+    }
+    let client = client.with_tls(tls);
+    // 开启保持连接的配置通过连接池发送，连接在发送后不关闭
+    let result = match (&setting, method) {
+        (Some(setting), "TCP") if setting.keep_alive.unwrap_or(false) => {
+            let id = setting_id.as_deref().unwrap_or_default();
+            pool_state.pool.send(id, &setting.name, client).await
+        }
+        _ => client.send_message().await,
+    };
     match result {
-        Ok(response) => Ok(response),
-        Err(e) => Err(e.to_string()),
+        // AE/AR 或控制 ID 不匹配时 success 为 false，但仍返回解析出的 ACK
+        Ok(response) => match &response.ack {
+            Some(ack) => Ok(ApiResponse {
+                success: ack.accepted,
+                message: ack.summary(&response.control_id),
+                data: Some(response.clone()),
+                error: None,
+            }),
+            None => Ok(ApiResponse::success("Message sent", Some(response))),
+        },
+        Err(e) => Ok(ApiResponse::error(
+            "Failed to send HL7 message",
+            Some(e.to_string()),
+        )),
+    }
+}
+
+/// Sends every message of an HL7 file (several messages or an FHS/BHS batch)
+/// over one MLLP connection to the chosen HL7 endpoint. The messages come from
+/// `file_path`, or from `content` when no file is given.
+#[command]
+pub async fn send_hl7_batch(
+    db_state: State<'_, DbState>,
+    setting_id: String,
+    file_path: Option<String>,
+    content: Option<String>,
+    handle: AppHandle,
+) -> Result<ApiResponse<Hl7BatchResult>, String> {
+    let setting: Option<Hl7SettingEntry> = db_state
+        .db
+        .select(("hl7_setting", &setting_id))
+        .await
+        .map_err(|e| format!("Failed to read hl7 setting entry: {}", e))?;
+    let setting =
+        setting.ok_or_else(|| format!("No hl7 setting entry found with id: {}", setting_id))?;
+
+    // 生产环境导出的文件不一定是 UTF-8
+    let content = match (file_path, content) {
+        (Some(path), _) => String::from_utf8_lossy(
+            &tokio::fs::read(&path)
+                .await
+                .map_err(|e| format!("Failed to read {}: {}", path, e))?,
+        )
+        .into_owned(),
+        (None, Some(content)) => content,
+        (None, None) => return Ok(ApiResponse::error("No HL7 file or content given", None)),
+    };
+    let messages = split_batch(&content);
+    if messages.is_empty() {
+        return Ok(ApiResponse::error("No HL7 messages found", None));
+    }
+
+    let Ok(port) = setting.port.trim().parse() else {
+        return Ok(ApiResponse::error(
+            &format!("Invalid port number: {}", setting.port),
+            None,
+        ));
+    };
+    let mut client = Hl7Client::new(String::new(), setting.ip.clone(), port, SendMethod::Tcp)
+        .with_tls(Hl7TlsConfig::from_setting(
+            &setting,
+            AppPath::Certs.resolve(&handle)?,
+        ));
+    if let Some(seconds) = setting.ack_timeout.filter(|s| *s > 0) {
+        client = client.with_ack_timeout(Duration::from_secs(seconds));
+    }
+    match client.send_batch(&messages).await {
+        Ok(result) => Ok(ApiResponse {
+            success: result.accepted == result.total,
+            message: result.summary(),
+            data: Some(result),
+            error: None,
+        }),
+        Err(e) => Ok(ApiResponse::error(
+            "Failed to send HL7 batch",
+            Some(e.to_string()),
+        )),
+    }
+}
+
+/// Checks an HL7 message against the structure profile of its type. The
+/// placeholders are rendered first; when some have no value the template itself
+/// is checked and the placeholders are left out of the field checks.
+#[command]
+pub async fn validate_hl7_message(
+    db_state: State<'_, DbState>,
+    message: String,
+    kind: Option<String>,
+    current_id: Option<String>,
+) -> Result<ApiResponse<Hl7ValidationResult>, String> {
+    let values = template_values(&db_state.db, kind.as_deref(), current_id.as_deref()).await?;
+    let (source, unrendered) = match render(&message, &values) {
+        Ok(rendered) => (rendered, None),
+        Err(e) => (message, Some(e)),
+    };
+    let mut result = validate(&source);
+    if let Some(e) = unrendered {
+        result.issues.insert(
+            0,
+            Hl7ValidationIssue {
+                severity: "warning".to_string(),
+                code: "PLACEHOLDER".to_string(),
+                location: String::new(),
+                segment: None,
+                field: None,
+                message: e,
+            },
+        );
+    }
+    Ok(ApiResponse {
+        success: result.valid,
+        message: result.summary(),
+        data: Some(result),
+        error: None,
+    })
+}
+
+/// Renders the placeholders of an HL7 message as `send_hl7_message` would.
+#[command]
+pub async fn preview_hl7_message(
+    db_state: State<'_, DbState>,
+    message: String,
+    kind: Option<String>,
+    current_id: Option<String>,
+) -> Result<ApiResponse<String>, String> {
+    let db = &db_state.db;
+    let values = template_values(db, kind.as_deref(), current_id.as_deref()).await?;
+    match render(&message, &values) {
+        Ok(rendered) => Ok(ApiResponse::success("HL7 message rendered", Some(rendered))),
+        Err(e) => Ok(ApiResponse::error(&e, None)),
+    }
+}
+
+// HL7 端点只做连接检查，超时与 DIMSE 保持一致
+const HL7_ECHO_TIMEOUT: Duration = Duration::from_secs(30);
+
+async fn echo_dicom(
+    client: Arc<dyn DimseClient>,
+    kind: &str,
+    id: Option<Thing>,
+    name: String,
+    node: Result<DicomNode, DicomClientError>,
+) -> EndpointEchoResult {
+    match node {
+        Ok(node) => EndpointEchoResult {
+            kind: kind.to_string(),
+            id,
+            name,
+            target: node.target(),
+            report: client.c_echo(&node).await,
+        },
+        Err(e) => EndpointEchoResult {
+            kind: kind.to_string(),
+            id,
+            name,
+            target: String::new(),
+            report: EchoReport::failed(EchoOutcome::Failed, e.to_string()),
+        },
+    }
+}
+
+async fn echo_hl7(entry: Hl7SettingEntry, certs_dir: PathBuf) -> EndpointEchoResult {
+    let target = format!("{}:{}", entry.ip, entry.port);
+    let tls = Hl7TlsConfig::from_setting(&entry, certs_dir);
+    let report = match entry.port.trim().parse::<u16>() {
+        Ok(port) => match Hl7Client::check_connection(
+            &entry.ip,
+            port,
+            HL7_ECHO_TIMEOUT,
+            tls.as_ref(),
+        )
+        .await
+        {
+            // 不发送 HL7 消息，只能说明端口可连接，不代表对方会回应 MLLP
+            Ok((elapsed, tls)) => EchoReport {
+                outcome: EchoOutcome::Reachable,
+                detail: match tls {
+                    Some(_) => "TCP reachable, TLS handshake completed (no HL7 message sent)"
+                        .to_string(),
+                    None => "TCP reachable (no HL7 message sent)".to_string(),
+                },
+                status: None,
+                association_ms: Some(elapsed.as_millis() as u64),
+                round_trip_ms: None,
+                tls,
+            },
+            Err(e) => EchoReport::failed(EchoOutcome::Failed, e.to_string()),
+        },
+        Err(_) => EchoReport::failed(
+            EchoOutcome::Failed,
+            format!("Invalid port number: {}", entry.port),
+        ),
+    };
+    EndpointEchoResult {
+        kind: "hl7".to_string(),
+        id: entry.id,
+        name: entry.name,
+        target,
+        report,
+    }
+}
+
+async fn echo_fhir(entry: FhirSettingEntry) -> EndpointEchoResult {
+    let target = entry.base_url.clone();
+    let report = match FhirClient::from_setting(&entry) {
+        Ok(client) => match client.metadata().await {
+            Ok((elapsed, detail)) => EchoReport {
+                outcome: EchoOutcome::Accepted,
+                detail,
+                status: None,
+                association_ms: None,
+                round_trip_ms: Some(elapsed.as_millis() as u64),
+                tls: None,
+            },
+            Err(e) => EchoReport::failed(EchoOutcome::Failed, e.to_string()),
+        },
+        Err(e) => EchoReport::failed(EchoOutcome::Failed, e.to_string()),
+    };
+    EndpointEchoResult {
+        kind: "fhir".to_string(),
+        id: entry.id,
+        name: entry.name,
+        target,
+        report,
+    }
+}
+
+/// 检查已配置端点的连通性。kind 为 worklist / mim / hl7 / fhir，未指定时检查所有类型；
+/// id 未指定时检查该类型下的全部条目。worklist 条目会同时检查 MPPS 端点。
+/// HL7 端点只检查 TCP (TLS) 是否可连接，结果为 reachable，不发送 HL7 消息。
+#[command]
+pub async fn echo_endpoint(
+    db_state: State<'_, DbState>,
+    dimse_state: State<'_, DimseState>,
+    kind: Option<String>,
+    id: Option<String>,
+    handle: AppHandle,
+) -> Result<ApiResponse<Vec<EndpointEchoResult>>, String> {
+    let kind = kind.map(|k| k.to_lowercase());
+    if let Some(k) = kind.as_deref() {
+        if !["worklist", "mim", "hl7", "fhir"].contains(&k) {
+            return Ok(ApiResponse::error(&format!("Unknown endpoint kind: {}", k), None));
+        }
+    }
+    if id.is_some() && kind.is_none() {
+        return Ok(ApiResponse::error("An endpoint kind is required when an id is given", None));
+    }
+    let wants = |k: &str| kind.as_deref().is_none_or(|kind| kind == k);
+
+    // 先读取全部配置，再并发执行网络检查
+    let db = &db_state.db;
+    let worklists: Vec<WorklistEntry> = if wants("worklist") {
+        select_entries(db, "worklist", id.as_deref())
+            .await
+            .map_err(|e| format!("Failed to read worklist entries: {}", e))?
+    } else {
+        Vec::new()
+    };
+    let mims: Vec<MimEntry> = if wants("mim") {
+        select_entries(db, "mim", id.as_deref())
+            .await
+            .map_err(|e| format!("Failed to read mim entries: {}", e))?
+    } else {
+        Vec::new()
+    };
+    let hl7s: Vec<Hl7SettingEntry> = if wants("hl7") {
+        select_entries(db, "hl7_setting", id.as_deref())
+            .await
+            .map_err(|e| format!("Failed to read HL7 setting entries: {}", e))?
+    } else {
+        Vec::new()
+    };
+    let fhirs: Vec<FhirSettingEntry> = if wants("fhir") {
+        select_entries(db, "fhir_setting", id.as_deref())
+            .await
+            .map_err(|e| format!("Failed to read FHIR setting entries: {}", e))?
+    } else {
+        Vec::new()
+    };
+
+    let client = dimse_state.client.clone();
+    let certs_dir = AppPath::Certs.resolve(&handle)?;
+    let mut checks: Vec<BoxFuture<'static, EndpointEchoResult>> = Vec::new();
+    for entry in worklists {
+        checks.push(Box::pin(echo_dicom(
+            client.clone(),
+            "worklist",
+            entry.id.clone(),
+            entry.name.clone(),
+            DicomNode::worklist(&entry),
+        )));
+        checks.push(Box::pin(echo_dicom(
+            client.clone(),
+            "mpps",
+            entry.id.clone(),
+            entry.name.clone(),
+            DicomNode::mpps(&entry),
+        )));
+    }
+    for entry in mims {
+        checks.push(Box::pin(echo_dicom(
+            client.clone(),
+            "mim",
+            entry.id.clone(),
+            entry.name.clone(),
+            DicomNode::storage(&entry),
+        )));
+    }
+    for entry in hl7s {
+        checks.push(Box::pin(echo_hl7(entry, certs_dir.clone())));
+    }
+    for entry in fhirs {
+        checks.push(Box::pin(echo_fhir(entry)));
+    }
+
+    if checks.is_empty() {
+        return Ok(ApiResponse::error("No endpoint found", None));
+    }
+    let results = join_all(checks).await;
+    let accepted = results
+        .iter()
+        .filter(|r| r.report.outcome == EchoOutcome::Accepted)
+        .count();
+    Ok(ApiResponse::success(
+        &format!("{} of {} endpoints reachable", accepted, results.len()),
+        Some(results),
+    ))
+}
+
+async fn select_entries<T: DeserializeOwned>(
+    db: &Surreal<Db>,
+    table: &str,
+    id: Option<&str>,
+) -> Result<Vec<T>, surrealdb::Error> {
+    match id {
+        Some(id) => {
+            let entry: Option<T> = db.select((table, id)).await?;
+            Ok(entry.into_iter().collect())
+        }
+        None => db.select(table).await,
     }
 }
 
@@ -47,7 +529,10 @@ pub async fn create_hl7_setting_entry(
     db_state: State<'_, DbState>,
     entry: Hl7SettingEntry,
 ) -> Result<ApiResponse<Hl7SettingEntry>, String> {
-    let db = db_state.db.lock().await;
+    if let Err(e) = Hl7TlsConfig::check_file_names(&entry) {
+        return Ok(ApiResponse::error(&e, None));
+    }
+    let db = &db_state.db;
 
     let created: Vec<Hl7SettingEntry> = db
         .create("hl7_setting")
@@ -71,7 +556,7 @@ pub async fn read_hl7_setting_entry(
     db_state: State<'_, DbState>,
     id: Option<String>, // 使用 Option 来允许 id 为 None
 ) -> Result<ApiResponse<Vec<Hl7SettingEntry>>, String> {
-    let db = db_state.db.lock().await;
+    let db = &db_state.db;
 
     if let Some(id) = id {
         // 如果提供了 id，则查询单个条目
@@ -108,10 +593,16 @@ pub async fn read_hl7_setting_entry(
 #[command]
 pub async fn update_hl7_setting_entry(
     db_state: State<'_, DbState>,
+    pool_state: State<'_, Hl7PoolState>,
     id: String,
     updated_entry: Hl7SettingEntry,
 ) -> Result<ApiResponse<Hl7SettingEntry>, String> {
-    let db = db_state.db.lock().await;
+    if let Err(e) = Hl7TlsConfig::check_file_names(&updated_entry) {
+        return Ok(ApiResponse::error(&e, None));
+    }
+    // 保持的连接按旧配置打开，下次发送时按新配置重新连接
+    pool_state.pool.close(&id).await;
+    let db = &db_state.db;
 
     let updated: Option<Hl7SettingEntry> = db
         .update(("hl7_setting", &id))
@@ -134,9 +625,11 @@ pub async fn update_hl7_setting_entry(
 #[command]
 pub async fn delete_hl7_setting_entry(
     db_state: State<'_, DbState>,
+    pool_state: State<'_, Hl7PoolState>,
     id: String,
 ) -> Result<ApiResponse<Hl7SettingEntry>, String> {
-    let db = db_state.db.lock().await;
+    pool_state.pool.close(&id).await;
+    let db = &db_state.db;
 
     let hl7_setting_entry: Option<Hl7SettingEntry> = db
         .delete(("hl7_setting", &id))
@@ -155,12 +648,206 @@ pub async fn delete_hl7_setting_entry(
     }
 }
 
+#[command]
+pub async fn create_fhir_setting_entry(
+    db_state: State<'_, DbState>,
+    entry: FhirSettingEntry,
+) -> Result<ApiResponse<FhirSettingEntry>, String> {
+    let db = &db_state.db;
+
+    let created: Vec<FhirSettingEntry> = db
+        .create("fhir_setting")
+        .content(entry)
+        .await
+        .map_err(|e| format!("Failed to create FHIR setting entry: {}", e))?;
+
+    match created.into_iter().next() {
+        Some(e) => Ok(ApiResponse::success(
+            "FHIR setting entry created successfully",
+            Some(e),
+        )),
+        None => Ok(ApiResponse::error(
+            "Failed to create FHIR setting entry",
+            None,
+        )),
+    }
+}
+
+#[command]
+pub async fn read_fhir_setting_entry(
+    db_state: State<'_, DbState>,
+    id: Option<String>,
+) -> Result<ApiResponse<Vec<FhirSettingEntry>>, String> {
+    let db = &db_state.db;
+    let entries: Vec<FhirSettingEntry> = select_entries(db, "fhir_setting", id.as_deref())
+        .await
+        .map_err(|e| format!("Failed to read FHIR setting entries: {}", e))?;
+    Ok(ApiResponse::success("FHIR setting entries found", Some(entries)))
+}
+
+#[command]
+pub async fn update_fhir_setting_entry(
+    db_state: State<'_, DbState>,
+    id: String,
+    updated_entry: FhirSettingEntry,
+) -> Result<ApiResponse<FhirSettingEntry>, String> {
+    let db = &db_state.db;
+
+    let updated: Option<FhirSettingEntry> = db
+        .update(("fhir_setting", &id))
+        .content(updated_entry)
+        .await
+        .map_err(|e| format!("Failed to update FHIR setting entry: {}", e))?;
+
+    match updated {
+        Some(e) => Ok(ApiResponse::success(
+            "FHIR setting entry updated successfully",
+            Some(e),
+        )),
+        None => Ok(ApiResponse::error(
+            "No FHIR setting entry found to update",
+            None,
+        )),
+    }
+}
+
+#[command]
+pub async fn delete_fhir_setting_entry(
+    db_state: State<'_, DbState>,
+    id: String,
+) -> Result<ApiResponse<FhirSettingEntry>, String> {
+    let db = &db_state.db;
+
+    let deleted: Option<FhirSettingEntry> = db
+        .delete(("fhir_setting", &id))
+        .await
+        .map_err(|e| format!("Failed to delete FHIR setting entry: {}", e))?;
+
+    match deleted {
+        Some(e) => Ok(ApiResponse::success(
+            "FHIR setting entry deleted successfully",
+            Some(e),
+        )),
+        None => Ok(ApiResponse::error(
+            "No FHIR setting entry found to delete",
+            None,
+        )),
+    }
+}
+
+/// Creates a Patient, ServiceRequest or ImagingStudy on the chosen FHIR
+/// server. `kind` is `patient` (Patient only) or `mpps`; resources sent for an
+/// `mpps` entry are logged on it. The patient is created on demand for the
+/// other two, matched by patient ID.
+#[command]
+pub async fn send_fhir_resource(
+    db_state: State<'_, DbState>,
+    setting_id: String,
+    resource_type: String,
+    kind: String,
+    current_id: String,
+) -> Result<ApiResponse<FhirResponse>, String> {
+    let db = &db_state.db;
+    let setting: Option<FhirSettingEntry> = db
+        .select(("fhir_setting", &setting_id))
+        .await
+        .map_err(|e| format!("Failed to read FHIR setting entry: {}", e))?;
+    let setting =
+        setting.ok_or_else(|| format!("No FHIR setting entry found with id: {}", setting_id))?;
+    let client = match FhirClient::from_setting(&setting) {
+        Ok(client) => client,
+        Err(e) => return Ok(ApiResponse::error(&e.to_string(), None)),
+    };
+
+    let result = match kind.as_str() {
+        "patient" => {
+            if resource_type != "Patient" {
+                return Ok(ApiResponse::error(
+                    &format!("{} cannot be built from a patient entry", resource_type),
+                    None,
+                ));
+            }
+            let entry: Option<PatientEntry> = db
+                .select(("patient", &current_id))
+                .await
+                .map_err(|e| format!("Failed to read patient entry: {}", e))?;
+            let entry =
+                entry.ok_or_else(|| format!("No patient entry found with id: {}", current_id))?;
+            client.create_patient(&FhirPatient::from(&entry)).await
+        }
+        "mpps" => {
+            let _claim = db_state.claim("mpps", &current_id)?;
+            let entry: Option<MppsEntry> = db
+                .select(("mpps", &current_id))
+                .await
+                .map_err(|e| format!("Failed to read MppsEntry entry: {}", e))?;
+            let mut entry =
+                entry.ok_or_else(|| format!("No MppsEntry entry found with id: {}", current_id))?;
+            let stored = entry.clone();
+            let result = match resource_type.as_str() {
+                "Patient" => client.create_patient(&FhirPatient::from(&entry)).await,
+                "ServiceRequest" => client.create_service_request(&entry).await,
+                "ImagingStudy" => client.create_imaging_study(&entry).await,
+                _ => {
+                    return Ok(ApiResponse::error(
+                        &format!("Unsupported FHIR resource type: {}", resource_type),
+                        None,
+                    ))
+                }
+            };
+            entry
+                .fhir_publish
+                .push(publish_log(&resource_type, &setting, &result));
+            merge_changes(db, "mpps", &current_id, &stored, &entry).await?;
+            result
+        }
+        _ => return Err(format!("Unknown FHIR source kind: {}", kind)),
+    };
+
+    match result {
+        Ok(response) => Ok(ApiResponse::success(
+            &format!(
+                "{} {} (HTTP {})",
+                response.reference().unwrap_or(response.resource_type.clone()),
+                if response.status == 201 { "created" } else { "already exists" },
+                response.status
+            ),
+            Some(response),
+        )),
+        Err(e) => Ok(ApiResponse::error(
+            "Failed to send FHIR resource",
+            Some(e.to_string()),
+        )),
+    }
+}
+
+// 读取保持中的 HL7 连接及其状态
+#[command]
+pub async fn read_hl7_connections(
+    pool_state: State<'_, Hl7PoolState>,
+) -> Result<ApiResponse<Vec<Hl7ConnectionStatus>>, String> {
+    let statuses = pool_state.pool.status().await;
+    Ok(ApiResponse::success("HL7 connections", Some(statuses)))
+}
+
+#[command]
+pub async fn close_hl7_connection(
+    pool_state: State<'_, Hl7PoolState>,
+    setting_id: String,
+) -> Result<ApiResponse<()>, String> {
+    if pool_state.pool.close(&setting_id).await {
+        Ok(ApiResponse::success("HL7 connection closed", None))
+    } else {
+        Ok(ApiResponse::error("No open HL7 connection for this setting", None))
+    }
+}
+
 #[command]
 pub async fn create_mim_entry(
     db_state: State<'_, DbState>,
     entry: MimEntry,
 ) -> Result<ApiResponse<MimEntry>, String> {
-    let db = db_state.db.lock().await;
+    let db = &db_state.db;
 
     let created: Vec<MimEntry> = db
         .create("mim")
@@ -184,7 +871,7 @@ pub async fn read_mim_entry(
     db_state: State<'_, DbState>,
     id: Option<String>, // 使用 Option 来允许 id 为 None
 ) -> Result<ApiResponse<Vec<MimEntry>>, String> {
-    let db = db_state.db.lock().await;
+    let db = &db_state.db;
 
     if let Some(id) = id {
         // 如果提供了 id，则查询单个条目
@@ -218,7 +905,7 @@ pub async fn update_mim_entry(
     id: String,
     updated_entry: MimEntry,
 ) -> Result<ApiResponse<MimEntry>, String> {
-    let db = db_state.db.lock().await;
+    let db = &db_state.db;
 
     let updated: Option<MimEntry> = db
         .update(("mim", &id))
@@ -240,7 +927,7 @@ pub async fn delete_mim_entry(
     db_state: State<'_, DbState>,
     id: String,
 ) -> Result<ApiResponse<MimEntry>, String> {
-    let db = db_state.db.lock().await;
+    let db = &db_state.db;
 
     let mim_entry: Option<MimEntry> = db
         .delete(("mim", &id))
@@ -260,9 +947,10 @@ pub async fn delete_mim_entry(
 pub async fn search_worklist_data(
     id: String,
     db_state: State<'_, DbState>,
+    dimse_state: State<'_, DimseState>,
     handle: AppHandle,
 ) -> Result<String, String> {
-    let db = db_state.db.lock().await;
+    let db = &db_state.db;
 
     // 查询单个条目
     let entry: Option<WorklistEntry> = db
@@ -272,72 +960,13 @@ pub async fn search_worklist_data(
 
     // 如果没有查询到结果，抛出异常
     let entry = entry.ok_or_else(|| format!("No worklist entry found with id: {}", id))?;
+    let queries = vec![
+        load_template(&AppPath::CFindRq.resolve(&handle)?)?,
+        load_template(&AppPath::MrModality.resolve(&handle)?)?,
+    ];
+    let result = query_worklist(dimse_state.client.as_ref(), &entry, queries).await?;
 
-    // 从 entry 中构建 DicomWorklistParams
-    let params = DicomWorklistParams {
-        calling_ae_title: entry.calling_ae_title,
-        ae_title: entry.worklist_ae_title,
-        ae_address: entry.worklist_ip,
-        ae_port: entry.worklist_port.parse().unwrap_or(106), // 假设端口为字符串，需要解析
-        c_find_rq_path: AppPath::CFindRq
-            .resolve(&handle)?
-            .to_string_lossy()
-            .into_owned(),
-        mr_modality_path: AppPath::MrModality
-            .resolve(&handle)?
-            .to_string_lossy()
-            .into_owned(),
-    };
-
-    let certs_path = AppPath::Certs
-    .resolve(&handle)?
-    .to_string_lossy()
-    .into_owned();
-
-    // 解析 Python 脚本路径
-    let script_path = AppPath::PythonScript.resolve(&handle)?;
-
-    // 执行 Python 脚本
-    let result: Result<String, String> = Python::with_gil(|py| {
-        let script_content = fs::read_to_string(&script_path)
-            .map_err(|e| format!("Failed to read Python script file: {}", e))?;
-
-        let module =
-            PyModule::from_code_bound(py, &script_content, "dicom_utils.py", "dicom_utils")
-                .map_err(|e| format!("Failed to load Python module: {}", e))?;
-
-        let get_work_list = module
-            .getattr("get_work_list_with_paths")
-            .and_then(|f| {
-              let tls_enabled_py = match entry.tls_enabled {
-                Some(value) => value,
-                None => false,
-            };
-                f.call1((
-                    &params.calling_ae_title,
-                    &params.ae_title,
-                    &params.ae_address,
-                    params.ae_port,
-                    &params.c_find_rq_path,
-                    &params.mr_modality_path,
-                    tls_enabled_py,
-                    certs_path,
-                ))
-            })
-            .map_err(|e| format!("Failed to call Python function: {}", e))?;
-
-        let work_list_result = get_work_list
-            .extract::<String>()
-            .map_err(|e| format!("Failed to extract Python function result: {}", e))?;
-
-        Ok(work_list_result)
-    });
-
-    // 返回结果
-    match result {
-        Ok(work_list_message) => Ok(work_list_message),
-        Err(e) => Err(format!("An error occurred: {}", e)),
-    }
+    serde_json::to_string(&result).map_err(|e| format!("An error occurred: {}", e))
 }
 
 #[command]
@@ -345,7 +974,7 @@ pub async fn create_worklist_entry(
     db_state: State<'_, DbState>,
     entry: WorklistEntry,
 ) -> Result<ApiResponse<WorklistEntry>, String> {
-    let db = db_state.db.lock().await;
+    let db = &db_state.db;
 
     let created: Vec<WorklistEntry> = db
         .create("worklist")
@@ -369,7 +998,7 @@ pub async fn read_worklist_entry(
     db_state: State<'_, DbState>,
     id: Option<String>, // 使用 Option 来允许 id 为 None
 ) -> Result<ApiResponse<Vec<WorklistEntry>>, String> {
-    let db = db_state.db.lock().await;
+    let db = &db_state.db;
 
     if let Some(id) = id {
         // 如果提供了 id，则查询单个条目
@@ -406,7 +1035,7 @@ pub async fn update_worklist_entry(
     id: String,
     updated_entry: WorklistEntry,
 ) -> Result<ApiResponse<WorklistEntry>, String> {
-    let db = db_state.db.lock().await;
+    let db = &db_state.db;
 
     let updated: Option<WorklistEntry> = db
         .update(("worklist", &id))
@@ -431,7 +1060,7 @@ pub async fn delete_worklist_entry(
     db_state: State<'_, DbState>,
     id: String,
 ) -> Result<ApiResponse<WorklistEntry>, String> {
-    let db = db_state.db.lock().await;
+    let db = &db_state.db;
 
     let worklist_entry: Option<WorklistEntry> = db
         .delete(("worklist", &id))
@@ -453,14 +1082,12 @@ pub async fn delete_worklist_entry(
 #[command]
 pub async fn create_mpps_entry(
     db_state: State<'_, DbState>,
+    dimse_state: State<'_, DimseState>,
     selected_id: String,
     mut entry: MppsEntry,
     handle: AppHandle,
 ) -> Result<ApiResponse<MppsEntry>, String> {
-    // 查询数据
-
-    // 调用python代码
-    let db = db_state.db.lock().await;
+    let db = &db_state.db;
 
     // 查询单个条目
     let worklist: Option<WorklistEntry> = db
@@ -471,56 +1098,38 @@ pub async fn create_mpps_entry(
     // 如果没有查询到结果，抛出异常
     let worklist =
         worklist.ok_or_else(|| format!("No worklist entry found with id: {}", selected_id))?;
-    let worklist_json = serde_json::to_string(&worklist).unwrap();
-    let mpps_json = serde_json::to_string(&entry).unwrap();
-    // 解析 Python 脚本路径
-    let script_path = AppPath::PythonScript.resolve(&handle)?;
-    let inprogress_path_str = AppPath::InProgress
-        .resolve(&handle)?
-        .to_string_lossy()
-        .into_owned();
-    // 执行 Python 脚本
-    let result: Result<MppsResponse, String> = Python::with_gil(|py| {
-        let script_content = fs::read_to_string(&script_path)
-            .map_err(|e| format!("Failed to read Python script file: {}", e))?;
-
-        let module =
-            PyModule::from_code_bound(py, &script_content, "dicom_utils.py", "dicom_utils")
-                .map_err(|e| format!("Failed to load Python module: {}", e))?;
-
-        let certs_path = AppPath::Certs
-        .resolve(&handle)?
-        .to_string_lossy()
-        .into_owned();
-
-        let send_mpps_in_progress = module
-            .getattr("send_mpps_in_progress")
-            .and_then(|f: Bound<'_, PyAny>| {
-                f.call1((worklist_json, mpps_json, inprogress_path_str, false, certs_path))
-            })
-            .map_err(|e| format!("Failed to call Python function: {}", e))?;
+    let node = DicomNode::mpps(&worklist).map_err(|e| e.to_string())?;
 
-        let result_send_mpps_in_progress = send_mpps_in_progress
-            .extract::<String>()
-            .map_err(|e| format!("Failed to extract Python function result: {}", e))?;
-
-        // 解析返回的 JSON 字符串
-        let response: MppsResponse = serde_json::from_str(&result_send_mpps_in_progress)
-            .map_err(|e| format!("Failed to parse response: {}", e))?;
-
-        if response.success {
-            Ok(response)
-        } else {
-            Err(format!("Failed: {}", response.message))
-        }
-    });
-    if let Err(e) = result {
-        return Err(e);
+    // 前端传入的是目标状态，新建的条目总是从未开始状态出发
+    let target = entry.status.take().unwrap_or(MppsStatus::InProgress);
+    if let Err(e) = entry.check_transition(target) {
+        return Ok(ApiResponse::error(&e, None));
     }
 
-    if let Ok(response) = result {
-        entry.mpps_instance_uid = Some(response.result);
-    }
+    let mpps_instance_uid = entry
+        .mpps_instance_uid
+        .clone()
+        .filter(|uid| !uid.is_empty())
+        .unwrap_or_else(generate_uid);
+    let attributes = build_in_progress(
+        load_template(&AppPath::InProgress.resolve(&handle)?)?,
+        &entry,
+        &worklist,
+    )?;
+
+    dimse_state
+        .client
+        .n_create(
+            &node,
+            uids::MODALITY_PERFORMED_PROCEDURE_STEP,
+            &mpps_instance_uid,
+            attributes,
+        )
+        .await
+        .map_err(|e| format!("Failed: {}", e))?;
+    entry.mpps_instance_uid = Some(mpps_instance_uid);
+    entry.status_history.clear();
+    entry.transition(target)?;
 
     let created: Vec<MppsEntry> = db
         .create("mpps")
@@ -556,16 +1165,20 @@ pub async fn get_base_log_dir(handle: AppHandle) -> Result<String, String> {
 }
 
 #[command]
+#[allow(clippy::too_many_arguments)]
 pub async fn update_mpps_entry(
     db_state: State<'_, DbState>,
+    dimse_state: State<'_, DimseState>,
     dcm_file: String,
     selected_id: String,
     id: String,
     status: String,
     description: Option<String>,
+    report: Option<MppsReportSetting>,
     handle: AppHandle,
 ) -> Result<ApiResponse<MppsEntry>, String> {
-    let db = db_state.db.lock().await;
+    let db = &db_state.db;
+    let _claim = db_state.claim("mpps", &id)?;
 
     // 查询单个条目
     let worklist: Option<WorklistEntry> = db
@@ -585,75 +1198,85 @@ pub async fn update_mpps_entry(
     // 如果没有查询到结果，抛出异常
     let mut updated_entry =
         updated_entry.ok_or_else(|| format!("No worklist entry found with id: {}", id))?;
-    updated_entry.status = Some(status);
+    let stored = updated_entry.clone();
+
+    // 该命令只负责 COMPLETED，中止走 discontinue_mpps_entry
+    let target = match status.parse::<MppsStatus>() {
+        Ok(MppsStatus::Completed) => MppsStatus::Completed,
+        Ok(other) => {
+            return Ok(ApiResponse::error(
+                &format!("update_mpps_entry cannot set status {}", other),
+                None,
+            ))
+        }
+        Err(e) => return Ok(ApiResponse::error(&e, None)),
+    };
+    if let Err(e) = updated_entry.check_transition(target) {
+        return Ok(ApiResponse::error(&e, None));
+    }
     updated_entry.description = description;
 
-    let worklist_json = serde_json::to_string(&worklist).unwrap();
-    let mpps_json = serde_json::to_string(&updated_entry).unwrap();
-
-    // 解析 Python 脚本路径
-    let script_path = AppPath::PythonScript.resolve(&handle)?;
-    let completed_path_str = AppPath::Completed
-        .resolve(&handle)?
-        .to_string_lossy()
-        .into_owned();
-    // 执行 Python 脚本
-    let result: Result<MppsResponse, String> = Python::with_gil(|py| {
-        let script_content = fs::read_to_string(&script_path)
-            .map_err(|e| format!("Failed to read Python script file: {}", e))?;
-
-        let module =
-            PyModule::from_code_bound(py, &script_content, "dicom_utils.py", "dicom_utils")
-                .map_err(|e| format!("Failed to load Python module: {}", e))?;
-
-
-        let certs_path = AppPath::Certs
-        .resolve(&handle)?
-        .to_string_lossy()
-        .into_owned();
-        let send_mpps_completed = module
-            .getattr("send_mpps_completed")
-            .and_then(|f: Bound<'_, PyAny>| {
-                f.call1((
-                    worklist_json,
-                    mpps_json,
-                    &dcm_file,
-                    completed_path_str,
-                    false,
-                    certs_path,
-                ))
-            })
-            .map_err(|e| format!("Failed to call Python function: {}", e))?;
-
-        let result_send_mpps_completed = send_mpps_completed
-            .extract::<String>()
-            .map_err(|e| format!("Failed to extract Python function result: {}", e))?;
-
-        // 解析返回的 JSON 字符串
-        let response: MppsResponse = serde_json::from_str(&result_send_mpps_completed)
-            .map_err(|e| format!("Failed to parse response: {}", e))?;
-
-        if response.success {
-            Ok(response)
-        } else {
-            Err(format!("Failed: {}", response.message))
-        }
-    });
+    let mpps_instance_uid = updated_entry
+        .mpps_instance_uid
+        .clone()
+        .ok_or_else(|| format!("mpps entry {} has no MPPS instance UID", id))?;
+    let node = DicomNode::mpps(&worklist).map_err(|e| e.to_string())?;
 
-    if let Err(e) = result {
-        return Err(e);
+    let series = collect_dcm_files(Path::new(&dcm_file))?;
+    if series.is_empty() {
+        return Err("Failed: No SOP Instance UIDs found.".to_string());
     }
+    let modifications = build_completed(
+        load_template(&AppPath::Completed.resolve(&handle)?)?,
+        &updated_entry,
+        &series,
+    )?;
+
+    // 报告的 HL7 设置和证书目录在 N-SET 之前确定，查找失败时不发送 N-SET
+    let report_target = match report {
+        Some(report) => {
+            let setting: Option<Hl7SettingEntry> = db
+                .select(("hl7_setting", &report.hl7_setting_id))
+                .await
+                .map_err(|e| format!("Failed to read hl7 setting entry: {}", e))?;
+            let setting = setting.ok_or_else(|| {
+                format!(
+                    "No hl7 setting entry found with id: {}",
+                    report.hl7_setting_id
+                )
+            })?;
+            let message_type = report.message_type.unwrap_or_else(|| "ORU".to_string());
+            let certs_dir = AppPath::Certs.resolve(&handle)?;
+            Some((setting, message_type, certs_dir))
+        }
+        None => None,
+    };
 
-    if let Ok(response) = result {
-        updated_entry.sop_instance_uids = Some(response.result);
-        updated_entry.dcm_file = Some(dcm_file);
+    dimse_state
+        .client
+        .n_set(
+            &node,
+            uids::MODALITY_PERFORMED_PROCEDURE_STEP,
+            &mpps_instance_uid,
+            modifications,
+        )
+        .await
+        .map_err(|e| format!("Failed: {}", e))?;
+
+    updated_entry.sop_instance_uids = Some(
+        serde_json::to_string(&series)
+            .map_err(|e| format!("Failed to serialize SopInstanceUids: {}", e))?,
+    );
+    updated_entry.dcm_file = Some(dcm_file);
+    updated_entry.transition(target)?;
+
+    // 报告发送失败不影响 MPPS 完成，结果记录在条目上
+    if let Some((setting, message_type, certs_dir)) = report_target {
+        updated_entry.hl7_report =
+            Some(send_report(&updated_entry, &setting, &message_type, certs_dir).await);
     }
 
-    let updated: Option<MppsEntry> = db
-        .update(("mpps", &id))
-        .content(updated_entry)
-        .await
-        .map_err(|e| format!("Failed to update mpps entry: {}", e))?;
+    let updated = merge_changes(db, "mpps", &id, &stored, &updated_entry).await?;
 
     match updated {
         Some(e) => Ok(ApiResponse::success(
@@ -665,32 +1288,116 @@ pub async fn update_mpps_entry(
 }
 
 #[command]
-pub async fn read_mpps_entry(
+pub async fn discontinue_mpps_entry(
     db_state: State<'_, DbState>,
-    id: Option<String>, // 使用 Option 来允许 id 为 None
-) -> Result<ApiResponse<Vec<MppsEntry>>, String> {
-    let db = db_state.db.lock().await;
+    dimse_state: State<'_, DimseState>,
+    selected_id: String,
+    id: String,
+    reason_code: String,
+    description: Option<String>,
+    handle: AppHandle,
+) -> Result<ApiResponse<MppsEntry>, String> {
+    let db = &db_state.db;
+    let _claim = db_state.claim("mpps", &id)?;
 
-    if let Some(id) = id {
-        // 如果提供了 id，则查询单个条目
-        let entry: Option<MppsEntry> = db
-            .select(("mpps", &id))
-            .await
-            .map_err(|e| format!("Failed to read mpps entry: {}", e))?;
+    // 查询单个条目
+    let worklist: Option<WorklistEntry> = db
+        .select(("worklist", &selected_id))
+        .await
+        .map_err(|e| format!("Failed to read worklist entry: {}", e))?;
 
-        match entry {
-            Some(e) => Ok(ApiResponse::success("mpps entry found", Some(vec![e]))), // 返回单个条目作为列表
-            None => Ok(ApiResponse::error("No mpps entry found", None)),
-        }
-    } else {
-        // 如果没有提供 id，则查询全部条目
-        let entries: Vec<MppsEntry> = db
-            .select("mpps")
-            .await
-            .map_err(|e| format!("Failed to read mpps entries: {}", e))?;
+    // 如果没有查询到结果，抛出异常
+    let worklist =
+        worklist.ok_or_else(|| format!("No worklist entry found with id: {}", selected_id))?;
 
-        if !entries.is_empty() {
-            Ok(ApiResponse::success("mpps entries found", Some(entries)))
+    let mpps_entry: Option<MppsEntry> = db
+        .select(("mpps", &id))
+        .await
+        .map_err(|e| format!("Failed to read mpps entry: {}", e))?;
+    let mut mpps_entry = mpps_entry.ok_or_else(|| format!("No mpps entry found with id: {}", id))?;
+    let stored = mpps_entry.clone();
+
+    if let Err(e) = mpps_entry.check_transition(MppsStatus::Discontinued) {
+        return Ok(ApiResponse::error(&e, None));
+    }
+    let Some(reason) = discontinuation_reason(&reason_code) else {
+        return Ok(ApiResponse::error(
+            &format!("Unknown discontinuation reason code: {}", reason_code),
+            None,
+        ));
+    };
+
+    let mpps_instance_uid = mpps_entry
+        .mpps_instance_uid
+        .clone()
+        .ok_or_else(|| format!("mpps entry {} has no MPPS instance UID", id))?;
+    let node = DicomNode::mpps(&worklist).map_err(|e| e.to_string())?;
+
+    mpps_entry.description = description;
+    let modifications = build_discontinued(
+        load_template(&AppPath::DisContinued.resolve(&handle)?)?,
+        &mpps_entry,
+        &reason,
+    )?;
+
+    dimse_state
+        .client
+        .n_set(
+            &node,
+            uids::MODALITY_PERFORMED_PROCEDURE_STEP,
+            &mpps_instance_uid,
+            modifications,
+        )
+        .await
+        .map_err(|e| format!("Failed: {}", e))?;
+    mpps_entry.transition(MppsStatus::Discontinued)?;
+
+    let updated = merge_changes(db, "mpps", &id, &stored, &mpps_entry).await?;
+
+    match updated {
+        Some(e) => Ok(ApiResponse::success(
+            "mpps entry discontinued successfully",
+            Some(e),
+        )),
+        None => Ok(ApiResponse::error("No mpps entry found to update", None)),
+    }
+}
+
+#[command]
+pub async fn read_discontinuation_reasons() -> Result<ApiResponse<Vec<DiscontinuationReason>>, String> {
+    Ok(ApiResponse::success(
+        "discontinuation reasons found",
+        Some(discontinuation_reasons()),
+    ))
+}
+
+#[command]
+pub async fn read_mpps_entry(
+    db_state: State<'_, DbState>,
+    id: Option<String>, // 使用 Option 来允许 id 为 None
+) -> Result<ApiResponse<Vec<MppsEntry>>, String> {
+    let db = &db_state.db;
+
+    if let Some(id) = id {
+        // 如果提供了 id，则查询单个条目
+        let entry: Option<MppsEntry> = db
+            .select(("mpps", &id))
+            .await
+            .map_err(|e| format!("Failed to read mpps entry: {}", e))?;
+
+        match entry {
+            Some(e) => Ok(ApiResponse::success("mpps entry found", Some(vec![e]))), // 返回单个条目作为列表
+            None => Ok(ApiResponse::error("No mpps entry found", None)),
+        }
+    } else {
+        // 如果没有提供 id，则查询全部条目
+        let entries: Vec<MppsEntry> = db
+            .select("mpps")
+            .await
+            .map_err(|e| format!("Failed to read mpps entries: {}", e))?;
+
+        if !entries.is_empty() {
+            Ok(ApiResponse::success("mpps entries found", Some(entries)))
         } else {
             Ok(ApiResponse::error("No mpps entries found", None))
         }
@@ -702,35 +1409,49 @@ pub async fn delete_mpps_entry(
     db_state: State<'_, DbState>,
     id: String,
 ) -> Result<ApiResponse<Vec<MppsEntry>>, String> {
-    let db = db_state.db.lock().await; 
+    let db = &db_state.db;
     if id == "all" {
-        // 查询 status 为 COMPLETED 的条目
+        // 查询已结束 (COMPLETED / DISCONTINUED) 的条目
         let mut complete_entries = db
-            .query("SELECT * FROM mpps WHERE status = 'COMPLETED'")
+            .query("SELECT * FROM mpps WHERE status INSIDE ['COMPLETED', 'DISCONTINUED']")
             .await
             .map_err(|e| format!("Failed to retrieve complete mpps entries: {}", e))?;
 
           // 提取结果集
           let result: Vec<MppsEntry> = complete_entries.take(0).map_err(|e| format!("Failed to extract mpps entries: {}", e))?;
 
-          // 删除查询出来的每一条记录
-          for entry in &result {
+          // 删除查询出来的每一条记录，正在发送 (send_to_pacs 等) 的条目跳过
+          let mut deleted = Vec::new();
+          let mut skipped = 0;
+          for entry in result {
               if let Some(ref id) = entry.id {
                 let tb: String = id.tb.clone();
                 let raw = &id.id.to_raw();
-                let _: Option<MppsEntry> = db.delete((tb, raw))
+                let Ok(_claim) = db_state.claim(&tb, raw) else {
+                    skipped += 1;
+                    continue;
+                };
+                let _: Option<MppsEntry> = db.delete((tb.as_str(), raw.as_str()))
                       .await
                       .map_err(|e| format!("Failed to delete mpps entry with id {}: {}", id, e))?;
+                deleted.push(entry);
               } else {
                   return Err("Missing ID for an entry, cannot delete".to_string());
               }
           }
 
-          Ok(ApiResponse::success(
-              "All complete mpps entries deleted successfully",
-              Some(result),
-          ))
+          let message = if skipped == 0 {
+              "All complete mpps entries deleted successfully".to_string()
+          } else {
+              format!(
+                  "{} complete mpps entries deleted, {} busy entries skipped",
+                  deleted.len(),
+                  skipped
+              )
+          };
+          Ok(ApiResponse::success(&message, Some(deleted)))
     } else {
+        let _claim = db_state.claim("mpps", &id)?;
         let worklist_entry: Option<MppsEntry> = db
             .delete(("mpps", &id))
             .await
@@ -746,253 +1467,901 @@ pub async fn delete_mpps_entry(
     }
 }
 
-#[command]
-pub async fn send_to_pacs(
-    db_state: State<'_, DbState>,
-    id: String,
-    current_id: String,
-    handle: AppHandle,
-) -> Result<ApiResponse<String>, String> {
-    let db = db_state.db.lock().await;
-
-    let mim_entry: Option<MimEntry> = db
-        .select(("mim", &id))
+// 只写回本次操作改动的字段，操作期间另外写入的字段 (例如异步到达的 Storage Commitment 报告) 不会被旧值覆盖
+async fn merge_changes<T: Serialize + DeserializeOwned>(
+    db: &Surreal<Db>,
+    table: &str,
+    id: &str,
+    before: &T,
+    after: &T,
+) -> Result<Option<T>, String> {
+    let changes = changed_fields(before, after)
+        .map_err(|e| format!("Failed to serialize {} entry: {}", table, e))?;
+    // 条目已被删除时不重新创建
+    let mut response = db
+        .query("UPDATE type::thing($table, $id) MERGE $changes WHERE id != NONE RETURN AFTER")
+        .bind(("table", table.to_string()))
+        .bind(("id", id.to_string()))
+        .bind(("changes", changes))
         .await
-        .map_err(|e| format!("Failed to read MimEntry entry: {}", e))?;
+        .map_err(|e| format!("Failed to update {} entry: {}", table, e))?;
+    response
+        .take(0)
+        .map_err(|e| format!("Failed to update {} entry: {}", table, e))
+}
 
-    let mim_entry = mim_entry.ok_or_else(|| format!("No MimEntry entry found with id: {}", id))?;
+/// Top-level fields of `after` that differ from `before`. Fields that were
+/// removed map to null.
+fn changed_fields<T: Serialize>(
+    before: &T,
+    after: &T,
+) -> Result<serde_json::Map<String, serde_json::Value>, serde_json::Error> {
+    let serde_json::Value::Object(before) = serde_json::to_value(before)? else {
+        return Ok(Default::default());
+    };
+    let serde_json::Value::Object(after) = serde_json::to_value(after)? else {
+        return Ok(Default::default());
+    };
+    let mut changes: serde_json::Map<String, serde_json::Value> = after
+        .iter()
+        .filter(|(key, value)| before.get(*key) != Some(*value))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    for (key, value) in &before {
+        if !after.contains_key(key) && !value.is_null() {
+            changes.insert(key.clone(), serde_json::Value::Null);
+        }
+    }
+    Ok(changes)
+}
 
-    let mpps_entry: Option<MppsEntry> = db
-        .select(("mpps", &current_id))
+// 读取 worklist 配置 (UPS SCP 与 worklist 共用 AE) 和要操作的 UPS 条目
+async fn ups_target(
+    db: &Surreal<Db>,
+    selected_id: &str,
+    id: &str,
+) -> Result<(WorklistEntry, DicomNode, UpsEntry), String> {
+    let worklist: Option<WorklistEntry> = db
+        .select(("worklist", selected_id))
         .await
-        .map_err(|e| format!("Failed to read MppsEntry entry: {}", e))?;
-
-    let mpps_entry =
-        mpps_entry.ok_or_else(|| format!("No MppsEntry entry found with id: {}", current_id))?;
-
-    let script_path = AppPath::PythonScript.resolve(&handle)?;
-
-    let mim_entry_json = serde_json::to_string(&mim_entry).unwrap();
-    let mpps_entry_json = serde_json::to_string(&mpps_entry).unwrap();
-
-    let result: Result<String, String> = Python::with_gil(|py| {
-        let script_content = fs::read_to_string(&script_path)
-            .map_err(|e| format!("Failed to read Python script file: {}", e))?;
-
-        let module =
-            PyModule::from_code_bound(py, &script_content, "dicom_utils.py", "dicom_utils")
-                .map_err(|e| format!("Failed to load Python module: {}", e))?;
-        let certs_path = AppPath::Certs
-        .resolve(&handle)?
-        .to_string_lossy()
-        .into_owned();
-        let get_work_list = module
-            .getattr("send_c_store_requests")
-            .and_then(|f| f.call1((mpps_entry_json, mim_entry_json, certs_path)))
-            .map_err(|e| format!("Failed to call Python function: {}", e))?;
+        .map_err(|e| format!("Failed to read worklist entry: {}", e))?;
+    let worklist =
+        worklist.ok_or_else(|| format!("No worklist entry found with id: {}", selected_id))?;
+    let node = DicomNode::worklist(&worklist).map_err(|e| e.to_string())?;
 
-        let work_list_result = get_work_list
-            .extract::<String>()
-            .map_err(|e| format!("Failed to extract Python function result: {}", e))?;
+    let entry: Option<UpsEntry> = db
+        .select((UPS_TABLE, id))
+        .await
+        .map_err(|e| format!("Failed to read ups entry: {}", e))?;
+    let entry = entry.ok_or_else(|| format!("No ups entry found with id: {}", id))?;
+    Ok((worklist, node, entry))
+}
 
-        // 解析返回的 JSON 字符串
-        let response: MppsResponse = serde_json::from_str(&work_list_result)
-            .map_err(|e| format!("Failed to parse response: {}", e))?;
+async fn save_ups_entry(
+    db: &Surreal<Db>,
+    id: &str,
+    entry: UpsEntry,
+    message: &str,
+) -> Result<ApiResponse<UpsEntry>, String> {
+    let updated: Option<UpsEntry> = db
+        .update((UPS_TABLE, id))
+        .content(entry)
+        .await
+        .map_err(|e| format!("Failed to update ups entry: {}", e))?;
 
-        if response.success {
-            Ok(response.message)
-        } else {
-            Err(format!("Failed: {}", response.message))
-        }
-    });
-    if let Err(e) = result {
-        return Err(e);
+    match updated {
+        Some(e) => Ok(ApiResponse::success(message, Some(e))),
+        None => Ok(ApiResponse::error("No ups entry found to update", None)),
     }
-
-    Ok(ApiResponse::success("message", None))
 }
 
+/// Schedules a UPS workitem on the UPS SCP with N-CREATE.
 #[command]
-pub async fn create_hl7_message_setting(
+pub async fn create_ups_entry(
     db_state: State<'_, DbState>,
-    entry: HL7MessageSetting,
-) -> Result<ApiResponse<HL7MessageSetting>, String> {
-    let db = db_state.db.lock().await;
+    dimse_state: State<'_, DimseState>,
+    selected_id: String,
+    mut entry: UpsEntry,
+) -> Result<ApiResponse<UpsEntry>, String> {
+    let db = &db_state.db;
 
-    let created: Vec<HL7MessageSetting> = db
-        .create("hl7_message_setting")
+    let worklist: Option<WorklistEntry> = db
+        .select(("worklist", &selected_id))
+        .await
+        .map_err(|e| format!("Failed to read worklist entry: {}", e))?;
+    let worklist =
+        worklist.ok_or_else(|| format!("No worklist entry found with id: {}", selected_id))?;
+    let node = DicomNode::worklist(&worklist).map_err(|e| e.to_string())?;
+
+    // 新建的条目总是从未创建状态出发
+    entry.state = None;
+    entry.state_history.clear();
+    entry.transaction_uid = None;
+    entry.progress = None;
+    if entry.study_instance_uid.is_empty() {
+        entry.study_instance_uid = generate_uid();
+    }
+    if entry.scheduled_procedure_step_priority.is_empty() {
+        entry.scheduled_procedure_step_priority = "MEDIUM".to_string();
+    }
+    if entry.scheduled_procedure_step_start_date_time.is_empty() {
+        entry.scheduled_procedure_step_start_date_time =
+            Local::now().format("%Y%m%d%H%M%S").to_string();
+    }
+    let ups_instance_uid = entry
+        .ups_instance_uid
+        .clone()
+        .filter(|uid| !uid.is_empty())
+        .unwrap_or_else(generate_uid);
+
+    dimse_state
+        .client
+        .n_create(
+            &node,
+            uids::UNIFIED_PROCEDURE_STEP_PUSH,
+            &ups_instance_uid,
+            build_ups_create(&entry, &worklist),
+        )
+        .await
+        .map_err(|e| format!("Failed: {}", e))?;
+    entry.ups_instance_uid = Some(ups_instance_uid);
+    entry.transition(UpsState::Scheduled)?;
+
+    let created: Vec<UpsEntry> = db
+        .create(UPS_TABLE)
         .content(entry)
         .await
-        .map_err(|e| format!("Failed to create HL7 message setting entry: {}", e))?;
+        .map_err(|e| format!("Failed to create ups entry: {}", e))?;
 
-    let created_entry = created.into_iter().next().ok_or_else(|| {
-        "Failed to create HL7 message setting entry, no entries returned".to_string()
-    })?;
+    let result = created.into_iter().next().unwrap();
 
     Ok(ApiResponse::success(
-        "HL7 message setting entry created successfully",
-        Some(created_entry),
+        "ups entry created successfully",
+        Some(result),
     ))
 }
 
+/// Claims a scheduled workitem by moving it to IN PROGRESS under a new transaction UID.
 #[command]
-pub async fn read_hl7_message_setting(
+pub async fn claim_ups_entry(
     db_state: State<'_, DbState>,
-    id: Option<String>, // 使用 Option 来允许 id 为 None
-) -> Result<ApiResponse<Vec<HL7MessageSetting>>, String> {
-    let db = db_state.db.lock().await;
+    dimse_state: State<'_, DimseState>,
+    selected_id: String,
+    id: String,
+) -> Result<ApiResponse<UpsEntry>, String> {
+    let db = &db_state.db;
+    let _claim = db_state.claim(UPS_TABLE, &id)?;
+    let (_, node, mut entry) = ups_target(db, &selected_id, &id).await?;
 
-    if let Some(id) = id {
-        // 如果提供了 id，则查询单个条目
-        let entry: Option<HL7MessageSetting> = db
-            .select(("hl7_message_setting", &id))
-            .await
-            .map_err(|e| format!("Failed to read HL7 message setting entry: {}", e))?;
+    if let Err(e) = entry.check_transition(UpsState::InProgress) {
+        return Ok(ApiResponse::error(&e, None));
+    }
+    let ups_instance_uid = entry
+        .ups_instance_uid
+        .clone()
+        .ok_or_else(|| format!("ups entry {} has no UPS instance UID", id))?;
+
+    let transaction_uid = generate_uid();
+    dimse_state
+        .client
+        .n_action(
+            &node,
+            uids::UNIFIED_PROCEDURE_STEP_PULL,
+            uids::UNIFIED_PROCEDURE_STEP_PUSH,
+            &ups_instance_uid,
+            ACTION_CHANGE_STATE,
+            Some(ups_change_state(UpsState::InProgress, &transaction_uid)),
+        )
+        .await
+        .map_err(|e| format!("Failed: {}", e))?;
+    entry.transaction_uid = Some(transaction_uid);
+    entry.progress = Some(0);
+    entry.transition(UpsState::InProgress)?;
 
-        match entry {
-            Some(e) => Ok(ApiResponse::success(
-                "HL7 message setting entry found",
-                Some(vec![e]),
-            )), // 返回单个条目作为列表
-            None => Ok(ApiResponse::error(
-                "No HL7 message setting entry found",
-                None,
-            )),
-        }
-    } else {
-        // 如果没有提供 id，则查询全部条目
-        let entries: Vec<HL7MessageSetting> = db
-            .select("hl7_message_setting")
-            .await
-            .map_err(|e| format!("Failed to read HL7 message setting entries: {}", e))?;
+    save_ups_entry(db, &id, entry, "ups entry claimed successfully").await
+}
 
-        if !entries.is_empty() {
-            Ok(ApiResponse::success(
-                "HL7 message setting entries found",
-                Some(entries),
-            ))
-        } else {
-            Ok(ApiResponse::error(
-                "No HL7 message setting entries found",
+/// Reports the progress of a claimed workitem with N-SET.
+#[command]
+pub async fn update_ups_entry(
+    db_state: State<'_, DbState>,
+    dimse_state: State<'_, DimseState>,
+    selected_id: String,
+    id: String,
+    progress: u8,
+    description: Option<String>,
+) -> Result<ApiResponse<UpsEntry>, String> {
+    let db = &db_state.db;
+    let _claim = db_state.claim(UPS_TABLE, &id)?;
+    let (_, node, mut entry) = ups_target(db, &selected_id, &id).await?;
+
+    let transaction_uid = match (entry.state, entry.transaction_uid.clone()) {
+        (Some(UpsState::InProgress), Some(transaction_uid)) => transaction_uid,
+        _ => {
+            return Ok(ApiResponse::error(
+                &format!("ups entry {} is not in progress for this simulator", id),
                 None,
             ))
         }
-    }
+    };
+    let ups_instance_uid = entry
+        .ups_instance_uid
+        .clone()
+        .ok_or_else(|| format!("ups entry {} has no UPS instance UID", id))?;
+    entry.progress = Some(progress.min(100));
+    entry.description = description;
+
+    dimse_state
+        .client
+        .n_set_on(
+            &node,
+            uids::UNIFIED_PROCEDURE_STEP_PULL,
+            uids::UNIFIED_PROCEDURE_STEP_PUSH,
+            &ups_instance_uid,
+            build_ups_progress(&entry, &transaction_uid),
+        )
+        .await
+        .map_err(|e| format!("Failed: {}", e))?;
+
+    save_ups_entry(db, &id, entry, "ups entry updated successfully").await
 }
 
+/// Completes a claimed workitem: N-SET of the final attributes, then N-ACTION.
 #[command]
-pub async fn update_hl7_message_setting(
+pub async fn complete_ups_entry(
     db_state: State<'_, DbState>,
+    dimse_state: State<'_, DimseState>,
+    selected_id: String,
     id: String,
-    updated_entry: HL7MessageSetting,
-) -> Result<ApiResponse<HL7MessageSetting>, String> {
-    let db = db_state.db.lock().await;
+    description: Option<String>,
+) -> Result<ApiResponse<UpsEntry>, String> {
+    let db = &db_state.db;
+    let _claim = db_state.claim(UPS_TABLE, &id)?;
+    let (worklist, node, mut entry) = ups_target(db, &selected_id, &id).await?;
 
-    let updated: Option<HL7MessageSetting> = db
-        .update(("hl7_message_setting", &id))
-        .content(updated_entry)
+    if let Err(e) = entry.check_transition(UpsState::Completed) {
+        return Ok(ApiResponse::error(&e, None));
+    }
+    let Some(transaction_uid) = entry.transaction_uid.clone() else {
+        return Ok(ApiResponse::error(
+            &format!("ups entry {} was not claimed by this simulator", id),
+            None,
+        ));
+    };
+    let ups_instance_uid = entry
+        .ups_instance_uid
+        .clone()
+        .ok_or_else(|| format!("ups entry {} has no UPS instance UID", id))?;
+    entry.progress = Some(100);
+    entry.description = description;
+
+    let client = &dimse_state.client;
+    client
+        .n_set_on(
+            &node,
+            uids::UNIFIED_PROCEDURE_STEP_PULL,
+            uids::UNIFIED_PROCEDURE_STEP_PUSH,
+            &ups_instance_uid,
+            build_ups_final(&entry, &worklist, &transaction_uid, UpsState::Completed, None),
+        )
         .await
-        .map_err(|e| format!("Failed to update HL7 message setting entry: {}", e))?;
+        .map_err(|e| format!("Failed: {}", e))?;
+    client
+        .n_action(
+            &node,
+            uids::UNIFIED_PROCEDURE_STEP_PULL,
+            uids::UNIFIED_PROCEDURE_STEP_PUSH,
+            &ups_instance_uid,
+            ACTION_CHANGE_STATE,
+            Some(ups_change_state(UpsState::Completed, &transaction_uid)),
+        )
+        .await
+        .map_err(|e| format!("Failed: {}", e))?;
+    entry.transition(UpsState::Completed)?;
 
-    match updated {
-        Some(e) => Ok(ApiResponse::success(
-            "HL7 message setting entry updated successfully",
-            Some(e),
-        )),
-        None => Ok(ApiResponse::error(
-            "No HL7 message setting entry found to update",
-            None,
-        )),
-    }
+    save_ups_entry(db, &id, entry, "ups entry completed successfully").await
 }
 
+/// Cancels a workitem. A workitem claimed by this simulator is canceled directly;
+/// otherwise a cancel request is sent and the outcome arrives as a UPS event.
 #[command]
-pub async fn delete_hl7_message_setting(
+pub async fn cancel_ups_entry(
     db_state: State<'_, DbState>,
+    dimse_state: State<'_, DimseState>,
+    selected_id: String,
     id: String,
-) -> Result<ApiResponse<HL7MessageSetting>, String> {
-    let db = db_state.db.lock().await;
+    reason_code: String,
+    description: Option<String>,
+) -> Result<ApiResponse<UpsEntry>, String> {
+    let db = &db_state.db;
+    let _claim = db_state.claim(UPS_TABLE, &id)?;
+    let (worklist, node, mut entry) = ups_target(db, &selected_id, &id).await?;
 
-    let hl7_message_setting_entry: Option<HL7MessageSetting> = db
-        .delete(("hl7_message_setting", &id))
+    if let Err(e) = entry.check_transition(UpsState::Canceled) {
+        return Ok(ApiResponse::error(&e, None));
+    }
+    let Some(reason) = discontinuation_reason(&reason_code) else {
+        return Ok(ApiResponse::error(
+            &format!("Unknown discontinuation reason code: {}", reason_code),
+            None,
+        ));
+    };
+    let ups_instance_uid = entry
+        .ups_instance_uid
+        .clone()
+        .ok_or_else(|| format!("ups entry {} has no UPS instance UID", id))?;
+    entry.description = description;
+
+    let client = &dimse_state.client;
+    let message = match (entry.state, entry.transaction_uid.clone()) {
+        (Some(UpsState::InProgress), Some(transaction_uid)) => {
+            client
+                .n_set_on(
+                    &node,
+                    uids::UNIFIED_PROCEDURE_STEP_PULL,
+                    uids::UNIFIED_PROCEDURE_STEP_PUSH,
+                    &ups_instance_uid,
+                    build_ups_final(
+                        &entry,
+                        &worklist,
+                        &transaction_uid,
+                        UpsState::Canceled,
+                        Some(&reason),
+                    ),
+                )
+                .await
+                .map_err(|e| format!("Failed: {}", e))?;
+            client
+                .n_action(
+                    &node,
+                    uids::UNIFIED_PROCEDURE_STEP_PULL,
+                    uids::UNIFIED_PROCEDURE_STEP_PUSH,
+                    &ups_instance_uid,
+                    ACTION_CHANGE_STATE,
+                    Some(ups_change_state(UpsState::Canceled, &transaction_uid)),
+                )
+                .await
+                .map_err(|e| format!("Failed: {}", e))?;
+            entry.transition(UpsState::Canceled)?;
+            "ups entry canceled successfully"
+        }
+        _ => {
+            client
+                .n_action(
+                    &node,
+                    uids::UNIFIED_PROCEDURE_STEP_PUSH,
+                    uids::UNIFIED_PROCEDURE_STEP_PUSH,
+                    &ups_instance_uid,
+                    ACTION_REQUEST_CANCEL,
+                    Some(ups_cancel_request(&reason)),
+                )
+                .await
+                .map_err(|e| format!("Failed: {}", e))?;
+            "ups cancel requested"
+        }
+    };
+
+    save_ups_entry(db, &id, entry, message).await
+}
+
+#[command]
+pub async fn read_ups_entry(
+    db_state: State<'_, DbState>,
+    id: Option<String>,
+) -> Result<ApiResponse<Vec<UpsEntry>>, String> {
+    let db = &db_state.db;
+    let entries: Vec<UpsEntry> = select_entries(db, UPS_TABLE, id.as_deref())
         .await
-        .map_err(|e| format!("Failed to delete HL7 message setting entry: {}", e))?;
+        .map_err(|e| format!("Failed to read ups entries: {}", e))?;
+    if entries.is_empty() {
+        Ok(ApiResponse::error("No ups entry found", None))
+    } else {
+        Ok(ApiResponse::success("ups entries found", Some(entries)))
+    }
+}
 
-    match hl7_message_setting_entry {
+#[command]
+pub async fn delete_ups_entry(
+    db_state: State<'_, DbState>,
+    id: String,
+) -> Result<ApiResponse<Vec<UpsEntry>>, String> {
+    let db = &db_state.db;
+    if id == "all" {
+        // 只删除已结束 (COMPLETED / CANCELED) 的条目
+        let mut response = db
+            .query(
+                "DELETE FROM type::table($table) \
+                 WHERE state INSIDE ['COMPLETED', 'CANCELED'] RETURN BEFORE",
+            )
+            .bind(("table", UPS_TABLE))
+            .await
+            .map_err(|e| format!("Failed to delete ups entries: {}", e))?;
+        let deleted: Vec<UpsEntry> = response
+            .take(0)
+            .map_err(|e| format!("Failed to delete ups entries: {}", e))?;
+        return Ok(ApiResponse::success(
+            "All finished ups entries deleted successfully",
+            Some(deleted),
+        ));
+    }
+    let deleted: Option<UpsEntry> = db
+        .delete((UPS_TABLE, &id))
+        .await
+        .map_err(|e| format!("Failed to delete ups entry: {}", e))?;
+    match deleted {
         Some(e) => Ok(ApiResponse::success(
-            "HL7 message setting entry deleted successfully",
-            Some(e),
-        )),
-        None => Ok(ApiResponse::error(
-            "No HL7 message setting entry found to delete",
-            None,
+            "ups entry deleted successfully",
+            Some(vec![e]),
         )),
+        None => Ok(ApiResponse::error("No ups entry found to delete", None)),
     }
 }
 
+/// Subscribes to UPS events for one workitem, or globally when no UID is given.
+/// Events are delivered to the storage SCP, which has to be running.
 #[command]
-pub async fn send_rt_s(
+pub async fn subscribe_ups(
     db_state: State<'_, DbState>,
-    dcm_file: String,
+    dimse_state: State<'_, DimseState>,
     selected_id: String,
-    id: String,
-    handle: AppHandle,
-) -> Result<ApiResponse<MppsEntry>, String> {
-    let db = db_state.db.lock().await;
+    ups_instance_uid: Option<String>,
+    deletion_lock: Option<bool>,
+) -> Result<ApiResponse<UpsSubscription>, String> {
+    let db = &db_state.db;
 
-    let mim_entry: Option<MimEntry> = db
-        .select(("mim", &selected_id))
+    let worklist: Option<WorklistEntry> = db
+        .select(("worklist", &selected_id))
         .await
-        .map_err(|e| format!("Failed to read MimEntry entry: {}", e))?;
+        .map_err(|e| format!("Failed to read worklist entry: {}", e))?;
+    let worklist =
+        worklist.ok_or_else(|| format!("No worklist entry found with id: {}", selected_id))?;
+    let node = DicomNode::worklist(&worklist).map_err(|e| e.to_string())?;
+
+    let ups_instance_uid = ups_instance_uid.filter(|uid| !uid.is_empty());
+    let deletion_lock = deletion_lock.unwrap_or(false);
+    dimse_state
+        .client
+        .n_action(
+            &node,
+            uids::UNIFIED_PROCEDURE_STEP_WATCH,
+            uids::UNIFIED_PROCEDURE_STEP_PUSH,
+            ups_instance_uid
+                .as_deref()
+                .unwrap_or(uids::UPS_GLOBAL_SUBSCRIPTION_INSTANCE),
+            ACTION_SUBSCRIBE,
+            Some(ups_subscription(deletion_lock)),
+        )
+        .await
+        .map_err(|e| format!("Failed: {}", e))?;
+
+    let created: Vec<UpsSubscription> = db
+        .create(UPS_SUBSCRIPTION_TABLE)
+        .content(UpsSubscription {
+            id: None,
+            worklist_id: selected_id,
+            ups_instance_uid,
+            deletion_lock,
+            subscribed_at: Local::now().to_rfc3339(),
+        })
+        .await
+        .map_err(|e| format!("Failed to create ups subscription: {}", e))?;
 
-    let mim_entry = mim_entry.ok_or_else(|| format!("No MimEntry entry found with id: {}", selected_id))?;
+    Ok(ApiResponse::success(
+        "ups subscription created successfully",
+        created.into_iter().next(),
+    ))
+}
 
-    // 查询单个条目
-    let mpps_entry: Option<MppsEntry> = db
-        .select(("mpps", &id))
+#[command]
+pub async fn unsubscribe_ups(
+    db_state: State<'_, DbState>,
+    dimse_state: State<'_, DimseState>,
+    id: String,
+) -> Result<ApiResponse<UpsSubscription>, String> {
+    let db = &db_state.db;
+
+    let subscription: Option<UpsSubscription> = db
+        .select((UPS_SUBSCRIPTION_TABLE, &id))
+        .await
+        .map_err(|e| format!("Failed to read ups subscription: {}", e))?;
+    let subscription =
+        subscription.ok_or_else(|| format!("No ups subscription found with id: {}", id))?;
+    let worklist: Option<WorklistEntry> = db
+        .select(("worklist", &subscription.worklist_id))
         .await
         .map_err(|e| format!("Failed to read worklist entry: {}", e))?;
-    // 如果没有查询到结果，抛出异常
-    let mpps_entry =
-        mpps_entry.ok_or_else(|| format!("No worklist entry found with id: {}", id))?;
+    let worklist = worklist.ok_or_else(|| {
+        format!("No worklist entry found with id: {}", subscription.worklist_id)
+    })?;
+    let node = DicomNode::worklist(&worklist).map_err(|e| e.to_string())?;
+
+    dimse_state
+        .client
+        .n_action(
+            &node,
+            uids::UNIFIED_PROCEDURE_STEP_WATCH,
+            uids::UNIFIED_PROCEDURE_STEP_PUSH,
+            subscription
+                .ups_instance_uid
+                .as_deref()
+                .unwrap_or(uids::UPS_GLOBAL_SUBSCRIPTION_INSTANCE),
+            ACTION_UNSUBSCRIBE,
+            None,
+        )
+        .await
+        .map_err(|e| format!("Failed: {}", e))?;
+
+    let deleted: Option<UpsSubscription> = db
+        .delete((UPS_SUBSCRIPTION_TABLE, &id))
+        .await
+        .map_err(|e| format!("Failed to delete ups subscription: {}", e))?;
+    Ok(ApiResponse::success("ups subscription removed successfully", deleted))
+}
+
+#[command]
+pub async fn read_ups_subscription(
+    db_state: State<'_, DbState>,
+) -> Result<ApiResponse<Vec<UpsSubscription>>, String> {
+    let db = &db_state.db;
+    let entries: Vec<UpsSubscription> = db
+        .select(UPS_SUBSCRIPTION_TABLE)
+        .await
+        .map_err(|e| format!("Failed to read ups subscriptions: {}", e))?;
+    if entries.is_empty() {
+        Ok(ApiResponse::error("No ups subscription found", None))
+    } else {
+        Ok(ApiResponse::success("ups subscriptions found", Some(entries)))
+    }
+}
+
+#[command]
+pub async fn read_ups_event(
+    db_state: State<'_, DbState>,
+    id: Option<String>,
+) -> Result<ApiResponse<Vec<UpsEvent>>, String> {
+    let db = &db_state.db;
+    let entries: Vec<UpsEvent> = select_entries(db, UPS_EVENT_TABLE, id.as_deref())
+        .await
+        .map_err(|e| format!("Failed to read ups events: {}", e))?;
+    if entries.is_empty() {
+        Ok(ApiResponse::error("No ups event found", None))
+    } else {
+        Ok(ApiResponse::success("ups events found", Some(entries)))
+    }
+}
+
+#[command]
+pub async fn delete_ups_event(
+    db_state: State<'_, DbState>,
+    id: String,
+) -> Result<ApiResponse<Vec<UpsEvent>>, String> {
+    let db = &db_state.db;
+    if id == "all" {
+        let deleted: Vec<UpsEvent> = db
+            .delete(UPS_EVENT_TABLE)
+            .await
+            .map_err(|e| format!("Failed to delete ups events: {}", e))?;
+        return Ok(ApiResponse::success(
+            "All ups events deleted successfully",
+            Some(deleted),
+        ));
+    }
+    let deleted: Option<UpsEvent> = db
+        .delete((UPS_EVENT_TABLE, &id))
+        .await
+        .map_err(|e| format!("Failed to delete ups event: {}", e))?;
+    match deleted {
+        Some(e) => Ok(ApiResponse::success(
+            "ups event deleted successfully",
+            Some(vec![e]),
+        )),
+        None => Ok(ApiResponse::error("No ups event found to delete", None)),
+    }
+}
+
+#[command]
+pub async fn send_to_pacs(
+    db_state: State<'_, DbState>,
+    dimse_state: State<'_, DimseState>,
+    id: String,
+    current_id: String,
+    storage_commitment: Option<bool>,
+    fhir_setting_id: Option<String>,
+) -> Result<ApiResponse<String>, String> {
+    let db = &db_state.db;
+    let _claim = db_state.claim("mpps", &current_id)?;
+
+    let mim_entry: Option<MimEntry> = db
+        .select(("mim", &id))
+        .await
+        .map_err(|e| format!("Failed to read MimEntry entry: {}", e))?;
+
+    let mim_entry = mim_entry.ok_or_else(|| format!("No MimEntry entry found with id: {}", id))?;
+
+    let mpps_entry: Option<MppsEntry> = db
+        .select(("mpps", &current_id))
+        .await
+        .map_err(|e| format!("Failed to read MppsEntry entry: {}", e))?;
+
+    let mut mpps_entry =
+        mpps_entry.ok_or_else(|| format!("No MppsEntry entry found with id: {}", current_id))?;
+    let stored = mpps_entry.clone();
+
+    let node = DicomNode::storage(&mim_entry).map_err(|e| e.to_string())?;
+    let objects = prepare_instances(&mpps_entry).map_err(|e| format!("Failed: {}", e))?;
+    dimse_state
+        .client
+        .c_store(&node, objects)
+        .await
+        .map_err(|e| format!("Failed: {}", e))?;
+
+    // 发送完成后向 FHIR 服务器发布 ImagingStudy，失败不影响发送结果
+    let mut messages = Vec::new();
+    if let Some(setting_id) = fhir_setting_id.filter(|id| !id.is_empty()) {
+        let setting: Option<FhirSettingEntry> = db
+            .select(("fhir_setting", &setting_id))
+            .await
+            .map_err(|e| format!("Failed to read FHIR setting entry: {}", e))?;
+        let setting =
+            setting.ok_or_else(|| format!("No FHIR setting entry found with id: {}", setting_id))?;
+        let result = match FhirClient::from_setting(&setting) {
+            Ok(client) => client.create_imaging_study(&mpps_entry).await,
+            Err(e) => Err(e),
+        };
+        messages.push(match &result {
+            Ok(response) => format!(
+                "ImagingStudy published: {}",
+                response.reference().unwrap_or_default()
+            ),
+            Err(e) => format!("ImagingStudy not published: {}", e),
+        });
+        mpps_entry
+            .fhir_publish
+            .push(publish_log("ImagingStudy", &setting, &result));
+    }
+
+    if !messages.is_empty() {
+        merge_changes(db, "mpps", &current_id, &stored, &mpps_entry).await?;
+    }
 
-    let mim_entry_json = serde_json::to_string(&mim_entry).unwrap();
-    let mpps_json = serde_json::to_string(&mpps_entry).unwrap();
+    // 实例已经发送成功，提交失败只记录在条目上并在消息中说明
+    if storage_commitment.unwrap_or(false) {
+        let result = match mpps_commitment(&mpps_entry) {
+            Ok(mut commitment) => request_commitment(
+                db,
+                dimse_state.client.as_ref(),
+                &node,
+                "mpps",
+                &current_id,
+                &mut commitment,
+            )
+            .await
+            .map(|_| commitment),
+            Err(e) => Err(e),
+        };
+        messages.push(match result {
+            Ok(commitment) => format!("Storage commitment: {}", commitment.summary()),
+            Err(e) => format!("Storage commitment not requested: {}", e),
+        });
+    }
+    if messages.is_empty() {
+        return Ok(ApiResponse::success("message", None));
+    }
+    Ok(ApiResponse::success(&messages.join("; "), None))
+}
 
-    // 解析 Python 脚本路径
-    let script_path = AppPath::PythonScript.resolve(&handle)?;
-    // 执行 Python 脚本
-    let result: Result<MppsResponse, String> = Python::with_gil(|py| {
-        let script_content = fs::read_to_string(&script_path)
-            .map_err(|e| format!("Failed to read Python script file: {}", e))?;
+/// Requests storage commitment for the instances last sent for an `mpps` or
+/// `patient` entry. Results reported later on a separate association are
+/// applied by the storage SCP.
+#[command]
+pub async fn request_storage_commitment(
+    db_state: State<'_, DbState>,
+    dimse_state: State<'_, DimseState>,
+    id: String,
+    kind: String,
+    current_id: String,
+) -> Result<ApiResponse<StorageCommitment>, String> {
+    let db = &db_state.db;
+    let _claim = db_state.claim(&kind, &current_id)?;
 
-        let module =
-            PyModule::from_code_bound(py, &script_content, "dicom_utils.py", "dicom_utils")
-                .map_err(|e| format!("Failed to load Python module: {}", e))?;
+    let mim_entry: Option<MimEntry> = db
+        .select(("mim", &id))
+        .await
+        .map_err(|e| format!("Failed to read MimEntry entry: {}", e))?;
+    let mim_entry = mim_entry.ok_or_else(|| format!("No MimEntry entry found with id: {}", id))?;
+    let node = DicomNode::storage(&mim_entry).map_err(|e| e.to_string())?;
+
+    let mut commitment = match kind.as_str() {
+        "mpps" => {
+            let entry: Option<MppsEntry> = db
+                .select(("mpps", &current_id))
+                .await
+                .map_err(|e| format!("Failed to read MppsEntry entry: {}", e))?;
+            let entry = entry
+                .ok_or_else(|| format!("No MppsEntry entry found with id: {}", current_id))?;
+            mpps_commitment(&entry)?
+        }
+        "patient" => {
+            let entry: Option<PatientEntry> = db
+                .select(("patient", &current_id))
+                .await
+                .map_err(|e| format!("Failed to read patient entry: {}", e))?;
+            let entry = entry
+                .ok_or_else(|| format!("No patient entry found with id: {}", current_id))?;
+            entry
+                .storage_commitment
+                .ok_or_else(|| "No instances have been sent for this patient entry".to_string())?
+        }
+        _ => return Err(format!("Unknown storage commitment kind: {}", kind)),
+    };
+    request_commitment(
+        db,
+        dimse_state.client.as_ref(),
+        &node,
+        &kind,
+        &current_id,
+        &mut commitment,
+    )
+    .await?;
 
-        let send_rt_s = module
-            .getattr("send_rt_s")
-            .and_then(|f: Bound<'_, PyAny>| f.call1((mim_entry_json, mpps_json, &dcm_file, false)))
-            .map_err(|e| format!("Failed to call Python function: {}", e))?;
+    Ok(ApiResponse::success(
+        &format!("Storage commitment: {}", commitment.summary()),
+        Some(commitment),
+    ))
+}
 
-        let result_send_rt_s = send_rt_s
-            .extract::<String>()
-            .map_err(|e| format!("Failed to extract Python function result: {}", e))?;
+#[command]
+pub async fn create_hl7_message_setting(
+    db_state: State<'_, DbState>,
+    entry: HL7MessageSetting,
+) -> Result<ApiResponse<HL7MessageSetting>, String> {
+    let db = &db_state.db;
 
-        // 解析返回的 JSON 字符串
-        let response: MppsResponse = serde_json::from_str(&result_send_rt_s)
-            .map_err(|e| format!("Failed to parse response: {}", e))?;
+    let created: Vec<HL7MessageSetting> = db
+        .create("hl7_message_setting")
+        .content(entry)
+        .await
+        .map_err(|e| format!("Failed to create HL7 message setting entry: {}", e))?;
 
-        if response.success {
-            Ok(response)
+    let created_entry = created.into_iter().next().ok_or_else(|| {
+        "Failed to create HL7 message setting entry, no entries returned".to_string()
+    })?;
+
+    Ok(ApiResponse::success(
+        "HL7 message setting entry created successfully",
+        Some(created_entry),
+    ))
+}
+
+#[command]
+pub async fn read_hl7_message_setting(
+    db_state: State<'_, DbState>,
+    id: Option<String>, // 使用 Option 来允许 id 为 None
+) -> Result<ApiResponse<Vec<HL7MessageSetting>>, String> {
+    let db = &db_state.db;
+
+    if let Some(id) = id {
+        // 如果提供了 id，则查询单个条目
+        let entry: Option<HL7MessageSetting> = db
+            .select(("hl7_message_setting", &id))
+            .await
+            .map_err(|e| format!("Failed to read HL7 message setting entry: {}", e))?;
+
+        match entry {
+            Some(e) => Ok(ApiResponse::success(
+                "HL7 message setting entry found",
+                Some(vec![e]),
+            )), // 返回单个条目作为列表
+            None => Ok(ApiResponse::error(
+                "No HL7 message setting entry found",
+                None,
+            )),
+        }
+    } else {
+        // 如果没有提供 id，则查询全部条目
+        let entries: Vec<HL7MessageSetting> = db
+            .select("hl7_message_setting")
+            .await
+            .map_err(|e| format!("Failed to read HL7 message setting entries: {}", e))?;
+
+        if !entries.is_empty() {
+            Ok(ApiResponse::success(
+                "HL7 message setting entries found",
+                Some(entries),
+            ))
         } else {
-            Err(format!("Failed: {}", response.message))
+            Ok(ApiResponse::error(
+                "No HL7 message setting entries found",
+                None,
+            ))
         }
-    });
+    }
+}
+
+#[command]
+pub async fn update_hl7_message_setting(
+    db_state: State<'_, DbState>,
+    id: String,
+    updated_entry: HL7MessageSetting,
+) -> Result<ApiResponse<HL7MessageSetting>, String> {
+    let db = &db_state.db;
+
+    let updated: Option<HL7MessageSetting> = db
+        .update(("hl7_message_setting", &id))
+        .content(updated_entry)
+        .await
+        .map_err(|e| format!("Failed to update HL7 message setting entry: {}", e))?;
 
-    if let Err(e) = result {
-        return Err(e);
+    match updated {
+        Some(e) => Ok(ApiResponse::success(
+            "HL7 message setting entry updated successfully",
+            Some(e),
+        )),
+        None => Ok(ApiResponse::error(
+            "No HL7 message setting entry found to update",
+            None,
+        )),
     }
+}
+
+#[command]
+pub async fn delete_hl7_message_setting(
+    db_state: State<'_, DbState>,
+    id: String,
+) -> Result<ApiResponse<HL7MessageSetting>, String> {
+    let db = &db_state.db;
+
+    let hl7_message_setting_entry: Option<HL7MessageSetting> = db
+        .delete(("hl7_message_setting", &id))
+        .await
+        .map_err(|e| format!("Failed to delete HL7 message setting entry: {}", e))?;
+
+    match hl7_message_setting_entry {
+        Some(e) => Ok(ApiResponse::success(
+            "HL7 message setting entry deleted successfully",
+            Some(e),
+        )),
+        None => Ok(ApiResponse::error(
+            "No HL7 message setting entry found to delete",
+            None,
+        )),
+    }
+}
+
+#[command]
+pub async fn send_rt_s(
+    db_state: State<'_, DbState>,
+    dimse_state: State<'_, DimseState>,
+    dcm_file: String,
+    selected_id: String,
+    id: String,
+) -> Result<ApiResponse<MppsEntry>, String> {
+    let db = &db_state.db;
+
+    let mim_entry: Option<MimEntry> = db
+        .select(("mim", &selected_id))
+        .await
+        .map_err(|e| format!("Failed to read MimEntry entry: {}", e))?;
+
+    let mim_entry = mim_entry.ok_or_else(|| format!("No MimEntry entry found with id: {}", selected_id))?;
+
+    // 查询单个条目
+    let mpps_entry: Option<MppsEntry> = db
+        .select(("mpps", &id))
+        .await
+        .map_err(|e| format!("Failed to read worklist entry: {}", e))?;
+    // 如果没有查询到结果，抛出异常
+    let mpps_entry =
+        mpps_entry.ok_or_else(|| format!("No worklist entry found with id: {}", id))?;
+
+    let node = DicomNode::storage(&mim_entry).map_err(|e| e.to_string())?;
+    let rt_struct =
+        prepare_rt_struct(Path::new(&dcm_file), &mpps_entry).map_err(|e| format!("Failed: {}", e))?;
+    dimse_state
+        .client
+        .c_store(&node, vec![rt_struct])
+        .await
+        .map_err(|e| format!("Failed: {}", e))?;
 
     Ok(ApiResponse::success(
         "mpps entry updated successfully",
@@ -1000,7 +2369,6 @@ pub async fn send_rt_s(
     ))
 }
 
-
 #[command]
 pub async fn read_log_file(handle: AppHandle) -> Result<String, String> {
   let dicom_base_dir = AppPath::Log
@@ -1027,7 +2395,7 @@ pub async fn create_patient_entry(
     db_state: State<'_, DbState>,
     entry: PatientEntry,
 ) -> Result<ApiResponse<PatientEntry>, String> {
-    let db = db_state.db.lock().await;
+    let db = &db_state.db;
 
     let created: Vec<PatientEntry> = db
         .create("patient")
@@ -1053,7 +2421,7 @@ pub async fn read_patient_entry(
     db_state: State<'_, DbState>,
     id: Option<String>, // 使用 Option 允许 id 为 None
 ) -> Result<ApiResponse<Vec<PatientEntry>>, String> {
-    let db = db_state.db.lock().await;
+    let db = &db_state.db;
 
     if let Some(id) = id {
         // 如果提供了 id，则查询单个条目
@@ -1094,7 +2462,7 @@ pub async fn update_patient_entry(
     id: String,
     updated_entry: PatientEntry,
 ) -> Result<ApiResponse<PatientEntry>, String> {
-    let db = db_state.db.lock().await;
+    let db = &db_state.db;
 
     let updated: Option<PatientEntry> = db
         .update(("patient", &id))
@@ -1120,7 +2488,9 @@ pub async fn delete_patient_entry(
     db_state: State<'_, DbState>,
     id: String,
 ) -> Result<ApiResponse<PatientEntry>, String> {
-    let db = db_state.db.lock().await;
+    let db = &db_state.db;
+    // 不删除正在 send_cstore_headless 的条目
+    let _claim = db_state.claim("patient", &id)?;
 
     let patient_entry: Option<PatientEntry> = db
         .delete(("patient", &id))
@@ -1141,16 +2511,19 @@ pub async fn delete_patient_entry(
 
 
 #[command]
+#[allow(clippy::too_many_arguments)]
 pub async fn send_cstore_headless(
     db_state: State<'_, DbState>,
+    dimse_state: State<'_, DimseState>,
     dcm_file: String,
     selected_id: String,
     id: String,
     description: Option<String>,
     generate: Option<bool>,
-    handle: AppHandle,
+    storage_commitment: Option<bool>,
 ) -> Result<ApiResponse<PatientEntry>, String> {
-    let db = db_state.db.lock().await;
+    let db = &db_state.db;
+    let _claim = db_state.claim("patient", &id)?;
 
     let mim_entry: Option<MimEntry> = db
         .select(("mim", &selected_id))
@@ -1167,61 +2540,903 @@ pub async fn send_cstore_headless(
     // 如果没有查询到结果，抛出异常
     let mut mpps_entry =
         mpps_entry.ok_or_else(|| format!("No worklist entry found with id: {}", id))?;
+    let stored = mpps_entry.clone();
     mpps_entry.description = description;
     mpps_entry.generate = generate;
 
-    let mim_entry_json = serde_json::to_string(&mim_entry).unwrap();
-    let mpps_json = serde_json::to_string(&mpps_entry).unwrap();
+    let node = DicomNode::storage(&mim_entry).map_err(|e| e.to_string())?;
+    let path = Path::new(&dcm_file);
 
-    // 解析 Python 脚本路径
-    let script_path = AppPath::PythonScript.resolve(&handle)?;
-    // 执行 Python 脚本
-    let result: Result<MppsResponse, String> = Python::with_gil(|py| {
-        let script_content = fs::read_to_string(&script_path)
-            .map_err(|e| format!("Failed to read Python script file: {}", e))?;
+    // 生成模式下所有文件放入同一个新 Study，单个文件时沿用患者记录中的 Series
+    let study_instance_uid = generate.unwrap_or(false).then(generate_uid);
+    let referenced_series_uid = if path.is_dir() {
+        study_instance_uid.as_ref().map(|_| generate_uid())
+    } else {
+        mpps_entry.sop_instance_uids.clone()
+    };
 
-        let module =
-            PyModule::from_code_bound(py, &script_content, "dicom_utils.py", "dicom_utils")
-                .map_err(|e| format!("Failed to load Python module: {}", e))?;
+    let mut series_instance_uid = referenced_series_uid.clone();
+    let mut objects = Vec::new();
+    for file in find_dcm_files(path)? {
+        let (object, series) = prepare_headless(
+            &file,
+            &mpps_entry,
+            study_instance_uid.as_deref(),
+            referenced_series_uid.as_deref(),
+        )
+        .map_err(|e| format!("Failed: Error during C-STORE: {}", e))?;
+        series_instance_uid = series;
+        objects.push(object);
+    }
+    // 每次发送都会生成新的 SOP Instance UID，记录下来供 Storage Commitment 使用
+    let mut commitment = sent_commitment(&objects);
+    dimse_state
+        .client
+        .c_store(&node, objects)
+        .await
+        .map_err(|e| format!("Failed: Error during C-STORE: {}", e))?;
+    mpps_entry.sop_instance_uids = series_instance_uid;
+    mpps_entry.storage_commitment = Some(commitment.clone());
 
-        let send_rt_s = module
-            .getattr("send_cstore_headless")
-            .and_then(|f: Bound<'_, PyAny>| f.call1((mim_entry_json, mpps_json, &dcm_file, false)))
-            .map_err(|e| format!("Failed to call Python function: {}", e))?;
+    let updated = merge_changes(db, "patient", &id, &stored, &mpps_entry).await?;
+    let Some(updated) = updated else {
+        return Ok(ApiResponse::error("No patient entry found to update", None));
+    };
+    if !storage_commitment.unwrap_or(false) {
+        return Ok(ApiResponse::success(
+            "patient entry updated successfully",
+            Some(updated),
+        ));
+    }
 
-        let result_send_rt_s = send_rt_s
-            .extract::<String>()
-            .map_err(|e| format!("Failed to extract Python function result: {}", e))?;
+    // 实例已经发送成功，提交失败只记录在条目上并在消息中说明
+    let message = match request_commitment(
+        db,
+        dimse_state.client.as_ref(),
+        &node,
+        "patient",
+        &id,
+        &mut commitment,
+    )
+    .await
+    {
+        Ok(()) => format!("Storage commitment: {}", commitment.summary()),
+        Err(e) => format!("Storage commitment not requested: {}", e),
+    };
+    let updated: Option<PatientEntry> = db
+        .select(("patient", &id))
+        .await
+        .map_err(|e| format!("Failed to read patient entry: {}", e))?;
+    Ok(ApiResponse::success(
+        &format!("patient entry updated successfully; {}", message),
+        updated,
+    ))
+}
 
-        // 解析返回的 JSON 字符串
-        let response: MppsResponse = serde_json::from_str(&result_send_rt_s)
-            .map_err(|e| format!("Failed to parse response: {}", e))?;
 
-        if response.success {
-            Ok(response)
-        } else {
-            Err(format!("Failed: {}", response.message))
+// 启动一个内置 SCP，同类型的 SCP 已在运行时先将其停止
+async fn start_scp(
+    scp_state: &ScpState,
+    handle: &AppHandle,
+    kind: &str,
+    config: ScpConfig,
+    service: Arc<dyn ScpService>,
+) -> Result<ApiResponse<ScpStatus>, String> {
+    let certs_dir = AppPath::Certs.resolve(handle)?;
+    let mut servers = scp_state.servers.lock().await;
+    if let Some(running) = servers.remove(kind) {
+        running.stop();
+    }
+    match ScpServer::start(config, &certs_dir, service).await {
+        Ok(server) => {
+            let status = server.status(kind);
+            servers.insert(kind.to_string(), server);
+            Ok(ApiResponse::success(
+                &format!("{} SCP started", kind),
+                Some(status),
+            ))
         }
-    });
+        Err(e) => Ok(ApiResponse::error(&e.to_string(), None)),
+    }
+}
+
+fn scp_config(ae_title: &str, port: &str, tls_enabled: Option<bool>) -> Result<ScpConfig, String> {
+    Ok(ScpConfig {
+        ae_title: ae_title.trim().to_string(),
+        port: port
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid port number: {}", port))?,
+        tls_enabled: tls_enabled.unwrap_or(false),
+    })
+}
 
-    if let Err(e) = result {
-        return Err(e);
+#[command]
+pub async fn start_worklist_scp(
+    db_state: State<'_, DbState>,
+    scp_state: State<'_, ScpState>,
+    handle: AppHandle,
+    setting: WorklistScpSetting,
+) -> Result<ApiResponse<ScpStatus>, String> {
+    let config = scp_config(&setting.ae_title, &setting.port, setting.tls_enabled)?;
+    let service = WorklistScp::new(
+        db_state.db.clone(),
+        setting.modality.unwrap_or_else(|| "CT".to_string()),
+        setting.station_ae_title.unwrap_or_default(),
+    );
+    start_scp(&scp_state, &handle, "worklist", config, Arc::new(service)).await
+}
+
+#[command]
+pub async fn start_mpps_scp(
+    db_state: State<'_, DbState>,
+    scp_state: State<'_, ScpState>,
+    handle: AppHandle,
+    setting: ScpSetting,
+) -> Result<ApiResponse<ScpStatus>, String> {
+    let config = scp_config(&setting.ae_title, &setting.port, setting.tls_enabled)?;
+    let service = MppsScp::new(db_state.db.clone(), handle.clone());
+    start_scp(&scp_state, &handle, "mpps", config, Arc::new(service)).await
+}
+
+// 读取 MPPS SCP 收到的检查步骤
+#[command]
+pub async fn read_received_mpps_entry(
+    db_state: State<'_, DbState>,
+    id: Option<String>,
+) -> Result<ApiResponse<Vec<MppsEntry>>, String> {
+    let db = &db_state.db;
+    let entries: Vec<MppsEntry> = select_entries(db, MPPS_SCP_TABLE, id.as_deref())
+        .await
+        .map_err(|e| format!("Failed to read received mpps entries: {}", e))?;
+    if entries.is_empty() {
+        Ok(ApiResponse::error("No received mpps entry found", None))
+    } else {
+        Ok(ApiResponse::success("Received mpps entries found", Some(entries)))
+    }
+}
+
+#[command]
+pub async fn delete_received_mpps_entry(
+    db_state: State<'_, DbState>,
+    id: String,
+) -> Result<ApiResponse<Vec<MppsEntry>>, String> {
+    let db = &db_state.db;
+    if id == "all" {
+        let deleted: Vec<MppsEntry> = db
+            .delete(MPPS_SCP_TABLE)
+            .await
+            .map_err(|e| format!("Failed to delete received mpps entries: {}", e))?;
+        return Ok(ApiResponse::success(
+            "All received mpps entries deleted successfully",
+            Some(deleted),
+        ));
     }
-    if let Ok(response) = result {
-      mpps_entry.sop_instance_uids = Some(response.result);
+    let deleted: Option<MppsEntry> = db
+        .delete((MPPS_SCP_TABLE, &id))
+        .await
+        .map_err(|e| format!("Failed to delete received mpps entry: {}", e))?;
+    match deleted {
+        Some(e) => Ok(ApiResponse::success(
+            "Received mpps entry deleted successfully",
+            Some(vec![e]),
+        )),
+        None => Ok(ApiResponse::error("No received mpps entry found to delete", None)),
     }
+}
 
-    let updated: Option<PatientEntry> = db
-        .update(("patient", &id))
-        .content(mpps_entry)
+#[command]
+pub async fn start_storage_scp(
+    db_state: State<'_, DbState>,
+    scp_state: State<'_, ScpState>,
+    handle: AppHandle,
+    setting: ScpSetting,
+) -> Result<ApiResponse<ScpStatus>, String> {
+    let config = scp_config(&setting.ae_title, &setting.port, setting.tls_enabled)?;
+    // 接收的影像归档到 dicom 目录下的 received 子目录
+    let archive_dir = AppPath::Dcm.resolve(&handle)?.join("received");
+    let service = StorageScp::new(db_state.db.clone(), handle.clone(), archive_dir);
+    start_scp(&scp_state, &handle, "storage", config, Arc::new(service)).await
+}
+
+#[command]
+pub async fn read_received_instance(
+    db_state: State<'_, DbState>,
+    id: Option<String>,
+) -> Result<ApiResponse<Vec<ReceivedInstance>>, String> {
+    let db = &db_state.db;
+    let entries: Vec<ReceivedInstance> = select_entries(db, STORAGE_SCP_TABLE, id.as_deref())
         .await
-        .map_err(|e| format!("Failed to update patient entry: {}", e))?;
+        .map_err(|e| format!("Failed to read received instances: {}", e))?;
+    if entries.is_empty() {
+        Ok(ApiResponse::error("No received instance found", None))
+    } else {
+        Ok(ApiResponse::success("Received instances found", Some(entries)))
+    }
+}
+
+#[command]
+pub async fn read_storage_scp_associations(
+    db_state: State<'_, DbState>,
+) -> Result<ApiResponse<Vec<AssociationSummary>>, String> {
+    let db = &db_state.db;
+    let mut response = db
+        .query("SELECT * FROM type::table($table) ORDER BY started_at DESC")
+        .bind(("table", STORAGE_SCP_ASSOCIATION_TABLE))
+        .await
+        .map_err(|e| format!("Failed to read storage SCP associations: {}", e))?;
+    let entries: Vec<AssociationSummary> = response
+        .take(0)
+        .map_err(|e| format!("Failed to read storage SCP associations: {}", e))?;
+    Ok(ApiResponse::success("Storage SCP associations", Some(entries)))
+}
+
+#[command]
+pub async fn stop_scp(
+    scp_state: State<'_, ScpState>,
+    kind: String,
+) -> Result<ApiResponse<()>, String> {
+    match scp_state.servers.lock().await.remove(&kind) {
+        Some(server) => {
+            server.stop();
+            Ok(ApiResponse::success(&format!("{} SCP stopped", kind), None))
+        }
+        None => Ok(ApiResponse::error(&format!("{} SCP is not running", kind), None)),
+    }
+}
+
+#[command]
+pub async fn read_scp_status(
+    scp_state: State<'_, ScpState>,
+) -> Result<ApiResponse<Vec<ScpStatus>>, String> {
+    let servers = scp_state.servers.lock().await;
+    let statuses: Vec<ScpStatus> = servers
+        .iter()
+        .map(|(kind, server)| server.status(kind))
+        .collect();
+    Ok(ApiResponse::success("SCP status", Some(statuses)))
+}
+
+#[command]
+pub async fn start_hl7_listener(
+    db_state: State<'_, DbState>,
+    listener_state: State<'_, Hl7ListenerState>,
+    handle: AppHandle,
+    setting: Hl7ListenerSetting,
+) -> Result<ApiResponse<Hl7ListenerStatus>, String> {
+    let port: u16 = setting
+        .port
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid port number: {}", setting.port))?;
+    let mut server = listener_state.server.lock().await;
+    if let Some(running) = server.take() {
+        running.stop();
+    }
+    let service = Hl7Listener::new(db_state.db.clone(), handle, setting);
+    match Hl7Server::start(port, Arc::new(service)).await {
+        Ok(started) => {
+            let status = started.status();
+            *server = Some(started);
+            Ok(ApiResponse::success("HL7 listener started", Some(status)))
+        }
+        Err(e) => Ok(ApiResponse::error(&e.to_string(), None)),
+    }
+}
+
+#[command]
+pub async fn stop_hl7_listener(
+    listener_state: State<'_, Hl7ListenerState>,
+) -> Result<ApiResponse<()>, String> {
+    match listener_state.server.lock().await.take() {
+        Some(server) => {
+            server.stop();
+            Ok(ApiResponse::success("HL7 listener stopped", None))
+        }
+        None => Ok(ApiResponse::error("HL7 listener is not running", None)),
+    }
+}
+
+#[command]
+pub async fn read_hl7_listener_status(
+    listener_state: State<'_, Hl7ListenerState>,
+) -> Result<ApiResponse<Hl7ListenerStatus>, String> {
+    match listener_state.server.lock().await.as_ref() {
+        Some(server) => Ok(ApiResponse::success(
+            "HL7 listener status",
+            Some(server.status()),
+        )),
+        None => Ok(ApiResponse::error("HL7 listener is not running", None)),
+    }
+}
+
+// 读取 HL7 监听器收到的报文，按接收时间倒序
+#[command]
+pub async fn read_received_hl7_message(
+    db_state: State<'_, DbState>,
+    id: Option<String>,
+) -> Result<ApiResponse<Vec<ReceivedHl7Message>>, String> {
+    let db = &db_state.db;
+    let entries: Vec<ReceivedHl7Message> = match id {
+        Some(id) => select_entries(db, HL7_RECEIVED_TABLE, Some(&id))
+            .await
+            .map_err(|e| format!("Failed to read received HL7 messages: {}", e))?,
+        None => {
+            let mut response = db
+                .query("SELECT * FROM type::table($table) ORDER BY received_at DESC")
+                .bind(("table", HL7_RECEIVED_TABLE))
+                .await
+                .map_err(|e| format!("Failed to read received HL7 messages: {}", e))?;
+            response
+                .take(0)
+                .map_err(|e| format!("Failed to read received HL7 messages: {}", e))?
+        }
+    };
+    if entries.is_empty() {
+        Ok(ApiResponse::error("No received HL7 message found", None))
+    } else {
+        Ok(ApiResponse::success("Received HL7 messages found", Some(entries)))
+    }
+}
+
+#[command]
+pub async fn delete_received_hl7_message(
+    db_state: State<'_, DbState>,
+    id: String,
+) -> Result<ApiResponse<Vec<ReceivedHl7Message>>, String> {
+    let db = &db_state.db;
+    if id == "all" {
+        let deleted: Vec<ReceivedHl7Message> = db
+            .delete(HL7_RECEIVED_TABLE)
+            .await
+            .map_err(|e| format!("Failed to delete received HL7 messages: {}", e))?;
+        return Ok(ApiResponse::success(
+            "All received HL7 messages deleted successfully",
+            Some(deleted),
+        ));
+    }
+    let deleted: Option<ReceivedHl7Message> = db
+        .delete((HL7_RECEIVED_TABLE, &id))
+        .await
+        .map_err(|e| format!("Failed to delete received HL7 message: {}", e))?;
+    match deleted {
+        Some(e) => Ok(ApiResponse::success(
+            "Received HL7 message deleted successfully",
+            Some(vec![e]),
+        )),
+        None => Ok(ApiResponse::error("No received HL7 message found to delete", None)),
+    }
+}
+
+/// Turns an ORM^O01 / OMI^O23 message into scheduled steps served by the
+/// worklist SCP. `id` selects a saved HL7 message, otherwise `message` is used.
+#[command]
+pub async fn schedule_hl7_order(
+    db_state: State<'_, DbState>,
+    handle: AppHandle,
+    id: Option<String>,
+    message: Option<String>,
+) -> Result<ApiResponse<Hl7BridgeResult>, String> {
+    let db = &db_state.db;
+    let message = match id {
+        Some(id) => {
+            let setting: Option<HL7MessageSetting> = db
+                .select(("hl7_message_setting", &id))
+                .await
+                .map_err(|e| format!("Failed to read HL7 message setting entry: {}", e))?;
+            match setting {
+                Some(setting) => setting.message,
+                None => return Ok(ApiResponse::error("No HL7 message setting entry found", None)),
+            }
+        }
+        None => message.unwrap_or_default(),
+    };
+    let orders = match parse_orders(&message) {
+        Ok(orders) => orders,
+        Err(e) => return Ok(ApiResponse::error(&e, None)),
+    };
+    let result = apply_orders(db, orders).await?;
+    if let Err(e) = handle.emit(HL7_BRIDGE_EVENT, &result) {
+        eprintln!("Failed to emit {}: {}", HL7_BRIDGE_EVENT, e);
+    }
+    Ok(ApiResponse::success(&result.summary(), Some(result)))
+}
+
+const API_SETTING_TABLE: &str = "api_setting";
+const API_SETTING_ID: &str = "default";
+
+// 启动自动化接口，已在运行时先停止旧的
+pub(crate) async fn launch_api_server(
+    api_state: &ApiServerState,
+    handle: AppHandle,
+    setting: &ApiServerSetting,
+) -> Result<ApiServerStatus, String> {
+    let port: u16 = setting
+        .port
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid port number: {}", setting.port))?;
+    let mut server = api_state.server.lock().await;
+    if let Some(running) = server.take() {
+        running.stop();
+    }
+    let service = TauriCommands::new(handle);
+    let started = ApiServer::start(port, setting.token.clone(), Arc::new(service)).await?;
+    let status = started.status();
+    *server = Some(started);
+    Ok(status)
+}
+
+pub(crate) async fn read_api_setting_record(
+    db: &Surreal<Db>,
+) -> Result<Option<ApiServerSetting>, String> {
+    db.select((API_SETTING_TABLE, API_SETTING_ID))
+        .await
+        .map_err(|e| format!("Failed to read automation API setting: {}", e))
+}
+
+#[command]
+pub async fn read_api_setting(
+    db_state: State<'_, DbState>,
+) -> Result<ApiResponse<ApiServerSetting>, String> {
+    let db = &db_state.db;
+    match read_api_setting_record(db).await? {
+        Some(setting) => Ok(ApiResponse::success(
+            "Automation API setting",
+            Some(setting),
+        )),
+        None => Ok(ApiResponse::error("No automation API setting saved", None)),
+    }
+}
+
+// 保存设置并启动，下次程序启动时按 autoStart 决定是否自动开启
+#[command]
+pub async fn start_api_server(
+    db_state: State<'_, DbState>,
+    api_state: State<'_, ApiServerState>,
+    handle: AppHandle,
+    setting: ApiServerSetting,
+) -> Result<ApiResponse<ApiServerStatus>, String> {
+    let saved: Option<ApiServerSetting> = db_state
+        .db
+        .update((API_SETTING_TABLE, API_SETTING_ID))
+        .content(setting.clone())
+        .await
+        .map_err(|e| format!("Failed to save automation API setting: {}", e))?;
+    let setting = saved.unwrap_or(setting);
+    match launch_api_server(&api_state, handle, &setting).await {
+        Ok(status) => Ok(ApiResponse::success("Automation API started", Some(status))),
+        Err(e) => Ok(ApiResponse::error(&e, None)),
+    }
+}
+
+#[command]
+pub async fn stop_api_server(
+    api_state: State<'_, ApiServerState>,
+) -> Result<ApiResponse<()>, String> {
+    match api_state.server.lock().await.take() {
+        Some(server) => {
+            server.stop();
+            Ok(ApiResponse::success("Automation API stopped", None))
+        }
+        None => Ok(ApiResponse::error("Automation API is not running", None)),
+    }
+}
+
+#[command]
+pub async fn read_api_server_status(
+    api_state: State<'_, ApiServerState>,
+) -> Result<ApiResponse<ApiServerStatus>, String> {
+    match api_state.server.lock().await.as_ref() {
+        Some(server) => Ok(ApiResponse::success(
+            "Automation API status",
+            Some(server.status()),
+        )),
+        None => Ok(ApiResponse::error("Automation API is not running", None)),
+    }
+}
+
+#[command]
+pub async fn create_scenario(
+    db_state: State<'_, DbState>,
+    entry: ScenarioEntry,
+) -> Result<ApiResponse<ScenarioEntry>, String> {
+    if let Err(e) = Scenario::parse(&entry.definition) {
+        return Ok(ApiResponse::error(&e, None));
+    }
+    let db = &db_state.db;
+
+    let created: Vec<ScenarioEntry> = db
+        .create(SCENARIO_TABLE)
+        .content(entry)
+        .await
+        .map_err(|e| format!("Failed to create scenario: {}", e))?;
+
+    match created.into_iter().next() {
+        Some(e) => Ok(ApiResponse::success("Scenario created successfully", Some(e))),
+        None => Ok(ApiResponse::error("Failed to create scenario", None)),
+    }
+}
+
+#[command]
+pub async fn read_scenario(
+    db_state: State<'_, DbState>,
+    id: Option<String>,
+) -> Result<ApiResponse<Vec<ScenarioEntry>>, String> {
+    let db = &db_state.db;
+    let entries: Vec<ScenarioEntry> = select_entries(db, SCENARIO_TABLE, id.as_deref())
+        .await
+        .map_err(|e| format!("Failed to read scenarios: {}", e))?;
+    Ok(ApiResponse::success("Scenarios found", Some(entries)))
+}
+
+#[command]
+pub async fn update_scenario(
+    db_state: State<'_, DbState>,
+    id: String,
+    updated_entry: ScenarioEntry,
+) -> Result<ApiResponse<ScenarioEntry>, String> {
+    if let Err(e) = Scenario::parse(&updated_entry.definition) {
+        return Ok(ApiResponse::error(&e, None));
+    }
+    let db = &db_state.db;
+
+    let updated: Option<ScenarioEntry> = db
+        .update((SCENARIO_TABLE, &id))
+        .content(updated_entry)
+        .await
+        .map_err(|e| format!("Failed to update scenario: {}", e))?;
 
     match updated {
+        Some(e) => Ok(ApiResponse::success("Scenario updated successfully", Some(e))),
+        None => Ok(ApiResponse::error("No scenario found to update", None)),
+    }
+}
+
+#[command]
+pub async fn delete_scenario(
+    db_state: State<'_, DbState>,
+    id: String,
+) -> Result<ApiResponse<ScenarioEntry>, String> {
+    let db = &db_state.db;
+
+    let deleted: Option<ScenarioEntry> = db
+        .delete((SCENARIO_TABLE, &id))
+        .await
+        .map_err(|e| format!("Failed to delete scenario: {}", e))?;
+
+    match deleted {
+        Some(e) => Ok(ApiResponse::success("Scenario deleted successfully", Some(e))),
+        None => Ok(ApiResponse::error("No scenario found to delete", None)),
+    }
+}
+
+// 按场景脚本执行 FULL CT SCAN 流程，每完成一步发送 scenario-progress 事件，报告保存到 scenario_run
+#[command]
+pub async fn run_scenario(
+    db_state: State<'_, DbState>,
+    dimse_state: State<'_, DimseState>,
+    handle: AppHandle,
+    id: String,
+    worklist_id: String,
+    mim_id: Option<String>,
+) -> Result<ApiResponse<ScenarioReport>, String> {
+    // 先读取配置，执行过程中每一步的 MPPS 条目随时保存
+    let db = &db_state.db;
+    let entry: Option<ScenarioEntry> = db
+        .select((SCENARIO_TABLE, &id))
+        .await
+        .map_err(|e| format!("Failed to read scenario: {}", e))?;
+    let worklist: Option<WorklistEntry> = db
+        .select(("worklist", &worklist_id))
+        .await
+        .map_err(|e| format!("Failed to read worklist entry: {}", e))?;
+    let pacs: Option<MimEntry> = match mim_id.as_deref().filter(|id| !id.is_empty()) {
+        Some(mim_id) => Some(
+            db.select(("mim", mim_id))
+                .await
+                .map_err(|e| format!("Failed to read MimEntry entry: {}", e))?
+                .ok_or_else(|| format!("No MimEntry entry found with id: {}", mim_id))?,
+        ),
+        None => None,
+    };
+    let entry = entry.ok_or_else(|| format!("No scenario found with id: {}", id))?;
+    let worklist =
+        worklist.ok_or_else(|| format!("No worklist entry found with id: {}", worklist_id))?;
+    let scenario = match Scenario::parse(&entry.definition) {
+        Ok(mut scenario) => {
+            if scenario.name.is_empty() {
+                scenario.name = entry.name.clone();
+            }
+            scenario
+        }
+        Err(e) => return Ok(ApiResponse::error(&e, None)),
+    };
+
+    let templates = ScenarioTemplates {
+        queries: vec![
+            load_template(&AppPath::CFindRq.resolve(&handle)?)?,
+            load_template(&AppPath::MrModality.resolve(&handle)?)?,
+        ],
+        in_progress: load_template(&AppPath::InProgress.resolve(&handle)?)?,
+        completed: load_template(&AppPath::Completed.resolve(&handle)?)?,
+        discontinued: load_template(&AppPath::DisContinued.resolve(&handle)?)?,
+    };
+    let progress_handle = handle.clone();
+    let runner = ScenarioRunner::new(
+        dimse_state.client.clone(),
+        worklist,
+        templates,
+        AppPath::Dcm.resolve(&handle)?,
+    )
+    .with_pacs(pacs)
+    .with_db(db_state.db.clone())
+    .with_listener(Arc::new(move |step: &ScenarioStepReport| {
+        if let Err(e) = progress_handle.emit(SCENARIO_PROGRESS_EVENT, step) {
+            eprintln!("Failed to emit {}: {}", SCENARIO_PROGRESS_EVENT, e);
+        }
+    }));
+    let report = runner.run(&scenario).await;
+
+    let created: Vec<ScenarioReport> = db_state
+        .db
+        .create(SCENARIO_RUN_TABLE)
+        .content(report.clone())
+        .await
+        .map_err(|e| format!("Failed to save scenario report: {}", e))?;
+    let report = created.into_iter().next().unwrap_or(report);
+    Ok(ApiResponse::success(&report.summary(), Some(report)))
+}
+
+// 读取场景执行报告，按开始时间倒序
+#[command]
+pub async fn read_scenario_run(
+    db_state: State<'_, DbState>,
+    id: Option<String>,
+) -> Result<ApiResponse<Vec<ScenarioReport>>, String> {
+    let db = &db_state.db;
+    let entries: Vec<ScenarioReport> = match id {
+        Some(id) => select_entries(db, SCENARIO_RUN_TABLE, Some(&id))
+            .await
+            .map_err(|e| format!("Failed to read scenario reports: {}", e))?,
+        None => {
+            let mut response = db
+                .query("SELECT * FROM type::table($table) ORDER BY started_at DESC")
+                .bind(("table", SCENARIO_RUN_TABLE))
+                .await
+                .map_err(|e| format!("Failed to read scenario reports: {}", e))?;
+            response
+                .take(0)
+                .map_err(|e| format!("Failed to read scenario reports: {}", e))?
+        }
+    };
+    Ok(ApiResponse::success("Scenario reports found", Some(entries)))
+}
+
+#[command]
+pub async fn delete_scenario_run(
+    db_state: State<'_, DbState>,
+    id: String,
+) -> Result<ApiResponse<Vec<ScenarioReport>>, String> {
+    let db = &db_state.db;
+    if id == "all" {
+        let deleted: Vec<ScenarioReport> = db
+            .delete(SCENARIO_RUN_TABLE)
+            .await
+            .map_err(|e| format!("Failed to delete scenario reports: {}", e))?;
+        return Ok(ApiResponse::success(
+            "All scenario reports deleted successfully",
+            Some(deleted),
+        ));
+    }
+    let deleted: Option<ScenarioReport> = db
+        .delete((SCENARIO_RUN_TABLE, &id))
+        .await
+        .map_err(|e| format!("Failed to delete scenario report: {}", e))?;
+    match deleted {
         Some(e) => Ok(ApiResponse::success(
-            "patient entry updated successfully",
-            Some(e),
+            "Scenario report deleted successfully",
+            Some(vec![e]),
+        )),
+        None => Ok(ApiResponse::error("No scenario report found to delete", None)),
+    }
+}
+
+// 启动压力测试后立即返回，测试在后台执行，结束后报告保存到 load_test_run
+#[command]
+pub async fn start_load_test(
+    db_state: State<'_, DbState>,
+    dimse_state: State<'_, DimseState>,
+    load_state: State<'_, LoadTestState>,
+    handle: AppHandle,
+    worklist_id: String,
+    mim_id: Option<String>,
+    config: LoadTestConfig,
+) -> Result<ApiResponse<LoadTestReport>, String> {
+    let mut run = load_state.run.lock().await;
+    if run.is_some() {
+        return Ok(ApiResponse::error("A load test is already running", None));
+    }
+    let db = &db_state.db;
+    let worklist: Option<WorklistEntry> = db
+        .select(("worklist", &worklist_id))
+        .await
+        .map_err(|e| format!("Failed to read worklist entry: {}", e))?;
+    let pacs: Option<MimEntry> = match mim_id.as_deref().filter(|id| !id.is_empty()) {
+        Some(mim_id) => Some(
+            db.select(("mim", mim_id))
+                .await
+                .map_err(|e| format!("Failed to read MimEntry entry: {}", e))?
+                .ok_or_else(|| format!("No MimEntry entry found with id: {}", mim_id))?,
+        ),
+        None => None,
+    };
+    let worklist =
+        worklist.ok_or_else(|| format!("No worklist entry found with id: {}", worklist_id))?;
+    let templates = ScenarioTemplates {
+        queries: vec![
+            load_template(&AppPath::CFindRq.resolve(&handle)?)?,
+            load_template(&AppPath::MrModality.resolve(&handle)?)?,
+        ],
+        in_progress: load_template(&AppPath::InProgress.resolve(&handle)?)?,
+        completed: load_template(&AppPath::Completed.resolve(&handle)?)?,
+        discontinued: load_template(&AppPath::DisContinued.resolve(&handle)?)?,
+    };
+    let test = match LoadTest::new(
+        dimse_state.client.clone(),
+        worklist,
+        pacs,
+        templates,
+        &AppPath::Dcm.resolve(&handle)?,
+        config,
+    ) {
+        Ok(test) => test,
+        Err(e) => return Ok(ApiResponse::error(&e, None)),
+    };
+    let test_handle = test.handle();
+    *run = Some(test_handle.clone());
+
+    let db = db_state.db.clone();
+    tauri::async_runtime::spawn(async move {
+        let report = test.run().await;
+        let saved: Result<Vec<LoadTestReport>, _> =
+            db.create(LOAD_TEST_RUN_TABLE).content(report.clone()).await;
+        if let Err(e) = saved {
+            eprintln!("Failed to save load test report: {}", e);
+        }
+        handle.state::<LoadTestState>().run.lock().await.take();
+        if let Err(e) = handle.emit(LOAD_TEST_FINISHED_EVENT, &report) {
+            eprintln!("Failed to emit {}: {}", LOAD_TEST_FINISHED_EVENT, e);
+        }
+    });
+    Ok(ApiResponse::success(
+        "Load test started",
+        Some(test_handle.report()),
+    ))
+}
+
+#[command]
+pub async fn stop_load_test(
+    load_state: State<'_, LoadTestState>,
+) -> Result<ApiResponse<LoadTestReport>, String> {
+    match load_state.run.lock().await.as_ref() {
+        Some(run) => {
+            run.stop();
+            Ok(ApiResponse::success("Load test stopping", Some(run.report())))
+        }
+        None => Ok(ApiResponse::error("No load test is running", None)),
+    }
+}
+
+#[command]
+pub async fn read_load_test_status(
+    load_state: State<'_, LoadTestState>,
+) -> Result<ApiResponse<LoadTestReport>, String> {
+    match load_state.run.lock().await.as_ref() {
+        Some(run) => Ok(ApiResponse::success("Load test status", Some(run.report()))),
+        None => Ok(ApiResponse::error("No load test is running", None)),
+    }
+}
+
+// 读取压力测试报告，按开始时间倒序
+#[command]
+pub async fn read_load_test_run(
+    db_state: State<'_, DbState>,
+    id: Option<String>,
+) -> Result<ApiResponse<Vec<LoadTestReport>>, String> {
+    let db = &db_state.db;
+    let entries: Vec<LoadTestReport> = match id {
+        Some(id) => select_entries(db, LOAD_TEST_RUN_TABLE, Some(&id))
+            .await
+            .map_err(|e| format!("Failed to read load test reports: {}", e))?,
+        None => {
+            let mut response = db
+                .query("SELECT * FROM type::table($table) ORDER BY started_at DESC")
+                .bind(("table", LOAD_TEST_RUN_TABLE))
+                .await
+                .map_err(|e| format!("Failed to read load test reports: {}", e))?;
+            response
+                .take(0)
+                .map_err(|e| format!("Failed to read load test reports: {}", e))?
+        }
+    };
+    Ok(ApiResponse::success("Load test reports found", Some(entries)))
+}
+
+#[command]
+pub async fn delete_load_test_run(
+    db_state: State<'_, DbState>,
+    id: String,
+) -> Result<ApiResponse<Vec<LoadTestReport>>, String> {
+    let db = &db_state.db;
+    if id == "all" {
+        let deleted: Vec<LoadTestReport> = db
+            .delete(LOAD_TEST_RUN_TABLE)
+            .await
+            .map_err(|e| format!("Failed to delete load test reports: {}", e))?;
+        return Ok(ApiResponse::success(
+            "All load test reports deleted successfully",
+            Some(deleted),
+        ));
+    }
+    let deleted: Option<LoadTestReport> = db
+        .delete((LOAD_TEST_RUN_TABLE, &id))
+        .await
+        .map_err(|e| format!("Failed to delete load test report: {}", e))?;
+    match deleted {
+        Some(e) => Ok(ApiResponse::success(
+            "Load test report deleted successfully",
+            Some(vec![e]),
         )),
-        None => Ok(ApiResponse::error("No patient entry found to update", None)),
+        None => Ok(ApiResponse::error("No load test report found to delete", None)),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Serialize, Clone)]
+    struct Entry {
+        status: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        report: Option<String>,
+        commitment: Option<String>,
+    }
+
+    #[test]
+    fn changed_fields_leaves_untouched_fields_out() {
+        let before = Entry {
+            status: "IN PROGRESS".to_string(),
+            report: None,
+            commitment: None,
+        };
+        let mut after = before.clone();
+        after.status = "COMPLETED".to_string();
+
+        let changes = changed_fields(&before, &after).unwrap();
+        assert_eq!(
+            serde_json::Value::Object(changes),
+            json!({ "status": "COMPLETED" })
+        );
+        assert!(changed_fields(&before, &before).unwrap().is_empty());
+    }
+
+    #[test]
+    fn changed_fields_nulls_removed_fields() {
+        let before = Entry {
+            status: "COMPLETED".to_string(),
+            report: Some("AA".to_string()),
+            commitment: None,
+        };
+        let mut after = before.clone();
+        after.report = None;
+
+        let changes = changed_fields(&before, &after).unwrap();
+        assert_eq!(
+            serde_json::Value::Object(changes),
+            json!({ "report": null })
+        );
+    }
+}
@@ -0,0 +1,147 @@
+// worklist_scp.rs
+// 内置的 Modality Worklist SCP，使用 patient / mpps 表中的数据响应 C-FIND
+use crate::dicom_server::{
+    ScpReply, ScpRequest, ScpService, STATUS_IDENTIFIER_DOES_NOT_MATCH_SOP_CLASS,
+    STATUS_PENDING, STATUS_PROCESSING_FAILURE, STATUS_SUCCESS, STATUS_UNRECOGNIZED_OPERATION,
+};
+use crate::dicom_utils::{matches_query, response_identifier, worklist_item};
+use crate::models::{MppsEntry, PatientEntry};
+use chrono::Local;
+use dicom_dictionary_std::uids;
+use dicom_object::InMemDicomObject;
+use futures::future::BoxFuture;
+use surrealdb::engine::local::Db;
+use surrealdb::Surreal;
+use uuid::Uuid;
+
+pub struct WorklistScp {
+    db: Surreal<Db>,
+    modality: String,
+    station_ae_title: String,
+}
+
+impl WorklistScp {
+    /// `modality` and `station_ae_title` are used for the items built from the
+    /// `patient` table, which has no scheduling information of its own.
+    pub fn new(
+        db: Surreal<Db>,
+        modality: String,
+        station_ae_title: String,
+    ) -> WorklistScp {
+        WorklistScp {
+            db,
+            modality,
+            station_ae_title,
+        }
+    }
+
+    async fn load_items(&self) -> Result<Vec<InMemDicomObject>, String> {
+        let scheduled: Vec<MppsEntry> = self
+            .db
+            .select("mpps")
+            .await
+            .map_err(|e| format!("Failed to read mpps entries: {}", e))?;
+        let patients: Vec<PatientEntry> = self
+            .db
+            .select("patient")
+            .await
+            .map_err(|e| format!("Failed to read patient entries: {}", e))?;
+
+        let mut items: Vec<InMemDicomObject> = scheduled.iter().map(worklist_item).collect();
+        items.extend(
+            patients
+                .iter()
+                .map(|patient| worklist_item(&self.scheduled_from_patient(patient))),
+        );
+        Ok(items)
+    }
+
+    // patient 表中的条目没有检查信息，按当天、默认设备类型生成一个预约检查
+    fn scheduled_from_patient(&self, patient: &PatientEntry) -> MppsEntry {
+        let key = patient
+            .id
+            .as_ref()
+            .map(|id| id.id.to_raw())
+            .unwrap_or_default();
+        // StudyInstanceUID 由记录 id 派生，多次查询结果保持一致
+        let study_uid = Uuid::new_v5(&Uuid::NAMESPACE_OID, key.as_bytes()).as_u128();
+        MppsEntry {
+            id: patient.id.clone(),
+            accession_number: key.chars().take(16).collect(),
+            requested_procedure_description: patient.description.clone().unwrap_or_default(),
+            patient_name: patient.patient_name.clone(),
+            patient_id: patient.patient_id.clone(),
+            patient_birth_date: patient.patient_birth_date.clone(),
+            patient_sex: patient.patient_sex.clone(),
+            modality: self.modality.clone(),
+            scheduled_station_ae_title: self.station_ae_title.clone(),
+            scheduled_procedure_step_start_date: Local::now().format("%Y%m%d").to_string(),
+            scheduled_performing_physician_name: String::new(),
+            study_instance_uid: format!("2.25.{}", study_uid),
+            status: None,
+            mpps_instance_uid: None,
+            sop_instance_uids: None,
+            dcm_file: None,
+            description: None,
+            status_history: Vec::new(),
+            storage_commitment: None,
+            hl7_report: None,
+            fhir_publish: Vec::new(),
+        }
+    }
+
+    async fn c_find(&self, request: &ScpRequest) -> Vec<ScpReply> {
+        let query = match request.decode_data_set() {
+            Ok(Some(query)) => query,
+            _ => {
+                return vec![ScpReply::response(
+                    request,
+                    STATUS_IDENTIFIER_DOES_NOT_MATCH_SOP_CLASS,
+                    None,
+                )]
+            }
+        };
+        let items = match self.load_items().await {
+            Ok(items) => items,
+            Err(e) => {
+                eprintln!("Worklist SCP: {}", e);
+                return vec![ScpReply::response(request, STATUS_PROCESSING_FAILURE, None)];
+            }
+        };
+
+        let mut replies: Vec<ScpReply> = items
+            .iter()
+            .filter(|item| matches_query(item, &query))
+            .map(|item| {
+                ScpReply::response(
+                    request,
+                    STATUS_PENDING,
+                    Some(response_identifier(item, &query)),
+                )
+            })
+            .collect();
+        replies.push(ScpReply::response(request, STATUS_SUCCESS, None));
+        replies
+    }
+}
+
+impl ScpService for WorklistScp {
+    fn abstract_syntaxes(&self) -> Vec<String> {
+        vec![uids::MODALITY_WORKLIST_INFORMATION_MODEL_FIND.to_string()]
+    }
+
+    fn handle<'a>(&'a self, request: &'a ScpRequest) -> BoxFuture<'a, Vec<ScpReply>> {
+        Box::pin(async move {
+            match request.command_field() {
+                0x0020 => self.c_find(request).await,
+                // C-CANCEL 到达时所有响应已经发出，无需处理
+                0x0FFF => Vec::new(),
+                _ => vec![ScpReply::response(
+                    request,
+                    STATUS_UNRECOGNIZED_OPERATION,
+                    None,
+                )],
+            }
+        })
+    }
+}
@@ -46,6 +46,33 @@ export default {
         showBackButton: true,
       },
     },
+    {
+      path: "/dicom/scenario",
+      name: "scenario",
+      component: () => import("@/views/dicom/scenario/index.vue"),
+      meta: {
+        title: $t("dicom.scenario"),
+        showBackButton: true,
+      },
+    },
+    {
+      path: "/dicom/loadtest",
+      name: "loadtest",
+      component: () => import("@/views/dicom/loadtest/index.vue"),
+      meta: {
+        title: $t("dicom.loadtest"),
+        showBackButton: true,
+      },
+    },
+    {
+      path: "/dicom/mppsscp",
+      name: "mppsscp",
+      component: () => import("@/views/dicom/mppsscp/index.vue"),
+      meta: {
+        title: $t("dicom.mppsscp"),
+        showBackButton: true,
+      },
+    },
     {
       path: "/worklistsetting",
       name: "worklistsetting",
@@ -70,6 +97,22 @@ export default {
         title: $t("dicom.worklist"),
       },
     },
+    {
+      path: "/settingfhir",
+      name: "fhirsetting",
+      component: () => import("@/views/dicom/setting/FhirSetting.vue"),
+      meta: {
+        title: $t("dicom.worklist"),
+      },
+    },
+    {
+      path: "/settingapi",
+      name: "apisetting",
+      component: () => import("@/views/dicom/setting/ApiSetting.vue"),
+      meta: {
+        title: $t("dicom.worklist"),
+      },
+    },
     {
       path: "/settingmessage",
       name: "settingmessage",